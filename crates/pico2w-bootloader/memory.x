@@ -0,0 +1,61 @@
+MEMORY {
+    /*
+     * Flash layout shared with the application's memory.x; keep the two in
+     * sync. The bootloader owns the first 32K.
+     */
+    FLASH : ORIGIN = 0x10000000, LENGTH = 32K
+    /* Swap progress and the "new image pending" magic */
+    BOOTLOADER_STATE : ORIGIN = 0x10008000, LENGTH = 4K
+    /* The image that runs */
+    ACTIVE : ORIGIN = 0x10009000, LENGTH = 1000K
+    /* Where updates are written; one page larger than ACTIVE for the swap */
+    DFU : ORIGIN = 0x10103000, LENGTH = 1004K
+    /*
+     * 0x101FE000 is spare, the next 4K sector holds the application's WiFi
+     * settings and the upper 2 MiB its animations drive.
+     */
+    RAM : ORIGIN = 0x20000000, LENGTH = 512K
+}
+
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - ORIGIN(FLASH);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - ORIGIN(FLASH);
+
+__bootloader_active_start = ORIGIN(ACTIVE) - ORIGIN(FLASH);
+__bootloader_active_end = ORIGIN(ACTIVE) + LENGTH(ACTIVE) - ORIGIN(FLASH);
+
+__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(FLASH);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(FLASH);
+
+SECTIONS {
+    /* ### Boot ROM info
+     *
+     * Goes after .vector_table, to keep it in the first 4K of flash
+     * where the Boot ROM (and picotool) can find it
+     */
+    .start_block : ALIGN(4)
+    {
+        __start_block_addr = .;
+        KEEP(*(.start_block));
+        KEEP(*(.boot_info));
+    } > FLASH
+
+} INSERT AFTER .vector_table;
+
+/* move .text to start /after/ the boot info */
+_stext = ADDR(.start_block) + SIZEOF(.start_block);
+
+SECTIONS {
+    /* ### Boot ROM extra info
+     *
+     * Goes after everything in our program, so it can contain a signature.
+     */
+    .end_block : ALIGN(4)
+    {
+        __end_block_addr = .;
+        KEEP(*(.end_block));
+    } > FLASH
+
+} INSERT AFTER .uninit;
+
+PROVIDE(start_to_end = __end_block_addr - __start_block_addr);
+PROVIDE(end_to_start = __start_block_addr - __end_block_addr);
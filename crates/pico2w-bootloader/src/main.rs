@@ -0,0 +1,56 @@
+// file: main.rs
+// desc: A/B bootloader for the Pico 2 W applications
+//
+// Lives in the first 32K of flash. On every boot it finishes or rolls back a
+// pending swap between the ACTIVE and DFU partitions (see memory.x), then
+// jumps to ACTIVE. An application that was swapped in must mark itself booted
+// before the next reset, or this reverts to the previous image.
+#![no_std]
+#![no_main]
+
+use core::cell::RefCell;
+
+use cortex_m_rt::{entry, exception};
+use embassy_boot_rp::{BootLoader, BootLoaderConfig, WatchdogFlash};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Duration;
+
+use defmt_rtt as _;
+
+// Matches FLASH_SIZE in the application's settings store
+const FLASH_SIZE: usize = 4 * 1024 * 1024;
+// A swap copies page by page and feeds the watchdog as it goes; a hang resets
+// and resumes it
+const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(8);
+
+#[entry]
+fn main() -> ! {
+    let p = embassy_rp::init(Default::default());
+
+    let flash = WatchdogFlash::<FLASH_SIZE>::start(p.FLASH, p.WATCHDOG, WATCHDOG_TIMEOUT);
+    let flash = Mutex::new(RefCell::new(flash));
+
+    let config = BootLoaderConfig::from_linkerfile_blocking(&flash, &flash, &flash);
+    let active_offset = config.active.offset();
+    let bootloader: BootLoader = BootLoader::prepare(config);
+
+    unsafe { bootloader.load(embassy_rp::flash::FLASH_BASE as u32 + active_offset) }
+}
+
+#[unsafe(no_mangle)]
+#[cfg_attr(target_os = "none", unsafe(link_section = ".HardFault.user"))]
+unsafe extern "C" fn HardFault() {
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+#[exception]
+unsafe fn DefaultHandler(_: i16) -> ! {
+    const SCB_ICSR: *const u32 = 0xE000_ED04 as *const u32;
+    let irqn = unsafe { core::ptr::read_volatile(SCB_ICSR) } as u8 as i16 - 16;
+    panic!("DefaultHandler #{:?}", irqn);
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    cortex_m::asm::udf();
+}
@@ -0,0 +1,49 @@
+// file: display.rs
+// desc: 128x64 SSD1306 OLED on I2C0
+use defmt::*;
+use embassy_rp::bind_interrupts;
+use embassy_rp::i2c::{self, Config};
+use embassy_rp::peripherals::{I2C0, PIN_0, PIN_1};
+use embassy_rp::Peri;
+use embassy_time::Timer;
+
+use ssd1306::{prelude::*, I2CDisplayInterface, Ssd1306};
+
+bind_interrupts!(struct Irqs {
+    I2C0_IRQ => i2c::InterruptHandler<I2C0>;
+});
+
+pub type Display = Ssd1306<
+    I2CInterface<i2c::I2c<'static, I2C0, i2c::Async>>,
+    DisplaySize128x64,
+    ssd1306::mode::BufferedGraphicsMode<DisplaySize128x64>
+>;
+
+pub async fn setup_display(
+    i2c0: Peri<'static, I2C0>,
+    sda_pin: Peri<'static, PIN_0>,
+    scl_pin: Peri<'static, PIN_1>,
+) -> Display {
+    // Setup i2c
+    info!("Setting up i2c on pins SDA=0, SCL=1");
+    let i2c = i2c::I2c::new_async(i2c0, scl_pin, sda_pin, Irqs, Config::default());
+
+    // Setup OLED display
+    info!("Initializing OLED display at address 0x3C");
+    let interface = I2CDisplayInterface::new(i2c);
+    let mut display = Ssd1306::new(interface, DisplaySize128x64, DisplayRotation::Rotate0)
+        .into_buffered_graphics_mode();
+
+    // Initialize display
+    match display.init() {
+        Ok(_) => info!("OLED display initialized successfully"),
+        Err(_) => {
+            error!("Failed to initialize OLED display");
+            loop {
+                Timer::after_secs(1).await;
+            }
+        }
+    }
+
+    display
+}
@@ -0,0 +1,156 @@
+// file: lib.rs
+// desc: board support for the Raspberry Pi Pico 2 W
+//
+// `Board::init` claims the pins wired to the cyw43 radio (GPIO 23, 24, 25, 29,
+// PIO0 and DMA_CH0), brings the chip up and hands back everything else.
+#![no_std]
+
+use cyw43_pio::{PioSpi, RM2_CLOCK_DIVIDER};
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_rp::bind_interrupts;
+#[cfg(feature = "net")]
+use embassy_rp::clocks::RoscRng;
+use embassy_rp::gpio::{Level, Output};
+use embassy_rp::peripherals::{DMA_CH0, PIO0};
+use embassy_rp::pio::{InterruptHandler, Pio};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use static_cell::StaticCell;
+
+#[cfg(feature = "net")]
+use embassy_net::{Stack, StackResources};
+
+mod pins;
+pub use pins::Pins;
+use pins::RadioPins;
+
+#[cfg(feature = "display")]
+pub mod display;
+
+bind_interrupts!(struct Irqs {
+    PIO0_IRQ_0 => InterruptHandler<PIO0>;
+});
+
+/// The cyw43 controller, shared between every task that talks to the radio.
+pub type Radio = Mutex<CriticalSectionRawMutex, cyw43::Control<'static>>;
+
+pub struct Board {
+    pub radio: &'static Radio,
+    pub led: OnboardLed,
+    #[cfg(feature = "net")]
+    pub stack: Option<Stack<'static>>,
+    pub pins: Pins,
+}
+
+impl Board {
+    /// Bring up the radio without a network stack.
+    pub async fn init(p: embassy_rp::Peripherals, spawner: &Spawner) -> Self {
+        let (pins, _net_device, radio) = start_radio(p, spawner).await;
+
+        Board {
+            radio,
+            led: OnboardLed { radio },
+            #[cfg(feature = "net")]
+            stack: None,
+            pins,
+        }
+    }
+
+    /// Bring up the radio and an embassy-net stack on top of it.
+    ///
+    /// `resources` decides how many sockets the stack can hold at once.
+    #[cfg(feature = "net")]
+    pub async fn init_with_net<const SOCK: usize>(
+        p: embassy_rp::Peripherals,
+        spawner: &Spawner,
+        config: embassy_net::Config,
+        resources: &'static mut StackResources<SOCK>,
+    ) -> Self {
+        let (pins, net_device, radio) = start_radio(p, spawner).await;
+
+        let mut rng = RoscRng;
+        let seed = rng.next_u64();
+        let (stack, runner) = embassy_net::new(net_device, config, resources, seed);
+        unwrap!(spawner.spawn(net_task(runner)));
+        info!("Network stack initialized!");
+
+        Board {
+            radio,
+            led: OnboardLed { radio },
+            stack: Some(stack),
+            pins,
+        }
+    }
+}
+
+/// The green LED, wired to GPIO 0 of the cyw43 rather than the RP2350.
+#[derive(Clone, Copy)]
+pub struct OnboardLed {
+    radio: &'static Radio,
+}
+
+impl OnboardLed {
+    pub async fn set(&self, on: bool) {
+        self.radio.lock().await.gpio_set(0, on).await;
+    }
+
+    pub async fn on(&self) {
+        self.set(true).await;
+    }
+
+    pub async fn off(&self) {
+        self.set(false).await;
+    }
+}
+
+#[embassy_executor::task]
+async fn cyw43_task(runner: cyw43::Runner<'static, Output<'static>, PioSpi<'static, PIO0, 0, DMA_CH0>>) -> ! {
+    runner.run().await
+}
+
+#[cfg(feature = "net")]
+#[embassy_executor::task]
+async fn net_task(mut runner: embassy_net::Runner<'static, cyw43::NetDriver<'static>>) -> ! {
+    runner.run().await
+}
+
+async fn start_radio(
+    p: embassy_rp::Peripherals,
+    spawner: &Spawner,
+) -> (Pins, cyw43::NetDriver<'static>, &'static Radio) {
+    let (radio_pins, pins) = pins::split(p);
+
+    // The firmware and CLM live next to this crate's Cargo.toml.
+    let fw = include_bytes!("../cyw43-firmware/43439A0.bin");
+    let clm = include_bytes!("../cyw43-firmware/43439A0_clm.bin");
+
+    let RadioPins { pwr, cs, dio, clk, pio, dma } = radio_pins;
+    let pwr = Output::new(pwr, Level::Low);
+    let cs = Output::new(cs, Level::High);
+    let mut pio = Pio::new(pio, Irqs);
+    let spi = PioSpi::new(
+        &mut pio.common,
+        pio.sm0,
+        RM2_CLOCK_DIVIDER,
+        pio.irq0,
+        cs,
+        dio,
+        clk,
+        dma,
+    );
+
+    static STATE: StaticCell<cyw43::State> = StaticCell::new();
+    let state = STATE.init(cyw43::State::new());
+    let (net_device, mut control, runner) = cyw43::new(state, pwr, spi, fw).await;
+    unwrap!(spawner.spawn(cyw43_task(runner)));
+
+    control.init(clm).await;
+    control.gpio_set(0, false).await;
+    info!("WiFi initialized!");
+
+    static RADIO: StaticCell<Radio> = StaticCell::new();
+    let radio = RADIO.init(Mutex::new(control));
+
+    (pins, net_device, radio)
+}
@@ -0,0 +1,175 @@
+// file: pins.rs
+// desc: split `embassy_rp::Peripherals` into the radio's share and the rest
+use embassy_rp::Peri;
+use embassy_rp::peripherals::*;
+
+/// Everything on the RP2350 that the board did not claim for the radio.
+#[allow(non_snake_case)]
+pub struct Pins {
+    // GPIO 23, 24, 25 and 29 are wired to the cyw43
+    pub PIN_0: Peri<'static, PIN_0>,
+    pub PIN_1: Peri<'static, PIN_1>,
+    pub PIN_2: Peri<'static, PIN_2>,
+    pub PIN_3: Peri<'static, PIN_3>,
+    pub PIN_4: Peri<'static, PIN_4>,
+    pub PIN_5: Peri<'static, PIN_5>,
+    pub PIN_6: Peri<'static, PIN_6>,
+    pub PIN_7: Peri<'static, PIN_7>,
+    pub PIN_8: Peri<'static, PIN_8>,
+    pub PIN_9: Peri<'static, PIN_9>,
+    pub PIN_10: Peri<'static, PIN_10>,
+    pub PIN_11: Peri<'static, PIN_11>,
+    pub PIN_12: Peri<'static, PIN_12>,
+    pub PIN_13: Peri<'static, PIN_13>,
+    pub PIN_14: Peri<'static, PIN_14>,
+    pub PIN_15: Peri<'static, PIN_15>,
+    pub PIN_16: Peri<'static, PIN_16>,
+    pub PIN_17: Peri<'static, PIN_17>,
+    pub PIN_18: Peri<'static, PIN_18>,
+    pub PIN_19: Peri<'static, PIN_19>,
+    pub PIN_20: Peri<'static, PIN_20>,
+    pub PIN_21: Peri<'static, PIN_21>,
+    pub PIN_22: Peri<'static, PIN_22>,
+    pub PIN_26: Peri<'static, PIN_26>,
+    pub PIN_27: Peri<'static, PIN_27>,
+    pub PIN_28: Peri<'static, PIN_28>,
+
+    pub I2C0: Peri<'static, I2C0>,
+    pub I2C1: Peri<'static, I2C1>,
+    pub SPI0: Peri<'static, SPI0>,
+    pub SPI1: Peri<'static, SPI1>,
+    pub UART0: Peri<'static, UART0>,
+    pub UART1: Peri<'static, UART1>,
+    pub PIO1: Peri<'static, PIO1>,
+    pub PIO2: Peri<'static, PIO2>,
+    pub DMA_CH1: Peri<'static, DMA_CH1>,
+    pub DMA_CH2: Peri<'static, DMA_CH2>,
+    pub DMA_CH3: Peri<'static, DMA_CH3>,
+    pub DMA_CH4: Peri<'static, DMA_CH4>,
+    pub DMA_CH5: Peri<'static, DMA_CH5>,
+    pub DMA_CH6: Peri<'static, DMA_CH6>,
+    pub DMA_CH7: Peri<'static, DMA_CH7>,
+    pub DMA_CH8: Peri<'static, DMA_CH8>,
+    pub DMA_CH9: Peri<'static, DMA_CH9>,
+    pub DMA_CH10: Peri<'static, DMA_CH10>,
+    pub DMA_CH11: Peri<'static, DMA_CH11>,
+    pub DMA_CH12: Peri<'static, DMA_CH12>,
+    pub DMA_CH13: Peri<'static, DMA_CH13>,
+    pub DMA_CH14: Peri<'static, DMA_CH14>,
+    pub DMA_CH15: Peri<'static, DMA_CH15>,
+    pub PWM_SLICE0: Peri<'static, PWM_SLICE0>,
+    pub PWM_SLICE1: Peri<'static, PWM_SLICE1>,
+    pub PWM_SLICE2: Peri<'static, PWM_SLICE2>,
+    pub PWM_SLICE3: Peri<'static, PWM_SLICE3>,
+    pub PWM_SLICE4: Peri<'static, PWM_SLICE4>,
+    pub PWM_SLICE5: Peri<'static, PWM_SLICE5>,
+    pub PWM_SLICE6: Peri<'static, PWM_SLICE6>,
+    pub PWM_SLICE7: Peri<'static, PWM_SLICE7>,
+    pub PWM_SLICE8: Peri<'static, PWM_SLICE8>,
+    pub PWM_SLICE9: Peri<'static, PWM_SLICE9>,
+    pub PWM_SLICE10: Peri<'static, PWM_SLICE10>,
+    pub PWM_SLICE11: Peri<'static, PWM_SLICE11>,
+    pub USB: Peri<'static, USB>,
+    pub FLASH: Peri<'static, FLASH>,
+    pub ADC: Peri<'static, ADC>,
+    pub ADC_TEMP_SENSOR: Peri<'static, ADC_TEMP_SENSOR>,
+    pub WATCHDOG: Peri<'static, WATCHDOG>,
+    pub CORE1: Peri<'static, CORE1>,
+    pub BOOTSEL: Peri<'static, BOOTSEL>,
+    pub TRNG: Peri<'static, TRNG>,
+}
+
+pub(crate) struct RadioPins {
+    pub pwr: Peri<'static, PIN_23>,
+    pub dio: Peri<'static, PIN_24>,
+    pub cs: Peri<'static, PIN_25>,
+    pub clk: Peri<'static, PIN_29>,
+    pub pio: Peri<'static, PIO0>,
+    pub dma: Peri<'static, DMA_CH0>,
+}
+
+pub(crate) fn split(p: embassy_rp::Peripherals) -> (RadioPins, Pins) {
+    let radio = RadioPins {
+        pwr: p.PIN_23,
+        dio: p.PIN_24,
+        cs: p.PIN_25,
+        clk: p.PIN_29,
+        pio: p.PIO0,
+        dma: p.DMA_CH0,
+    };
+
+    let pins = Pins {
+        PIN_0: p.PIN_0,
+        PIN_1: p.PIN_1,
+        PIN_2: p.PIN_2,
+        PIN_3: p.PIN_3,
+        PIN_4: p.PIN_4,
+        PIN_5: p.PIN_5,
+        PIN_6: p.PIN_6,
+        PIN_7: p.PIN_7,
+        PIN_8: p.PIN_8,
+        PIN_9: p.PIN_9,
+        PIN_10: p.PIN_10,
+        PIN_11: p.PIN_11,
+        PIN_12: p.PIN_12,
+        PIN_13: p.PIN_13,
+        PIN_14: p.PIN_14,
+        PIN_15: p.PIN_15,
+        PIN_16: p.PIN_16,
+        PIN_17: p.PIN_17,
+        PIN_18: p.PIN_18,
+        PIN_19: p.PIN_19,
+        PIN_20: p.PIN_20,
+        PIN_21: p.PIN_21,
+        PIN_22: p.PIN_22,
+        PIN_26: p.PIN_26,
+        PIN_27: p.PIN_27,
+        PIN_28: p.PIN_28,
+
+        I2C0: p.I2C0,
+        I2C1: p.I2C1,
+        SPI0: p.SPI0,
+        SPI1: p.SPI1,
+        UART0: p.UART0,
+        UART1: p.UART1,
+        PIO1: p.PIO1,
+        PIO2: p.PIO2,
+        DMA_CH1: p.DMA_CH1,
+        DMA_CH2: p.DMA_CH2,
+        DMA_CH3: p.DMA_CH3,
+        DMA_CH4: p.DMA_CH4,
+        DMA_CH5: p.DMA_CH5,
+        DMA_CH6: p.DMA_CH6,
+        DMA_CH7: p.DMA_CH7,
+        DMA_CH8: p.DMA_CH8,
+        DMA_CH9: p.DMA_CH9,
+        DMA_CH10: p.DMA_CH10,
+        DMA_CH11: p.DMA_CH11,
+        DMA_CH12: p.DMA_CH12,
+        DMA_CH13: p.DMA_CH13,
+        DMA_CH14: p.DMA_CH14,
+        DMA_CH15: p.DMA_CH15,
+        PWM_SLICE0: p.PWM_SLICE0,
+        PWM_SLICE1: p.PWM_SLICE1,
+        PWM_SLICE2: p.PWM_SLICE2,
+        PWM_SLICE3: p.PWM_SLICE3,
+        PWM_SLICE4: p.PWM_SLICE4,
+        PWM_SLICE5: p.PWM_SLICE5,
+        PWM_SLICE6: p.PWM_SLICE6,
+        PWM_SLICE7: p.PWM_SLICE7,
+        PWM_SLICE8: p.PWM_SLICE8,
+        PWM_SLICE9: p.PWM_SLICE9,
+        PWM_SLICE10: p.PWM_SLICE10,
+        PWM_SLICE11: p.PWM_SLICE11,
+        USB: p.USB,
+        FLASH: p.FLASH,
+        ADC: p.ADC,
+        ADC_TEMP_SENSOR: p.ADC_TEMP_SENSOR,
+        WATCHDOG: p.WATCHDOG,
+        CORE1: p.CORE1,
+        BOOTSEL: p.BOOTSEL,
+        TRNG: p.TRNG,
+    };
+
+    (radio, pins)
+}
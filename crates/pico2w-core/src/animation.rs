@@ -0,0 +1,311 @@
+// file: animation.rs
+// desc: animation lookup and the frame-index state machine
+use crate::command::Command;
+
+/// Frame interval the display starts with, in milliseconds.
+pub const DEFAULT_FRAME_INTERVAL_MS: u16 = 100;
+/// Allowed `SetSpeed` range; faster than this the I2C flush cannot keep up.
+pub const FRAME_INTERVAL_RANGE_MS: core::ops::RangeInclusive<u16> = 20..=5000;
+
+/// Every frame of one animation, each a complete BMP file. Compiled-in ones
+/// are `'static`; ones read off the USB drive live as long as its table.
+pub type Frames<'a> = &'a [&'a [u8]];
+
+/// Look up an animation by its 1-based number, falling back to the first one.
+pub fn get_animation_data<'a>(animations: &[Frames<'a>], animation_num: u8) -> (Frames<'a>, usize) {
+    let frames = (animation_num as usize)
+        .checked_sub(1)
+        .and_then(|index| animations.get(index))
+        .unwrap_or(&animations[0]);
+
+    (frames, frames.len())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PlaybackError {
+    /// The animation number was outside `1..=animation_count`.
+    InvalidAnimation(u8),
+    /// The frame interval was outside `FRAME_INTERVAL_RANGE_MS`.
+    InvalidSpeed(u16),
+}
+
+/// What the display should draw next.
+#[derive(Debug, Clone, Copy)]
+pub struct Step<'a> {
+    pub animation: u8,
+    pub frame_index: usize,
+    pub frame_count: usize,
+    pub frame: &'a [u8],
+    /// True on the first frame after the animation changed.
+    pub switched: bool,
+}
+
+/// Snapshot of the player for status reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PlaybackState {
+    pub animation: u8,
+    pub animation_count: u8,
+    /// Frame on screen, counted from 0.
+    pub frame_index: usize,
+    pub frame_count: usize,
+    pub paused: bool,
+    pub frame_interval_ms: u16,
+}
+
+/// Tracks which animation is playing and which frame comes next.
+pub struct Player<'a> {
+    animations: &'a [Frames<'a>],
+    current: u8,
+    previous: u8,
+    frame_index: usize,
+    shown_frame: usize,
+    paused: bool,
+    frame_interval_ms: u16,
+}
+
+impl<'a> Player<'a> {
+    /// Start on animation 1. Every animation must have at least one frame.
+    pub const fn new(animations: &'a [Frames<'a>]) -> Self {
+        assert!(!animations.is_empty());
+        Player {
+            animations,
+            current: 1,
+            previous: 0,
+            frame_index: 0,
+            shown_frame: 0,
+            paused: false,
+            frame_interval_ms: DEFAULT_FRAME_INTERVAL_MS,
+        }
+    }
+
+    pub fn animation_count(&self) -> u8 {
+        self.animations.len() as u8
+    }
+
+    pub fn current(&self) -> u8 {
+        self.current
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn frame_interval_ms(&self) -> u16 {
+        self.frame_interval_ms
+    }
+
+    pub fn state(&self) -> PlaybackState {
+        let (_, frame_count) = get_animation_data(self.animations, self.current);
+        PlaybackState {
+            animation: self.current,
+            animation_count: self.animation_count(),
+            // A new selection shows its first frame next
+            frame_index: if self.current == self.previous { self.shown_frame } else { 0 },
+            frame_count,
+            paused: self.paused,
+            frame_interval_ms: self.frame_interval_ms,
+        }
+    }
+
+    /// Switch to another animation. It restarts from frame 0 on the next `step`.
+    pub fn select(&mut self, animation_num: u8) -> Result<(), PlaybackError> {
+        if (1..=self.animation_count()).contains(&animation_num) {
+            self.current = animation_num;
+            Ok(())
+        } else {
+            Err(PlaybackError::InvalidAnimation(animation_num))
+        }
+    }
+
+    /// Apply the playback commands; display-only ones such as
+    /// `SetBrightness` and `ShowText` are left to the caller.
+    pub fn apply(&mut self, command: &Command) -> Result<(), PlaybackError> {
+        let count = self.animation_count();
+        match *command {
+            Command::PlayAnimation(animation_num) => {
+                self.select(animation_num)?;
+                self.paused = false;
+            }
+            Command::Pause => self.paused = true,
+            Command::Resume => self.paused = false,
+            Command::Next => self.current = self.current % count + 1,
+            Command::Previous => self.current = if self.current == 1 { count } else { self.current - 1 },
+            Command::SetSpeed(interval_ms) => {
+                if !FRAME_INTERVAL_RANGE_MS.contains(&interval_ms) {
+                    return Err(PlaybackError::InvalidSpeed(interval_ms));
+                }
+                self.frame_interval_ms = interval_ms;
+            }
+            Command::SetBrightness(_) | Command::ShowText(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Hand out the frame to draw now and, unless paused, move on to the one
+    /// after it.
+    pub fn step(&mut self) -> Step<'a> {
+        let switched = self.current != self.previous;
+        if switched {
+            self.frame_index = 0;
+            self.previous = self.current;
+        }
+
+        let (frames, frame_count) = get_animation_data(self.animations, self.current);
+        let frame_index = self.frame_index % frame_count;
+        self.shown_frame = frame_index;
+        if !self.paused {
+            self.frame_index = (frame_index + 1) % frame_count;
+        }
+
+        Step {
+            animation: self.current,
+            frame_index,
+            frame_count,
+            frame: frames[frame_index],
+            switched,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONE: Frames = &[b"1a", b"1b", b"1c"];
+    const TWO: Frames = &[b"2a", b"2b"];
+    const ANIMATIONS: &[Frames] = &[ONE, TWO];
+
+    #[test]
+    fn lookup_is_one_based() {
+        assert_eq!(get_animation_data(ANIMATIONS, 1), (ONE, 3));
+        assert_eq!(get_animation_data(ANIMATIONS, 2), (TWO, 2));
+    }
+
+    #[test]
+    fn lookup_falls_back_to_first() {
+        assert_eq!(get_animation_data(ANIMATIONS, 0).0, ONE);
+        assert_eq!(get_animation_data(ANIMATIONS, 3).0, ONE);
+        assert_eq!(get_animation_data(ANIMATIONS, 255).0, ONE);
+    }
+
+    #[test]
+    fn first_step_reports_switch() {
+        let mut player = Player::new(ANIMATIONS);
+        let step = player.step();
+        assert!(step.switched);
+        assert_eq!(step.animation, 1);
+        assert_eq!(step.frame_index, 0);
+        assert!(!player.step().switched);
+    }
+
+    #[test]
+    fn frames_wrap_around() {
+        let mut player = Player::new(ANIMATIONS);
+        let indices: [usize; 5] = core::array::from_fn(|_| player.step().frame_index);
+        assert_eq!(indices, [0, 1, 2, 0, 1]);
+    }
+
+    #[test]
+    fn select_restarts_from_first_frame() {
+        let mut player = Player::new(ANIMATIONS);
+        player.step();
+        player.step();
+
+        player.select(2).unwrap();
+        let step = player.step();
+        assert!(step.switched);
+        assert_eq!(step.animation, 2);
+        assert_eq!(step.frame_index, 0);
+        assert_eq!(step.frame, b"2a");
+        assert_eq!(step.frame_count, 2);
+    }
+
+    #[test]
+    fn reselecting_current_animation_keeps_position() {
+        let mut player = Player::new(ANIMATIONS);
+        player.step();
+        player.select(1).unwrap();
+        let step = player.step();
+        assert!(!step.switched);
+        assert_eq!(step.frame_index, 1);
+    }
+
+    #[test]
+    fn select_rejects_out_of_range() {
+        let mut player = Player::new(ANIMATIONS);
+        assert_eq!(player.select(0), Err(PlaybackError::InvalidAnimation(0)));
+        assert_eq!(player.select(3), Err(PlaybackError::InvalidAnimation(3)));
+        assert_eq!(player.current(), 1);
+    }
+
+    #[test]
+    fn pause_holds_frame() {
+        let mut player = Player::new(ANIMATIONS);
+        player.step();
+        player.apply(&Command::Pause).unwrap();
+        assert_eq!(player.step().frame_index, 1);
+        assert_eq!(player.step().frame_index, 1);
+        player.apply(&Command::Resume).unwrap();
+        assert_eq!(player.step().frame_index, 1);
+        assert_eq!(player.step().frame_index, 2);
+    }
+
+    #[test]
+    fn play_resumes() {
+        let mut player = Player::new(ANIMATIONS);
+        player.apply(&Command::Pause).unwrap();
+        player.apply(&Command::PlayAnimation(2)).unwrap();
+        assert!(!player.is_paused());
+        assert_eq!(player.current(), 2);
+    }
+
+    #[test]
+    fn invalid_play_keeps_state() {
+        let mut player = Player::new(ANIMATIONS);
+        player.apply(&Command::Pause).unwrap();
+        assert_eq!(player.apply(&Command::PlayAnimation(7)), Err(PlaybackError::InvalidAnimation(7)));
+        assert!(player.is_paused());
+        assert_eq!(player.current(), 1);
+    }
+
+    #[test]
+    fn next_and_previous_wrap() {
+        let mut player = Player::new(ANIMATIONS);
+        player.apply(&Command::Next).unwrap();
+        assert_eq!(player.current(), 2);
+        player.apply(&Command::Next).unwrap();
+        assert_eq!(player.current(), 1);
+        player.apply(&Command::Previous).unwrap();
+        assert_eq!(player.current(), 2);
+        player.apply(&Command::Previous).unwrap();
+        assert_eq!(player.current(), 1);
+        assert!(player.step().switched);
+    }
+
+    #[test]
+    fn state_reports_frame_on_screen() {
+        let mut player = Player::new(ANIMATIONS);
+        player.step();
+        player.step();
+        let state = player.state();
+        assert_eq!((state.animation, state.animation_count), (1, 2));
+        assert_eq!((state.frame_index, state.frame_count), (1, 3));
+        assert!(!state.paused);
+
+        player.apply(&Command::PlayAnimation(2)).unwrap();
+        assert_eq!((player.state().frame_index, player.state().frame_count), (0, 2));
+    }
+
+    #[test]
+    fn speed_is_range_checked() {
+        let mut player = Player::new(ANIMATIONS);
+        assert_eq!(player.frame_interval_ms(), DEFAULT_FRAME_INTERVAL_MS);
+        player.apply(&Command::SetSpeed(250)).unwrap();
+        assert_eq!(player.frame_interval_ms(), 250);
+        assert_eq!(player.apply(&Command::SetSpeed(1)), Err(PlaybackError::InvalidSpeed(1)));
+        assert_eq!(player.apply(&Command::SetSpeed(60_000)), Err(PlaybackError::InvalidSpeed(60_000)));
+        assert_eq!(player.frame_interval_ms(), 250);
+    }
+}
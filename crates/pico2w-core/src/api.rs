@@ -0,0 +1,245 @@
+// file: api.rs
+// desc: JSON bodies of the REST API
+//
+// `GET /api/status` answers with a `StatusReport`. `PUT /api/animation` and
+// `PUT /api/playback` bodies are parsed into the same `Command`s the buttons
+// and legacy URLs produce. `POST /api/reboot` takes a `RebootTarget`.
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::animation::{PlaybackState, FRAME_INTERVAL_RANGE_MS};
+use crate::command::Command;
+use crate::http::Status;
+use crate::reboot::RebootTarget;
+
+/// Room for a `StatusReport` with every field at its longest.
+pub const STATUS_JSON_LEN: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct StatusReport<'a> {
+    pub animation: u8,
+    pub animation_count: u8,
+    pub frame_index: usize,
+    pub frame_count: usize,
+    pub paused: bool,
+    pub frame_interval_ms: u16,
+    pub uptime_s: u64,
+    /// Dotted quad, absent until the network is configured.
+    pub ip: Option<&'a str>,
+    /// Signal strength in dBm, absent when not joined.
+    pub rssi: Option<i32>,
+    pub firmware_version: &'a str,
+}
+
+impl<'a> StatusReport<'a> {
+    pub fn new(
+        playback: PlaybackState,
+        uptime_s: u64,
+        ip: Option<&'a str>,
+        rssi: Option<i32>,
+        firmware_version: &'a str,
+    ) -> Self {
+        StatusReport {
+            animation: playback.animation,
+            animation_count: playback.animation_count,
+            frame_index: playback.frame_index,
+            frame_count: playback.frame_count,
+            paused: playback.paused,
+            frame_interval_ms: playback.frame_interval_ms,
+            uptime_s,
+            ip,
+            rssi,
+            firmware_version,
+        }
+    }
+
+    /// Serialize into `buf` and return the used prefix.
+    pub fn to_json<'b>(&self, buf: &'b mut [u8]) -> Option<&'b [u8]> {
+        let len = serde_json_core::to_slice(self, buf).ok()?;
+        Some(&buf[..len])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ApiError {
+    /// Not JSON, or not the shape the endpoint expects.
+    InvalidJson,
+    /// Well-formed, but a value is outside what the board accepts.
+    OutOfRange,
+}
+
+impl ApiError {
+    pub fn status(self) -> Status {
+        match self {
+            ApiError::InvalidJson => Status::BadRequest,
+            ApiError::OutOfRange => Status::UnprocessableContent,
+        }
+    }
+
+    /// The error as a JSON body.
+    pub fn json(self) -> &'static str {
+        match self {
+            ApiError::InvalidJson => r#"{"error":"invalid JSON body"}"#,
+            ApiError::OutOfRange => r#"{"error":"value out of range"}"#,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AnimationBody {
+    animation: u8,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RebootBody {
+    #[serde(default)]
+    target: RebootTarget,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlaybackBody {
+    #[serde(default)]
+    paused: Option<bool>,
+    #[serde(default)]
+    frame_interval_ms: Option<u16>,
+}
+
+fn from_json<'de, T: Deserialize<'de>>(body: &'de [u8]) -> Result<T, ApiError> {
+    serde_json_core::from_slice(body)
+        .map(|(value, _)| value)
+        .map_err(|_| ApiError::InvalidJson)
+}
+
+/// `{"animation": N}`, with N counted from 1.
+pub fn parse_animation(body: &[u8], animation_count: u8) -> Result<Command, ApiError> {
+    let AnimationBody { animation } = from_json(body)?;
+    if !(1..=animation_count).contains(&animation) {
+        return Err(ApiError::OutOfRange);
+    }
+    Ok(Command::PlayAnimation(animation))
+}
+
+/// `{"paused": bool, "frame_interval_ms": N}`; either field may be left out.
+pub fn parse_playback(body: &[u8]) -> Result<Vec<Command, 2>, ApiError> {
+    let PlaybackBody { paused, frame_interval_ms } = from_json(body)?;
+
+    let mut commands = Vec::new();
+    if let Some(interval_ms) = frame_interval_ms {
+        if !FRAME_INTERVAL_RANGE_MS.contains(&interval_ms) {
+            return Err(ApiError::OutOfRange);
+        }
+        commands.push(Command::SetSpeed(interval_ms)).ok();
+    }
+    if let Some(paused) = paused {
+        commands.push(if paused { Command::Pause } else { Command::Resume }).ok();
+    }
+    Ok(commands)
+}
+
+/// `{"target": "normal" | "bootloader"}`; an empty body reboots normally.
+pub fn parse_reboot(body: &[u8]) -> Result<RebootTarget, ApiError> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(RebootTarget::Normal);
+    }
+    let RebootBody { target } = from_json(body)?;
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAYBACK: PlaybackState = PlaybackState {
+        animation: 2,
+        animation_count: 4,
+        frame_index: 5,
+        frame_count: 12,
+        paused: false,
+        frame_interval_ms: 100,
+    };
+
+    #[test]
+    fn status_json() {
+        let report = StatusReport::new(PLAYBACK, 42, Some("192.168.68.100"), Some(-61), "0.1.0");
+        let mut buf = [0u8; STATUS_JSON_LEN];
+        assert_eq!(
+            core::str::from_utf8(report.to_json(&mut buf).unwrap()).unwrap(),
+            r#"{"animation":2,"animation_count":4,"frame_index":5,"frame_count":12,"paused":false,"frame_interval_ms":100,"uptime_s":42,"ip":"192.168.68.100","rssi":-61,"firmware_version":"0.1.0"}"#
+        );
+    }
+
+    #[test]
+    fn status_json_worst_case_fits() {
+        let playback = PlaybackState {
+            animation: u8::MAX,
+            animation_count: u8::MAX,
+            frame_index: usize::MAX,
+            frame_count: usize::MAX,
+            paused: true,
+            frame_interval_ms: u16::MAX,
+        };
+        let report = StatusReport::new(playback, u64::MAX, Some("255.255.255.255"), Some(i32::MIN), "255.255.255");
+        let mut buf = [0u8; STATUS_JSON_LEN];
+        assert!(report.to_json(&mut buf).is_some());
+    }
+
+    #[test]
+    fn status_json_before_network() {
+        let report = StatusReport::new(PLAYBACK, 0, None, None, "0.1.0");
+        let mut buf = [0u8; STATUS_JSON_LEN];
+        let json = core::str::from_utf8(report.to_json(&mut buf).unwrap()).unwrap();
+        assert!(json.contains(r#""ip":null,"rssi":null"#));
+    }
+
+    #[test]
+    fn animation_body() {
+        assert_eq!(parse_animation(br#"{"animation":3}"#, 4), Ok(Command::PlayAnimation(3)));
+        assert_eq!(parse_animation(br#" { "animation" : 1 } "#, 4), Ok(Command::PlayAnimation(1)));
+        assert_eq!(parse_animation(br#"{"animation":0}"#, 4), Err(ApiError::OutOfRange));
+        assert_eq!(parse_animation(br#"{"animation":5}"#, 4), Err(ApiError::OutOfRange));
+        assert_eq!(parse_animation(br#"{"animation":"2"}"#, 4), Err(ApiError::InvalidJson));
+        assert_eq!(parse_animation(br#"{"anim":2}"#, 4), Err(ApiError::InvalidJson));
+        assert_eq!(parse_animation(b"", 4), Err(ApiError::InvalidJson));
+    }
+
+    #[test]
+    fn playback_body() {
+        assert_eq!(parse_playback(br#"{"paused":true}"#).unwrap(), [Command::Pause]);
+        assert_eq!(parse_playback(br#"{"paused":false}"#).unwrap(), [Command::Resume]);
+        assert_eq!(parse_playback(br#"{"frame_interval_ms":250}"#).unwrap(), [Command::SetSpeed(250)]);
+        assert_eq!(
+            parse_playback(br#"{"paused":false,"frame_interval_ms":50}"#).unwrap(),
+            [Command::SetSpeed(50), Command::Resume]
+        );
+        assert!(parse_playback(b"{}").unwrap().is_empty());
+    }
+
+    #[test]
+    fn playback_body_rejects() {
+        assert_eq!(parse_playback(br#"{"frame_interval_ms":1}"#), Err(ApiError::OutOfRange));
+        assert_eq!(parse_playback(br#"{"frame_interval_ms":70000}"#), Err(ApiError::InvalidJson));
+        assert_eq!(parse_playback(br#"{"paused":"yes"}"#), Err(ApiError::InvalidJson));
+        assert_eq!(parse_playback(br#"{"speed":100}"#), Err(ApiError::InvalidJson));
+        assert_eq!(parse_playback(b"paused"), Err(ApiError::InvalidJson));
+    }
+
+    #[test]
+    fn reboot_body() {
+        assert_eq!(parse_reboot(b""), Ok(RebootTarget::Normal));
+        assert_eq!(parse_reboot(b"{}"), Ok(RebootTarget::Normal));
+        assert_eq!(parse_reboot(br#"{"target":"normal"}"#), Ok(RebootTarget::Normal));
+        assert_eq!(parse_reboot(br#"{"target":"bootloader"}"#), Ok(RebootTarget::Bootloader));
+        assert_eq!(parse_reboot(br#"{"target":"dfu"}"#), Err(ApiError::InvalidJson));
+        assert_eq!(parse_reboot(br#"{"mode":"normal"}"#), Err(ApiError::InvalidJson));
+    }
+
+    #[test]
+    fn errors_map_to_statuses() {
+        assert_eq!(ApiError::InvalidJson.status(), Status::BadRequest);
+        assert_eq!(ApiError::OutOfRange.status(), Status::UnprocessableContent);
+    }
+}
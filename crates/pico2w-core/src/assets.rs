@@ -0,0 +1,252 @@
+// file: assets.rs
+// desc: animations found on the USB drive
+//
+// A folder of BMP frames is an animation, named after the folder. Its frames
+// play in the order of the number in their file names, so frame-2 comes before
+// frame-10. A folder with no frames of its own is an asset pack, and every
+// folder inside it is an animation. Loose files, and the dot-files macOS
+// leaves behind, are ignored. GIFs are not decoded on the board: they count as
+// skipped frames, so the log shows them, and have to be split into BMPs first.
+use core::ops::Range;
+
+use heapless::{String, Vec};
+
+use crate::fat::{Entry, Volume};
+
+/// Animations kept from one drive; the rest are skipped.
+pub const MAX_ANIMATIONS: usize = 16;
+/// Frames kept from one folder.
+pub const MAX_FOLDER_FRAMES: usize = 128;
+/// Longest animation name kept.
+pub const NAME_LEN: usize = 16;
+
+// File header and the smallest info header
+const BMP_HEADER_LEN: usize = 14 + 12;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Animation {
+    pub name: String<NAME_LEN>,
+    /// Where its frames are in the table `scan` filled.
+    pub frames: Range<usize>,
+}
+
+#[derive(Debug, Default)]
+pub struct Catalog {
+    /// By name.
+    pub animations: Vec<Animation, MAX_ANIMATIONS>,
+    /// Entries of the frame table in use.
+    pub frame_count: usize,
+    /// Frames left out: GIFs, not a BMP, fragmented, or no room for them.
+    pub skipped: usize,
+}
+
+/// Find the animations on `volume`, putting their frames in `frames`.
+pub fn scan<'a>(volume: &Volume<'a>, frames: &mut [&'a [u8]]) -> Catalog {
+    let mut catalog = Catalog::default();
+    for entry in volume.root().filter(is_folder) {
+        if !add_folder(volume, &entry, frames, &mut catalog) {
+            for inner in volume.dir(&entry).filter(is_folder) {
+                add_folder(volume, &inner, frames, &mut catalog);
+            }
+        }
+    }
+    catalog.animations.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+    catalog
+}
+
+fn is_folder(entry: &Entry) -> bool {
+    entry.is_dir() && !entry.is_hidden()
+}
+
+// Add the folder's frames as one animation; false if it has none
+fn add_folder<'a>(volume: &Volume<'a>, folder: &Entry, frames: &mut [&'a [u8]], catalog: &mut Catalog) -> bool {
+    // Frame number, then position in the folder, so equal numbers keep their order
+    let mut found: Vec<(u32, usize, &'a [u8]), MAX_FOLDER_FRAMES> = Vec::new();
+    let files = volume.dir(folder).filter(|file| !file.is_dir() && !file.is_hidden());
+    for (position, file) in files.enumerate() {
+        if has_extension(&file.name, ".gif") {
+            catalog.skipped += 1;
+            continue;
+        }
+        if !is_bmp_name(&file.name) {
+            continue;
+        }
+        let frame = match volume.file(&file) {
+            Ok(data) if is_bmp(data) => (frame_number(&file.name), position, data),
+            _ => {
+                catalog.skipped += 1;
+                continue;
+            }
+        };
+        if found.push(frame).is_err() {
+            catalog.skipped += 1;
+        }
+    }
+    if found.is_empty() {
+        return false;
+    }
+
+    let start = catalog.frame_count;
+    let end = start + found.len();
+    if end > frames.len() || catalog.animations.is_full() {
+        catalog.skipped += found.len();
+        return true;
+    }
+    found.sort_unstable_by_key(|&(number, position, _)| (number, position));
+    for (slot, &(_, _, data)) in frames[start..end].iter_mut().zip(found.iter()) {
+        *slot = data;
+    }
+    let mut name = String::new();
+    for c in folder.name.chars().take(NAME_LEN) {
+        name.push(c).ok();
+    }
+    catalog.animations.push(Animation { name, frames: start..end }).ok();
+    catalog.frame_count = end;
+    true
+}
+
+fn is_bmp_name(name: &str) -> bool {
+    has_extension(name, ".bmp")
+}
+
+fn has_extension(name: &str, extension: &str) -> bool {
+    let Some(split) = name.len().checked_sub(extension.len()).filter(|&split| split > 0) else {
+        return false;
+    };
+    name.is_char_boundary(split) && name[split..].eq_ignore_ascii_case(extension)
+}
+
+fn is_bmp(data: &[u8]) -> bool {
+    data.len() >= BMP_HEADER_LEN && data.starts_with(b"BM")
+}
+
+// The last number in the name; unnumbered frames go last
+fn frame_number(name: &str) -> u32 {
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    let digits = stem.trim_end_matches(|c: char| !c.is_ascii_digit());
+    let start = digits.rfind(|c: char| !c.is_ascii_digit()).map_or(0, |i| i + 1);
+    digits[start..].parse().unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fat::tests::{formatted, Writer, SECTORS};
+
+    extern crate std;
+    use std::format;
+    use std::vec::Vec as StdVec;
+
+    // Enough of a BMP for `is_bmp`, tagged so the tests can tell frames apart
+    fn bmp(tag: u8) -> StdVec<u8> {
+        let mut data = std::vec![0u8; 64];
+        data[..2].copy_from_slice(b"BM");
+        data[63] = tag;
+        data
+    }
+
+    fn short(name: &str) -> [u8; 11] {
+        let mut short = [b' '; 11];
+        let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+        for (slot, c) in short[..8].iter_mut().zip(base.bytes()) {
+            *slot = c.to_ascii_uppercase();
+        }
+        for (slot, c) in short[8..].iter_mut().zip(ext.bytes()) {
+            *slot = c.to_ascii_uppercase();
+        }
+        short
+    }
+
+    fn tags(catalog: &Catalog, frames: &[&[u8]], index: usize) -> StdVec<u8> {
+        frames[catalog.animations[index].frames.clone()].iter().map(|frame| frame[63]).collect()
+    }
+
+    #[test]
+    fn folders_become_animations() {
+        let mut image = formatted(SECTORS);
+        let mut writer = Writer::new(&mut image);
+        let wave = writer.dir(None, "wave", &short("wave"));
+        for number in [10, 2, 0, 1] {
+            let name = format!("frame-{number}.bmp");
+            writer.file(Some(wave), &name, &short(&format!("f{number}.bmp")), &bmp(number));
+        }
+        // AppleDouble companion and a note, neither of them frames
+        writer.file(Some(wave), "._frame-0.bmp", &short("_f0~1.bmp"), b"\0\x05\x16\x07");
+        writer.file(Some(wave), "notes.txt", &short("notes.txt"), b"hi");
+        let cat = writer.dir(None, "cat", &short("cat"));
+        writer.file(Some(cat), "cat.bmp", &short("cat.bmp"), &bmp(99));
+        writer.dir(None, ".fseventsd", &short("fseven~1"));
+        writer.file(None, "loose.bmp", &short("loose.bmp"), &bmp(50));
+
+        let volume = Volume::mount(&image).unwrap();
+        let mut frames = [&[][..]; 16];
+        let catalog = scan(&volume, &mut frames);
+        let names: StdVec<&str> = catalog.animations.iter().map(|animation| animation.name.as_str()).collect();
+        assert_eq!(names, ["cat", "wave"]);
+        assert_eq!(tags(&catalog, &frames, 0), [99]);
+        assert_eq!(tags(&catalog, &frames, 1), [0, 1, 2, 10]);
+        assert_eq!(catalog.frame_count, 5);
+        assert_eq!(catalog.skipped, 0);
+    }
+
+    #[test]
+    fn asset_pack_holds_animations() {
+        let mut image = formatted(SECTORS);
+        let mut writer = Writer::new(&mut image);
+        let pack = writer.dir(None, "my-pack", &short("my-pack"));
+        for (tag, name) in [(1, "blink"), (2, "a-very-long-animation-name")] {
+            let folder = writer.dir(Some(pack), name, &short(&name[..5]));
+            writer.file(Some(folder), "frame-0.bmp", &short("frame-0.bmp"), &bmp(tag));
+        }
+
+        let volume = Volume::mount(&image).unwrap();
+        let mut frames = [&[][..]; 4];
+        let catalog = scan(&volume, &mut frames);
+        assert_eq!(catalog.animations.len(), 2);
+        assert_eq!(catalog.animations[0].name, "a-very-long-anim");
+        assert_eq!(tags(&catalog, &frames, 0), [2]);
+        assert_eq!(catalog.animations[1].name, "blink");
+    }
+
+    #[test]
+    fn bad_frames_and_overflow_are_skipped() {
+        let mut image = formatted(SECTORS);
+        let mut writer = Writer::new(&mut image);
+        let first = writer.dir(None, "a", &short("a"));
+        writer.file(Some(first), "0.bmp", &short("0.bmp"), &bmp(0));
+        writer.file(Some(first), "1.bmp", &short("1.bmp"), b"GIF89a, not a bitmap");
+        writer.file(Some(first), "2.GIF", &short("2.gif"), b"GIF89a");
+        let second = writer.dir(None, "b", &short("b"));
+        for number in 0..3 {
+            writer.file(Some(second), &format!("{number}.bmp"), &short(&format!("{number}.bmp")), &bmp(number));
+        }
+
+        let volume = Volume::mount(&image).unwrap();
+        // Room for the first animation only
+        let mut frames = [&[][..]; 3];
+        let catalog = scan(&volume, &mut frames);
+        assert_eq!(catalog.animations.len(), 1);
+        assert_eq!(catalog.animations[0].frames, 0..1);
+        assert_eq!(catalog.skipped, 5);
+    }
+
+    #[test]
+    fn empty_drive_has_nothing() {
+        let image = formatted(SECTORS);
+        let volume = Volume::mount(&image).unwrap();
+        let catalog = scan(&volume, &mut []);
+        assert!(catalog.animations.is_empty());
+        assert_eq!(catalog.frame_count, 0);
+    }
+
+    #[test]
+    fn frame_numbers() {
+        assert_eq!(frame_number("frame-12.bmp"), 12);
+        assert_eq!(frame_number("007.BMP"), 7);
+        assert_eq!(frame_number("take2-frame3.bmp"), 3);
+        assert_eq!(frame_number("cover.bmp"), u32::MAX);
+        assert!(is_bmp_name("A.BMP"));
+        assert!(!is_bmp_name(".bmp"));
+        assert!(!is_bmp_name("frame.gif"));
+    }
+}
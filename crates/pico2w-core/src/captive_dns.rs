@@ -0,0 +1,142 @@
+// file: captive_dns.rs
+// desc: DNS responder that resolves every name to the setup access point
+//
+// Phones probe a few well-known hosts after joining a network; answering all
+// of them with our own address is what makes them pop up the setup page.
+use crate::network::Ipv4;
+
+pub const PORT: u16 = 53;
+/// Queries longer than this are dropped.
+pub const MAX_PACKET_LEN: usize = 512;
+pub const TTL_S: u32 = 60;
+
+const HEADER_LEN: usize = 12;
+const TYPE_A: u16 = 1;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+// Answer bytes after the question: name pointer, type, class, TTL, length, address
+const ANSWER_LEN: usize = 2 + 2 + 2 + 4 + 2 + 4;
+
+/// Answer `query` in `reply`, returning the reply length. Anything but a
+/// single standard query is ignored.
+pub fn answer(query: &[u8], address: Ipv4, reply: &mut [u8; MAX_PACKET_LEN]) -> Option<usize> {
+    if query.len() < HEADER_LEN {
+        return None;
+    }
+    let flags = u16::from_be_bytes([query[2], query[3]]);
+    let is_response = flags & 0x8000 != 0;
+    let opcode = (flags >> 11) & 0xf;
+    let questions = u16::from_be_bytes([query[4], query[5]]);
+    if is_response || opcode != 0 || questions != 1 {
+        return None;
+    }
+
+    // Walk the question name; queries never use compression
+    let mut at = HEADER_LEN;
+    loop {
+        let len = *query.get(at)? as usize;
+        at += 1;
+        if len == 0 {
+            break;
+        }
+        if len > 63 {
+            return None;
+        }
+        at += len;
+    }
+    let question = query.get(HEADER_LEN..at + 4)?;
+    let qtype = u16::from_be_bytes([query[at], query[at + 1]]);
+    let qclass = u16::from_be_bytes([query[at + 2], query[at + 3]]);
+    let answered = qclass == CLASS_IN && (qtype == TYPE_A || qtype == TYPE_ANY);
+
+    let len = HEADER_LEN + question.len() + if answered { ANSWER_LEN } else { 0 };
+    if len > MAX_PACKET_LEN {
+        return None;
+    }
+
+    reply[..2].copy_from_slice(&query[..2]);
+    // Response, authoritative, recursion desired copied, recursion available
+    let flags = 0x8000 | 0x0400 | (flags & 0x0100) | 0x0080;
+    reply[2..4].copy_from_slice(&flags.to_be_bytes());
+    reply[4..6].copy_from_slice(&1u16.to_be_bytes());
+    reply[6..8].copy_from_slice(&(answered as u16).to_be_bytes());
+    reply[8..12].fill(0);
+    reply[HEADER_LEN..HEADER_LEN + question.len()].copy_from_slice(question);
+
+    // Other record types get an empty NOERROR answer
+    if answered {
+        let answer = &mut reply[HEADER_LEN + question.len()..len];
+        answer[..2].copy_from_slice(&(0xc000u16 | HEADER_LEN as u16).to_be_bytes());
+        answer[2..4].copy_from_slice(&TYPE_A.to_be_bytes());
+        answer[4..6].copy_from_slice(&CLASS_IN.to_be_bytes());
+        answer[6..10].copy_from_slice(&TTL_S.to_be_bytes());
+        answer[10..12].copy_from_slice(&4u16.to_be_bytes());
+        answer[12..16].copy_from_slice(&address);
+    }
+    Some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::*;
+
+    const AP: Ipv4 = [192, 168, 4, 1];
+
+    fn query(name: &str, qtype: u16) -> Vec<u8> {
+        let mut packet = Vec::from([0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        for label in name.split('.') {
+            packet.push(label.len() as u8);
+            packet.extend_from_slice(label.as_bytes());
+        }
+        packet.push(0);
+        packet.extend_from_slice(&qtype.to_be_bytes());
+        packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn every_name_resolves_to_the_ap() {
+        let query = query("connectivitycheck.gstatic.com", TYPE_A);
+        let mut reply = [0u8; MAX_PACKET_LEN];
+        let len = answer(&query, AP, &mut reply).unwrap();
+
+        assert_eq!(len, query.len() + ANSWER_LEN);
+        assert_eq!(&reply[..2], &[0x12, 0x34]);
+        assert_eq!(reply[2] & 0x80, 0x80);
+        assert_eq!(reply[2] & 0x01, 0x01);
+        assert_eq!(&reply[6..8], &[0, 1]);
+        assert_eq!(&reply[HEADER_LEN..query.len()], &query[HEADER_LEN..]);
+        assert_eq!(&reply[len - 4..len], &AP);
+    }
+
+    #[test]
+    fn other_types_get_no_answer() {
+        // AAAA
+        let query = query("example.com", 28);
+        let mut reply = [0u8; MAX_PACKET_LEN];
+        let len = answer(&query, AP, &mut reply).unwrap();
+        assert_eq!(len, query.len());
+        assert_eq!(&reply[6..8], &[0, 0]);
+    }
+
+    #[test]
+    fn malformed_queries_are_dropped() {
+        let mut reply = [0u8; MAX_PACKET_LEN];
+        assert_eq!(answer(&[0; 4], AP, &mut reply), None);
+
+        let mut truncated = query("example.com", TYPE_A);
+        truncated.truncate(truncated.len() - 3);
+        assert_eq!(answer(&truncated, AP, &mut reply), None);
+
+        let mut response = query("example.com", TYPE_A);
+        response[2] |= 0x80;
+        assert_eq!(answer(&response, AP, &mut reply), None);
+
+        let mut compressed = query("example.com", TYPE_A);
+        compressed[HEADER_LEN] = 0xc0;
+        assert_eq!(answer(&compressed, AP, &mut reply), None);
+    }
+}
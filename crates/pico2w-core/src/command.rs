@@ -0,0 +1,314 @@
+// file: command.rs
+// desc: the typed command set and its wire encoding
+//
+// Every input (HTTP, buttons, network links, the USB console) produces a
+// `Command`. On the wire a command is one version byte followed by its postcard
+// encoding, so a peer built against another protocol revision is rejected
+// instead of misread.
+use heapless::String;
+use serde::{Deserialize, Serialize};
+
+use crate::http::{Method, Request, Status};
+
+/// Bumped whenever `Command` changes shape.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Longest message `ShowText` carries; a 128 px line of 6x10 glyphs is 21.
+pub const TEXT_LEN: usize = 32;
+
+/// Largest encoded command, version byte included.
+pub const MAX_ENCODED_LEN: usize = 1 + 1 + 1 + TEXT_LEN;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command {
+    /// Switch to animation N, counted from 1.
+    PlayAnimation(u8),
+    Pause,
+    Resume,
+    Next,
+    Previous,
+    /// Time between frames, in milliseconds.
+    SetSpeed(u16),
+    SetBrightness(Brightness),
+    ShowText(String<TEXT_LEN>),
+}
+
+/// Where a command came from; events it causes carry this along.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Http,
+    Button,
+    Scheduler,
+    Websocket,
+    Mqtt,
+    Serial,
+}
+
+/// A command on its way to the display task.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Input {
+    pub source: Source,
+    pub command: Command,
+}
+
+/// The SSD1306 has five useful contrast presets rather than a linear scale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Brightness {
+    Dimmest,
+    Dim,
+    Normal,
+    Bright,
+    Brightest,
+}
+
+impl Brightness {
+    /// Presets as a 1-based scale, dimmest first, for sliders.
+    pub const LEVELS: u8 = 5;
+
+    pub fn from_level(level: u8) -> Option<Self> {
+        match level {
+            1 => Some(Brightness::Dimmest),
+            2 => Some(Brightness::Dim),
+            3 => Some(Brightness::Normal),
+            4 => Some(Brightness::Bright),
+            5 => Some(Brightness::Brightest),
+            _ => None,
+        }
+    }
+
+    pub fn level(self) -> u8 {
+        match self {
+            Brightness::Dimmest => 1,
+            Brightness::Dim => 2,
+            Brightness::Normal => 3,
+            Brightness::Bright => 4,
+            Brightness::Brightest => 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ProtocolError {
+    /// The first byte named a protocol revision we do not speak.
+    Version(u8),
+    /// The buffer was empty or did not hold a valid command.
+    Malformed,
+    /// The output buffer is too small.
+    BufferFull,
+}
+
+impl Command {
+    /// Write the versioned encoding into `buf` and return the used prefix.
+    pub fn encode<'a>(&self, buf: &'a mut [u8]) -> Result<&'a mut [u8], ProtocolError> {
+        let (version, body) = buf.split_first_mut().ok_or(ProtocolError::BufferFull)?;
+        *version = PROTOCOL_VERSION;
+        let len = postcard::to_slice(self, body)
+            .map_err(|_| ProtocolError::BufferFull)?
+            .len();
+        Ok(&mut buf[..1 + len])
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        match bytes.split_first() {
+            Some((&PROTOCOL_VERSION, body)) => {
+                postcard::from_bytes(body).map_err(|_| ProtocolError::Malformed)
+            }
+            Some((&version, _)) => Err(ProtocolError::Version(version)),
+            None => Err(ProtocolError::Malformed),
+        }
+    }
+}
+
+/// Why a legacy URL did not name an animation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UrlError {
+    /// Not `/N` or `/command`.
+    NotFound,
+    /// `/command` without a numeric `value`.
+    Malformed,
+    /// A number, but not one of the animations.
+    OutOfRange,
+}
+
+impl UrlError {
+    pub fn status(self) -> Status {
+        match self {
+            UrlError::NotFound => Status::NotFound,
+            UrlError::Malformed => Status::BadRequest,
+            UrlError::OutOfRange => Status::UnprocessableContent,
+        }
+    }
+}
+
+/// Map the legacy `/N` and `/command?value=N` URLs onto a command, for a
+/// player with `animation_count` animations. HEAD resolves like GET, as the
+/// router treats it, so both get the same status.
+pub fn parse_command(request: &Request, animation_count: u8) -> Result<Command, UrlError> {
+    if !matches!(request.method, Method::Get | Method::Head) {
+        return Err(UrlError::NotFound);
+    }
+
+    let number: u8 = if request.path == "/command" {
+        // GET /command?value=X
+        let value = request.query_param("value").ok_or(UrlError::Malformed)?;
+        value.parse().map_err(|_| UrlError::Malformed)?
+    } else {
+        // Simple GET /N URLs
+        path_number(&request.path[1..]).ok_or(UrlError::NotFound)?
+    };
+    if !(1..=animation_count).contains(&number) {
+        return Err(UrlError::OutOfRange);
+    }
+    Ok(Command::PlayAnimation(number))
+}
+
+// Only the canonical spelling, so `/+1` and `/01` stay unrouted like any other path
+fn path_number(digits: &str) -> Option<u8> {
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    if digits.len() > 1 && digits.starts_with('0') {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern crate std;
+    use std::format;
+
+    fn roundtrip(command: Command) {
+        let mut buf = [0u8; MAX_ENCODED_LEN];
+        let encoded = command.encode(&mut buf).unwrap();
+        assert_eq!(encoded[0], PROTOCOL_VERSION);
+        assert_eq!(Command::decode(encoded), Ok(command));
+    }
+
+    #[test]
+    fn every_variant_roundtrips() {
+        roundtrip(Command::PlayAnimation(3));
+        roundtrip(Command::Pause);
+        roundtrip(Command::Resume);
+        roundtrip(Command::Next);
+        roundtrip(Command::Previous);
+        roundtrip(Command::SetSpeed(u16::MAX));
+        roundtrip(Command::SetBrightness(Brightness::Dim));
+        roundtrip(Command::ShowText(String::try_from("hello pico").unwrap()));
+    }
+
+    #[test]
+    fn brightness_levels() {
+        for level in 1..=Brightness::LEVELS {
+            assert_eq!(Brightness::from_level(level).unwrap().level(), level);
+        }
+        assert_eq!(Brightness::from_level(0), None);
+        assert_eq!(Brightness::from_level(Brightness::LEVELS + 1), None);
+    }
+
+    #[test]
+    fn longest_text_fits() {
+        let text: String<TEXT_LEN> = core::iter::repeat_n('x', TEXT_LEN).collect();
+        roundtrip(Command::ShowText(text));
+    }
+
+    #[test]
+    fn encoding_is_stable() {
+        let mut buf = [0u8; MAX_ENCODED_LEN];
+        assert_eq!(Command::PlayAnimation(2).encode(&mut buf).unwrap(), &[1, 0, 2]);
+        assert_eq!(Command::Pause.encode(&mut buf).unwrap(), &[1, 1]);
+        assert_eq!(Command::SetSpeed(300).encode(&mut buf).unwrap(), &[1, 5, 0xac, 0x02]);
+    }
+
+    #[test]
+    fn decode_rejects_other_versions() {
+        assert_eq!(Command::decode(&[2, 1]), Err(ProtocolError::Version(2)));
+        assert_eq!(Command::decode(&[0, 1]), Err(ProtocolError::Version(0)));
+    }
+
+    #[test]
+    fn decode_rejects_garbage() {
+        assert_eq!(Command::decode(&[]), Err(ProtocolError::Malformed));
+        assert_eq!(Command::decode(&[PROTOCOL_VERSION]), Err(ProtocolError::Malformed));
+        assert_eq!(Command::decode(&[PROTOCOL_VERSION, 42]), Err(ProtocolError::Malformed));
+        assert_eq!(Command::decode(&[PROTOCOL_VERSION, 0]), Err(ProtocolError::Malformed));
+    }
+
+    #[test]
+    fn encode_reports_small_buffer() {
+        assert_eq!(Command::Pause.encode(&mut []), Err(ProtocolError::BufferFull));
+        let mut buf = [0u8; 4];
+        let text = Command::ShowText(String::try_from("too long").unwrap());
+        assert_eq!(text.encode(&mut buf), Err(ProtocolError::BufferFull));
+    }
+
+    fn command_for(raw: &str) -> Result<Command, UrlError> {
+        let mut buf = [0u8; 256];
+        buf[..raw.len()].copy_from_slice(raw.as_bytes());
+        parse_command(&Request::parse(&mut buf[..raw.len()]).unwrap(), 4)
+    }
+
+    #[test]
+    fn short_paths() {
+        assert_eq!(command_for("GET /1 HTTP/1.1\r\nHost: pico\r\n\r\n"), Ok(Command::PlayAnimation(1)));
+        assert_eq!(command_for("GET /4 HTTP/1.1\r\nHost: pico\r\n\r\n"), Ok(Command::PlayAnimation(4)));
+        assert_eq!(command_for("GET /%32 HTTP/1.0\r\n\r\n"), Ok(Command::PlayAnimation(2)));
+    }
+
+    #[test]
+    fn query_value() {
+        assert_eq!(command_for("GET /command?value=3 HTTP/1.1\r\nHost: pico\r\n\r\n"), Ok(Command::PlayAnimation(3)));
+        assert_eq!(command_for("GET /command?x=y&value=2 HTTP/1.1\r\nHost: pico\r\n\r\n"), Ok(Command::PlayAnimation(2)));
+    }
+
+    #[test]
+    fn out_of_range_is_rejected() {
+        assert_eq!(command_for("GET /0 HTTP/1.1\r\nHost: pico\r\n\r\n"), Err(UrlError::OutOfRange));
+        assert_eq!(command_for("GET /5 HTTP/1.1\r\nHost: pico\r\n\r\n"), Err(UrlError::OutOfRange));
+        assert_eq!(command_for("GET /200 HTTP/1.1\r\nHost: pico\r\n\r\n"), Err(UrlError::OutOfRange));
+        assert_eq!(command_for("GET /command?value=9 HTTP/1.1\r\nHost: pico\r\n\r\n"), Err(UrlError::OutOfRange));
+        assert_eq!(UrlError::OutOfRange.status(), Status::UnprocessableContent);
+    }
+
+    #[test]
+    fn query_value_not_a_number() {
+        assert_eq!(command_for("GET /command?value=300 HTTP/1.1\r\nHost: pico\r\n\r\n"), Err(UrlError::Malformed));
+        assert_eq!(command_for("GET /command?value=abc HTTP/1.1\r\nHost: pico\r\n\r\n"), Err(UrlError::Malformed));
+        assert_eq!(command_for("GET /command HTTP/1.1\r\nHost: pico\r\n\r\n"), Err(UrlError::Malformed));
+    }
+
+    #[test]
+    fn other_paths() {
+        assert_eq!(command_for("GET / HTTP/1.1\r\nHost: pico\r\n\r\n"), Err(UrlError::NotFound));
+        assert_eq!(command_for("GET /index.html HTTP/1.1\r\nHost: pico\r\n\r\n"), Err(UrlError::NotFound));
+        assert_eq!(command_for("GET /300 HTTP/1.1\r\nHost: pico\r\n\r\n"), Err(UrlError::NotFound));
+        assert_eq!(command_for("POST /1 HTTP/1.1\r\nHost: pico\r\n\r\n"), Err(UrlError::NotFound));
+    }
+
+    #[test]
+    fn only_canonical_numbers_are_paths() {
+        assert_eq!(command_for("GET /+1 HTTP/1.1\r\nHost: pico\r\n\r\n"), Err(UrlError::NotFound));
+        assert_eq!(command_for("GET /01 HTTP/1.1\r\nHost: pico\r\n\r\n"), Err(UrlError::NotFound));
+        assert_eq!(command_for("GET /00 HTTP/1.1\r\nHost: pico\r\n\r\n"), Err(UrlError::NotFound));
+        assert_eq!(command_for("GET /-1 HTTP/1.1\r\nHost: pico\r\n\r\n"), Err(UrlError::NotFound));
+        assert_eq!(command_for("GET /%2B1 HTTP/1.1\r\nHost: pico\r\n\r\n"), Err(UrlError::NotFound));
+    }
+
+    #[test]
+    fn head_matches_get() {
+        for target in ["/1", "/9", "/x", "/command?value=2", "/command?value=9", "/command?value=abc"] {
+            let get = command_for(&format!("GET {target} HTTP/1.1\r\nHost: pico\r\n\r\n"));
+            let head = command_for(&format!("HEAD {target} HTTP/1.1\r\nHost: pico\r\n\r\n"));
+            assert_eq!(head.map_err(UrlError::status), get.map_err(UrlError::status), "{target}");
+        }
+    }
+}
@@ -0,0 +1,385 @@
+// file: credentials.rs
+// desc: saved Wi-Fi networks and their flash record format
+//
+// The list is kept as a `"P2WS"` record (see `record`). Version 1 held a
+// single network and is still read.
+use core::fmt;
+
+use heapless::{String, Vec};
+use serde::{Deserialize, Serialize};
+
+use crate::record;
+use crate::scan::NetworkList;
+
+pub use crate::record::RecordError;
+
+pub const SSID_LEN: usize = 32;
+/// WPA passphrases are 8 to 63 characters; 64 hex digits are a raw WPA2 key.
+pub const PASSWORD_LEN: usize = 64;
+/// Networks the board remembers.
+pub const MAX_SAVED_NETWORKS: usize = 4;
+
+/// Bumped whenever the payload changes shape.
+pub const RECORD_VERSION: u8 = 2;
+const MAGIC: &[u8; 4] = b"P2WS";
+/// Two flash pages; the record is padded to it with 0xFF.
+pub const RECORD_LEN: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[serde(rename_all = "lowercase")]
+pub enum Auth {
+    Open,
+    Wpa2,
+    Wpa3,
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Credentials {
+    pub ssid: String<SSID_LEN>,
+    /// Empty for an open network.
+    pub password: String<PASSWORD_LEN>,
+    pub auth: Auth,
+    /// Hidden networks never show up in scans, so they are tried anyway.
+    pub hidden: bool,
+    /// Higher is preferred over signal strength.
+    pub priority: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CredentialsError {
+    Ssid,
+    Password,
+}
+
+impl Credentials {
+    /// A visible network at priority 0, open if `password` is empty and WPA2
+    /// otherwise.
+    pub fn new(ssid: &str, password: &str) -> Result<Self, CredentialsError> {
+        let auth = if password.is_empty() { Auth::Open } else { Auth::Wpa2 };
+        Credentials::with_options(ssid, password, auth, false, 0)
+    }
+
+    pub fn with_options(ssid: &str, password: &str, auth: Auth, hidden: bool, priority: u8) -> Result<Self, CredentialsError> {
+        if ssid.is_empty() {
+            return Err(CredentialsError::Ssid);
+        }
+        let passphrase = (8..=63).contains(&password.len());
+        let raw_key = password.len() == 64 && password.bytes().all(|b| b.is_ascii_hexdigit());
+        let valid = match auth {
+            Auth::Open => password.is_empty(),
+            Auth::Wpa2 => passphrase || raw_key,
+            // SAE has no pre-hashed form
+            Auth::Wpa3 => passphrase,
+        };
+        if !valid {
+            return Err(CredentialsError::Password);
+        }
+        Ok(Credentials {
+            ssid: String::try_from(ssid).map_err(|_| CredentialsError::Ssid)?,
+            password: String::try_from(password).map_err(|_| CredentialsError::Password)?,
+            auth,
+            hidden,
+            priority,
+        })
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.auth == Auth::Open
+    }
+
+    // Re-check fields read back from flash
+    fn validated(self) -> Result<Self, CredentialsError> {
+        Credentials::with_options(&self.ssid, &self.password, self.auth, self.hidden, self.priority)
+    }
+}
+
+// The password stays out of logs
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("ssid", &self.ssid)
+            .field("password", &"<redacted>")
+            .field("auth", &self.auth)
+            .field("hidden", &self.hidden)
+            .field("priority", &self.priority)
+            .finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Credentials {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "Credentials {{ ssid: {=str}, password: <redacted>, auth: {}, hidden: {}, priority: {} }}",
+            self.ssid.as_str(),
+            self.auth,
+            self.hidden,
+            self.priority
+        )
+    }
+}
+
+/// Version 1 payload: one network, auth implied by the password.
+#[derive(Deserialize)]
+struct CredentialsV1 {
+    ssid: String<SSID_LEN>,
+    password: String<PASSWORD_LEN>,
+}
+
+/// The networks the board knows, at most one per SSID.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SavedNetworks {
+    networks: Vec<Credentials, MAX_SAVED_NETWORKS>,
+}
+
+impl SavedNetworks {
+    pub const fn new() -> Self {
+        SavedNetworks { networks: Vec::new() }
+    }
+
+    /// Add a network, replacing any saved one with the same SSID. A full list
+    /// makes room by dropping its lowest-priority, oldest entry, which is
+    /// returned.
+    pub fn save(&mut self, credentials: Credentials) -> Option<Credentials> {
+        if let Some(known) = self.networks.iter_mut().find(|known| known.ssid == credentials.ssid) {
+            return Some(core::mem::replace(known, credentials));
+        }
+        let dropped = match self.networks.is_full() {
+            true => {
+                let lowest = self.networks.iter().enumerate().min_by_key(|(_, known)| known.priority).map(|(i, _)| i)?;
+                Some(self.networks.remove(lowest))
+            }
+            false => None,
+        };
+        self.networks.push(credentials).ok();
+        dropped
+    }
+
+    /// Forget `ssid`; false if it was not saved.
+    pub fn forget(&mut self, ssid: &str) -> bool {
+        let before = self.networks.len();
+        self.networks.retain(|known| known.ssid != ssid);
+        self.networks.len() != before
+    }
+
+    pub fn get(&self, ssid: &str) -> Option<&Credentials> {
+        self.networks.iter().find(|known| known.ssid == ssid)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Credentials> {
+        self.networks.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.networks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+
+    /// The order to try networks in: those seen in `visible` by priority,
+    /// then signal strength. Hidden networks never show their SSID in a scan,
+    /// so the unseen hidden ones come next for a directed join, and the other
+    /// unseen ones last, in case the scan missed them.
+    pub fn join_order(&self, visible: &NetworkList) -> Vec<&Credentials, MAX_SAVED_NETWORKS> {
+        let mut order: Vec<&Credentials, MAX_SAVED_NETWORKS> = self.networks.iter().collect();
+        order.sort_unstable_by_key(|known| {
+            let rssi = visible.get(&known.ssid).map(|seen| seen.rssi);
+            core::cmp::Reverse((rssi.is_some(), known.hidden, known.priority, rssi.unwrap_or(i16::MIN)))
+        });
+        order
+    }
+
+    /// Encode into a full record, ready to be written to an erased sector.
+    pub fn to_record<'b>(&self, buf: &'b mut [u8; RECORD_LEN]) -> &'b [u8] {
+        // A full list is a little over 400 bytes, inside the record
+        if record::encode(MAGIC, RECORD_VERSION, &self.networks, buf).is_none() {
+            unreachable!("saved networks always fit a record");
+        }
+        buf
+    }
+
+    pub fn from_record(record: &[u8]) -> Result<Self, RecordError> {
+        let (version, payload) = record::decode(MAGIC, 1..=RECORD_VERSION, record)?;
+        let mut saved = SavedNetworks::new();
+        if version == 1 {
+            let old: CredentialsV1 = postcard::from_bytes(payload).map_err(|_| RecordError::Corrupt)?;
+            saved.save(Credentials::new(&old.ssid, &old.password).map_err(|_| RecordError::Corrupt)?);
+        } else {
+            let networks: Vec<Credentials, MAX_SAVED_NETWORKS> = postcard::from_bytes(payload).map_err(|_| RecordError::Corrupt)?;
+            for credentials in networks {
+                saved.save(credentials.validated().map_err(|_| RecordError::Corrupt)?);
+            }
+        }
+        Ok(saved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::format;
+
+    use super::*;
+    use crate::record::HEADER_LEN;
+    use crate::scan::Network;
+
+    fn home() -> Credentials {
+        Credentials::new("home-network", "correct horse battery").unwrap()
+    }
+
+    fn saved(networks: &[Credentials]) -> SavedNetworks {
+        let mut saved = SavedNetworks::new();
+        for credentials in networks {
+            saved.save(credentials.clone());
+        }
+        saved
+    }
+
+    fn seen(ssids: &[(&str, i16)]) -> NetworkList {
+        let mut list = NetworkList::new();
+        for (ssid, rssi) in ssids {
+            list.add(Network { ssid: String::try_from(*ssid).unwrap(), rssi: *rssi, channel: 1, secure: true });
+        }
+        list
+    }
+
+    fn with_priority(ssid: &str, priority: u8) -> Credentials {
+        Credentials::with_options(ssid, "password123", Auth::Wpa2, false, priority).unwrap()
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let lab = Credentials::with_options("lab", "sae-passphrase", Auth::Wpa3, true, 5).unwrap();
+        let cafe = Credentials::new("cafe", "").unwrap();
+        assert!(cafe.is_open());
+        let networks = saved(&[home(), lab, cafe]);
+
+        let mut buf = [0u8; RECORD_LEN];
+        let record = networks.to_record(&mut buf);
+        assert_eq!(record.len(), RECORD_LEN);
+        assert!(record.starts_with(b"P2WS\x02"));
+        assert_eq!(SavedNetworks::from_record(record), Ok(networks));
+    }
+
+    #[test]
+    fn full_list_fits() {
+        let networks: [Credentials; MAX_SAVED_NETWORKS] = core::array::from_fn(|i| {
+            let ssid = format!("{i}{}", "s".repeat(SSID_LEN - 1));
+            Credentials::with_options(&ssid, &"a".repeat(PASSWORD_LEN), Auth::Wpa2, true, u8::MAX).unwrap()
+        });
+        let networks = saved(&networks);
+        assert_eq!(networks.len(), MAX_SAVED_NETWORKS);
+        let mut buf = [0u8; RECORD_LEN];
+        assert_eq!(SavedNetworks::from_record(networks.to_record(&mut buf)), Ok(networks));
+    }
+
+    #[test]
+    fn version_1_records_migrate() {
+        // "P2WS", version 1, then the old single-network payload
+        let mut record = [0u8; RECORD_LEN];
+        record::encode(MAGIC, 1, &("home-network", "correct horse battery"), &mut record).unwrap();
+        assert!(record.starts_with(b"P2WS\x01"));
+
+        let networks = SavedNetworks::from_record(&record).unwrap();
+        assert_eq!(networks.iter().collect::<Vec<_, 4>>(), [&home()]);
+        assert_eq!(networks.get("home-network").unwrap().auth, Auth::Wpa2);
+    }
+
+    #[test]
+    fn erased_flash_is_empty() {
+        assert_eq!(SavedNetworks::from_record(&[0xff; RECORD_LEN]), Err(RecordError::Empty));
+        assert_eq!(SavedNetworks::from_record(&[0x00; RECORD_LEN]), Err(RecordError::Corrupt));
+    }
+
+    #[test]
+    fn flipped_bit_is_caught() {
+        let mut buf = [0u8; RECORD_LEN];
+        saved(&[home()]).to_record(&mut buf);
+        buf[HEADER_LEN + 3] ^= 0x01;
+        assert_eq!(SavedNetworks::from_record(&buf), Err(RecordError::Corrupt));
+    }
+
+    #[test]
+    fn bad_length_is_corrupt() {
+        let mut buf = [0u8; RECORD_LEN];
+        saved(&[home()]).to_record(&mut buf);
+        buf[MAGIC.len() + 1..HEADER_LEN].copy_from_slice(&u16::MAX.to_le_bytes());
+        assert_eq!(SavedNetworks::from_record(&buf), Err(RecordError::Corrupt));
+    }
+
+    #[test]
+    fn other_versions_are_refused() {
+        let mut buf = [0u8; RECORD_LEN];
+        saved(&[home()]).to_record(&mut buf);
+        buf[MAGIC.len()] = 7;
+        assert_eq!(SavedNetworks::from_record(&buf), Err(RecordError::UnsupportedVersion(7)));
+    }
+
+    #[test]
+    fn validation() {
+        assert_eq!(Credentials::new("", "password1").unwrap_err(), CredentialsError::Ssid);
+        assert_eq!(Credentials::new(&"s".repeat(33), "password1").unwrap_err(), CredentialsError::Ssid);
+        assert_eq!(Credentials::new("net", "short").unwrap_err(), CredentialsError::Password);
+        assert_eq!(Credentials::new("net", &"p".repeat(64)).unwrap_err(), CredentialsError::Password);
+        assert!(Credentials::new("net", &"a1".repeat(32)).is_ok());
+
+        let with = |password: &str, auth| Credentials::with_options("net", password, auth, false, 0);
+        assert_eq!(with("password1", Auth::Open).unwrap_err(), CredentialsError::Password);
+        assert_eq!(with("", Auth::Wpa2).unwrap_err(), CredentialsError::Password);
+        assert_eq!(with(&"a1".repeat(32), Auth::Wpa3).unwrap_err(), CredentialsError::Password);
+        assert!(with("password1", Auth::Wpa3).is_ok());
+    }
+
+    #[test]
+    fn same_ssid_replaces() {
+        let mut networks = saved(&[home()]);
+        let updated = Credentials::new("home-network", "new password").unwrap();
+        assert_eq!(networks.save(updated.clone()), Some(home()));
+        assert_eq!(networks.len(), 1);
+        assert_eq!(networks.get("home-network"), Some(&updated));
+
+        assert!(networks.forget("home-network"));
+        assert!(!networks.forget("home-network"));
+        assert!(networks.is_empty());
+    }
+
+    #[test]
+    fn full_list_drops_lowest_priority() {
+        let mut networks = saved(&[with_priority("a", 3), with_priority("b", 1), with_priority("c", 1), with_priority("d", 2)]);
+        assert_eq!(networks.save(with_priority("e", 0)).unwrap().ssid.as_str(), "b");
+        assert_eq!(networks.len(), MAX_SAVED_NETWORKS);
+        assert!(networks.get("e").is_some());
+    }
+
+    #[test]
+    fn join_order_prefers_seen_then_priority_then_signal() {
+        let networks = saved(&[with_priority("office", 0), with_priority("lab", 0), with_priority("home", 1), with_priority("hidden", 9)]);
+        let visible = seen(&[("office", -80), ("lab", -45), ("home", -90), ("neighbour", -30)]);
+        let order: Vec<&str, 4> = networks.join_order(&visible).iter().map(|c| c.ssid.as_str()).collect();
+        assert_eq!(order, ["home", "lab", "office", "hidden"]);
+
+        let nothing_seen: Vec<&str, 4> = networks.join_order(&NetworkList::new()).iter().map(|c| c.ssid.as_str()).collect();
+        assert_eq!(nothing_seen[0], "hidden");
+    }
+
+    #[test]
+    fn unseen_hidden_networks_are_still_joined() {
+        let attic = Credentials::with_options("attic", "password123", Auth::Wpa2, true, 0).unwrap();
+        let networks = saved(&[with_priority("home", 5), attic, with_priority("office", 0)]);
+        let visible = seen(&[("office", -70)]);
+        let order: Vec<&str, 4> = networks.join_order(&visible).iter().map(|c| c.ssid.as_str()).collect();
+        assert_eq!(order, ["office", "attic", "home"]);
+    }
+
+    #[test]
+    fn debug_hides_password() {
+        let shown = format!("{:?}", home());
+        assert!(shown.contains("home-network"));
+        assert!(!shown.contains("horse"));
+    }
+}
@@ -0,0 +1,380 @@
+// file: dhcp_server.rs
+// desc: minimal DHCPv4 server for the setup access point
+//
+// Hands out addresses from a small pool next to the server's own, one per
+// client MAC, and names the server as router and DNS server so the captive
+// DNS responder sees every lookup. Only DISCOVER, REQUEST and RELEASE are
+// handled; anything else is ignored. Leases run out, so phones that pick a
+// fresh random MAC on every join do not use up the pool.
+use crate::network::Ipv4;
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+/// Replies are padded to the BOOTP minimum, which some clients insist on.
+pub const MIN_PACKET_LEN: usize = 300;
+/// Largest reply we build.
+pub const MAX_PACKET_LEN: usize = 320;
+pub const LEASE_TIME_S: u32 = 3600;
+/// How long an offered address stays reserved for a REQUEST.
+pub const OFFER_HOLD_S: u32 = 60;
+
+const OP_REQUEST: u8 = 1;
+const OP_REPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const OPTIONS_AT: usize = 240;
+
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS: u8 = 6;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_END: u8 = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MessageType {
+    Discover,
+    Offer,
+    Request,
+    Decline,
+    Ack,
+    Nak,
+    Release,
+    Inform,
+}
+
+impl MessageType {
+    fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            1 => MessageType::Discover,
+            2 => MessageType::Offer,
+            3 => MessageType::Request,
+            4 => MessageType::Decline,
+            5 => MessageType::Ack,
+            6 => MessageType::Nak,
+            7 => MessageType::Release,
+            8 => MessageType::Inform,
+            _ => return None,
+        })
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            MessageType::Discover => 1,
+            MessageType::Offer => 2,
+            MessageType::Request => 3,
+            MessageType::Decline => 4,
+            MessageType::Ack => 5,
+            MessageType::Nak => 6,
+            MessageType::Release => 7,
+            MessageType::Inform => 8,
+        }
+    }
+}
+
+/// The fields of a client message the server cares about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ClientMessage {
+    kind: MessageType,
+    xid: [u8; 4],
+    flags: [u8; 2],
+    ciaddr: Ipv4,
+    mac: [u8; 6],
+    requested: Option<Ipv4>,
+    server_id: Option<Ipv4>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Lease {
+    mac: [u8; 6],
+    /// Seconds on the caller's clock; the slot is free again after this.
+    expires_s: u64,
+}
+
+/// A pool of `N` leases starting at `first`, all on the server's /24.
+pub struct DhcpServer<const N: usize> {
+    address: Ipv4,
+    first: Ipv4,
+    leases: [Option<Lease>; N],
+}
+
+impl<const N: usize> DhcpServer<N> {
+    /// `first` must leave room for `N` addresses in the last octet.
+    pub const fn new(address: Ipv4, first: Ipv4) -> Self {
+        DhcpServer { address, first, leases: [None; N] }
+    }
+
+    /// Answer one client packet at `now_s`, any monotonic seconds count.
+    /// Returns the reply length, to be broadcast to `CLIENT_PORT`, or `None`
+    /// when there is nothing to send.
+    pub fn handle(&mut self, packet: &[u8], now_s: u64, reply: &mut [u8; MAX_PACKET_LEN]) -> Option<usize> {
+        let message = parse(packet)?;
+        // A REQUEST naming another server means the client chose that one
+        if message.server_id.is_some_and(|id| id != self.address) {
+            self.release(&message.mac);
+            return None;
+        }
+
+        match message.kind {
+            MessageType::Discover => {
+                let slot = self.lease_for(&message.mac, now_s, OFFER_HOLD_S)?;
+                Some(self.build(&message, MessageType::Offer, self.slot_address(slot), reply))
+            }
+            MessageType::Request => {
+                let wanted = message.requested.unwrap_or(message.ciaddr);
+                match self.lease_for(&message.mac, now_s, LEASE_TIME_S) {
+                    Some(slot) if self.slot_address(slot) == wanted => {
+                        Some(self.build(&message, MessageType::Ack, wanted, reply))
+                    }
+                    _ => Some(self.build(&message, MessageType::Nak, [0; 4], reply)),
+                }
+            }
+            MessageType::Release | MessageType::Decline => {
+                self.release(&message.mac);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// The address leased to `mac` at `now_s`.
+    pub fn lease(&self, mac: &[u8; 6], now_s: u64) -> Option<Ipv4> {
+        let slot = self.slot_of(mac)?;
+        self.leases[slot].filter(|lease| lease.expires_s > now_s)?;
+        Some(self.slot_address(slot))
+    }
+
+    // Existing lease for `mac`, else the first free or expired one, held for
+    // `hold_s` from now; a client never loses time it was already given
+    fn lease_for(&mut self, mac: &[u8; 6], now_s: u64, hold_s: u32) -> Option<usize> {
+        let slot = self.slot_of(mac).or_else(|| {
+            self.leases.iter().position(|lease| lease.is_none_or(|lease| lease.expires_s <= now_s))
+        })?;
+        let expires_s = now_s + u64::from(hold_s);
+        match &mut self.leases[slot] {
+            Some(lease) if lease.mac == *mac => lease.expires_s = lease.expires_s.max(expires_s),
+            free => *free = Some(Lease { mac: *mac, expires_s }),
+        }
+        Some(slot)
+    }
+
+    fn slot_of(&self, mac: &[u8; 6]) -> Option<usize> {
+        self.leases.iter().position(|lease| lease.is_some_and(|lease| lease.mac == *mac))
+    }
+
+    fn release(&mut self, mac: &[u8; 6]) {
+        for lease in self.leases.iter_mut().filter(|lease| lease.is_some_and(|lease| lease.mac == *mac)) {
+            *lease = None;
+        }
+    }
+
+    fn slot_address(&self, slot: usize) -> Ipv4 {
+        let mut address = self.first;
+        address[3] = address[3].wrapping_add(slot as u8);
+        address
+    }
+
+    fn build(&self, message: &ClientMessage, kind: MessageType, yiaddr: Ipv4, reply: &mut [u8; MAX_PACKET_LEN]) -> usize {
+        reply.fill(0);
+        reply[0] = OP_REPLY;
+        reply[1] = HTYPE_ETHERNET;
+        reply[2] = 6;
+        reply[4..8].copy_from_slice(&message.xid);
+        reply[10..12].copy_from_slice(&message.flags);
+        reply[16..20].copy_from_slice(&yiaddr);
+        reply[20..24].copy_from_slice(&self.address);
+        reply[28..34].copy_from_slice(&message.mac);
+        reply[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+        let mut at = OPTIONS_AT;
+        let mut option = |code: u8, data: &[u8]| {
+            reply[at] = code;
+            reply[at + 1] = data.len() as u8;
+            reply[at + 2..at + 2 + data.len()].copy_from_slice(data);
+            at += 2 + data.len();
+        };
+        option(OPT_MESSAGE_TYPE, &[kind.as_u8()]);
+        option(OPT_SERVER_ID, &self.address);
+        if kind != MessageType::Nak {
+            option(OPT_LEASE_TIME, &LEASE_TIME_S.to_be_bytes());
+            option(OPT_SUBNET_MASK, &[255, 255, 255, 0]);
+            option(OPT_ROUTER, &self.address);
+            option(OPT_DNS, &self.address);
+        }
+        reply[at] = OPT_END;
+        (at + 1).max(MIN_PACKET_LEN)
+    }
+}
+
+fn parse(packet: &[u8]) -> Option<ClientMessage> {
+    if packet.len() < OPTIONS_AT || packet[0] != OP_REQUEST || packet[1] != HTYPE_ETHERNET || packet[2] != 6 {
+        return None;
+    }
+    if packet[236..OPTIONS_AT] != MAGIC_COOKIE {
+        return None;
+    }
+
+    let mut kind = None;
+    let mut requested = None;
+    let mut server_id = None;
+    let mut at = OPTIONS_AT;
+    while let Some(&code) = packet.get(at) {
+        match code {
+            OPT_PAD => {
+                at += 1;
+                continue;
+            }
+            OPT_END => break,
+            _ => {}
+        }
+        let len = *packet.get(at + 1)? as usize;
+        let data = packet.get(at + 2..at + 2 + len)?;
+        match (code, data) {
+            (OPT_MESSAGE_TYPE, [value]) => kind = MessageType::from_u8(*value),
+            (OPT_REQUESTED_IP, &[a, b, c, d]) => requested = Some([a, b, c, d]),
+            (OPT_SERVER_ID, &[a, b, c, d]) => server_id = Some([a, b, c, d]),
+            _ => {}
+        }
+        at += 2 + len;
+    }
+
+    Some(ClientMessage {
+        kind: kind?,
+        xid: packet[4..8].try_into().ok()?,
+        flags: packet[10..12].try_into().ok()?,
+        ciaddr: packet[12..16].try_into().ok()?,
+        mac: packet[28..34].try_into().ok()?,
+        requested,
+        server_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER: Ipv4 = [192, 168, 4, 1];
+    const PHONE: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+    const LAPTOP: [u8; 6] = [0x02, 0, 0, 0, 0, 0x02];
+
+    fn client_packet(kind: u8, mac: &[u8; 6], extra: &[u8]) -> [u8; 300] {
+        let mut packet = [0u8; 300];
+        packet[..3].copy_from_slice(&[OP_REQUEST, HTYPE_ETHERNET, 6]);
+        packet[4..8].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        packet[10] = 0x80;
+        packet[28..34].copy_from_slice(mac);
+        packet[236..240].copy_from_slice(&MAGIC_COOKIE);
+        packet[240..243].copy_from_slice(&[OPT_MESSAGE_TYPE, 1, kind]);
+        packet[243..243 + extra.len()].copy_from_slice(extra);
+        packet[243 + extra.len()] = OPT_END;
+        packet
+    }
+
+    fn option(reply: &[u8], code: u8) -> Option<&[u8]> {
+        let mut at = OPTIONS_AT;
+        while reply[at] != OPT_END {
+            let len = reply[at + 1] as usize;
+            if reply[at] == code {
+                return Some(&reply[at + 2..at + 2 + len]);
+            }
+            at += 2 + len;
+        }
+        None
+    }
+
+    #[test]
+    fn discover_then_request() {
+        let mut server = DhcpServer::<4>::new(SERVER, [192, 168, 4, 2]);
+        let mut reply = [0u8; MAX_PACKET_LEN];
+
+        let len = server.handle(&client_packet(1, &PHONE, &[]), 0, &mut reply).unwrap();
+        assert_eq!(len, MIN_PACKET_LEN);
+        assert_eq!(reply[0], OP_REPLY);
+        assert_eq!(&reply[4..8], &[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(reply[10], 0x80);
+        assert_eq!(&reply[16..20], &[192, 168, 4, 2]);
+        assert_eq!(&reply[28..34], &PHONE);
+        assert_eq!(option(&reply, OPT_MESSAGE_TYPE), Some(&[2][..]));
+        assert_eq!(option(&reply, OPT_DNS), Some(&SERVER[..]));
+
+        let request = [&[OPT_REQUESTED_IP, 4, 192, 168, 4, 2][..], &[OPT_SERVER_ID, 4, 192, 168, 4, 1]].concat();
+        server.handle(&client_packet(3, &PHONE, &request), 0, &mut reply).unwrap();
+        assert_eq!(option(&reply, OPT_MESSAGE_TYPE), Some(&[5][..]));
+        assert_eq!(option(&reply, OPT_LEASE_TIME), Some(&LEASE_TIME_S.to_be_bytes()[..]));
+        assert_eq!(server.lease(&PHONE, 0), Some([192, 168, 4, 2]));
+    }
+
+    #[test]
+    fn clients_get_their_own_address() {
+        let mut server = DhcpServer::<4>::new(SERVER, [192, 168, 4, 2]);
+        let mut reply = [0u8; MAX_PACKET_LEN];
+        server.handle(&client_packet(1, &PHONE, &[]), 0, &mut reply).unwrap();
+        server.handle(&client_packet(1, &LAPTOP, &[]), 0, &mut reply).unwrap();
+        assert_eq!(&reply[16..20], &[192, 168, 4, 3]);
+        // Asking again keeps the same lease
+        server.handle(&client_packet(1, &PHONE, &[]), 0, &mut reply).unwrap();
+        assert_eq!(&reply[16..20], &[192, 168, 4, 2]);
+    }
+
+    #[test]
+    fn wrong_address_is_refused() {
+        let mut server = DhcpServer::<4>::new(SERVER, [192, 168, 4, 2]);
+        let mut reply = [0u8; MAX_PACKET_LEN];
+        let request = [OPT_REQUESTED_IP, 4, 10, 0, 0, 9];
+        server.handle(&client_packet(3, &PHONE, &request), 0, &mut reply).unwrap();
+        assert_eq!(option(&reply, OPT_MESSAGE_TYPE), Some(&[6][..]));
+        assert_eq!(&reply[16..20], &[0; 4]);
+    }
+
+    #[test]
+    fn release_frees_the_lease() {
+        let mut server = DhcpServer::<1>::new(SERVER, [192, 168, 4, 2]);
+        let mut reply = [0u8; MAX_PACKET_LEN];
+        server.handle(&client_packet(1, &PHONE, &[]), 0, &mut reply).unwrap();
+        assert_eq!(server.handle(&client_packet(1, &LAPTOP, &[]), 0, &mut reply), None);
+
+        assert_eq!(server.handle(&client_packet(7, &PHONE, &[]), 0, &mut reply), None);
+        assert_eq!(server.lease(&PHONE, 0), None);
+        assert!(server.handle(&client_packet(1, &LAPTOP, &[]), 0, &mut reply).is_some());
+    }
+
+    #[test]
+    fn expired_leases_are_reclaimed() {
+        let mut server = DhcpServer::<1>::new(SERVER, [192, 168, 4, 2]);
+        let mut reply = [0u8; MAX_PACKET_LEN];
+        let request = [OPT_REQUESTED_IP, 4, 192, 168, 4, 2];
+
+        // An offer nobody takes up only holds the address briefly
+        server.handle(&client_packet(1, &PHONE, &[]), 0, &mut reply).unwrap();
+        assert_eq!(server.handle(&client_packet(1, &LAPTOP, &[]), 30, &mut reply), None);
+        server.handle(&client_packet(1, &LAPTOP, &[]), u64::from(OFFER_HOLD_S), &mut reply).unwrap();
+        assert_eq!(&reply[16..20], &[192, 168, 4, 2]);
+
+        // An acknowledged lease lasts its full time, then goes to the next client
+        server.handle(&client_packet(3, &LAPTOP, &request), 100, &mut reply).unwrap();
+        assert_eq!(option(&reply, OPT_MESSAGE_TYPE), Some(&[5][..]));
+        let expiry = 100 + u64::from(LEASE_TIME_S);
+        assert_eq!(server.lease(&LAPTOP, expiry - 1), Some([192, 168, 4, 2]));
+        assert_eq!(server.handle(&client_packet(1, &PHONE, &[]), expiry - 1, &mut reply), None);
+        assert_eq!(server.lease(&LAPTOP, expiry), None);
+        server.handle(&client_packet(1, &PHONE, &[]), expiry, &mut reply).unwrap();
+        assert_eq!(server.lease(&PHONE, expiry), Some([192, 168, 4, 2]));
+    }
+
+    #[test]
+    fn other_servers_and_junk_are_ignored() {
+        let mut server = DhcpServer::<4>::new(SERVER, [192, 168, 4, 2]);
+        let mut reply = [0u8; MAX_PACKET_LEN];
+        let request = [OPT_SERVER_ID, 4, 192, 168, 1, 1];
+        assert_eq!(server.handle(&client_packet(3, &PHONE, &request), 0, &mut reply), None);
+        assert_eq!(server.handle(&[0u8; 64], 0, &mut reply), None);
+
+        let mut truncated = client_packet(1, &PHONE, &[]);
+        truncated[240..243].copy_from_slice(&[OPT_MESSAGE_TYPE, 200, 1]);
+        assert_eq!(server.handle(&truncated, 0, &mut reply), None);
+    }
+}
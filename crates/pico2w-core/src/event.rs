@@ -0,0 +1,238 @@
+// file: event.rs
+// desc: events broadcast to live subscribers, and their SSE and JSON encodings
+//
+// The display task publishes an event whenever a command actually changes
+// playback. Subscribers such as `GET /events` add their own periodic frame and
+// heartbeat events on top.
+use core::fmt::Write as _;
+
+use heapless::{String, Vec};
+use serde::Serialize;
+
+use crate::animation::PlaybackState;
+use crate::command::{Brightness, Source};
+
+/// Room for the longest event in SSE or JSON framing.
+pub const SSE_EVENT_LEN: usize = 160;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AnimationEvent {
+    pub animation: u8,
+    pub frame_count: usize,
+    pub source: Source,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PlaybackEvent {
+    pub paused: bool,
+    pub frame_interval_ms: u16,
+    pub source: Source,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FrameEvent {
+    pub animation: u8,
+    pub frame_index: usize,
+    pub frame_count: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BrightnessEvent {
+    /// Sent as its 1-5 level, like the console and MQTT take it.
+    #[serde(serialize_with = "brightness_level")]
+    pub brightness: Brightness,
+    pub source: Source,
+}
+
+fn brightness_level<S: serde::Serializer>(brightness: &Brightness, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u8(brightness.level())
+}
+
+/// A front-panel button went down, whatever it ends up doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ButtonEvent {
+    /// Counted from 1.
+    pub button: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HeartbeatEvent {
+    pub uptime_s: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event {
+    Animation(AnimationEvent),
+    Playback(PlaybackEvent),
+    Frame(FrameEvent),
+    Brightness(BrightnessEvent),
+    Button(ButtonEvent),
+    Heartbeat(HeartbeatEvent),
+}
+
+impl Event {
+    /// The events a command caused, judged by the player state around it.
+    pub fn between(before: &PlaybackState, after: &PlaybackState, source: Source) -> Vec<Event, 2> {
+        let mut events = Vec::new();
+        if after.animation != before.animation {
+            events
+                .push(Event::Animation(AnimationEvent {
+                    animation: after.animation,
+                    frame_count: after.frame_count,
+                    source,
+                }))
+                .ok();
+        }
+        if (after.paused, after.frame_interval_ms) != (before.paused, before.frame_interval_ms) {
+            events
+                .push(Event::Playback(PlaybackEvent {
+                    paused: after.paused,
+                    frame_interval_ms: after.frame_interval_ms,
+                    source,
+                }))
+                .ok();
+        }
+        events
+    }
+
+    pub fn frame(state: &PlaybackState) -> Self {
+        Event::Frame(FrameEvent {
+            animation: state.animation,
+            frame_index: state.frame_index,
+            frame_count: state.frame_count,
+        })
+    }
+
+    /// The SSE `event:` field.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Animation(_) => "animation",
+            Event::Playback(_) => "playback",
+            Event::Frame(_) => "frame",
+            Event::Brightness(_) => "brightness",
+            Event::Button(_) => "button",
+            Event::Heartbeat(_) => "heartbeat",
+        }
+    }
+
+    /// One Server-Sent Events message, blank line included.
+    pub fn to_sse(&self) -> Option<String<SSE_EVENT_LEN>> {
+        let mut data = [0u8; SSE_EVENT_LEN];
+        let mut message = String::new();
+        write!(message, "event: {}\ndata: {}\n\n", self.name(), self.data_json(&mut data)?).ok()?;
+        Some(message)
+    }
+
+    /// The event as one JSON object, `{"event":"animation","data":{...}}`,
+    /// for WebSocket clients.
+    pub fn to_json(&self) -> Option<String<SSE_EVENT_LEN>> {
+        let mut data = [0u8; SSE_EVENT_LEN];
+        let mut message = String::new();
+        write!(message, r#"{{"event":"{}","data":{}}}"#, self.name(), self.data_json(&mut data)?).ok()?;
+        Some(message)
+    }
+
+    pub(crate) fn data_json<'b>(&self, buf: &'b mut [u8]) -> Option<&'b str> {
+        let len = match self {
+            Event::Animation(event) => serde_json_core::to_slice(event, buf),
+            Event::Playback(event) => serde_json_core::to_slice(event, buf),
+            Event::Frame(event) => serde_json_core::to_slice(event, buf),
+            Event::Brightness(event) => serde_json_core::to_slice(event, buf),
+            Event::Button(event) => serde_json_core::to_slice(event, buf),
+            Event::Heartbeat(event) => serde_json_core::to_slice(event, buf),
+        }
+        .ok()?;
+        // serde-json-core only ever writes UTF-8
+        core::str::from_utf8(&buf[..len]).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATE: PlaybackState = PlaybackState {
+        animation: 1,
+        animation_count: 4,
+        frame_index: 3,
+        frame_count: 12,
+        paused: false,
+        frame_interval_ms: 100,
+    };
+
+    #[test]
+    fn nothing_changed() {
+        assert!(Event::between(&STATE, &STATE, Source::Http).is_empty());
+        let moved_on = PlaybackState { frame_index: 4, ..STATE };
+        assert!(Event::between(&STATE, &moved_on, Source::Http).is_empty());
+    }
+
+    #[test]
+    fn animation_change() {
+        let after = PlaybackState { animation: 3, frame_index: 0, frame_count: 8, ..STATE };
+        assert_eq!(
+            Event::between(&STATE, &after, Source::Button),
+            [Event::Animation(AnimationEvent { animation: 3, frame_count: 8, source: Source::Button })]
+        );
+    }
+
+    #[test]
+    fn playback_change() {
+        let after = PlaybackState { paused: true, ..STATE };
+        assert_eq!(
+            Event::between(&STATE, &after, Source::Scheduler),
+            [Event::Playback(PlaybackEvent { paused: true, frame_interval_ms: 100, source: Source::Scheduler })]
+        );
+    }
+
+    #[test]
+    fn sse_framing() {
+        let event = Event::Animation(AnimationEvent { animation: 2, frame_count: 12, source: Source::Http });
+        assert_eq!(
+            event.to_sse().unwrap().as_str(),
+            "event: animation\ndata: {\"animation\":2,\"frame_count\":12,\"source\":\"http\"}\n\n"
+        );
+        let heartbeat = Event::Heartbeat(HeartbeatEvent { uptime_s: 7 });
+        assert_eq!(heartbeat.to_sse().unwrap().as_str(), "event: heartbeat\ndata: {\"uptime_s\":7}\n\n");
+        let button = Event::Button(ButtonEvent { button: 3 });
+        assert_eq!(button.to_sse().unwrap().as_str(), "event: button\ndata: {\"button\":3}\n\n");
+        let brightness = Event::Brightness(BrightnessEvent { brightness: Brightness::Dim, source: Source::Mqtt });
+        assert_eq!(
+            brightness.to_sse().unwrap().as_str(),
+            "event: brightness\ndata: {\"brightness\":2,\"source\":\"mqtt\"}\n\n"
+        );
+        assert_eq!(Event::frame(&STATE).name(), "frame");
+    }
+
+    #[test]
+    fn json_framing() {
+        let event = Event::Playback(PlaybackEvent { paused: true, frame_interval_ms: 50, source: Source::Http });
+        assert_eq!(
+            event.to_json().unwrap().as_str(),
+            r#"{"event":"playback","data":{"paused":true,"frame_interval_ms":50,"source":"http"}}"#
+        );
+    }
+
+    #[test]
+    fn longest_events_fit() {
+        let events = [
+            Event::Animation(AnimationEvent { animation: u8::MAX, frame_count: usize::MAX, source: Source::Scheduler }),
+            Event::Playback(PlaybackEvent { paused: false, frame_interval_ms: u16::MAX, source: Source::Scheduler }),
+            Event::Frame(FrameEvent { animation: u8::MAX, frame_index: usize::MAX, frame_count: usize::MAX }),
+            Event::Brightness(BrightnessEvent { brightness: Brightness::Brightest, source: Source::Websocket }),
+            Event::Button(ButtonEvent { button: u8::MAX }),
+            Event::Heartbeat(HeartbeatEvent { uptime_s: u64::MAX }),
+        ];
+        for event in events {
+            assert!(event.to_sse().is_some(), "{} does not fit", event.name());
+            assert!(event.to_json().is_some(), "{} does not fit", event.name());
+        }
+    }
+}
@@ -0,0 +1,749 @@
+// file: fat.rs
+// desc: a minimal FAT12/16 volume: a fresh image to format with, and read-only
+// traversal of whatever the host wrote since
+//
+// The firmware reads the volume straight out of memory-mapped flash, so files
+// come back as slices instead of going through a block cache. That only works
+// for files whose clusters are contiguous, which is what every host produces
+// when copying onto a volume with room to spare.
+use heapless::String;
+
+pub const SECTOR_SIZE: usize = 512;
+/// Longest name `Entry::name` keeps; longer ones are cut short.
+pub const NAME_LEN: usize = 64;
+
+const DIR_ENTRY_LEN: usize = 32;
+// 4K clusters line up with flash erase sectors
+const SECTORS_PER_CLUSTER: u32 = 8;
+const ROOT_ENTRIES: u32 = 128;
+const FAT_COUNT: u32 = 2;
+const MEDIA_FIXED: u8 = 0xf8;
+// Fewer clusters than this is FAT12, by definition
+const FAT12_MAX_CLUSTERS: u32 = 4084;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_HIDDEN: u8 = 0x02;
+const ATTR_SYSTEM: u8 = 0x04;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+const ENTRY_FREE: u8 = 0xe5;
+const ENTRY_END: u8 = 0x00;
+// First name byte of a short name that really starts with 0xe5
+const ENTRY_KANJI_E5: u8 = 0x05;
+const LAST_LONG_ENTRY: u8 = 0x40;
+// Case bits Windows keeps in an otherwise unused byte of short entries
+const LOWERCASE_BASE: u8 = 0x08;
+const LOWERCASE_EXT: u8 = 0x10;
+// Characters of a long name per directory entry
+const LONG_ENTRY_CHARS: usize = 13;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FatError {
+    /// No FAT boot sector; the volume needs formatting.
+    NotFat,
+    /// FAT32, exFAT or a sector size other than 512.
+    Unsupported,
+    /// The FAT points somewhere it cannot.
+    Corrupt,
+    /// The file's clusters are not one after the other.
+    Fragmented,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FatType {
+    Fat12,
+    Fat16,
+}
+
+/// Layout of a volume `format_sector` creates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Geometry {
+    pub sectors: u32,
+    reserved: u32,
+    fat_sectors: u32,
+    clusters: u32,
+}
+
+impl Geometry {
+    /// Lay out a volume of `sectors` 512-byte sectors, with the data area on
+    /// a cluster boundary.
+    pub const fn new(sectors: u32) -> Self {
+        let root_sectors = ROOT_ENTRIES * DIR_ENTRY_LEN as u32 / SECTOR_SIZE as u32;
+        // Sized for a cluster per sector group, which is never too small
+        let max_clusters = sectors / SECTORS_PER_CLUSTER;
+        let fat_bytes = if max_clusters < FAT12_MAX_CLUSTERS { (max_clusters + 2) * 3 / 2 + 1 } else { (max_clusters + 2) * 2 };
+        let fat_sectors = fat_bytes.div_ceil(SECTOR_SIZE as u32);
+        let metadata = 1 + FAT_COUNT * fat_sectors + root_sectors;
+        let reserved = 1 + metadata.next_multiple_of(SECTORS_PER_CLUSTER) - metadata;
+        let data_start = reserved + FAT_COUNT * fat_sectors + root_sectors;
+        Geometry { sectors, reserved, fat_sectors, clusters: (sectors - data_start) / SECTORS_PER_CLUSTER }
+    }
+
+    pub const fn fat_type(&self) -> FatType {
+        if self.clusters <= FAT12_MAX_CLUSTERS { FatType::Fat12 } else { FatType::Fat16 }
+    }
+
+    /// Sectors before the data area; formatting writes these and nothing else.
+    pub const fn data_start(&self) -> u32 {
+        self.reserved + FAT_COUNT * self.fat_sectors + ROOT_ENTRIES * DIR_ENTRY_LEN as u32 / SECTOR_SIZE as u32
+    }
+
+    /// Sector `lba` of a freshly formatted, empty volume.
+    pub fn format_sector(&self, lba: u32, label: &[u8; 11], volume_id: u32) -> [u8; SECTOR_SIZE] {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let root_start = self.reserved + FAT_COUNT * self.fat_sectors;
+        let fat_start = |fat: u32| self.reserved + fat * self.fat_sectors;
+
+        if lba == 0 {
+            self.boot_sector(&mut sector, label, volume_id);
+        } else if (0..FAT_COUNT).any(|fat| lba == fat_start(fat)) {
+            // Entries 0 and 1 are reserved: the media byte, then end of chain
+            let reserved: &[u8] = match self.fat_type() {
+                FatType::Fat12 => &[MEDIA_FIXED, 0xff, 0xff],
+                FatType::Fat16 => &[MEDIA_FIXED, 0xff, 0xff, 0xff],
+            };
+            sector[..reserved.len()].copy_from_slice(reserved);
+        } else if lba == root_start {
+            sector[..11].copy_from_slice(label);
+            sector[11] = ATTR_VOLUME_ID;
+        }
+        sector
+    }
+
+    fn boot_sector(&self, sector: &mut [u8; SECTOR_SIZE], label: &[u8; 11], volume_id: u32) {
+        // Jump over the BPB; nothing boots from here
+        sector[..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+        sector[3..11].copy_from_slice(b"MSWIN4.1");
+        sector[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+        sector[13] = SECTORS_PER_CLUSTER as u8;
+        sector[14..16].copy_from_slice(&(self.reserved as u16).to_le_bytes());
+        sector[16] = FAT_COUNT as u8;
+        sector[17..19].copy_from_slice(&(ROOT_ENTRIES as u16).to_le_bytes());
+        match u16::try_from(self.sectors) {
+            Ok(sectors) => sector[19..21].copy_from_slice(&sectors.to_le_bytes()),
+            Err(_) => sector[32..36].copy_from_slice(&self.sectors.to_le_bytes()),
+        }
+        sector[21] = MEDIA_FIXED;
+        sector[22..24].copy_from_slice(&(self.fat_sectors as u16).to_le_bytes());
+        // Sectors per track and heads, for tools that still look
+        sector[24..26].copy_from_slice(&32u16.to_le_bytes());
+        sector[26..28].copy_from_slice(&2u16.to_le_bytes());
+        sector[36] = 0x80;
+        // Extended boot signature: volume ID, label and type follow
+        sector[38] = 0x29;
+        sector[39..43].copy_from_slice(&volume_id.to_le_bytes());
+        sector[43..54].copy_from_slice(label);
+        sector[54..62].copy_from_slice(match self.fat_type() {
+            FatType::Fat12 => b"FAT12   ",
+            FatType::Fat16 => b"FAT16   ",
+        });
+        sector[510] = 0x55;
+        sector[511] = 0xaa;
+    }
+}
+
+/// A FAT12/16 volume mapped into memory.
+#[derive(Debug, Clone, Copy)]
+pub struct Volume<'a> {
+    image: &'a [u8],
+    fat_type: FatType,
+    cluster_len: usize,
+    fat_start: usize,
+    root_start: usize,
+    root_entries: usize,
+    data_start: usize,
+    clusters: u32,
+}
+
+impl<'a> Volume<'a> {
+    /// Read the boot sector; `image` is the whole volume.
+    pub fn mount(image: &'a [u8]) -> Result<Self, FatError> {
+        let boot = image.get(..SECTOR_SIZE).ok_or(FatError::NotFat)?;
+        let u16_at = |offset: usize| u16::from_le_bytes([boot[offset], boot[offset + 1]]) as usize;
+        if boot[510..] != [0x55, 0xaa] || !matches!(boot[0], 0xeb | 0xe9) {
+            return Err(FatError::NotFat);
+        }
+
+        let sector_size = u16_at(11);
+        let sectors_per_cluster = boot[13] as usize;
+        let reserved = u16_at(14);
+        let fats = boot[16] as usize;
+        let root_entries = u16_at(17);
+        let fat_sectors = u16_at(22);
+        let sectors = match u16_at(19) {
+            0 => u32::from_le_bytes([boot[32], boot[33], boot[34], boot[35]]) as usize,
+            sectors => sectors,
+        };
+        // FAT32 keeps its FAT size elsewhere and has no fixed root directory
+        if sector_size != SECTOR_SIZE || fat_sectors == 0 || root_entries == 0 {
+            return Err(FatError::Unsupported);
+        }
+        if !sectors_per_cluster.is_power_of_two() || reserved == 0 || fats == 0 {
+            return Err(FatError::NotFat);
+        }
+
+        let fat_start = reserved * SECTOR_SIZE;
+        let root_start = fat_start + fats * fat_sectors * SECTOR_SIZE;
+        let data_start = root_start + (root_entries * DIR_ENTRY_LEN).next_multiple_of(SECTOR_SIZE);
+        let len = sectors * SECTOR_SIZE;
+        if len > image.len() || data_start > len {
+            return Err(FatError::Corrupt);
+        }
+        let cluster_len = sectors_per_cluster * SECTOR_SIZE;
+        let clusters = ((len - data_start) / cluster_len) as u32;
+        let fat_type = if clusters <= FAT12_MAX_CLUSTERS { FatType::Fat12 } else { FatType::Fat16 };
+        if clusters > u16::MAX as u32 - 16 {
+            return Err(FatError::Unsupported);
+        }
+
+        Ok(Volume { image: &image[..len], fat_type, cluster_len, fat_start, root_start, root_entries, data_start, clusters })
+    }
+
+    pub fn fat_type(&self) -> FatType {
+        self.fat_type
+    }
+
+    /// The top-level directory.
+    pub fn root(&self) -> Dir<'a> {
+        Dir::new(*self, Location::Root { index: 0 })
+    }
+
+    /// The contents of a directory `entry`.
+    pub fn dir(&self, entry: &Entry) -> Dir<'a> {
+        let cluster = self.data_cluster(entry.cluster);
+        Dir::new(*self, Location::Chain { cluster, index: 0, left: self.clusters })
+    }
+
+    /// A file's contents, straight out of the image.
+    pub fn file(&self, entry: &Entry) -> Result<&'a [u8], FatError> {
+        let size = entry.size as usize;
+        if size == 0 {
+            return Ok(&[]);
+        }
+        let first = self.data_cluster(entry.cluster).ok_or(FatError::Corrupt)?;
+        let needed = size.div_ceil(self.cluster_len) as u32;
+        let mut cluster = first;
+        for _ in 1..needed {
+            match self.next(cluster)? {
+                Some(next) if next == cluster + 1 => cluster = next,
+                Some(_) => return Err(FatError::Fragmented),
+                None => return Err(FatError::Corrupt),
+            }
+        }
+        let start = self.cluster_offset(first);
+        self.image.get(start..start + size).ok_or(FatError::Corrupt)
+    }
+
+    // A cluster number that can hold data, if it is one
+    fn data_cluster(&self, cluster: u16) -> Option<u32> {
+        let cluster = cluster as u32;
+        (2..self.clusters + 2).contains(&cluster).then_some(cluster)
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> usize {
+        self.data_start + (cluster as usize - 2) * self.cluster_len
+    }
+
+    // The cluster after `cluster`; None at the end of the chain
+    fn next(&self, cluster: u32) -> Result<Option<u32>, FatError> {
+        let entry = match self.fat_type {
+            FatType::Fat12 => {
+                let offset = self.fat_start + cluster as usize * 3 / 2;
+                let pair = self.image.get(offset..offset + 2).ok_or(FatError::Corrupt)?;
+                let pair = u16::from_le_bytes([pair[0], pair[1]]);
+                let entry = if cluster & 1 == 1 { pair >> 4 } else { pair & 0x0fff };
+                if entry >= 0x0ff8 {
+                    return Ok(None);
+                }
+                entry
+            }
+            FatType::Fat16 => {
+                let offset = self.fat_start + cluster as usize * 2;
+                let pair = self.image.get(offset..offset + 2).ok_or(FatError::Corrupt)?;
+                let entry = u16::from_le_bytes([pair[0], pair[1]]);
+                if entry >= 0xfff8 {
+                    return Ok(None);
+                }
+                entry
+            }
+        };
+        self.data_cluster(entry).map(Some).ok_or(FatError::Corrupt)
+    }
+}
+
+/// A file or directory, with its long name if it has one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String<NAME_LEN>,
+    pub attributes: u8,
+    cluster: u16,
+    pub size: u32,
+}
+
+impl Entry {
+    pub fn is_dir(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    /// Hidden and system entries, and the dot-files macOS leaves behind.
+    pub fn is_hidden(&self) -> bool {
+        self.attributes & (ATTR_HIDDEN | ATTR_SYSTEM) != 0 || self.name.starts_with('.')
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Location {
+    Root { index: usize },
+    /// `left` counts down the clusters the chain may still move on to.
+    Chain { cluster: Option<u32>, index: usize, left: u32 },
+}
+
+/// Entries of one directory, in the order they are stored. Deleted entries,
+/// the volume label and `.`/`..` are left out.
+pub struct Dir<'a> {
+    volume: Volume<'a>,
+    location: Location,
+    // Long name collected from the entries before the short one
+    long_name: [u8; NAME_LEN],
+    long_len: usize,
+    long_checksum: Option<u8>,
+}
+
+impl<'a> Dir<'a> {
+    fn new(volume: Volume<'a>, location: Location) -> Self {
+        Dir { volume, location, long_name: [0; NAME_LEN], long_len: 0, long_checksum: None }
+    }
+
+    // The next raw 32-byte entry
+    fn raw(&mut self) -> Option<&'a [u8]> {
+        let volume = self.volume;
+        let per_cluster = volume.cluster_len / DIR_ENTRY_LEN;
+        match &mut self.location {
+            Location::Root { index } => {
+                if *index >= volume.root_entries {
+                    return None;
+                }
+                let offset = volume.root_start + *index * DIR_ENTRY_LEN;
+                *index += 1;
+                volume.image.get(offset..offset + DIR_ENTRY_LEN)
+            }
+            Location::Chain { cluster, index, left } => {
+                if *index == per_cluster {
+                    // No chain is longer than the volume, so a loop in the
+                    // FAT ends here
+                    *left = left.saturating_sub(1);
+                    *cluster = if *left == 0 { None } else { volume.next((*cluster)?).ok().flatten() };
+                    *index = 0;
+                }
+                let offset = volume.cluster_offset((*cluster)?) + *index * DIR_ENTRY_LEN;
+                *index += 1;
+                volume.image.get(offset..offset + DIR_ENTRY_LEN)
+            }
+        }
+    }
+
+    fn long_entry(&mut self, raw: &[u8]) {
+        let sequence = raw[0];
+        if sequence & LAST_LONG_ENTRY != 0 {
+            // Stored last part first; this starts a new name
+            self.long_name = [0; NAME_LEN];
+            self.long_len = 0;
+            self.long_checksum = Some(raw[13]);
+        } else if self.long_checksum != Some(raw[13]) {
+            self.long_checksum = None;
+            return;
+        }
+        let part = (sequence & 0x1f) as usize;
+        if part == 0 {
+            self.long_checksum = None;
+            return;
+        }
+        let chars = raw[1..11].chunks(2).chain(raw[14..26].chunks(2)).chain(raw[28..32].chunks(2));
+        for (i, char) in chars.enumerate() {
+            let position = (part - 1) * LONG_ENTRY_CHARS + i;
+            let char = u16::from_le_bytes([char[0], char[1]]);
+            if char == 0 || char == 0xffff || position >= NAME_LEN {
+                break;
+            }
+            // Anything outside ASCII is only there to compare and sort
+            self.long_name[position] = if char < 0x80 { char as u8 } else { b'?' };
+            self.long_len = self.long_len.max(position + 1);
+        }
+    }
+
+    fn short_entry(&mut self, raw: &[u8]) -> Entry {
+        let mut name: String<NAME_LEN> = String::new();
+        let long = self.long_checksum.take().filter(|&checksum| checksum == short_checksum(&raw[..11]));
+        match long {
+            Some(_) if self.long_len > 0 => {
+                for &c in &self.long_name[..self.long_len] {
+                    name.push(c as char).ok();
+                }
+            }
+            _ => short_name(raw, &mut name),
+        }
+        Entry {
+            name,
+            attributes: raw[11],
+            cluster: u16::from_le_bytes([raw[26], raw[27]]),
+            size: u32::from_le_bytes([raw[28], raw[29], raw[30], raw[31]]),
+        }
+    }
+}
+
+impl Iterator for Dir<'_> {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        loop {
+            let raw = self.raw()?;
+            match raw[0] {
+                ENTRY_END => return None,
+                ENTRY_FREE => self.long_checksum = None,
+                _ if raw[11] & 0x3f == ATTR_LONG_NAME => self.long_entry(raw),
+                _ if raw[11] & ATTR_VOLUME_ID != 0 || raw[0] == b'.' => self.long_checksum = None,
+                _ => return Some(self.short_entry(raw)),
+            }
+        }
+    }
+}
+
+// Ties a long name to the short entry right after it
+fn short_checksum(name: &[u8]) -> u8 {
+    name.iter().fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c))
+}
+
+// `NAME    EXT` as `NAME.EXT`, lowercased where Windows marked it so
+fn short_name(raw: &[u8], name: &mut String<NAME_LEN>) {
+    let case = raw[12];
+    let base = raw[..8].trim_ascii_end();
+    let ext = raw[8..11].trim_ascii_end();
+    push_short(name, base, case & LOWERCASE_BASE != 0);
+    if !ext.is_empty() {
+        name.push('.').ok();
+        push_short(name, ext, case & LOWERCASE_EXT != 0);
+    }
+}
+
+fn push_short(name: &mut String<NAME_LEN>, part: &[u8], lower: bool) {
+    for (i, &c) in part.iter().enumerate() {
+        let c = if i == 0 && c == ENTRY_KANJI_E5 { ENTRY_FREE } else { c };
+        let c = if lower { c.to_ascii_lowercase() } else { c };
+        name.push(if c < 0x80 { c as char } else { '?' }).ok();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    extern crate std;
+    use std::vec;
+    use std::vec::Vec;
+
+    const LABEL: &[u8; 11] = b"PICO2W     ";
+    // 2 MiB, the size the firmware uses
+    pub(crate) const SECTORS: u32 = 4096;
+
+    pub(crate) fn formatted(sectors: u32) -> Vec<u8> {
+        let geometry = Geometry::new(sectors);
+        let mut image = vec![0u8; sectors as usize * SECTOR_SIZE];
+        for lba in 0..geometry.data_start() {
+            let offset = lba as usize * SECTOR_SIZE;
+            image[offset..offset + SECTOR_SIZE].copy_from_slice(&geometry.format_sector(lba, LABEL, 0x1234_5678));
+        }
+        image
+    }
+
+    /// Writes files the way a host would, for the tests.
+    pub(crate) struct Writer<'i> {
+        pub(crate) image: &'i mut Vec<u8>,
+        pub(crate) next_cluster: u16,
+    }
+
+    impl Writer<'_> {
+        pub(crate) fn new(image: &mut Vec<u8>) -> Writer<'_> {
+            Writer { image, next_cluster: 2 }
+        }
+
+        fn volume(&self) -> Volume<'_> {
+            Volume::mount(self.image).unwrap()
+        }
+
+        pub(crate) fn set_fat(&mut self, cluster: u16, value: u16) {
+            let volume = self.volume();
+            let (fat_start, fat_type) = (volume.fat_start, volume.fat_type);
+            match fat_type {
+                FatType::Fat12 => {
+                    let offset = fat_start + cluster as usize * 3 / 2;
+                    let pair = u16::from_le_bytes([self.image[offset], self.image[offset + 1]]);
+                    let pair = if cluster & 1 == 1 {
+                        (pair & 0x000f) | (value & 0x0fff) << 4
+                    } else {
+                        (pair & 0xf000) | (value & 0x0fff)
+                    };
+                    self.image[offset..offset + 2].copy_from_slice(&pair.to_le_bytes());
+                }
+                FatType::Fat16 => {
+                    let offset = fat_start + cluster as usize * 2;
+                    self.image[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+
+        // Store `data` in the given clusters, chained in that order
+        pub(crate) fn store(&mut self, clusters: &[u16], data: &[u8]) {
+            let volume = self.volume();
+            let (cluster_len, data_start) = (volume.cluster_len, volume.data_start);
+            for (i, &cluster) in clusters.iter().enumerate() {
+                let next = clusters.get(i + 1).copied().unwrap_or(0xffff);
+                self.set_fat(cluster, next);
+                let chunk = data.chunks(cluster_len).nth(i).unwrap_or(&[]);
+                let offset = data_start + (cluster as usize - 2) * cluster_len;
+                self.image[offset..offset + chunk.len()].copy_from_slice(chunk);
+            }
+        }
+
+        fn allocate(&mut self, len: usize) -> Vec<u16> {
+            let count = len.div_ceil(self.volume().cluster_len).max(1) as u16;
+            let clusters = (self.next_cluster..self.next_cluster + count).collect();
+            self.next_cluster += count;
+            clusters
+        }
+
+        // Append a raw entry to a directory: the root, or the one at `dir`
+        pub(crate) fn entry(&mut self, dir: Option<u16>, raw: &[u8; 32]) {
+            let volume = self.volume();
+            let start = match dir {
+                None => volume.root_start,
+                Some(cluster) => volume.cluster_offset(cluster as u32),
+            };
+            let mut offset = start;
+            while self.image[offset] != ENTRY_END {
+                offset += DIR_ENTRY_LEN;
+            }
+            self.image[offset..offset + DIR_ENTRY_LEN].copy_from_slice(raw);
+        }
+
+        pub(crate) fn short(name: &[u8; 11], attributes: u8, cluster: u16, size: u32) -> [u8; 32] {
+            let mut raw = [0u8; 32];
+            raw[..11].copy_from_slice(name);
+            raw[11] = attributes;
+            raw[26..28].copy_from_slice(&cluster.to_le_bytes());
+            raw[28..32].copy_from_slice(&size.to_le_bytes());
+            raw
+        }
+
+        // Long name entries for `name`, last part first, as stored
+        pub(crate) fn long(name: &str, short: &[u8; 11]) -> Vec<[u8; 32]> {
+            let mut chars: Vec<u16> = name.encode_utf16().collect();
+            chars.push(0);
+            while !chars.len().is_multiple_of(LONG_ENTRY_CHARS) {
+                chars.push(0xffff);
+            }
+            let parts = chars.len() / LONG_ENTRY_CHARS;
+            (0..parts)
+                .rev()
+                .map(|part| {
+                    let mut raw = [0u8; 32];
+                    raw[0] = (part + 1) as u8 | if part + 1 == parts { LAST_LONG_ENTRY } else { 0 };
+                    raw[11] = ATTR_LONG_NAME;
+                    raw[13] = short_checksum(short);
+                    let slots = (1..11).step_by(2).chain((14..26).step_by(2)).chain((28..32).step_by(2));
+                    for (slot, &c) in slots.zip(&chars[part * LONG_ENTRY_CHARS..]) {
+                        raw[slot..slot + 2].copy_from_slice(&c.to_le_bytes());
+                    }
+                    raw
+                })
+                .collect()
+        }
+
+        /// A file with contiguous clusters; returns its first cluster.
+        pub(crate) fn file(&mut self, dir: Option<u16>, name: &str, short: &[u8; 11], data: &[u8]) -> u16 {
+            let clusters = self.allocate(data.len());
+            self.store(&clusters, data);
+            for raw in Self::long(name, short) {
+                self.entry(dir, &raw);
+            }
+            self.entry(dir, &Self::short(short, 0x20, clusters[0], data.len() as u32));
+            clusters[0]
+        }
+
+        /// An empty directory; returns its cluster.
+        pub(crate) fn dir(&mut self, parent: Option<u16>, name: &str, short: &[u8; 11]) -> u16 {
+            let cluster = self.allocate(0)[0];
+            self.store(&[cluster], &[]);
+            for raw in Self::long(name, short) {
+                self.entry(parent, &raw);
+            }
+            self.entry(parent, &Self::short(short, ATTR_DIRECTORY, cluster, 0));
+            self.entry(Some(cluster), &Self::short(b".          ", ATTR_DIRECTORY, cluster, 0));
+            self.entry(Some(cluster), &Self::short(b"..         ", ATTR_DIRECTORY, 0, 0));
+            cluster
+        }
+    }
+
+    fn names(dir: Dir) -> Vec<std::string::String> {
+        dir.map(|entry| entry.name.as_str().into()).collect()
+    }
+
+    #[test]
+    fn geometry_aligns_data_to_clusters() {
+        let geometry = Geometry::new(SECTORS);
+        assert_eq!(geometry.fat_type(), FatType::Fat12);
+        assert_eq!(geometry.data_start() % SECTORS_PER_CLUSTER, 0);
+        assert!(geometry.clusters >= 500);
+
+        let large = Geometry::new(64 * 1024);
+        assert_eq!(large.fat_type(), FatType::Fat16);
+        assert_eq!(large.data_start() % SECTORS_PER_CLUSTER, 0);
+    }
+
+    #[test]
+    fn formatted_volume_mounts_empty() {
+        for sectors in [SECTORS, 64 * 1024, 128 * 1024] {
+            let image = formatted(sectors);
+            let volume = Volume::mount(&image).unwrap();
+            assert_eq!(volume.fat_type(), Geometry::new(sectors).fat_type());
+            assert_eq!(volume.root().count(), 0);
+        }
+        let image = formatted(128 * 1024);
+        assert_eq!(&image[32..36], &(128u32 * 1024).to_le_bytes());
+        assert_eq!(&image[19..21], &[0, 0]);
+    }
+
+    #[test]
+    fn mount_rejects_other_images() {
+        assert_eq!(Volume::mount(&[0u8; 100]).unwrap_err(), FatError::NotFat);
+        assert_eq!(Volume::mount(&vec![0xffu8; 4096]).unwrap_err(), FatError::NotFat);
+
+        // FAT32 has no FAT size in the FAT12/16 field
+        let mut image = formatted(SECTORS);
+        image[22..24].copy_from_slice(&[0, 0]);
+        assert_eq!(Volume::mount(&image).unwrap_err(), FatError::Unsupported);
+
+        // Claims more sectors than there are
+        let mut image = formatted(SECTORS);
+        image.truncate(SECTOR_SIZE * 100);
+        assert_eq!(Volume::mount(&image).unwrap_err(), FatError::Corrupt);
+    }
+
+    #[test]
+    fn reads_long_and_short_names() {
+        for sectors in [SECTORS, 64 * 1024] {
+            let mut image = formatted(sectors);
+            let mut writer = Writer::new(&mut image);
+            writer.file(None, "frame-100.bmp", b"FRAME-~1BMP", b"BM hundred");
+            writer.entry(None, &Writer::short(b"README  TXT", 0x20, 0, 0));
+            let mut lower = Writer::short(b"NOTES   MD ", 0x20, 0, 0);
+            lower[12] = LOWERCASE_BASE | LOWERCASE_EXT;
+            writer.entry(None, &lower);
+
+            let volume = Volume::mount(&image).unwrap();
+            assert_eq!(names(volume.root()), ["frame-100.bmp", "README.TXT", "notes.md"]);
+            let entry = volume.root().next().unwrap();
+            assert_eq!(volume.file(&entry).unwrap(), b"BM hundred");
+        }
+    }
+
+    #[test]
+    fn stale_long_name_is_ignored() {
+        let mut image = formatted(SECTORS);
+        let mut writer = Writer::new(&mut image);
+        // Long name left over from a deleted file, checksum of another short name
+        for raw in Writer::long("old-name.bmp", b"OLD-NA~1BMP") {
+            writer.entry(None, &raw);
+        }
+        writer.entry(None, &Writer::short(b"NEW     BMP", 0x20, 0, 0));
+
+        let volume = Volume::mount(&image).unwrap();
+        assert_eq!(names(volume.root()), ["NEW.BMP"]);
+    }
+
+    #[test]
+    fn skips_deleted_label_and_dot_entries() {
+        let mut image = formatted(SECTORS);
+        let mut writer = Writer::new(&mut image);
+        let mut deleted = Writer::short(b"GONE    BMP", 0x20, 0, 0);
+        deleted[0] = ENTRY_FREE;
+        writer.entry(None, &deleted);
+        let dir = writer.dir(None, "nooo", b"NOOO       ");
+        writer.file(Some(dir), "frame-0.bmp", b"FRAME-0 BMP", b"BM0");
+
+        let volume = Volume::mount(&image).unwrap();
+        let entries: Vec<Entry> = volume.root().collect();
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].is_dir());
+        assert_eq!(names(volume.dir(&entries[0])), ["frame-0.bmp"]);
+    }
+
+    #[test]
+    fn directory_follows_its_cluster_chain() {
+        let mut image = formatted(SECTORS);
+        let mut writer = Writer::new(&mut image);
+        let dir = writer.dir(None, "many", b"MANY       ");
+        // 4K clusters hold 128 entries; the third file's long name spills over
+        let extra = writer.allocate(0)[0];
+        writer.store(&[dir, extra], &[]);
+        let volume_offset = Volume::mount(writer.image).unwrap().cluster_offset(dir as u32);
+        let used = 2 * DIR_ENTRY_LEN;
+        let free = (4096 - used) / DIR_ENTRY_LEN;
+        for i in 0..free {
+            let offset = volume_offset + used + i * DIR_ENTRY_LEN;
+            let mut raw = Writer::short(b"X       BMP", 0x20, 0, 0);
+            raw[0] = ENTRY_FREE;
+            writer.image[offset..offset + DIR_ENTRY_LEN].copy_from_slice(&raw);
+        }
+        writer.file(Some(extra), "last.bmp", b"LAST    BMP", b"BM");
+
+        let volume = Volume::mount(&image).unwrap();
+        let entry = volume.root().next().unwrap();
+        assert_eq!(names(volume.dir(&entry)), ["last.bmp"]);
+    }
+
+    #[test]
+    fn looped_directory_chain_ends() {
+        let mut image = formatted(SECTORS);
+        let mut writer = Writer::new(&mut image);
+        let dir = writer.dir(None, "loop", b"LOOP       ");
+        writer.file(Some(dir), "a.bmp", b"A       BMP", b"BM");
+        let offset = writer.volume().cluster_offset(dir as u32);
+        for entry in writer.image[offset..offset + 4096].chunks_mut(DIR_ENTRY_LEN) {
+            if entry[0] == ENTRY_END {
+                entry[0] = ENTRY_FREE;
+            }
+        }
+        writer.set_fat(dir, dir);
+
+        let volume = Volume::mount(&image).unwrap();
+        let entry = volume.root().next().unwrap();
+        let names = names(volume.dir(&entry));
+        assert_eq!(names.len(), volume.clusters as usize);
+        assert!(names.iter().all(|name| name == "a.bmp"));
+    }
+
+    #[test]
+    fn files_must_be_contiguous() {
+        let mut image = formatted(SECTORS);
+        let mut writer = Writer::new(&mut image);
+        let data: Vec<u8> = (0..9000u32).map(|i| i as u8).collect();
+        writer.store(&[10, 11, 12], &data);
+        writer.entry(None, &Writer::short(b"WHOLE   BMP", 0x20, 10, data.len() as u32));
+        writer.store(&[20, 22, 21], &data);
+        writer.entry(None, &Writer::short(b"SPLIT   BMP", 0x20, 20, data.len() as u32));
+        writer.entry(None, &Writer::short(b"EMPTY   BMP", 0x20, 0, 0));
+        // Chain ends before the size does
+        writer.store(&[30], &data[..4096]);
+        writer.entry(None, &Writer::short(b"SHORT   BMP", 0x20, 30, data.len() as u32));
+
+        let volume = Volume::mount(&image).unwrap();
+        let results: Vec<Result<&[u8], FatError>> = volume.root().map(|entry| volume.file(&entry)).collect();
+        assert_eq!(results[0], Ok(&data[..]));
+        assert_eq!(results[1], Err(FatError::Fragmented));
+        assert_eq!(results[2], Ok(&[][..]));
+        assert_eq!(results[3], Err(FatError::Corrupt));
+    }
+}
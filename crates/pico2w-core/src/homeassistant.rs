@@ -0,0 +1,300 @@
+// file: homeassistant.rs
+// desc: Home Assistant MQTT discovery configs for the board's entities
+//
+// Each entity gets a retained config on
+// `<discovery prefix>/<component>/<node id>/<object id>/config` that points
+// Home Assistant at the board's own topics (see `mqtt`). Configs use the
+// abbreviated keys and `~` for the topic prefix to stay small.
+use core::fmt::Write as _;
+
+use heapless::{String, Vec};
+use serde::Serialize;
+
+use crate::command::Brightness;
+use crate::mqtt::{
+    MqttSettings, ANIMATION_TOPIC, BRIGHTNESS_SET_TOPIC, BRIGHTNESS_TOPIC, BUTTON_TOPIC, CLIENT_ID_LEN, COMMAND_TOPIC,
+    PRESS, RSSI_TOPIC, STATUS_TOPIC,
+};
+
+pub const DEFAULT_DISCOVERY_PREFIX: &str = "homeassistant";
+pub const DISCOVERY_PREFIX_LEN: usize = 32;
+/// Home Assistant publishes `online` here when it starts; configs are sent
+/// again then.
+pub const BIRTH_TOPIC: &str = "status";
+pub const BUTTON_COUNT: u8 = 4;
+/// Options the animation selector can list.
+pub const MAX_ANIMATIONS: usize = 16;
+/// Room for the largest config.
+pub const CONFIG_JSON_LEN: usize = 768;
+pub const CONFIG_TOPIC_LEN: usize = 128;
+
+/// The device every entity belongs to.
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceInfo<'a> {
+    /// Shown as the device name; the hostname.
+    pub name: &'a str,
+    pub sw_version: &'a str,
+    pub animation_count: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Entity {
+    /// `select` between the animations.
+    Animation,
+    /// `event` for one front-panel button, counted from 1.
+    Button(u8),
+    /// `number` slider over the contrast presets.
+    Brightness,
+    /// Diagnostic `sensor` for the WiFi signal.
+    Rssi,
+}
+
+#[derive(Serialize)]
+struct Device<'a> {
+    ids: [&'a str; 1],
+    name: &'a str,
+    mf: &'static str,
+    mdl: &'static str,
+    sw: &'a str,
+}
+
+#[derive(Serialize)]
+struct Config<'a> {
+    #[serde(rename = "~")]
+    base: &'a str,
+    name: &'a str,
+    uniq_id: &'a str,
+    avty_t: &'a str,
+    stat_t: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cmd_t: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ops: Option<Vec<String<3>, MAX_ANIMATIONS>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    evt_typ: Option<[&'static str; 1]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    val_tpl: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit_of_meas: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dev_cla: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stat_cla: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ent_cat: Option<&'static str>,
+    dev: Device<'a>,
+}
+
+impl Entity {
+    pub fn all() -> impl Iterator<Item = Entity> {
+        [Entity::Animation, Entity::Brightness, Entity::Rssi]
+            .into_iter()
+            .chain((1..=BUTTON_COUNT).map(Entity::Button))
+    }
+
+    fn component(&self) -> &'static str {
+        match self {
+            Entity::Animation => "select",
+            Entity::Button(_) => "event",
+            Entity::Brightness => "number",
+            Entity::Rssi => "sensor",
+        }
+    }
+
+    fn object_id(&self) -> String<16> {
+        let mut id = String::new();
+        match self {
+            Entity::Animation => id.push_str("animation").ok(),
+            Entity::Button(button) => write!(id, "button_{button}").ok(),
+            Entity::Brightness => id.push_str("brightness").ok(),
+            Entity::Rssi => id.push_str("rssi").ok(),
+        };
+        id
+    }
+
+    pub fn config_topic(&self, discovery_prefix: &str, settings: &MqttSettings) -> Option<String<CONFIG_TOPIC_LEN>> {
+        let mut topic = String::new();
+        let (component, node, object) = (self.component(), node_id(&settings.client_id), self.object_id());
+        write!(topic, "{discovery_prefix}/{component}/{node}/{object}/config").ok()?;
+        Some(topic)
+    }
+
+    /// The retained config JSON, in `buf`.
+    pub fn config<'b>(&self, settings: &MqttSettings, device: &DeviceInfo, buf: &'b mut [u8]) -> Option<&'b [u8]> {
+        let node = node_id(&settings.client_id);
+        let object = self.object_id();
+        let mut unique_id: String<48> = String::new();
+        write!(unique_id, "{node}_{object}").ok()?;
+        let mut name: String<16> = String::new();
+        let mut state: String<16> = String::new();
+        let mut command: String<24> = String::new();
+        write!(state, "~/").ok()?;
+        write!(command, "~/").ok()?;
+
+        let mut config = Config {
+            base: &settings.prefix,
+            name: "",
+            uniq_id: "",
+            avty_t: "",
+            stat_t: "",
+            cmd_t: None,
+            ops: None,
+            evt_typ: None,
+            val_tpl: None,
+            min: None,
+            max: None,
+            mode: None,
+            unit_of_meas: None,
+            dev_cla: None,
+            stat_cla: None,
+            ent_cat: None,
+            dev: Device {
+                ids: [&node],
+                name: device.name,
+                mf: "Raspberry Pi",
+                mdl: "Pico 2 W",
+                sw: device.sw_version,
+            },
+        };
+        match self {
+            Entity::Animation => {
+                name.push_str("Animation").ok()?;
+                state.push_str(ANIMATION_TOPIC).ok()?;
+                command.push_str(COMMAND_TOPIC).ok()?;
+                let mut options = Vec::new();
+                for animation in 1..=device.animation_count.min(MAX_ANIMATIONS as u8) {
+                    let mut option = String::new();
+                    write!(option, "{animation}").ok()?;
+                    options.push(option).ok()?;
+                }
+                config.ops = Some(options);
+            }
+            Entity::Button(button) => {
+                write!(name, "Button {button}").ok()?;
+                write!(state, "{BUTTON_TOPIC}/{button}").ok()?;
+                config.evt_typ = Some([PRESS]);
+                // Events want JSON; the board sends the bare event type
+                config.val_tpl = Some(r#"{"event_type":"{{ value }}"}"#);
+            }
+            Entity::Brightness => {
+                name.push_str("Brightness").ok()?;
+                state.push_str(BRIGHTNESS_TOPIC).ok()?;
+                command.push_str(BRIGHTNESS_SET_TOPIC).ok()?;
+                config.min = Some(1);
+                config.max = Some(Brightness::LEVELS);
+                config.mode = Some("slider");
+            }
+            Entity::Rssi => {
+                name.push_str("WiFi signal").ok()?;
+                state.push_str(RSSI_TOPIC).ok()?;
+                config.unit_of_meas = Some("dBm");
+                config.dev_cla = Some("signal_strength");
+                config.stat_cla = Some("measurement");
+                config.ent_cat = Some("diagnostic");
+            }
+        }
+        let mut availability: String<16> = String::new();
+        write!(availability, "~/{STATUS_TOPIC}").ok()?;
+        config.name = &name;
+        config.uniq_id = &unique_id;
+        config.avty_t = &availability;
+        config.stat_t = &state;
+        config.cmd_t = (command.len() > 2).then_some(command.as_str());
+
+        let len = serde_json_core::to_slice(&config, buf).ok()?;
+        Some(&buf[..len])
+    }
+}
+
+/// `<discovery prefix>/status`.
+pub fn birth_topic(discovery_prefix: &str) -> Option<String<CONFIG_TOPIC_LEN>> {
+    let mut topic = String::new();
+    write!(topic, "{discovery_prefix}/{BIRTH_TOPIC}").ok()?;
+    Some(topic)
+}
+
+// Discovery node IDs only allow letters, digits, `_` and `-`
+fn node_id(client_id: &str) -> String<CLIENT_ID_LEN> {
+    client_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::mqtt::MqttEnv;
+
+    const DEVICE: DeviceInfo<'static> = DeviceInfo { name: "pico2w", sw_version: "0.1.0", animation_count: 4 };
+
+    fn settings(client_id: Option<&'static str>) -> MqttSettings {
+        let env = MqttEnv { broker: Some("broker"), client_id, ..MqttEnv::default() };
+        MqttSettings::from_env(&env, "pico2w").unwrap().unwrap()
+    }
+
+    fn config(entity: Entity) -> std::string::String {
+        let mut buf = [0u8; CONFIG_JSON_LEN];
+        let json = entity.config(&settings(None), &DEVICE, &mut buf).unwrap();
+        std::string::String::from_utf8(json.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn topics() {
+        let settings = settings(Some("desk.oled"));
+        assert_eq!(
+            Entity::Button(2).config_topic(DEFAULT_DISCOVERY_PREFIX, &settings).unwrap().as_str(),
+            "homeassistant/event/desk_oled/button_2/config"
+        );
+        assert_eq!(birth_topic("ha").unwrap().as_str(), "ha/status");
+        assert_eq!(Entity::all().count(), 3 + BUTTON_COUNT as usize);
+    }
+
+    #[test]
+    fn select_config() {
+        assert_eq!(
+            config(Entity::Animation),
+            r#"{"~":"pico2w/pico2w","name":"Animation","uniq_id":"pico2w_animation","avty_t":"~/status","stat_t":"~/animation","cmd_t":"~/command","ops":["1","2","3","4"],"dev":{"ids":["pico2w"],"name":"pico2w","mf":"Raspberry Pi","mdl":"Pico 2 W","sw":"0.1.0"}}"#
+        );
+    }
+
+    #[test]
+    fn event_config() {
+        let json = config(Entity::Button(3));
+        assert!(json.contains(r#""stat_t":"~/button/3","evt_typ":["press"],"val_tpl":"{\"event_type\":\"{{ value }}\"}""#));
+        assert!(!json.contains("cmd_t"));
+    }
+
+    #[test]
+    fn number_and_sensor_configs() {
+        assert!(config(Entity::Brightness).contains(r#""cmd_t":"~/brightness/set","min":1,"max":5,"mode":"slider""#));
+        assert!(config(Entity::Rssi).contains(r#""unit_of_meas":"dBm","dev_cla":"signal_strength","stat_cla":"measurement","ent_cat":"diagnostic""#));
+    }
+
+    #[test]
+    fn largest_config_fits() {
+        let env = MqttEnv {
+            broker: Some("broker"),
+            client_id: Some("c".repeat(CLIENT_ID_LEN).leak()),
+            prefix: Some("p".repeat(crate::mqtt::PREFIX_LEN).leak()),
+            ..MqttEnv::default()
+        };
+        let settings = MqttSettings::from_env(&env, "pico2w").unwrap().unwrap();
+        let name = "h".repeat(32);
+        let device = DeviceInfo { name: &name, sw_version: "10.100.1000", animation_count: u8::MAX };
+        let mut buf = [0u8; CONFIG_JSON_LEN];
+        for entity in Entity::all() {
+            assert!(entity.config(&settings, &device, &mut buf).is_some(), "{entity:?} does not fit");
+            assert!(entity.config_topic(&"d".repeat(DISCOVERY_PREFIX_LEN), &settings).is_some());
+        }
+    }
+}
@@ -0,0 +1,11 @@
+// file: http.rs
+// desc: the embedded HTTP server's protocol handling
+mod buffer;
+mod request;
+mod response;
+mod router;
+
+pub use buffer::RequestBuffer;
+pub use request::{head_len, request_len, Header, HttpError, Method, Request, Version, MAX_HEADERS, MAX_QUERY_PARAMS, MAX_TARGET_LEN};
+pub use response::{ChunkedBody, ContentType, Response, Status, MAX_RESPONSE_HEADERS};
+pub use router::{allow_header, Params, Route, RouteError, Router, MAX_PARAMS};
@@ -0,0 +1,143 @@
+// file: buffer.rs
+// desc: per-connection receive buffer that survives keep-alive
+//
+// A client may send its next request before we answer the current one, so
+// after a request is served whatever followed it is moved to the front rather
+// than thrown away.
+use super::{head_len, request_len, HttpError, Request};
+
+pub struct RequestBuffer<const N: usize> {
+    buf: [u8; N],
+    filled: usize,
+}
+
+impl<const N: usize> RequestBuffer<N> {
+    pub const fn new() -> Self {
+        RequestBuffer { buf: [0; N], filled: 0 }
+    }
+
+    /// The free tail to read into; follow up with `commit`.
+    pub fn space(&mut self) -> &mut [u8] {
+        &mut self.buf[self.filled..]
+    }
+
+    pub fn commit(&mut self, read: usize) {
+        self.filled = (self.filled + read).min(N);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filled == 0
+    }
+
+    /// Length of the first request once it has fully arrived.
+    pub fn request_len(&self) -> Result<Option<usize>, HttpError> {
+        if self.filled == 0 {
+            return Ok(None);
+        }
+        request_len(&self.buf[..self.filled], N)
+    }
+
+    /// Parse the first `len` bytes, as reported by `request_len`.
+    pub fn parse(&mut self, len: usize) -> Result<Request<'_>, HttpError> {
+        Request::parse(&mut self.buf[..len])
+    }
+
+    /// Split off a request whose body is too large to buffer: its head, and
+    /// as much of the body as has arrived. The rest is still in the socket,
+    /// so the connection cannot be reused afterwards.
+    pub fn parse_streamed(&mut self) -> Result<(Request<'_>, &[u8]), HttpError> {
+        let head_len = head_len(&self.buf[..self.filled]).ok_or(HttpError::HeaderFieldsTooLarge)?;
+        let (head, body) = self.buf[..self.filled].split_at_mut(head_len);
+        Ok((Request::parse_head(head)?, body))
+    }
+
+    /// Drop a served request, keeping anything pipelined behind it.
+    pub fn consume(&mut self, len: usize) {
+        let len = len.min(self.filled);
+        self.buf.copy_within(len..self.filled, 0);
+        self.filled -= len;
+    }
+
+    pub fn clear(&mut self) {
+        self.filled = 0;
+    }
+}
+
+impl<const N: usize> Default for RequestBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Method;
+
+    fn receive<const N: usize>(buffer: &mut RequestBuffer<N>, bytes: &[u8]) {
+        buffer.space()[..bytes.len()].copy_from_slice(bytes);
+        buffer.commit(bytes.len());
+    }
+
+    #[test]
+    fn request_arrives_in_pieces() {
+        let mut buffer = RequestBuffer::<128>::new();
+        assert_eq!(buffer.request_len(), Ok(None));
+        receive(&mut buffer, b"GET /1 HTT");
+        assert_eq!(buffer.request_len(), Ok(None));
+        receive(&mut buffer, b"P/1.1\r\nHost: pico\r\n\r\n");
+        let len = buffer.request_len().unwrap().unwrap();
+        assert_eq!(buffer.parse(len).unwrap().path, "/1");
+        buffer.consume(len);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn pipelined_requests_are_kept() {
+        let mut buffer = RequestBuffer::<128>::new();
+        receive(&mut buffer, b"GET /a%20b HTTP/1.1\r\nHost: pico\r\n\r\nPOST /c HTTP/1.1\r\nHost: pico\r\nContent-Length: 2\r\n\r\nhi");
+
+        let first = buffer.request_len().unwrap().unwrap();
+        assert_eq!(buffer.parse(first).unwrap().path, "/a b");
+        buffer.consume(first);
+
+        let second = buffer.request_len().unwrap().unwrap();
+        {
+            let request = buffer.parse(second).unwrap();
+            assert_eq!(request.method, Method::Post);
+            assert_eq!(request.body, b"hi");
+        }
+        buffer.consume(second);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn partial_second_request_waits() {
+        let mut buffer = RequestBuffer::<128>::new();
+        receive(&mut buffer, b"GET / HTTP/1.1\r\nHost: pico\r\n\r\nGET /2 HTTP/1.1\r\n");
+        let first = buffer.request_len().unwrap().unwrap();
+        buffer.consume(first);
+        assert_eq!(buffer.request_len(), Ok(None));
+        receive(&mut buffer, b"Host: pico\r\n\r\n");
+        let second = buffer.request_len().unwrap().unwrap();
+        assert_eq!(buffer.parse(second).unwrap().path, "/2");
+    }
+
+    #[test]
+    fn streamed_body() {
+        let mut buffer = RequestBuffer::<128>::new();
+        receive(&mut buffer, b"POST /api/firmware HTTP/1.1\r\nHost: pico\r\nContent-Length: 4096\r\n\r\nabc");
+        assert_eq!(buffer.request_len(), Err(HttpError::PayloadTooLarge));
+        let (request, body) = buffer.parse_streamed().unwrap();
+        assert_eq!(request.content_length(), Some(4096));
+        assert_eq!(body, b"abc");
+    }
+
+    #[test]
+    fn full_buffer_is_rejected() {
+        let mut buffer = RequestBuffer::<32>::new();
+        receive(&mut buffer, b"GET / HTTP/1.1\r\nHost: a-very-lon");
+        assert!(buffer.space().is_empty());
+        assert_eq!(buffer.request_len(), Err(HttpError::HeaderFieldsTooLarge));
+    }
+}
@@ -0,0 +1,603 @@
+// file: request.rs
+// desc: streaming, zero-allocation HTTP/1.1 request parser
+//
+// Feed `request_len` everything received so far until it reports a complete
+// request, then hand the same bytes to `Request::parse`. The path and query
+// are percent-decoded in place, so the request borrows the receive buffer and
+// never copies.
+use heapless::Vec;
+
+use super::Status;
+
+pub const MAX_HEADERS: usize = 16;
+pub const MAX_QUERY_PARAMS: usize = 8;
+/// Longest request target (path plus query) we accept.
+pub const MAX_TARGET_LEN: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Patch,
+    Delete,
+    Options,
+}
+
+impl Method {
+    fn from_token(token: &[u8]) -> Option<Self> {
+        Some(match token {
+            b"GET" => Method::Get,
+            b"HEAD" => Method::Head,
+            b"POST" => Method::Post,
+            b"PUT" => Method::Put,
+            b"PATCH" => Method::Patch,
+            b"DELETE" => Method::Delete,
+            b"OPTIONS" => Method::Options,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Patch => "PATCH",
+            Method::Delete => "DELETE",
+            Method::Options => "OPTIONS",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Header<'a> {
+    pub name: &'a str,
+    pub value: &'a str,
+}
+
+/// Why a request was rejected. Each maps onto the status we answer with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HttpError {
+    BadRequest,
+    MethodNotAllowed,
+    PayloadTooLarge,
+    UriTooLong,
+    HeaderFieldsTooLarge,
+}
+
+impl HttpError {
+    pub fn status(self) -> Status {
+        match self {
+            HttpError::BadRequest => Status::BadRequest,
+            HttpError::MethodNotAllowed => Status::MethodNotAllowed,
+            HttpError::PayloadTooLarge => Status::PayloadTooLarge,
+            HttpError::UriTooLong => Status::UriTooLong,
+            HttpError::HeaderFieldsTooLarge => Status::HeaderFieldsTooLarge,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Request<'a> {
+    pub method: Method,
+    pub version: Version,
+    /// Percent-decoded path, always starting with `/`.
+    pub path: &'a str,
+    /// Percent-decoded `key=value` pairs, `+` read as a space.
+    pub query: Vec<(&'a str, &'a str), MAX_QUERY_PARAMS>,
+    pub headers: Vec<Header<'a>, MAX_HEADERS>,
+    pub body: &'a [u8],
+    /// Bytes the request occupied, head and body; anything after is the next
+    /// pipelined request.
+    pub len: usize,
+}
+
+impl<'a> Request<'a> {
+    /// Parse the complete request at the start of `buf`, decoding it in place.
+    pub fn parse(buf: &'a mut [u8]) -> Result<Self, HttpError> {
+        let capacity = buf.len();
+        let len = request_len(buf, capacity)?.ok_or(HttpError::BadRequest)?;
+        Self::parse_until(buf, len)
+    }
+
+    /// Parse just the head at the start of `buf`, for a body too large to
+    /// buffer that the handler reads from the socket itself. `body` is empty;
+    /// `content_length` says how much follows.
+    pub fn parse_head(buf: &'a mut [u8]) -> Result<Self, HttpError> {
+        let head_len = head_len(buf).ok_or(HttpError::BadRequest)?;
+        Self::parse_until(buf, head_len)
+    }
+
+    fn parse_until(buf: &'a mut [u8], len: usize) -> Result<Self, HttpError> {
+        let line = request_line(buf)?;
+
+        let (head, rest) = buf[..len].split_at_mut(line.head_len);
+        let body: &'a [u8] = rest;
+        let (line_bytes, header_bytes) = head.split_at_mut(line.end);
+        let (headers, _) = parse_headers(&header_bytes[2..])?;
+
+        let target = &mut line_bytes[line.target.0..line.target.1];
+        let (path, query) = match target.iter().position(|&b| b == b'?') {
+            Some(at) => {
+                let (path, query) = target.split_at_mut(at);
+                (path, Some(&mut query[1..]))
+            }
+            None => (target, None),
+        };
+
+        let mut params = Vec::new();
+        for pair in query.into_iter().flat_map(|q| q.split_mut(|&b| b == b'&')) {
+            if pair.is_empty() {
+                continue;
+            }
+            let (key, value) = match pair.iter().position(|&b| b == b'=') {
+                Some(at) => {
+                    let (key, value) = pair.split_at_mut(at);
+                    (key, decode_in_place(&mut value[1..], true)?)
+                }
+                None => (pair, ""),
+            };
+            let key = decode_in_place(key, true)?;
+            params.push((key, value)).map_err(|_| HttpError::UriTooLong)?;
+        }
+
+        Ok(Request {
+            method: line.method,
+            version: line.version,
+            path: decode_in_place(path, false)?,
+            query: params,
+            headers,
+            body,
+            len,
+        })
+    }
+
+    /// Look a header up by name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| header.value)
+    }
+
+    /// The declared body length, which may be more than `body` holds after
+    /// `parse_head`.
+    pub fn content_length(&self) -> Option<usize> {
+        self.header("content-length")?.parse().ok()
+    }
+
+    pub fn query_param(&self, name: &str) -> Option<&'a str> {
+        self.query.iter().find(|(key, _)| *key == name).map(|(_, value)| *value)
+    }
+
+    /// Whether the client wants the connection kept open after this request.
+    pub fn keep_alive(&self) -> bool {
+        match (self.version, self.header("connection")) {
+            (_, Some(value)) if value.eq_ignore_ascii_case("close") => false,
+            (_, Some(value)) if value.eq_ignore_ascii_case("keep-alive") => true,
+            (Version::Http11, _) => true,
+            (Version::Http10, _) => false,
+        }
+    }
+}
+
+/// Check the bytes received so far.
+///
+/// Returns `Ok(None)` while more data is needed and the total request length
+/// once head and body are in. `capacity` is the size of the receive buffer, so
+/// anything that could never fit is rejected early.
+pub fn request_len(received: &[u8], capacity: usize) -> Result<Option<usize>, HttpError> {
+    let full = received.len() >= capacity;
+
+    let Some(line_end) = find_crlf(received) else {
+        return if full || received.len() > MAX_TARGET_LEN + "OPTIONS  HTTP/1.1".len() {
+            Err(HttpError::UriTooLong)
+        } else {
+            Ok(None)
+        };
+    };
+    let (_, version, _) = parse_request_line(&received[..line_end])?;
+
+    let Some(head_len) = head_len(received) else {
+        return if full { Err(HttpError::HeaderFieldsTooLarge) } else { Ok(None) };
+    };
+
+    let (headers, content_length) = parse_headers(&received[line_end + 2..head_len])?;
+    if version == Version::Http11 && !headers.iter().any(|h| h.name.eq_ignore_ascii_case("host")) {
+        return Err(HttpError::BadRequest);
+    }
+
+    let total = head_len.checked_add(content_length).ok_or(HttpError::PayloadTooLarge)?;
+    if total > capacity {
+        Err(HttpError::PayloadTooLarge)
+    } else if total > received.len() {
+        Ok(None)
+    } else {
+        Ok(Some(total))
+    }
+}
+
+/// Length of the request head (request line and headers) once it has all
+/// arrived.
+pub fn head_len(received: &[u8]) -> Option<usize> {
+    find(received, b"\r\n\r\n").map(|at| at + 4)
+}
+
+struct RequestLine {
+    method: Method,
+    version: Version,
+    /// Byte range of the request target within the line.
+    target: (usize, usize),
+    /// Offset of the CRLF ending the line.
+    end: usize,
+    head_len: usize,
+}
+
+fn request_line(buf: &[u8]) -> Result<RequestLine, HttpError> {
+    let end = find_crlf(buf).ok_or(HttpError::BadRequest)?;
+    let head_len = head_len(buf).ok_or(HttpError::BadRequest)?;
+    let (method, version, target) = parse_request_line(&buf[..end])?;
+    Ok(RequestLine { method, version, target, end, head_len })
+}
+
+fn parse_request_line(line: &[u8]) -> Result<(Method, Version, (usize, usize)), HttpError> {
+    let mut parts = line.splitn(3, |&b| b == b' ');
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(HttpError::BadRequest);
+    };
+
+    let method = match Method::from_token(method) {
+        Some(method) => method,
+        None if !method.is_empty() && method.iter().all(u8::is_ascii_uppercase) => {
+            return Err(HttpError::MethodNotAllowed);
+        }
+        None => return Err(HttpError::BadRequest),
+    };
+
+    let version = match version {
+        b"HTTP/1.1" => Version::Http11,
+        b"HTTP/1.0" => Version::Http10,
+        _ => return Err(HttpError::BadRequest),
+    };
+
+    if target.len() > MAX_TARGET_LEN {
+        return Err(HttpError::UriTooLong);
+    }
+    if target.first() != Some(&b'/') || !target.iter().all(|b| b.is_ascii_graphic()) {
+        return Err(HttpError::BadRequest);
+    }
+
+    let start = method.as_str().len() + 1;
+    Ok((method, version, (start, start + target.len())))
+}
+
+/// Parse the header lines (each ending in CRLF, the blank line included or not)
+/// and pull out the body length.
+fn parse_headers(block: &[u8]) -> Result<(Vec<Header<'_>, MAX_HEADERS>, usize), HttpError> {
+    let mut headers: Vec<Header<'_>, MAX_HEADERS> = Vec::new();
+    let mut content_length = None;
+
+    for line in block.split(|&b| b == b'\n') {
+        let line = match line {
+            [] | [b'\r'] => continue,
+            [line @ .., b'\r'] => line,
+            _ => return Err(HttpError::BadRequest),
+        };
+
+        // Obsolete line folding is not worth supporting
+        if matches!(line.first(), Some(b' ' | b'\t')) {
+            return Err(HttpError::BadRequest);
+        }
+
+        let colon = line.iter().position(|&b| b == b':').ok_or(HttpError::BadRequest)?;
+        let name = &line[..colon];
+        if name.is_empty() || !name.iter().all(|&b| is_token(b)) {
+            return Err(HttpError::BadRequest);
+        }
+        let value = core::str::from_utf8(&line[colon + 1..])
+            .map_err(|_| HttpError::BadRequest)?
+            .trim_matches([' ', '\t']);
+        // Token characters are ASCII, so this cannot fail
+        let name = core::str::from_utf8(name).map_err(|_| HttpError::BadRequest)?;
+
+        if name.eq_ignore_ascii_case("content-length") {
+            let length: usize = value.parse().map_err(|_| HttpError::BadRequest)?;
+            if content_length.is_some_and(|previous| previous != length) {
+                return Err(HttpError::BadRequest);
+            }
+            content_length = Some(length);
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            // Chunked request bodies are not supported
+            return Err(HttpError::BadRequest);
+        }
+
+        headers.push(Header { name, value }).map_err(|_| HttpError::HeaderFieldsTooLarge)?;
+    }
+
+    Ok((headers, content_length.unwrap_or(0)))
+}
+
+fn is_token(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn find_crlf(haystack: &[u8]) -> Option<usize> {
+    find(haystack, b"\r\n")
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Percent-decode `bytes` in place and return the decoded prefix.
+fn decode_in_place(bytes: &mut [u8], plus_as_space: bool) -> Result<&str, HttpError> {
+    let mut read = 0;
+    let mut write = 0;
+    while read < bytes.len() {
+        let decoded = match bytes[read] {
+            b'%' => {
+                let hi = bytes.get(read + 1).copied().and_then(hex_value);
+                let lo = bytes.get(read + 2).copied().and_then(hex_value);
+                let (Some(hi), Some(lo)) = (hi, lo) else {
+                    return Err(HttpError::BadRequest);
+                };
+                read += 2;
+                hi << 4 | lo
+            }
+            b'+' if plus_as_space => b' ',
+            b => b,
+        };
+        bytes[write] = decoded;
+        write += 1;
+        read += 1;
+    }
+
+    core::str::from_utf8(&bytes[..write]).map_err(|_| HttpError::BadRequest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAPACITY: usize = 512;
+
+    fn parse_str(raw: &str, f: impl FnOnce(Request)) {
+        let mut buf = [0u8; CAPACITY];
+        buf[..raw.len()].copy_from_slice(raw.as_bytes());
+        assert_eq!(request_len(&buf[..raw.len()], CAPACITY), Ok(Some(raw.len())));
+        f(Request::parse(&mut buf[..raw.len()]).unwrap());
+    }
+
+    fn rejected(raw: &str) -> HttpError {
+        request_len(raw.as_bytes(), CAPACITY).unwrap_err()
+    }
+
+    #[test]
+    fn simple_get() {
+        parse_str("GET /1 HTTP/1.1\r\nHost: pico\r\nAccept: */*\r\n\r\n", |request| {
+            assert_eq!(request.method, Method::Get);
+            assert_eq!(request.version, Version::Http11);
+            assert_eq!(request.path, "/1");
+            assert!(request.query.is_empty());
+            assert_eq!(request.headers.len(), 2);
+            assert_eq!(request.header("HOST"), Some("pico"));
+            assert_eq!(request.header("accept"), Some("*/*"));
+            assert_eq!(request.body, b"");
+        });
+    }
+
+    #[test]
+    fn head_of_a_large_upload() {
+        let mut raw = *b"POST /api/firmware HTTP/1.1\r\nHost: pico\r\nContent-Length: 600000\r\n\r\n\x00\x10";
+        assert_eq!(request_len(&raw, CAPACITY), Err(HttpError::PayloadTooLarge));
+        let head = head_len(&raw).unwrap();
+        assert_eq!(head, raw.len() - 2);
+        let request = Request::parse_head(&mut raw).unwrap();
+        assert_eq!(request.path, "/api/firmware");
+        assert_eq!(request.body, b"");
+        assert_eq!(request.len, head);
+        assert_eq!(request.content_length(), Some(600_000));
+    }
+
+    #[test]
+    fn byte_at_a_time() {
+        let raw = b"PUT /api HTTP/1.1\r\nHost: pico\r\nContent-Length: 5\r\n\r\nhello";
+        for end in 0..raw.len() {
+            assert_eq!(request_len(&raw[..end], CAPACITY), Ok(None), "after {end} bytes");
+        }
+        assert_eq!(request_len(raw, CAPACITY), Ok(Some(raw.len())));
+    }
+
+    #[test]
+    fn body_and_pipelined_leftover() {
+        let raw = "POST /echo HTTP/1.1\r\nHost: pico\r\nContent-Length: 3\r\n\r\nabcGET / HTTP/1.1\r\n";
+        let mut buf = [0u8; CAPACITY];
+        buf[..raw.len()].copy_from_slice(raw.as_bytes());
+        let request = Request::parse(&mut buf[..raw.len()]).unwrap();
+        assert_eq!(request.method, Method::Post);
+        assert_eq!(request.body, b"abc");
+        assert_eq!(request.len, raw.find("GET").unwrap());
+    }
+
+    #[test]
+    fn percent_decoded_path_and_query() {
+        parse_str("GET /a%20b/c%2Fd?value=3&name=hello+world&msg=50%25&flag HTTP/1.1\r\nHost: x\r\n\r\n", |request| {
+            assert_eq!(request.path, "/a b/c/d");
+            assert_eq!(request.query_param("value"), Some("3"));
+            assert_eq!(request.query_param("name"), Some("hello world"));
+            assert_eq!(request.query_param("msg"), Some("50%"));
+            assert_eq!(request.query_param("flag"), Some(""));
+            assert_eq!(request.query_param("missing"), None);
+        });
+    }
+
+    #[test]
+    fn plus_is_literal_in_path() {
+        parse_str("GET /a+b?k=a%2Bb HTTP/1.1\r\nHost: x\r\n\r\n", |request| {
+            assert_eq!(request.path, "/a+b");
+            assert_eq!(request.query_param("k"), Some("a+b"));
+        });
+    }
+
+    #[test]
+    fn empty_query_pairs_are_skipped() {
+        parse_str("GET /?&&a=1& HTTP/1.1\r\nHost: x\r\n\r\n", |request| {
+            assert_eq!(request.path, "/");
+            assert_eq!(request.query.as_slice(), &[("a", "1")]);
+        });
+    }
+
+    #[test]
+    fn header_whitespace_is_trimmed() {
+        parse_str("GET / HTTP/1.1\r\nHost:   pico \t\r\nX-Empty:\r\n\r\n", |request| {
+            assert_eq!(request.header("host"), Some("pico"));
+            assert_eq!(request.header("x-empty"), Some(""));
+        });
+    }
+
+    #[test]
+    fn http10_needs_no_host() {
+        parse_str("GET / HTTP/1.0\r\n\r\n", |request| {
+            assert_eq!(request.version, Version::Http10);
+            assert!(!request.keep_alive());
+        });
+    }
+
+    #[test]
+    fn keep_alive_rules() {
+        parse_str("GET / HTTP/1.1\r\nHost: x\r\n\r\n", |request| assert!(request.keep_alive()));
+        parse_str("GET / HTTP/1.1\r\nHost: x\r\nConnection: Close\r\n\r\n", |request| assert!(!request.keep_alive()));
+        parse_str("GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n", |request| assert!(request.keep_alive()));
+    }
+
+    #[test]
+    fn bad_request_line() {
+        assert_eq!(rejected("GET\r\n\r\n"), HttpError::BadRequest);
+        assert_eq!(rejected("GET /\r\n\r\n"), HttpError::BadRequest);
+        assert_eq!(rejected("GET / HTTP/2.0\r\n\r\n"), HttpError::BadRequest);
+        assert_eq!(rejected("GET index.html HTTP/1.1\r\n\r\n"), HttpError::BadRequest);
+        assert_eq!(rejected("GET  / HTTP/1.1\r\n\r\n"), HttpError::BadRequest);
+        assert_eq!(rejected("get / HTTP/1.1\r\n\r\n"), HttpError::BadRequest);
+    }
+
+    #[test]
+    fn bad_request_line_is_rejected_before_headers_arrive() {
+        assert_eq!(rejected("GARBAGE\r\nHost"), HttpError::BadRequest);
+    }
+
+    #[test]
+    fn unknown_method() {
+        assert_eq!(rejected("BREW /pot HTTP/1.1\r\n\r\n"), HttpError::MethodNotAllowed);
+        assert_eq!(rejected("TRACE / HTTP/1.1\r\nHost: x\r\n\r\n"), HttpError::MethodNotAllowed);
+    }
+
+    #[test]
+    fn bad_headers() {
+        assert_eq!(rejected("GET / HTTP/1.1\r\n\r\n"), HttpError::BadRequest);
+        assert_eq!(rejected("GET / HTTP/1.1\r\nHost x\r\n\r\n"), HttpError::BadRequest);
+        assert_eq!(rejected("GET / HTTP/1.1\r\nHo st: x\r\n\r\n"), HttpError::BadRequest);
+        assert_eq!(rejected("GET / HTTP/1.1\r\nHost: x\r\n folded\r\n\r\n"), HttpError::BadRequest);
+        assert_eq!(rejected("GET / HTTP/1.1\r\nHost: x\nAccept: y\r\n\r\n"), HttpError::BadRequest);
+    }
+
+    #[test]
+    fn bad_content_length() {
+        assert_eq!(rejected("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: abc\r\n\r\n"), HttpError::BadRequest);
+        assert_eq!(rejected("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: -1\r\n\r\n"), HttpError::BadRequest);
+        assert_eq!(
+            rejected("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n"),
+            HttpError::BadRequest
+        );
+        assert_eq!(
+            rejected("POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n"),
+            HttpError::BadRequest
+        );
+    }
+
+    #[test]
+    fn bad_percent_encoding() {
+        for target in ["/%zz", "/%4", "/?a=%", "/%ff"] {
+            let raw = format_request(target);
+            let mut buf = [0u8; CAPACITY];
+            buf[..raw.len()].copy_from_slice(raw.as_bytes());
+            assert_eq!(Request::parse(&mut buf[..raw.len()]).unwrap_err(), HttpError::BadRequest, "{target}");
+        }
+    }
+
+    fn format_request(target: &str) -> heapless::String<CAPACITY> {
+        let mut raw = heapless::String::new();
+        for part in ["GET ", target, " HTTP/1.1\r\nHost: x\r\n\r\n"] {
+            raw.push_str(part).unwrap();
+        }
+        raw
+    }
+
+    #[test]
+    fn body_too_large() {
+        assert_eq!(
+            rejected("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 100000\r\n\r\n"),
+            HttpError::PayloadTooLarge
+        );
+        assert_eq!(
+            rejected("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 99999999999999999999999\r\n\r\n"),
+            HttpError::BadRequest
+        );
+    }
+
+    #[test]
+    fn target_too_long() {
+        let long = [b'a'; MAX_TARGET_LEN];
+        let long = core::str::from_utf8(&long).unwrap();
+        let mut target = heapless::String::<{ MAX_TARGET_LEN + 1 }>::new();
+        target.push('/').unwrap();
+        target.push_str(long).unwrap();
+        assert_eq!(rejected(&format_request(&target)), HttpError::UriTooLong);
+
+        // Still waiting for the end of the line, but it can no longer be short enough
+        let mut partial = heapless::String::<CAPACITY>::new();
+        partial.push_str("GET /").unwrap();
+        partial.push_str(long).unwrap();
+        partial.push_str("aaaaaaaaaaaaaaaaaaaa").unwrap();
+        assert_eq!(rejected(&partial), HttpError::UriTooLong);
+    }
+
+    #[test]
+    fn buffer_full_without_end_of_line() {
+        assert_eq!(request_len(b"GET /abcdef", 11), Err(HttpError::UriTooLong));
+    }
+
+    #[test]
+    fn headers_too_large() {
+        assert_eq!(request_len(b"GET / HTTP/1.1\r\nHost: x\r\nX-Long: aaaa", 36), Err(HttpError::HeaderFieldsTooLarge));
+
+        let mut raw = heapless::String::<CAPACITY>::new();
+        raw.push_str("GET / HTTP/1.1\r\n").unwrap();
+        for _ in 0..=MAX_HEADERS {
+            raw.push_str("A: b\r\n").unwrap();
+        }
+        raw.push_str("\r\n").unwrap();
+        assert_eq!(rejected(&raw), HttpError::HeaderFieldsTooLarge);
+    }
+}
@@ -0,0 +1,383 @@
+// file: response.rs
+// desc: response builder that writes straight to the socket
+//
+// A `Response` only describes the status line and headers. `send` writes them
+// with a Content-Length body; `send_chunked` writes them and returns a
+// `ChunkedBody` for bodies whose size is not known up front. Everything goes
+// out through `write_all`, so a full socket buffer never truncates a response.
+use core::fmt::Write as _;
+
+use embedded_io_async::Write;
+use heapless::{String, Vec};
+
+pub const MAX_RESPONSE_HEADERS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Status {
+    SwitchingProtocols,
+    Ok,
+    Created,
+    Accepted,
+    NoContent,
+    Found,
+    NotModified,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    Conflict,
+    LengthRequired,
+    PayloadTooLarge,
+    UriTooLong,
+    UnsupportedMediaType,
+    UnprocessableContent,
+    HeaderFieldsTooLarge,
+    InternalServerError,
+    ServiceUnavailable,
+}
+
+impl Status {
+    pub fn code(self) -> u16 {
+        match self {
+            Status::SwitchingProtocols => 101,
+            Status::Ok => 200,
+            Status::Created => 201,
+            Status::Accepted => 202,
+            Status::NoContent => 204,
+            Status::Found => 302,
+            Status::NotModified => 304,
+            Status::BadRequest => 400,
+            Status::Unauthorized => 401,
+            Status::Forbidden => 403,
+            Status::NotFound => 404,
+            Status::MethodNotAllowed => 405,
+            Status::Conflict => 409,
+            Status::LengthRequired => 411,
+            Status::PayloadTooLarge => 413,
+            Status::UriTooLong => 414,
+            Status::UnsupportedMediaType => 415,
+            Status::UnprocessableContent => 422,
+            Status::HeaderFieldsTooLarge => 431,
+            Status::InternalServerError => 500,
+            Status::ServiceUnavailable => 503,
+        }
+    }
+
+    pub fn reason(self) -> &'static str {
+        match self {
+            Status::SwitchingProtocols => "Switching Protocols",
+            Status::Ok => "OK",
+            Status::Created => "Created",
+            Status::Accepted => "Accepted",
+            Status::NoContent => "No Content",
+            Status::Found => "Found",
+            Status::NotModified => "Not Modified",
+            Status::BadRequest => "Bad Request",
+            Status::Unauthorized => "Unauthorized",
+            Status::Forbidden => "Forbidden",
+            Status::NotFound => "Not Found",
+            Status::MethodNotAllowed => "Method Not Allowed",
+            Status::Conflict => "Conflict",
+            Status::LengthRequired => "Length Required",
+            Status::PayloadTooLarge => "Payload Too Large",
+            Status::UriTooLong => "URI Too Long",
+            Status::UnsupportedMediaType => "Unsupported Media Type",
+            Status::UnprocessableContent => "Unprocessable Content",
+            Status::HeaderFieldsTooLarge => "Request Header Fields Too Large",
+            Status::InternalServerError => "Internal Server Error",
+            Status::ServiceUnavailable => "Service Unavailable",
+        }
+    }
+
+    /// 1xx, 204 and 304 responses never carry a body or Content-Length.
+    fn allows_body(self) -> bool {
+        !matches!(self, Status::SwitchingProtocols | Status::NoContent | Status::NotModified)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ContentType {
+    Text,
+    Html,
+    Json,
+    Png,
+    Pbm,
+    EventStream,
+    OctetStream,
+}
+
+impl ContentType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ContentType::Text => "text/plain; charset=utf-8",
+            ContentType::Html => "text/html; charset=utf-8",
+            ContentType::Json => "application/json",
+            ContentType::Png => "image/png",
+            ContentType::Pbm => "image/x-portable-bitmap",
+            ContentType::EventStream => "text/event-stream",
+            ContentType::OctetStream => "application/octet-stream",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Response<'a> {
+    status: Status,
+    content_type: Option<ContentType>,
+    headers: Vec<(&'a str, &'a str), MAX_RESPONSE_HEADERS>,
+    keep_alive: bool,
+    head_only: bool,
+}
+
+impl<'a> Response<'a> {
+    /// A response that closes the connection after it is sent.
+    pub fn new(status: Status) -> Self {
+        Response {
+            status,
+            content_type: None,
+            headers: Vec::new(),
+            keep_alive: false,
+            head_only: false,
+        }
+    }
+
+    pub fn ok() -> Self {
+        Self::new(Status::Ok)
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    pub fn content_type(mut self, content_type: ContentType) -> Self {
+        self.content_type = Some(content_type);
+        self
+    }
+
+    /// Add a header. Past `MAX_RESPONSE_HEADERS` further headers are dropped.
+    pub fn header(mut self, name: &'a str, value: &'a str) -> Self {
+        debug_assert!(!self.headers.is_full(), "too many response headers");
+        self.headers.push((name, value)).ok();
+        self
+    }
+
+    /// Answer with `Connection: keep-alive` instead of `close`.
+    pub fn keep_alive(mut self, keep_alive: bool) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Send only the head, as a HEAD request expects; Content-Length still
+    /// describes the body a GET would have returned.
+    pub fn head_only(mut self, head_only: bool) -> Self {
+        self.head_only = head_only;
+        self
+    }
+
+    /// Write the head and `body` in full.
+    pub async fn send<W: Write>(self, out: &mut W, body: &[u8]) -> Result<(), W::Error> {
+        let body = if self.status.allows_body() { body } else { &[] };
+        self.write_head(out, Some(body.len())).await?;
+        if !self.head_only {
+            out.write_all(body).await?;
+        }
+        out.flush().await
+    }
+
+    pub async fn send_text<W: Write>(self, out: &mut W, text: &str) -> Result<(), W::Error> {
+        self.content_type(ContentType::Text).send(out, text.as_bytes()).await
+    }
+
+    /// Write the head for a body of unknown length and hand back the writer
+    /// for its chunks.
+    pub async fn send_chunked<'w, W: Write>(self, out: &'w mut W) -> Result<ChunkedBody<'w, W>, W::Error> {
+        let head_only = self.head_only;
+        self.write_head(out, None).await?;
+        Ok(ChunkedBody { out, head_only })
+    }
+
+    async fn write_head<W: Write>(&self, out: &mut W, content_length: Option<usize>) -> Result<(), W::Error> {
+        let mut status_line: String<64> = String::new();
+        write!(status_line, "HTTP/1.1 {} {}\r\n", self.status.code(), self.status.reason()).ok();
+        out.write_all(status_line.as_bytes()).await?;
+
+        if let Some(content_type) = self.content_type {
+            write_header(out, "Content-Type", content_type.as_str()).await?;
+        }
+        for (name, value) in &self.headers {
+            write_header(out, name, value).await?;
+        }
+
+        if self.status.allows_body() {
+            match content_length {
+                Some(len) => {
+                    let mut digits: String<20> = String::new();
+                    write!(digits, "{}", len).ok();
+                    write_header(out, "Content-Length", &digits).await?;
+                }
+                None => write_header(out, "Transfer-Encoding", "chunked").await?,
+            }
+        }
+
+        // A 101 hands the connection to another protocol; it has no say here
+        if self.status != Status::SwitchingProtocols {
+            let connection = if self.keep_alive { "keep-alive" } else { "close" };
+            write_header(out, "Connection", connection).await?;
+        }
+        out.write_all(b"\r\n").await
+    }
+}
+
+async fn write_header<W: Write>(out: &mut W, name: &str, value: &str) -> Result<(), W::Error> {
+    out.write_all(name.as_bytes()).await?;
+    out.write_all(b": ").await?;
+    out.write_all(value.as_bytes()).await?;
+    out.write_all(b"\r\n").await
+}
+
+/// The body of a `Transfer-Encoding: chunked` response. Call `finish` to
+/// write the terminating chunk; dropping it early leaves the body truncated.
+pub struct ChunkedBody<'w, W: Write> {
+    out: &'w mut W,
+    head_only: bool,
+}
+
+impl<W: Write> ChunkedBody<'_, W> {
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), W::Error> {
+        // An empty chunk would end the body
+        if data.is_empty() || self.head_only {
+            return Ok(());
+        }
+        let mut size: String<18> = String::new();
+        write!(size, "{:x}\r\n", data.len()).ok();
+        self.out.write_all(size.as_bytes()).await?;
+        self.out.write_all(data).await?;
+        self.out.write_all(b"\r\n").await
+    }
+
+    pub async fn finish(self) -> Result<(), W::Error> {
+        if !self.head_only {
+            self.out.write_all(b"0\r\n\r\n").await?;
+        }
+        self.out.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+
+    /// Accepts at most `limit` bytes per `write`, like a nearly full socket.
+    struct Sink {
+        data: Vec<u8, 1024>,
+        limit: usize,
+    }
+
+    impl Sink {
+        fn new(limit: usize) -> Self {
+            Sink { data: Vec::new(), limit }
+        }
+
+        fn as_str(&self) -> &str {
+            core::str::from_utf8(&self.data).unwrap()
+        }
+    }
+
+    impl embedded_io_async::ErrorType for Sink {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Write for Sink {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            let len = buf.len().min(self.limit);
+            self.data.extend_from_slice(&buf[..len]).unwrap();
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn fixed_length_body() {
+        let mut sink = Sink::new(usize::MAX);
+        block_on(Response::ok().content_type(ContentType::Html).send(&mut sink, b"<h1>hi</h1>")).unwrap();
+        assert_eq!(
+            sink.as_str(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: 11\r\nConnection: close\r\n\r\n<h1>hi</h1>"
+        );
+    }
+
+    #[test]
+    fn short_writes_do_not_truncate() {
+        let mut sink = Sink::new(3);
+        let body = [b'x'; 300];
+        block_on(Response::ok().send(&mut sink, &body)).unwrap();
+        let (head, sent) = sink.as_str().split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("Content-Length: 300"));
+        assert_eq!(sent.len(), 300);
+    }
+
+    #[test]
+    fn extra_headers_and_keep_alive() {
+        let mut sink = Sink::new(usize::MAX);
+        let response = Response::new(Status::MethodNotAllowed).header("Allow", "GET, HEAD").keep_alive(true);
+        block_on(response.send_text(&mut sink, "nope")).unwrap();
+        assert_eq!(
+            sink.as_str(),
+            "HTTP/1.1 405 Method Not Allowed\r\nContent-Type: text/plain; charset=utf-8\r\nAllow: GET, HEAD\r\nContent-Length: 4\r\nConnection: keep-alive\r\n\r\nnope"
+        );
+    }
+
+    #[test]
+    fn head_only_keeps_length() {
+        let mut sink = Sink::new(usize::MAX);
+        block_on(Response::ok().head_only(true).send(&mut sink, b"hello")).unwrap();
+        assert_eq!(sink.as_str(), "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\n");
+    }
+
+    #[test]
+    fn no_content_has_no_length() {
+        let mut sink = Sink::new(usize::MAX);
+        block_on(Response::new(Status::NoContent).send(&mut sink, b"ignored")).unwrap();
+        assert_eq!(sink.as_str(), "HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n");
+    }
+
+    #[test]
+    fn chunked_body() {
+        let mut sink = Sink::new(usize::MAX);
+        block_on(async {
+            let mut body = Response::ok().content_type(ContentType::Text).send_chunked(&mut sink).await?;
+            body.write(b"hello, ").await?;
+            body.write(b"").await?;
+            body.write(&[b'z'; 26]).await?;
+            body.finish().await
+        })
+        .unwrap();
+        assert_eq!(
+            sink.as_str(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+             7\r\nhello, \r\n1a\r\nzzzzzzzzzzzzzzzzzzzzzzzzzz\r\n0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn error_statuses_match_their_codes() {
+        use crate::http::HttpError;
+        for (error, code) in [
+            (HttpError::BadRequest, 400),
+            (HttpError::MethodNotAllowed, 405),
+            (HttpError::PayloadTooLarge, 413),
+            (HttpError::UriTooLong, 414),
+            (HttpError::HeaderFieldsTooLarge, 431),
+        ] {
+            assert_eq!(error.status().code(), code);
+            let mut sink = Sink::new(usize::MAX);
+            block_on(Response::new(error.status()).send_text(&mut sink, error.status().reason())).unwrap();
+            let (head, body) = sink.as_str().split_once("\r\n\r\n").unwrap();
+            assert!(head.starts_with("HTTP/1.1 4"));
+            assert_eq!(body, error.status().reason());
+        }
+    }
+}
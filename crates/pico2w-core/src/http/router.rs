@@ -0,0 +1,240 @@
+// file: router.rs
+// desc: static route table keyed on method and path pattern
+//
+// Patterns are matched segment by segment. A `:name` segment captures one path
+// segment and a trailing `*` captures the rest of the path. The handler type
+// is up to the caller: a fn pointer, or an enum its server matches on.
+use heapless::Vec;
+
+use super::{Method, Status};
+
+/// Most captures one pattern may hold.
+pub const MAX_PARAMS: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Route<H> {
+    pub method: Method,
+    pub pattern: &'static str,
+    pub handler: H,
+}
+
+impl<H> Route<H> {
+    pub const fn new(method: Method, pattern: &'static str, handler: H) -> Self {
+        Route { method, pattern, handler }
+    }
+
+    /// Also answers HEAD requests.
+    pub const fn get(pattern: &'static str, handler: H) -> Self {
+        Self::new(Method::Get, pattern, handler)
+    }
+
+    pub const fn post(pattern: &'static str, handler: H) -> Self {
+        Self::new(Method::Post, pattern, handler)
+    }
+
+    pub const fn put(pattern: &'static str, handler: H) -> Self {
+        Self::new(Method::Put, pattern, handler)
+    }
+
+    pub const fn delete(pattern: &'static str, handler: H) -> Self {
+        Self::new(Method::Delete, pattern, handler)
+    }
+
+    fn accepts(&self, method: Method) -> bool {
+        self.method == method || (self.method == Method::Get && method == Method::Head)
+    }
+}
+
+/// Path segments captured by `:name` and `*`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Params<'a> {
+    captures: Vec<(&'static str, &'a str), MAX_PARAMS>,
+}
+
+impl<'a> Params<'a> {
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.captures.iter().find(|(key, _)| *key == name).map(|&(_, value)| value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteError {
+    NotFound,
+    /// The path exists but not for this method; `allow` lists the ones it does
+    /// take, for the Allow header.
+    MethodNotAllowed { allow: Vec<Method, 8> },
+}
+
+impl RouteError {
+    pub fn status(&self) -> Status {
+        match self {
+            RouteError::NotFound => Status::NotFound,
+            RouteError::MethodNotAllowed { .. } => Status::MethodNotAllowed,
+        }
+    }
+}
+
+pub struct Router<'r, H> {
+    routes: &'r [Route<H>],
+}
+
+impl<'r, H> Router<'r, H> {
+    pub const fn new(routes: &'r [Route<H>]) -> Self {
+        Router { routes }
+    }
+
+    /// Find the first route matching `method` and `path`. Order matters: put
+    /// literal patterns ahead of captures that would also match them.
+    pub fn resolve<'a>(&self, method: Method, path: &'a str) -> Result<(&'r H, Params<'a>), RouteError> {
+        let mut allow: Vec<Method, 8> = Vec::new();
+        for route in self.routes {
+            let Some(params) = match_pattern(route.pattern, path) else {
+                continue;
+            };
+            if route.accepts(method) {
+                return Ok((&route.handler, params));
+            }
+            for allowed in [Some(route.method), (route.method == Method::Get).then_some(Method::Head)].into_iter().flatten() {
+                if !allow.contains(&allowed) {
+                    allow.push(allowed).ok();
+                }
+            }
+        }
+
+        if allow.is_empty() {
+            Err(RouteError::NotFound)
+        } else {
+            Err(RouteError::MethodNotAllowed { allow })
+        }
+    }
+}
+
+fn match_pattern<'a>(pattern: &'static str, path: &'a str) -> Option<Params<'a>> {
+    let mut params = Params::default();
+    let mut pattern_segments = pattern.trim_start_matches('/').split('/');
+    let mut rest = path.trim_start_matches('/');
+
+    loop {
+        match pattern_segments.next() {
+            Some("*") => {
+                params.captures.push(("*", rest)).ok()?;
+                return Some(params);
+            }
+            Some(expected) => {
+                let (segment, tail) = rest.split_once('/').map_or((rest, None), |(s, t)| (s, Some(t)));
+                if let Some(name) = expected.strip_prefix(':') {
+                    if segment.is_empty() {
+                        return None;
+                    }
+                    params.captures.push((name, segment)).ok()?;
+                } else if segment != expected {
+                    return None;
+                }
+                match tail {
+                    Some(tail) => rest = tail,
+                    // Path used up; the pattern must be too
+                    None => return pattern_segments.next().is_none().then_some(params),
+                }
+            }
+            None => return None,
+        }
+    }
+}
+
+/// Join methods as an Allow header value, e.g. `GET, HEAD`.
+pub fn allow_header(methods: &[Method]) -> heapless::String<64> {
+    let mut value = heapless::String::new();
+    for (i, method) in methods.iter().enumerate() {
+        if i > 0 {
+            value.push_str(", ").ok();
+        }
+        value.push_str(method.as_str()).ok();
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Page {
+        Index,
+        Status,
+        SetStatus,
+        Animation,
+        Asset,
+    }
+
+    const ROUTES: &[Route<Page>] = &[
+        Route::get("/", Page::Index),
+        Route::get("/api/status", Page::Status),
+        Route::put("/api/status", Page::SetStatus),
+        Route::get("/api/animations/:id", Page::Animation),
+        Route::get("/static/*", Page::Asset),
+    ];
+    const ROUTER: Router<Page> = Router::new(ROUTES);
+
+    fn page(method: Method, path: &str) -> Result<Page, RouteError> {
+        ROUTER.resolve(method, path).map(|(page, _)| *page)
+    }
+
+    #[test]
+    fn literal_paths() {
+        assert_eq!(page(Method::Get, "/"), Ok(Page::Index));
+        assert_eq!(page(Method::Get, "/api/status"), Ok(Page::Status));
+        assert_eq!(page(Method::Put, "/api/status"), Ok(Page::SetStatus));
+    }
+
+    #[test]
+    fn head_follows_get() {
+        assert_eq!(page(Method::Head, "/"), Ok(Page::Index));
+        assert_eq!(page(Method::Head, "/api/status"), Ok(Page::Status));
+    }
+
+    #[test]
+    fn captures() {
+        let (handler, params) = ROUTER.resolve(Method::Get, "/api/animations/3").unwrap();
+        assert_eq!(*handler, Page::Animation);
+        assert_eq!(params.get("id"), Some("3"));
+        assert_eq!(params.get("other"), None);
+    }
+
+    #[test]
+    fn wildcard_takes_the_rest() {
+        let (handler, params) = ROUTER.resolve(Method::Get, "/static/css/site.css").unwrap();
+        assert_eq!(*handler, Page::Asset);
+        assert_eq!(params.get("*"), Some("css/site.css"));
+    }
+
+    #[test]
+    fn unknown_paths() {
+        assert_eq!(page(Method::Get, "/nope"), Err(RouteError::NotFound));
+        assert_eq!(page(Method::Get, "/api"), Err(RouteError::NotFound));
+        assert_eq!(page(Method::Get, "/api/status/extra"), Err(RouteError::NotFound));
+        assert_eq!(page(Method::Get, "/api/animations/"), Err(RouteError::NotFound));
+        assert_eq!(page(Method::Get, "/api/animations/1/2"), Err(RouteError::NotFound));
+    }
+
+    #[test]
+    fn wrong_method_lists_allowed() {
+        let Err(error) = page(Method::Delete, "/api/status") else {
+            panic!("DELETE should not route");
+        };
+        assert_eq!(error.status(), Status::MethodNotAllowed);
+        let RouteError::MethodNotAllowed { allow } = error else {
+            unreachable!();
+        };
+        assert_eq!(allow_header(&allow).as_str(), "GET, HEAD, PUT");
+    }
+
+    #[test]
+    fn fn_pointer_handlers() {
+        fn hello() -> &'static str {
+            "hello"
+        }
+        let routes: [Route<fn() -> &'static str>; 1] = [Route::get("/hello", hello)];
+        let (handler, _) = Router::new(&routes).resolve(Method::Get, "/hello").unwrap();
+        assert_eq!(handler(), "hello");
+    }
+}
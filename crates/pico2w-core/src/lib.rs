@@ -0,0 +1,38 @@
+// file: lib.rs
+// desc: board-independent logic shared by the firmware examples
+//
+// Nothing in here touches embassy or the RP2350, so `cargo test` runs it on the
+// host.
+#![no_std]
+
+pub mod animation;
+pub mod api;
+pub mod assets;
+pub mod captive_dns;
+pub mod command;
+pub mod credentials;
+pub mod dhcp_server;
+pub mod event;
+pub mod fat;
+pub mod homeassistant;
+pub mod http;
+pub mod link;
+pub mod macropad;
+pub mod mdns;
+pub mod mqtt;
+pub mod msc;
+pub mod network;
+pub mod ota;
+pub mod provision;
+pub mod reboot;
+pub mod record;
+pub mod scan;
+pub mod schedule;
+pub mod screen;
+pub mod shell;
+pub mod sntp;
+pub mod websocket;
+
+pub use animation::{get_animation_data, Frames, PlaybackError, PlaybackState, Player, Step};
+pub use command::{parse_command, Brightness, Command, Input, ProtocolError, Source, UrlError};
+pub use event::Event;
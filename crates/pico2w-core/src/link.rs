@@ -0,0 +1,145 @@
+// file: link.rs
+// desc: Wi-Fi link states and the reconnect backoff schedule
+//
+// The firmware's supervisor task drives these; the display and LED only read
+// the current `LinkState`.
+use core::time::Duration;
+
+/// First retry delay; it doubles after every failed attempt.
+pub const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+pub const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Consecutive failures before the link is reported as failed, at which
+/// point the firmware stops retrying and opens its setup portal.
+pub const FAILED_AFTER: u32 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LinkState {
+    Connecting,
+    Connected,
+    /// Waiting `delay_s` before attempt number `attempt + 1`.
+    Retrying { attempt: u32, delay_s: u32 },
+    Failed { attempt: u32 },
+    /// Serving the setup access point; its name comes from the radio's MAC.
+    Provisioning { mac: [u8; 6] },
+}
+
+/// How the onboard LED shows a link state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LedPattern {
+    Off,
+    On,
+    /// Toggle every `half_period_ms`.
+    Blink { half_period_ms: u16 },
+}
+
+impl LinkState {
+    pub fn is_connected(&self) -> bool {
+        matches!(self, LinkState::Connected)
+    }
+
+    /// A few characters for the corner of the OLED.
+    pub fn badge(&self) -> &'static str {
+        match self {
+            LinkState::Connecting => "join",
+            LinkState::Connected => "wifi",
+            LinkState::Retrying { .. } => "retry",
+            LinkState::Failed { .. } => "no wifi",
+            LinkState::Provisioning { .. } => "setup",
+        }
+    }
+
+    pub fn led(&self) -> LedPattern {
+        match self {
+            LinkState::Connecting => LedPattern::Blink { half_period_ms: 125 },
+            LinkState::Connected => LedPattern::On,
+            LinkState::Retrying { .. } => LedPattern::Blink { half_period_ms: 500 },
+            LinkState::Failed { .. } => LedPattern::Off,
+            LinkState::Provisioning { .. } => LedPattern::Blink { half_period_ms: 1000 },
+        }
+    }
+}
+
+/// Exponential backoff between join attempts.
+#[derive(Debug, Clone, Default)]
+pub struct Backoff {
+    failures: u32,
+}
+
+impl Backoff {
+    pub const fn new() -> Self {
+        Backoff { failures: 0 }
+    }
+
+    /// Record a failed attempt and return how long to wait before the next
+    /// one, along with the state to report meanwhile.
+    pub fn fail(&mut self) -> (Duration, LinkState) {
+        self.failures = self.failures.saturating_add(1);
+        // 1 s, 2 s, 4 s, ... capped; the shift is bounded so it cannot overflow
+        let doublings = (self.failures - 1).min(16);
+        let delay = INITIAL_BACKOFF.saturating_mul(1 << doublings).min(MAX_BACKOFF);
+        let state = if self.failures >= FAILED_AFTER {
+            LinkState::Failed { attempt: self.failures }
+        } else {
+            LinkState::Retrying { attempt: self.failures, delay_s: delay.as_secs() as u32 }
+        };
+        (delay, state)
+    }
+
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_double_up_to_the_cap() {
+        let mut backoff = Backoff::new();
+        let delays: [u64; 8] = core::array::from_fn(|_| backoff.fail().0.as_secs());
+        assert_eq!(delays, [1, 2, 4, 8, 16, 32, 60, 60]);
+
+        for _ in 0..100 {
+            backoff.fail();
+        }
+        assert_eq!(backoff.fail().0, MAX_BACKOFF);
+    }
+
+    #[test]
+    fn reports_retrying_then_failed() {
+        let mut backoff = Backoff::new();
+        assert_eq!(backoff.fail().1, LinkState::Retrying { attempt: 1, delay_s: 1 });
+        for _ in 2..FAILED_AFTER {
+            assert!(matches!(backoff.fail().1, LinkState::Retrying { .. }));
+        }
+        assert_eq!(backoff.fail().1, LinkState::Failed { attempt: FAILED_AFTER });
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut backoff = Backoff::new();
+        backoff.fail();
+        backoff.fail();
+        backoff.reset();
+        assert_eq!(backoff.failures(), 0);
+        assert_eq!(backoff.fail().0, INITIAL_BACKOFF);
+    }
+
+    #[test]
+    fn led_and_badge() {
+        assert_eq!(LinkState::Connected.led(), LedPattern::On);
+        assert_eq!(LinkState::Failed { attempt: 9 }.led(), LedPattern::Off);
+        assert!(matches!(LinkState::Connecting.led(), LedPattern::Blink { .. }));
+        assert!(LinkState::Connected.is_connected());
+        assert!(!LinkState::Retrying { attempt: 1, delay_s: 1 }.is_connected());
+        assert_eq!(LinkState::Retrying { attempt: 2, delay_s: 2 }.badge(), "retry");
+        assert_eq!(LinkState::Provisioning { mac: [0; 6] }.badge(), "setup");
+    }
+}
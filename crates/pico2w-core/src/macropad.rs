@@ -0,0 +1,420 @@
+// file: macropad.rs
+// desc: macro pad layers, key actions and the HID reports they turn into
+//
+// Each layer binds the four buttons to an action: a key sequence, typed text
+// or a media key. A short press sends the active layer's action; a long press
+// on button N switches to layer N. Keys go out on a boot keyboard interface,
+// media keys on a separate consumer control interface, each with a plain
+// report descriptor below.
+
+/// Buttons on the board, and so bindings per layer.
+pub const BUTTON_COUNT: usize = 4;
+/// Held this long, a press switches layers instead.
+pub const LONG_PRESS_MS: u64 = 600;
+/// A label fits one OLED line behind its button number.
+pub const LABEL_LEN: usize = 19;
+pub const KEYBOARD_REPORT_LEN: usize = 8;
+pub const CONSUMER_REPORT_LEN: usize = 2;
+
+/// Modifier bits of the keyboard report's first byte.
+pub mod modifier {
+    pub const CTRL: u8 = 0x01;
+    pub const SHIFT: u8 = 0x02;
+    pub const ALT: u8 = 0x04;
+    pub const GUI: u8 = 0x08;
+}
+
+/// Keyboard page usages for keys `KeyStroke::from_ascii` does not cover.
+pub mod key {
+    pub const ENTER: u8 = 0x28;
+    pub const ESCAPE: u8 = 0x29;
+    pub const BACKSPACE: u8 = 0x2a;
+    pub const TAB: u8 = 0x2b;
+    pub const DELETE: u8 = 0x4c;
+    pub const RIGHT: u8 = 0x4f;
+    pub const LEFT: u8 = 0x50;
+    pub const DOWN: u8 = 0x51;
+    pub const UP: u8 = 0x52;
+
+    /// F1 to F24.
+    pub const fn f(n: u8) -> u8 {
+        match n {
+            1..=12 => 0x3a + n - 1,
+            _ => 0x68 + n - 13,
+        }
+    }
+
+    /// `a` to `z`, for chords like Ctrl+C.
+    pub const fn letter(c: u8) -> u8 {
+        0x04 + c.to_ascii_lowercase() - b'a'
+    }
+}
+
+/// Consumer page usages.
+pub mod media {
+    pub const PLAY_PAUSE: u16 = 0xcd;
+    pub const NEXT_TRACK: u16 = 0xb5;
+    pub const PREVIOUS_TRACK: u16 = 0xb6;
+    pub const STOP: u16 = 0xb7;
+    pub const MUTE: u16 = 0xe2;
+    pub const VOLUME_UP: u16 = 0xe9;
+    pub const VOLUME_DOWN: u16 = 0xea;
+}
+
+/// One key, with the modifiers held while it is down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct KeyStroke {
+    pub modifiers: u8,
+    pub key: u8,
+}
+
+impl KeyStroke {
+    pub const fn new(modifiers: u8, key: u8) -> Self {
+        KeyStroke { modifiers, key }
+    }
+
+    /// A printable character, newline or tab on a US layout.
+    pub fn from_ascii(c: u8) -> Option<Self> {
+        let plain = |key| Some(KeyStroke::new(0, key));
+        let shifted = |key| Some(KeyStroke::new(modifier::SHIFT, key));
+        match c {
+            b'a'..=b'z' => plain(key::letter(c)),
+            b'A'..=b'Z' => shifted(key::letter(c)),
+            b'1'..=b'9' => plain(0x1e + c - b'1'),
+            b'0' => plain(0x27),
+            b'\n' => plain(key::ENTER),
+            b'\t' => plain(key::TAB),
+            b' ' => plain(0x2c),
+            b'-' => plain(0x2d),
+            b'=' => plain(0x2e),
+            b'[' => plain(0x2f),
+            b']' => plain(0x30),
+            b'\\' => plain(0x31),
+            b';' => plain(0x33),
+            b'\'' => plain(0x34),
+            b'`' => plain(0x35),
+            b',' => plain(0x36),
+            b'.' => plain(0x37),
+            b'/' => plain(0x38),
+            b'!' => shifted(0x1e),
+            b'@' => shifted(0x1f),
+            b'#' => shifted(0x20),
+            b'$' => shifted(0x21),
+            b'%' => shifted(0x22),
+            b'^' => shifted(0x23),
+            b'&' => shifted(0x24),
+            b'*' => shifted(0x25),
+            b'(' => shifted(0x26),
+            b')' => shifted(0x27),
+            b'_' => shifted(0x2d),
+            b'+' => shifted(0x2e),
+            b'{' => shifted(0x2f),
+            b'}' => shifted(0x30),
+            b'|' => shifted(0x31),
+            b':' => shifted(0x33),
+            b'"' => shifted(0x34),
+            b'~' => shifted(0x35),
+            b'<' => shifted(0x36),
+            b'>' => shifted(0x37),
+            b'?' => shifted(0x38),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Pressed and released one after the other.
+    Keys(&'static [KeyStroke]),
+    /// Typed character by character; anything `from_ascii` does not know is
+    /// skipped.
+    Text(&'static str),
+    /// A consumer control usage, see `media`.
+    Media(u16),
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Binding {
+    pub label: &'static str,
+    pub action: Action,
+}
+
+impl Binding {
+    pub const fn new(label: &'static str, action: Action) -> Self {
+        Binding { label, action }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layer {
+    pub name: &'static str,
+    /// Button N is `bindings[N - 1]`.
+    pub bindings: [Binding; BUTTON_COUNT],
+}
+
+/// An HID input report, for the interface it belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Report {
+    Keyboard([u8; KEYBOARD_REPORT_LEN]),
+    Consumer([u8; CONSUMER_REPORT_LEN]),
+}
+
+const KEYS_UP: Report = Report::Keyboard([0; KEYBOARD_REPORT_LEN]);
+const MEDIA_UP: Report = Report::Consumer([0; CONSUMER_REPORT_LEN]);
+
+impl Report {
+    fn key_down(stroke: KeyStroke) -> Self {
+        Report::Keyboard([stroke.modifiers, 0, stroke.key, 0, 0, 0, 0, 0])
+    }
+}
+
+impl Action {
+    /// Every report the action takes, a press and a release per key.
+    pub fn reports(&self) -> Reports {
+        Reports { action: *self, index: 0, released: true }
+    }
+
+    fn stroke(&self, index: usize) -> Option<Option<KeyStroke>> {
+        match self {
+            Action::Keys(strokes) => strokes.get(index).map(|&stroke| Some(stroke)),
+            Action::Text(text) => text.as_bytes().get(index).map(|&c| KeyStroke::from_ascii(c)),
+            Action::Media(_) | Action::None => None,
+        }
+    }
+}
+
+pub struct Reports {
+    action: Action,
+    index: usize,
+    // Whatever the last report pressed has been let go again
+    released: bool,
+}
+
+impl Iterator for Reports {
+    type Item = Report;
+
+    fn next(&mut self) -> Option<Report> {
+        if !self.released {
+            self.released = true;
+            return Some(match self.action {
+                Action::Media(_) => MEDIA_UP,
+                _ => KEYS_UP,
+            });
+        }
+        if let Action::Media(usage) = self.action {
+            if self.index > 0 {
+                return None;
+            }
+            self.index = 1;
+            self.released = false;
+            return Some(Report::Consumer(usage.to_le_bytes()));
+        }
+        loop {
+            let stroke = self.action.stroke(self.index)?;
+            self.index += 1;
+            if let Some(stroke) = stroke {
+                self.released = false;
+                return Some(Report::key_down(stroke));
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Press {
+    Short,
+    Long,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Send(Action),
+    /// The new active layer, counted from 0.
+    Layer(usize),
+    Ignored,
+}
+
+/// The active layer, and what a press does with it.
+pub struct MacroPad {
+    layers: &'static [Layer],
+    active: usize,
+}
+
+impl MacroPad {
+    pub const fn new(layers: &'static [Layer]) -> Self {
+        MacroPad { layers, active: 0 }
+    }
+
+    pub fn layer(&self) -> Option<&'static Layer> {
+        self.layers.get(self.active)
+    }
+
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    /// `button` counted from 1.
+    pub fn press(&mut self, button: u8, press: Press) -> Outcome {
+        let index = usize::from(button).wrapping_sub(1);
+        match press {
+            Press::Short => match self.layer().and_then(|layer| layer.bindings.get(index)) {
+                Some(Binding { action: Action::None, .. }) | None => Outcome::Ignored,
+                Some(binding) => Outcome::Send(binding.action),
+            },
+            Press::Long if index < self.layers.len() && index != self.active => {
+                self.active = index;
+                Outcome::Layer(index)
+            }
+            Press::Long => Outcome::Ignored,
+        }
+    }
+}
+
+/// Boot keyboard: modifiers, a reserved byte and six keys in; five LEDs out.
+pub const KEYBOARD_REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x06, // Usage (Keyboard)
+    0xa1, 0x01, // Collection (Application)
+    0x05, 0x07, //   Usage Page (Keyboard)
+    0x19, 0xe0, //   Usage Minimum (Left Control)
+    0x29, 0xe7, //   Usage Maximum (Right GUI)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x01, //   Logical Maximum (1)
+    0x75, 0x01, //   Report Size (1)
+    0x95, 0x08, //   Report Count (8)
+    0x81, 0x02, //   Input (Data, Variable, Absolute)
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x08, //   Report Size (8)
+    0x81, 0x01, //   Input (Constant)
+    0x95, 0x05, //   Report Count (5)
+    0x75, 0x01, //   Report Size (1)
+    0x05, 0x08, //   Usage Page (LEDs)
+    0x19, 0x01, //   Usage Minimum (Num Lock)
+    0x29, 0x05, //   Usage Maximum (Kana)
+    0x91, 0x02, //   Output (Data, Variable, Absolute)
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x03, //   Report Size (3)
+    0x91, 0x01, //   Output (Constant)
+    0x95, 0x06, //   Report Count (6)
+    0x75, 0x08, //   Report Size (8)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x65, //   Logical Maximum (101)
+    0x05, 0x07, //   Usage Page (Keyboard)
+    0x19, 0x00, //   Usage Minimum (0)
+    0x29, 0x65, //   Usage Maximum (101)
+    0x81, 0x00, //   Input (Data, Array, Absolute)
+    0xc0, // End Collection
+];
+
+/// Consumer control: one 16-bit usage, zero when nothing is held.
+pub const CONSUMER_REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x0c, // Usage Page (Consumer)
+    0x09, 0x01, // Usage (Consumer Control)
+    0xa1, 0x01, // Collection (Application)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x26, 0xff, 0x03, //   Logical Maximum (1023)
+    0x19, 0x00, //   Usage Minimum (0)
+    0x2a, 0xff, 0x03, //   Usage Maximum (1023)
+    0x75, 0x10, //   Report Size (16)
+    0x95, 0x01, //   Report Count (1)
+    0x81, 0x00, //   Input (Data, Array, Absolute)
+    0xc0, // End Collection
+];
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    const COPY: &[KeyStroke] = &[KeyStroke::new(modifier::CTRL, key::letter(b'c'))];
+    const LAYERS: &[Layer] = &[
+        Layer {
+            name: "Media",
+            bindings: [
+                Binding::new("Play/Pause", Action::Media(media::PLAY_PAUSE)),
+                Binding::new("Copy", Action::Keys(COPY)),
+                Binding::new("Hi", Action::Text("Hi!\n")),
+                Binding::new("", Action::None),
+            ],
+        },
+        Layer {
+            name: "Second",
+            bindings: [Binding::new("", Action::None); BUTTON_COUNT],
+        },
+    ];
+
+    fn reports(action: Action) -> Vec<Report> {
+        action.reports().collect()
+    }
+
+    #[test]
+    fn ascii_keys() {
+        assert_eq!(KeyStroke::from_ascii(b'a'), Some(KeyStroke::new(0, 0x04)));
+        assert_eq!(KeyStroke::from_ascii(b'Z'), Some(KeyStroke::new(modifier::SHIFT, 0x1d)));
+        assert_eq!(KeyStroke::from_ascii(b'1'), Some(KeyStroke::new(0, 0x1e)));
+        assert_eq!(KeyStroke::from_ascii(b'0'), Some(KeyStroke::new(0, 0x27)));
+        assert_eq!(KeyStroke::from_ascii(b')'), Some(KeyStroke::new(modifier::SHIFT, 0x27)));
+        assert_eq!(KeyStroke::from_ascii(b'\n'), Some(KeyStroke::new(0, key::ENTER)));
+        assert_eq!(KeyStroke::from_ascii(0x1b), None);
+        assert_eq!(KeyStroke::from_ascii(0xc3), None);
+        // Every printable character has a key
+        assert!((b' '..=b'~').all(|c| KeyStroke::from_ascii(c).is_some()));
+        assert_eq!((key::f(1), key::f(12), key::f(13), key::f(24)), (0x3a, 0x45, 0x68, 0x73));
+    }
+
+    #[test]
+    fn key_reports() {
+        assert_eq!(reports(Action::Keys(COPY)), [
+            Report::Keyboard([modifier::CTRL, 0, 0x06, 0, 0, 0, 0, 0]),
+            KEYS_UP
+        ]);
+        // Repeated letters work because every key is released in between
+        let typed = reports(Action::Text("oo\x07"));
+        assert_eq!(typed, [
+            Report::Keyboard([0, 0, 0x12, 0, 0, 0, 0, 0]),
+            KEYS_UP,
+            Report::Keyboard([0, 0, 0x12, 0, 0, 0, 0, 0]),
+            KEYS_UP
+        ]);
+        assert!(reports(Action::Text("")).is_empty());
+        assert!(reports(Action::None).is_empty());
+    }
+
+    #[test]
+    fn media_reports() {
+        assert_eq!(reports(Action::Media(media::VOLUME_UP)), [Report::Consumer([0xe9, 0x00]), MEDIA_UP]);
+    }
+
+    #[test]
+    fn presses() {
+        let mut pad = MacroPad::new(LAYERS);
+        assert_eq!(pad.press(1, Press::Short), Outcome::Send(Action::Media(media::PLAY_PAUSE)));
+        assert_eq!(pad.press(3, Press::Short), Outcome::Send(Action::Text("Hi!\n")));
+        assert_eq!(pad.press(4, Press::Short), Outcome::Ignored);
+        assert_eq!(pad.press(0, Press::Short), Outcome::Ignored);
+        assert_eq!(pad.press(5, Press::Short), Outcome::Ignored);
+
+        assert_eq!(pad.press(2, Press::Long), Outcome::Layer(1));
+        assert_eq!(pad.layer().unwrap().name, "Second");
+        assert_eq!(pad.press(1, Press::Short), Outcome::Ignored);
+        // Already there, and no layer behind button 3
+        assert_eq!(pad.press(2, Press::Long), Outcome::Ignored);
+        assert_eq!(pad.press(3, Press::Long), Outcome::Ignored);
+        assert_eq!(pad.press(1, Press::Long), Outcome::Layer(0));
+        assert_eq!(pad.active(), 0);
+    }
+
+    #[test]
+    fn descriptors() {
+        assert_eq!(KEYBOARD_REPORT_DESCRIPTOR.len(), 63);
+        assert_eq!(CONSUMER_REPORT_DESCRIPTOR.len(), 23);
+        assert_eq!(KEYBOARD_REPORT_DESCRIPTOR.last(), Some(&0xc0));
+        assert_eq!(CONSUMER_REPORT_DESCRIPTOR.last(), Some(&0xc0));
+    }
+}
@@ -0,0 +1,449 @@
+// file: mdns.rs
+// desc: multicast DNS responder for `<hostname>.local` and its HTTP service
+//
+// Answers A queries for the host name and DNS-SD browsing for `_http._tcp`
+// (PTR, SRV and TXT), and builds the unsolicited announcement sent when the
+// board gets an address. Names are written uncompressed; every reply still
+// fits a single small packet.
+use core::fmt::Write as _;
+
+use heapless::String;
+
+use crate::network::Ipv4;
+
+pub const PORT: u16 = 5353;
+pub const GROUP: Ipv4 = [224, 0, 0, 251];
+/// Ethernet address for `GROUP`, for the radio's multicast filter.
+pub const GROUP_MAC: [u8; 6] = [0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb];
+/// Longer queries are dropped; replies never exceed it.
+pub const MAX_PACKET_LEN: usize = 512;
+pub const TTL_S: u32 = 120;
+/// RFC 6762 caps TTLs in replies to one-shot (legacy unicast) queries.
+pub const LEGACY_TTL_S: u32 = 10;
+
+pub const SERVICE_TYPE: &str = "_http._tcp.local";
+const SERVICES: &str = "_services._dns-sd._udp.local";
+
+const HEADER_LEN: usize = 12;
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+// Top bit of the class: QU in questions, cache-flush in answers
+const CLASS_TOP_BIT: u16 = 0x8000;
+// Longest dotted name, and the pointer hops allowed while reading one
+const NAME_LEN: usize = 255;
+const MAX_POINTERS: usize = 8;
+
+// Records as bits, so a reply lists each at most once
+const RECORD_A: u8 = 1 << 0;
+const RECORD_SERVICES: u8 = 1 << 1;
+const RECORD_PTR: u8 = 1 << 2;
+const RECORD_SRV: u8 = 1 << 3;
+const RECORD_TXT: u8 = 1 << 4;
+const ALL_RECORDS: u8 = RECORD_A | RECORD_SERVICES | RECORD_PTR | RECORD_SRV | RECORD_TXT;
+
+/// What the board advertises. The service instance is named after the host.
+#[derive(Debug, Clone, Copy)]
+pub struct Responder<'a> {
+    /// Without `.local`.
+    pub hostname: &'a str,
+    pub address: Ipv4,
+    pub http_port: u16,
+    /// `key=value` strings for the TXT record.
+    pub txt: &'a [&'a str],
+}
+
+// What a legacy unicast reply must carry over from the query (RFC 6762 §6.7)
+#[derive(Clone, Copy)]
+struct Legacy<'q> {
+    id: u16,
+    questions: u16,
+    /// The question section, copied verbatim.
+    section: &'q [u8],
+}
+
+/// A reply built in the caller's buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reply {
+    pub len: usize,
+    /// Send to the querier rather than the multicast group.
+    pub unicast: bool,
+}
+
+impl Responder<'_> {
+    /// Answer `query`. `legacy` is set when it came from a port other than
+    /// `PORT`, i.e. from a plain DNS resolver rather than an mDNS one; that
+    /// gets a unicast reply echoing the question, with short TTLs and no
+    /// cache-flush bits. Queries for names we do not own get no reply.
+    pub fn answer(&self, query: &[u8], legacy: bool, reply: &mut [u8; MAX_PACKET_LEN]) -> Option<Reply> {
+        if query.len() < HEADER_LEN || query.len() > MAX_PACKET_LEN {
+            return None;
+        }
+        let flags = u16::from_be_bytes([query[2], query[3]]);
+        let is_response = flags & 0x8000 != 0;
+        let opcode = (flags >> 11) & 0xf;
+        if is_response || opcode != 0 {
+            return None;
+        }
+
+        let host = self.host_name()?;
+        let instance = self.instance_name()?;
+        let questions = u16::from_be_bytes([query[4], query[5]]);
+        let mut answers = 0;
+        let mut unicast = legacy;
+        let mut at = HEADER_LEN;
+        for _ in 0..questions {
+            let (name, next) = read_name(query, at)?;
+            let qtype = u16::from_be_bytes([*query.get(next)?, *query.get(next + 1)?]);
+            let qclass = u16::from_be_bytes([*query.get(next + 2)?, *query.get(next + 3)?]);
+            at = next + 4;
+            if qclass & !CLASS_TOP_BIT != CLASS_IN {
+                continue;
+            }
+
+            let any = qtype == TYPE_ANY;
+            let matched = if name.eq_ignore_ascii_case(&host) {
+                if any || qtype == TYPE_A { RECORD_A } else { 0 }
+            } else if name.eq_ignore_ascii_case(SERVICE_TYPE) {
+                if any || qtype == TYPE_PTR { RECORD_PTR } else { 0 }
+            } else if name.eq_ignore_ascii_case(&instance) {
+                (if any || qtype == TYPE_SRV { RECORD_SRV } else { 0 })
+                    | (if any || qtype == TYPE_TXT { RECORD_TXT } else { 0 })
+            } else if name.eq_ignore_ascii_case(SERVICES) {
+                if any || qtype == TYPE_PTR { RECORD_SERVICES } else { 0 }
+            } else {
+                0
+            };
+            if matched != 0 && qclass & CLASS_TOP_BIT != 0 {
+                unicast = true;
+            }
+            answers |= matched;
+        }
+        if answers == 0 {
+            return None;
+        }
+
+        // What a browser needs next: the PTR implies SRV and TXT, SRV implies A
+        let mut additional = 0;
+        if answers & RECORD_PTR != 0 {
+            additional |= RECORD_SRV | RECORD_TXT | RECORD_A;
+        }
+        if answers & RECORD_SRV != 0 {
+            additional |= RECORD_A;
+        }
+        additional &= !answers;
+
+        // Legacy resolvers match replies by ID and question, like plain DNS
+        let legacy = legacy.then(|| Legacy {
+            id: u16::from_be_bytes([query[0], query[1]]),
+            questions,
+            section: &query[HEADER_LEN..at],
+        });
+        let len = self.write(reply, legacy, answers, additional)?;
+        Some(Reply { len, unicast })
+    }
+
+    /// An unsolicited response with every record, sent to the group when the
+    /// board gets an address.
+    pub fn announce(&self, reply: &mut [u8; MAX_PACKET_LEN]) -> Option<usize> {
+        self.write(reply, None, ALL_RECORDS, 0)
+    }
+
+    fn host_name(&self) -> Option<String<NAME_LEN>> {
+        let mut name = String::new();
+        write!(name, "{}.local", self.hostname).ok()?;
+        Some(name)
+    }
+
+    fn instance_name(&self) -> Option<String<NAME_LEN>> {
+        let mut name = String::new();
+        write!(name, "{}.{SERVICE_TYPE}", self.hostname).ok()?;
+        Some(name)
+    }
+
+    fn write(&self, buf: &mut [u8; MAX_PACKET_LEN], legacy: Option<Legacy>, answers: u8, additional: u8) -> Option<usize> {
+        let host = self.host_name()?;
+        let instance = self.instance_name()?;
+        // Plain DNS caches know nothing of the cache-flush bit
+        let mut out = Writer { buf, len: HEADER_LEN, cache_flush: legacy.is_none() };
+        let ttl = if legacy.is_some() { LEGACY_TTL_S } else { TTL_S };
+        out.buf[..2].copy_from_slice(&legacy.map_or(0, |legacy| legacy.id).to_be_bytes());
+        // Response, authoritative
+        out.buf[2..4].copy_from_slice(&0x8400u16.to_be_bytes());
+        out.buf[4..6].copy_from_slice(&legacy.map_or(0, |legacy| legacy.questions).to_be_bytes());
+        // Same offset as in the query, so compression pointers in it still hold
+        out.bytes(legacy.map_or(&[], |legacy| legacy.section))?;
+        out.buf[6..8].copy_from_slice(&(answers.count_ones() as u16).to_be_bytes());
+        out.buf[8..10].fill(0);
+        out.buf[10..12].copy_from_slice(&(additional.count_ones() as u16).to_be_bytes());
+
+        for records in [answers, additional] {
+            // Shared records (the PTRs) must not carry the cache-flush bit
+            if records & RECORD_SERVICES != 0 {
+                out.record(SERVICES, TYPE_PTR, false, ttl, |out| out.name(SERVICE_TYPE))?;
+            }
+            if records & RECORD_PTR != 0 {
+                out.record(SERVICE_TYPE, TYPE_PTR, false, ttl, |out| out.name(&instance))?;
+            }
+            if records & RECORD_SRV != 0 {
+                out.record(&instance, TYPE_SRV, true, ttl, |out| {
+                    // Priority, weight, port, target
+                    out.bytes(&[0, 0, 0, 0])?;
+                    out.bytes(&self.http_port.to_be_bytes())?;
+                    out.name(&host)
+                })?;
+            }
+            if records & RECORD_TXT != 0 {
+                out.record(&instance, TYPE_TXT, true, ttl, |out| {
+                    if self.txt.is_empty() {
+                        return out.bytes(&[0]);
+                    }
+                    for entry in self.txt {
+                        out.bytes(&[u8::try_from(entry.len()).ok()?])?;
+                        out.bytes(entry.as_bytes())?;
+                    }
+                    Some(())
+                })?;
+            }
+            if records & RECORD_A != 0 {
+                out.record(&host, TYPE_A, true, ttl, |out| out.bytes(&self.address))?;
+            }
+        }
+        Some(out.len)
+    }
+}
+
+struct Writer<'b> {
+    buf: &'b mut [u8; MAX_PACKET_LEN],
+    len: usize,
+    cache_flush: bool,
+}
+
+impl Writer<'_> {
+    fn bytes(&mut self, bytes: &[u8]) -> Option<()> {
+        self.buf.get_mut(self.len..self.len + bytes.len())?.copy_from_slice(bytes);
+        self.len += bytes.len();
+        Some(())
+    }
+
+    fn name(&mut self, dotted: &str) -> Option<()> {
+        for label in dotted.split('.') {
+            if label.is_empty() || label.len() > 63 {
+                return None;
+            }
+            self.bytes(&[label.len() as u8])?;
+            self.bytes(label.as_bytes())?;
+        }
+        self.bytes(&[0])
+    }
+
+    fn record(&mut self, name: &str, rtype: u16, unique: bool, ttl: u32, data: impl FnOnce(&mut Self) -> Option<()>) -> Option<()> {
+        self.name(name)?;
+        self.bytes(&rtype.to_be_bytes())?;
+        let class = if unique && self.cache_flush { CLASS_IN | CLASS_TOP_BIT } else { CLASS_IN };
+        self.bytes(&class.to_be_bytes())?;
+        self.bytes(&ttl.to_be_bytes())?;
+        // Length is filled in once the data is written
+        let length_at = self.len;
+        self.bytes(&[0, 0])?;
+        data(self)?;
+        let data_len = (self.len - length_at - 2) as u16;
+        self.buf[length_at..length_at + 2].copy_from_slice(&data_len.to_be_bytes());
+        Some(())
+    }
+}
+
+// The dotted name at `at` and the offset just past it, following
+// compression pointers.
+fn read_name(packet: &[u8], mut at: usize) -> Option<(String<NAME_LEN>, usize)> {
+    let mut name = String::new();
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let len = *packet.get(at)? as usize;
+        match len {
+            0 => break,
+            0xc0.. => {
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return None;
+                }
+                let target = (len & 0x3f) << 8 | *packet.get(at + 1)? as usize;
+                end.get_or_insert(at + 2);
+                at = target;
+            }
+            64.. => return None,
+            _ => {
+                let label = core::str::from_utf8(packet.get(at + 1..at + 1 + len)?).ok()?;
+                if !name.is_empty() {
+                    name.push('.').ok()?;
+                }
+                name.push_str(label).ok()?;
+                at += 1 + len;
+            }
+        }
+    }
+    Some((name, end.unwrap_or(at + 1)))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::*;
+
+    const RESPONDER: Responder<'static> = Responder {
+        hostname: "pico2w",
+        address: [192, 168, 68, 100],
+        http_port: 80,
+        txt: &["version=0.1.0", "animations=4"],
+    };
+
+    fn query(questions: &[(&str, u16)]) -> Vec<u8> {
+        let mut packet = Vec::from([0x12, 0x34, 0, 0, 0, questions.len() as u8, 0, 0, 0, 0, 0, 0]);
+        for (name, qtype) in questions {
+            for label in name.split('.') {
+                packet.push(label.len() as u8);
+                packet.extend_from_slice(label.as_bytes());
+            }
+            packet.push(0);
+            packet.extend_from_slice(&qtype.to_be_bytes());
+            packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+        }
+        packet
+    }
+
+    // (name, type, data) of every record in a reply
+    fn records(reply: &[u8]) -> Vec<(String<NAME_LEN>, u16, Vec<u8>)> {
+        let count = u16::from_be_bytes([reply[6], reply[7]]) + u16::from_be_bytes([reply[10], reply[11]]);
+        let mut at = HEADER_LEN;
+        for _ in 0..u16::from_be_bytes([reply[4], reply[5]]) {
+            at = read_name(reply, at).unwrap().1 + 4;
+        }
+        let mut found = Vec::new();
+        for _ in 0..count {
+            let (name, next) = read_name(reply, at).unwrap();
+            let rtype = u16::from_be_bytes([reply[next], reply[next + 1]]);
+            let len = u16::from_be_bytes([reply[next + 8], reply[next + 9]]) as usize;
+            found.push((name, rtype, reply[next + 10..next + 10 + len].to_vec()));
+            at = next + 10 + len;
+        }
+        assert_eq!(at, reply.len());
+        found
+    }
+
+    #[test]
+    fn host_name_resolves() {
+        let mut reply = [0u8; MAX_PACKET_LEN];
+        let answer = RESPONDER.answer(&query(&[("Pico2W.local", TYPE_A)]), false, &mut reply).unwrap();
+        assert!(!answer.unicast);
+        let reply = &reply[..answer.len];
+        assert_eq!(&reply[..4], &[0, 0, 0x84, 0]);
+        let records = records(reply);
+        assert_eq!(records.len(), 1);
+        assert_eq!((records[0].0.as_str(), records[0].1), ("pico2w.local", TYPE_A));
+        assert_eq!(records[0].2, [192, 168, 68, 100]);
+    }
+
+    #[test]
+    fn browsing_gets_service_details() {
+        let mut reply = [0u8; MAX_PACKET_LEN];
+        let answer = RESPONDER.answer(&query(&[(SERVICE_TYPE, TYPE_PTR)]), false, &mut reply).unwrap();
+        let records = records(&reply[..answer.len]);
+        let types: Vec<u16> = records.iter().map(|r| r.1).collect();
+        assert_eq!(types, [TYPE_PTR, TYPE_SRV, TYPE_TXT, TYPE_A]);
+        assert_eq!(&reply[6..8], &[0, 1]);
+        assert_eq!(&reply[10..12], &[0, 3]);
+
+        assert_eq!(records[0].2, b"\x06pico2w\x05_http\x04_tcp\x05local\x00");
+        assert_eq!(records[1].0.as_str(), "pico2w._http._tcp.local");
+        assert_eq!(&records[1].2[..6], &[0, 0, 0, 0, 0, 80]);
+        assert_eq!(&records[1].2[6..], b"\x06pico2w\x05local\x00");
+        assert_eq!(records[2].2, b"\x0dversion=0.1.0\x0canimations=4");
+    }
+
+    #[test]
+    fn legacy_queries_get_a_plain_dns_reply() {
+        let packet = query(&[("pico2w.local", TYPE_ANY)]);
+        let mut reply = [0u8; MAX_PACKET_LEN];
+        let answer = RESPONDER.answer(&packet, true, &mut reply).unwrap();
+        assert!(answer.unicast);
+        let reply = &reply[..answer.len];
+        // Same ID and question, then the answer
+        assert_eq!(&reply[..6], &[0x12, 0x34, 0x84, 0, 0, 1]);
+        assert_eq!(&reply[HEADER_LEN..packet.len()], &packet[HEADER_LEN..]);
+        assert_eq!(records(reply)[0].2, [192, 168, 68, 100]);
+
+        // A record: no cache-flush bit, short TTL
+        let class_at = packet.len() + "pico2w.local".len() + 2 + 2;
+        assert_eq!(&reply[class_at..class_at + 2], &CLASS_IN.to_be_bytes());
+        assert_eq!(&reply[class_at + 2..class_at + 6], &LEGACY_TTL_S.to_be_bytes());
+    }
+
+    #[test]
+    fn multicast_replies_flush_caches() {
+        let mut reply = [0u8; MAX_PACKET_LEN];
+        let answer = RESPONDER.answer(&query(&[("pico2w.local", TYPE_A)]), false, &mut reply).unwrap();
+        assert_eq!(&reply[4..6], &[0, 0]);
+        let class_at = HEADER_LEN + "pico2w.local".len() + 2 + 2;
+        assert_eq!(&reply[class_at..class_at + 2], &(CLASS_IN | CLASS_TOP_BIT).to_be_bytes());
+        assert_eq!(&reply[class_at + 2..class_at + 6], &TTL_S.to_be_bytes());
+        assert_eq!(answer.len, class_at + 6 + 2 + 4);
+    }
+
+    #[test]
+    fn qu_bit_asks_for_unicast() {
+        let mut packet = query(&[("pico2w.local", TYPE_A)]);
+        let class_at = packet.len() - 2;
+        packet[class_at] |= 0x80;
+        let mut reply = [0u8; MAX_PACKET_LEN];
+        assert!(RESPONDER.answer(&packet, false, &mut reply).unwrap().unicast);
+    }
+
+    #[test]
+    fn compressed_questions() {
+        // The second question points back into the first
+        let mut packet = query(&[("other.local", TYPE_A)]);
+        packet[5] = 2;
+        packet.extend_from_slice(b"\x06pico2w\xc0\x12");
+        packet.extend_from_slice(&TYPE_A.to_be_bytes());
+        packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+        let mut reply = [0u8; MAX_PACKET_LEN];
+        let answer = RESPONDER.answer(&packet, false, &mut reply).unwrap();
+        assert_eq!(records(&reply[..answer.len])[0].0.as_str(), "pico2w.local");
+
+        // A pointer loop is dropped
+        let mut looped = query(&[("pico2w.local", TYPE_A)]);
+        looped[HEADER_LEN] = 0xc0;
+        looped[HEADER_LEN + 1] = HEADER_LEN as u8;
+        assert_eq!(RESPONDER.answer(&looped, false, &mut reply), None);
+    }
+
+    #[test]
+    fn ignores_other_names_and_responses() {
+        let mut reply = [0u8; MAX_PACKET_LEN];
+        assert_eq!(RESPONDER.answer(&query(&[("printer.local", TYPE_A)]), false, &mut reply), None);
+        assert_eq!(RESPONDER.answer(&query(&[("pico2w.local", 28)]), false, &mut reply), None);
+        let mut response = query(&[("pico2w.local", TYPE_A)]);
+        response[2] = 0x84;
+        assert_eq!(RESPONDER.answer(&response, false, &mut reply), None);
+        assert_eq!(RESPONDER.answer(&[0; 6], false, &mut reply), None);
+    }
+
+    #[test]
+    fn announcement_has_everything() {
+        let mut reply = [0u8; MAX_PACKET_LEN];
+        let len = RESPONDER.announce(&mut reply).unwrap();
+        let types: Vec<u16> = records(&reply[..len]).iter().map(|r| r.1).collect();
+        assert_eq!(types, [TYPE_PTR, TYPE_PTR, TYPE_SRV, TYPE_TXT, TYPE_A]);
+
+        // Longest host name still fits
+        let hostname = "h".repeat(32);
+        let long = Responder { hostname: &hostname, ..RESPONDER };
+        assert!(long.announce(&mut reply).is_some());
+    }
+}
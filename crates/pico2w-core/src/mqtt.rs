@@ -0,0 +1,507 @@
+// file: mqtt.rs
+// desc: MQTT 3.1.1 packets, the board's topics and their payloads
+//
+// The firmware keeps one connection to the broker: it subscribes to
+// `<prefix>/command` and `<prefix>/brightness/set`, keeps `<prefix>/status`
+// (online/offline, the latter as its Last Will), `<prefix>/animation` and
+// `<prefix>/brightness` retained, and publishes button presses to
+// `<prefix>/button/N` and the WiFi signal to `<prefix>/rssi`. Everything is
+// QoS 0, so no packet ever needs acknowledging or resending.
+use core::fmt::Write as _;
+
+use heapless::String;
+
+use crate::command::{Brightness, Command};
+use crate::homeassistant::{DEFAULT_DISCOVERY_PREFIX, DISCOVERY_PREFIX_LEN};
+
+pub const DEFAULT_PORT: u16 = 1883;
+pub const KEEP_ALIVE_S: u16 = 60;
+/// Incoming packets longer than this end the connection.
+pub const MAX_PACKET_LEN: usize = 512;
+/// Brokers must accept client IDs up to 23 characters.
+pub const CLIENT_ID_LEN: usize = 23;
+pub const BROKER_LEN: usize = 64;
+pub const USERNAME_LEN: usize = 32;
+pub const PASSWORD_LEN: usize = 64;
+pub const PREFIX_LEN: usize = 48;
+pub const TOPIC_LEN: usize = 64;
+
+pub const STATUS_TOPIC: &str = "status";
+pub const ANIMATION_TOPIC: &str = "animation";
+pub const COMMAND_TOPIC: &str = "command";
+pub const BUTTON_TOPIC: &str = "button";
+pub const BRIGHTNESS_TOPIC: &str = "brightness";
+pub const BRIGHTNESS_SET_TOPIC: &str = "brightness/set";
+pub const RSSI_TOPIC: &str = "rssi";
+pub const ONLINE: &str = "online";
+pub const OFFLINE: &str = "offline";
+pub const PRESS: &str = "press";
+
+pub const PINGREQ: [u8; 2] = [0xc0, 0x00];
+pub const DISCONNECT: [u8; 2] = [0xe0, 0x00];
+
+const PROTOCOL_LEVEL: u8 = 4;
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGRESP: u8 = 13;
+// Remaining length is a base-128 varint of at most four bytes
+const MAX_HEADER_LEN: usize = 5;
+
+/// Where the broker is and who we are, from the build-time variables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MqttSettings {
+    /// Host name or dotted quad.
+    pub broker: String<BROKER_LEN>,
+    pub port: u16,
+    pub client_id: String<CLIENT_ID_LEN>,
+    pub username: Option<String<USERNAME_LEN>>,
+    pub password: Option<String<PASSWORD_LEN>>,
+    /// Every topic starts with this, `pico2w/<hostname>` by default.
+    pub prefix: String<PREFIX_LEN>,
+    /// Home Assistant discovery topics start with this; `None` turns
+    /// discovery off.
+    pub discovery_prefix: Option<String<DISCOVERY_PREFIX_LEN>>,
+}
+
+/// The raw build-time variables, each `None` when unset.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MqttEnv<'a> {
+    /// MQTT stays off without a broker.
+    pub broker: Option<&'a str>,
+    pub port: Option<&'a str>,
+    pub client_id: Option<&'a str>,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a str>,
+    pub prefix: Option<&'a str>,
+    /// Empty turns Home Assistant discovery off.
+    pub discovery_prefix: Option<&'a str>,
+}
+
+/// Which variable was malformed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MqttConfigError {
+    Broker,
+    Port,
+    ClientId,
+    Username,
+    Password,
+    Prefix,
+    DiscoveryPrefix,
+}
+
+impl MqttSettings {
+    /// `None` when no broker is configured. The client ID and topic prefix
+    /// default to the board's hostname.
+    pub fn from_env(env: &MqttEnv, hostname: &str) -> Result<Option<Self>, MqttConfigError> {
+        let Some(broker) = env.broker.map(str::trim) else {
+            return Ok(None);
+        };
+        if broker.is_empty() {
+            return Err(MqttConfigError::Broker);
+        }
+        let port = match env.port {
+            Some(port) => port.trim().parse().ok().filter(|&p| p > 0).ok_or(MqttConfigError::Port)?,
+            None => DEFAULT_PORT,
+        };
+        let client_id = match env.client_id {
+            Some(id) if !id.is_empty() => String::try_from(id).map_err(|_| MqttConfigError::ClientId)?,
+            Some(_) => return Err(MqttConfigError::ClientId),
+            None => String::try_from(hostname).map_err(|_| MqttConfigError::ClientId)?,
+        };
+        let prefix = match env.prefix {
+            Some(prefix) if is_valid_prefix(prefix) => String::try_from(prefix).map_err(|_| MqttConfigError::Prefix)?,
+            Some(_) => return Err(MqttConfigError::Prefix),
+            None => {
+                let mut prefix = String::new();
+                write!(prefix, "pico2w/{hostname}").map_err(|_| MqttConfigError::Prefix)?;
+                prefix
+            }
+        };
+        let discovery_prefix = match env.discovery_prefix {
+            Some("") => None,
+            Some(prefix) if is_valid_prefix(prefix) => {
+                Some(String::try_from(prefix).map_err(|_| MqttConfigError::DiscoveryPrefix)?)
+            }
+            Some(_) => return Err(MqttConfigError::DiscoveryPrefix),
+            None => String::try_from(DEFAULT_DISCOVERY_PREFIX).ok(),
+        };
+        Ok(Some(MqttSettings {
+            broker: String::try_from(broker).map_err(|_| MqttConfigError::Broker)?,
+            port,
+            client_id,
+            username: env.username.map(String::try_from).transpose().map_err(|_| MqttConfigError::Username)?,
+            password: env.password.map(String::try_from).transpose().map_err(|_| MqttConfigError::Password)?,
+            prefix,
+            discovery_prefix,
+        }))
+    }
+
+    /// `<prefix>/<leaf>`.
+    pub fn topic(&self, leaf: &str) -> Option<String<TOPIC_LEN>> {
+        let mut topic = String::new();
+        write!(topic, "{}/{leaf}", self.prefix).ok()?;
+        Some(topic)
+    }
+
+    /// `<prefix>/button/<button>`.
+    pub fn button_topic(&self, button: u8) -> Option<String<TOPIC_LEN>> {
+        let mut topic = String::new();
+        write!(topic, "{}/{BUTTON_TOPIC}/{button}", self.prefix).ok()?;
+        Some(topic)
+    }
+}
+
+// Wildcards would make the topics unusable for publishing
+fn is_valid_prefix(prefix: &str) -> bool {
+    !prefix.is_empty() && !prefix.starts_with('/') && !prefix.ends_with('/') && !prefix.contains(['+', '#', '\0'])
+}
+
+/// A command topic payload: an animation number like the `/N` URLs, a word
+/// (`pause`, `resume`, `next`, `previous`), or the JSON form the WebSocket
+/// takes, e.g. `{"SetSpeed":50}`.
+pub fn parse_command(payload: &[u8]) -> Option<Command> {
+    let text = core::str::from_utf8(payload).ok()?.trim();
+    if let Ok(animation) = text.parse() {
+        return Some(Command::PlayAnimation(animation));
+    }
+    let word = [
+        ("pause", Command::Pause),
+        ("resume", Command::Resume),
+        ("next", Command::Next),
+        ("previous", Command::Previous),
+    ]
+    .into_iter()
+    .find_map(|(word, command)| text.eq_ignore_ascii_case(word).then_some(command));
+    word.or_else(|| serde_json_core::from_str(text).ok().map(|(command, _)| command))
+}
+
+/// A brightness topic payload: a level from 1 (dimmest) to
+/// `Brightness::LEVELS`. Sliders may send `3.0`.
+pub fn parse_brightness(payload: &[u8]) -> Option<Command> {
+    let text = core::str::from_utf8(payload).ok()?.trim();
+    let level = text.strip_suffix(".0").unwrap_or(text).parse().ok()?;
+    Brightness::from_level(level).map(Command::SetBrightness)
+}
+
+/// Everything the broker needs to open a session. The session is always
+/// clean, so subscriptions are made again after every connect.
+#[derive(Debug, Clone, Copy)]
+pub struct Connect<'a> {
+    pub client_id: &'a str,
+    pub keep_alive_s: u16,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a str>,
+    /// Published, retained, by the broker if we vanish.
+    pub will: Option<(&'a str, &'a [u8])>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MqttError {
+    /// Not a valid MQTT packet.
+    Malformed,
+    /// Longer than the buffer it has to fit in.
+    TooLarge,
+}
+
+/// A packet from the broker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Packet<'a> {
+    /// Code 0 means accepted.
+    ConnAck { session_present: bool, code: u8 },
+    Publish { topic: &'a str, payload: &'a [u8] },
+    /// One code per filter: the QoS, or 0x80 if the subscription was refused.
+    SubAck { packet_id: u16, granted: &'a [u8] },
+    PingResp,
+    /// Anything else, by packet type.
+    Other(u8),
+}
+
+pub fn encode_connect(connect: &Connect, buf: &mut [u8]) -> Result<usize, MqttError> {
+    let field = |text: Option<&str>| text.map_or(0, |text| 2 + text.len());
+    let will_len = connect.will.map_or(0, |(topic, payload)| 2 + topic.len() + 2 + payload.len());
+    let remaining = 10 + 2 + connect.client_id.len() + will_len + field(connect.username) + field(connect.password);
+
+    // Clean session, plus a retained QoS 0 will and credentials when given
+    let mut flags = 0x02;
+    if connect.will.is_some() {
+        flags |= 0x04 | 0x20;
+    }
+    if connect.username.is_some() {
+        flags |= 0x80;
+    }
+    if connect.password.is_some() {
+        flags |= 0x40;
+    }
+
+    let mut out = Writer::start(buf, CONNECT << 4, remaining)?;
+    out.string("MQTT")?;
+    out.bytes(&[PROTOCOL_LEVEL, flags])?;
+    out.bytes(&connect.keep_alive_s.to_be_bytes())?;
+    out.string(connect.client_id)?;
+    if let Some((topic, payload)) = connect.will {
+        out.string(topic)?;
+        out.binary(payload)?;
+    }
+    if let Some(username) = connect.username {
+        out.string(username)?;
+    }
+    if let Some(password) = connect.password {
+        out.string(password)?;
+    }
+    Ok(out.len)
+}
+
+/// A QoS 0 publish.
+pub fn encode_publish(topic: &str, payload: &[u8], retain: bool, buf: &mut [u8]) -> Result<usize, MqttError> {
+    let mut out = Writer::start(buf, PUBLISH << 4 | retain as u8, 2 + topic.len() + payload.len())?;
+    out.string(topic)?;
+    out.bytes(payload)?;
+    Ok(out.len)
+}
+
+/// Subscribe to topic filters at QoS 0.
+pub fn encode_subscribe(packet_id: u16, filters: &[&str], buf: &mut [u8]) -> Result<usize, MqttError> {
+    let remaining = 2 + filters.iter().map(|filter| 2 + filter.len() + 1).sum::<usize>();
+    // The reserved flag bits of SUBSCRIBE are 0b0010
+    let mut out = Writer::start(buf, SUBSCRIBE << 4 | 0x02, remaining)?;
+    out.bytes(&packet_id.to_be_bytes())?;
+    for filter in filters {
+        out.string(filter)?;
+        out.bytes(&[0])?;
+    }
+    Ok(out.len)
+}
+
+/// Decode the first packet in `buf`. `Ok(None)` means more bytes are needed;
+/// otherwise the packet comes back with the number of bytes it used.
+pub fn decode(buf: &[u8]) -> Result<Option<(Packet<'_>, usize)>, MqttError> {
+    let Some(&first) = buf.first() else {
+        return Ok(None);
+    };
+    let mut remaining = 0usize;
+    let mut at = 1;
+    loop {
+        if at == MAX_HEADER_LEN {
+            return Err(MqttError::Malformed);
+        }
+        let Some(&byte) = buf.get(at) else {
+            return Ok(None);
+        };
+        remaining |= usize::from(byte & 0x7f) << (7 * (at - 1));
+        at += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    let end = at + remaining;
+    if end > MAX_PACKET_LEN {
+        return Err(MqttError::TooLarge);
+    }
+    let Some(body) = buf.get(at..end) else {
+        return Ok(None);
+    };
+
+    let packet = match first >> 4 {
+        CONNACK => match body {
+            [flags, code] => Packet::ConnAck { session_present: flags & 1 != 0, code: *code },
+            _ => return Err(MqttError::Malformed),
+        },
+        PUBLISH => {
+            let qos = (first >> 1) & 0x3;
+            let topic_len = usize::from(u16::from_be_bytes([*body.first().ok_or(MqttError::Malformed)?, *body.get(1).ok_or(MqttError::Malformed)?]));
+            let topic = body.get(2..2 + topic_len).ok_or(MqttError::Malformed)?;
+            let topic = core::str::from_utf8(topic).map_err(|_| MqttError::Malformed)?;
+            // QoS 1 and 2 carry a packet id; we only subscribe at QoS 0
+            let payload_at = 2 + topic_len + if qos > 0 { 2 } else { 0 };
+            let payload = body.get(payload_at..).ok_or(MqttError::Malformed)?;
+            Packet::Publish { topic, payload }
+        }
+        SUBACK => match body {
+            [high, low, granted @ ..] if !granted.is_empty() => {
+                Packet::SubAck { packet_id: u16::from_be_bytes([*high, *low]), granted }
+            }
+            _ => return Err(MqttError::Malformed),
+        },
+        PINGRESP => Packet::PingResp,
+        other => Packet::Other(other),
+    };
+    Ok(Some((packet, end)))
+}
+
+struct Writer<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl<'b> Writer<'b> {
+    fn start(buf: &'b mut [u8], first: u8, mut remaining: usize) -> Result<Self, MqttError> {
+        let mut out = Writer { buf, len: 0 };
+        out.bytes(&[first])?;
+        loop {
+            let mut byte = (remaining & 0x7f) as u8;
+            remaining >>= 7;
+            if remaining > 0 {
+                byte |= 0x80;
+            }
+            out.bytes(&[byte])?;
+            if remaining == 0 {
+                return Ok(out);
+            }
+        }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), MqttError> {
+        let end = self.len + bytes.len();
+        self.buf.get_mut(self.len..end).ok_or(MqttError::TooLarge)?.copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    fn binary(&mut self, bytes: &[u8]) -> Result<(), MqttError> {
+        let len = u16::try_from(bytes.len()).map_err(|_| MqttError::TooLarge)?;
+        self.bytes(&len.to_be_bytes())?;
+        self.bytes(bytes)
+    }
+
+    fn string(&mut self, text: &str) -> Result<(), MqttError> {
+        self.binary(text.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> MqttSettings {
+        let env = MqttEnv { broker: Some("192.168.68.10"), ..MqttEnv::default() };
+        MqttSettings::from_env(&env, "pico2w").unwrap().unwrap()
+    }
+
+    #[test]
+    fn env_defaults() {
+        assert_eq!(MqttSettings::from_env(&MqttEnv::default(), "pico2w"), Ok(None));
+        let settings = settings();
+        assert_eq!(settings.port, DEFAULT_PORT);
+        assert_eq!(settings.client_id.as_str(), "pico2w");
+        assert_eq!(settings.topic(COMMAND_TOPIC).unwrap().as_str(), "pico2w/pico2w/command");
+        assert_eq!(settings.button_topic(2).unwrap().as_str(), "pico2w/pico2w/button/2");
+        assert_eq!(settings.discovery_prefix.as_deref(), Some("homeassistant"));
+    }
+
+    #[test]
+    fn env_overrides_and_errors() {
+        let env = MqttEnv {
+            broker: Some("broker.lan"),
+            port: Some("8883"),
+            client_id: Some("desk-oled"),
+            username: Some("pico"),
+            password: Some("secret"),
+            prefix: Some("home/desk"),
+            discovery_prefix: Some(""),
+        };
+        let settings = MqttSettings::from_env(&env, "pico2w").unwrap().unwrap();
+        assert_eq!((settings.broker.as_str(), settings.port), ("broker.lan", 8883));
+        assert_eq!(settings.username.as_deref(), Some("pico"));
+        assert_eq!(settings.topic(STATUS_TOPIC).unwrap().as_str(), "home/desk/status");
+        assert_eq!(settings.discovery_prefix, None);
+
+        let with = |env: MqttEnv<'static>| MqttSettings::from_env(&MqttEnv { broker: Some("b"), ..env }, "pico2w");
+        assert_eq!(with(MqttEnv { port: Some("0"), ..MqttEnv::default() }), Err(MqttConfigError::Port));
+        assert_eq!(with(MqttEnv { prefix: Some("home/#"), ..MqttEnv::default() }), Err(MqttConfigError::Prefix));
+        assert_eq!(with(MqttEnv { prefix: Some("home/"), ..MqttEnv::default() }), Err(MqttConfigError::Prefix));
+        let discovery = MqttEnv { discovery_prefix: Some("ha/+"), ..MqttEnv::default() };
+        assert_eq!(with(discovery), Err(MqttConfigError::DiscoveryPrefix));
+        let long_id = "a-client-id-over-23-chars";
+        assert_eq!(with(MqttEnv { client_id: Some(long_id), ..MqttEnv::default() }), Err(MqttConfigError::ClientId));
+        assert_eq!(MqttSettings::from_env(&MqttEnv { broker: Some(" "), ..MqttEnv::default() }, "p"), Err(MqttConfigError::Broker));
+    }
+
+    #[test]
+    fn command_payloads() {
+        assert_eq!(parse_command(b"3"), Some(Command::PlayAnimation(3)));
+        assert_eq!(parse_command(b" 2\n"), Some(Command::PlayAnimation(2)));
+        assert_eq!(parse_command(b"Pause"), Some(Command::Pause));
+        assert_eq!(parse_command(b"next"), Some(Command::Next));
+        assert_eq!(parse_command(br#"{"SetSpeed":50}"#), Some(Command::SetSpeed(50)));
+        assert_eq!(parse_command(br#""Resume""#), Some(Command::Resume));
+        assert_eq!(parse_command(b"dance"), None);
+        assert_eq!(parse_command(&[0xff]), None);
+
+        assert_eq!(parse_brightness(b"1"), Some(Command::SetBrightness(Brightness::Dimmest)));
+        assert_eq!(parse_brightness(b"4.0"), Some(Command::SetBrightness(Brightness::Bright)));
+        assert_eq!(parse_brightness(b"6"), None);
+    }
+
+    #[test]
+    fn connect_packet() {
+        let connect = Connect {
+            client_id: "pico",
+            keep_alive_s: 60,
+            username: Some("u"),
+            password: Some("pw"),
+            will: Some(("p/status", b"offline")),
+        };
+        let mut buf = [0u8; MAX_PACKET_LEN];
+        let len = encode_connect(&connect, &mut buf).unwrap();
+        assert_eq!(
+            &buf[..len],
+            b"\x10\x2a\x00\x04MQTT\x04\xe6\x00\x3c\x00\x04pico\x00\x08p/status\x00\x07offline\x00\x01u\x00\x02pw"
+        );
+
+        let bare = Connect { username: None, password: None, will: None, ..connect };
+        let len = encode_connect(&bare, &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"\x10\x10\x00\x04MQTT\x04\x02\x00\x3c\x00\x04pico");
+    }
+
+    #[test]
+    fn publish_round_trip() {
+        let mut buf = [0u8; MAX_PACKET_LEN];
+        let len = encode_publish("p/animation", b"2", true, &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"\x31\x0e\x00\x0bp/animation2");
+        assert_eq!(decode(&buf[..len + 3]), Ok(Some((Packet::Publish { topic: "p/animation", payload: b"2" }, len))));
+        assert_eq!(decode(&buf[..len - 1]), Ok(None));
+
+        // A payload long enough for a two-byte length
+        let payload = [b'x'; 200];
+        let len = encode_publish("t", &payload, false, &mut buf).unwrap();
+        assert_eq!(&buf[..3], &[0x30, 0xcb, 0x01]);
+        assert_eq!(decode(&buf[..len]).unwrap().unwrap().0, Packet::Publish { topic: "t", payload: &payload });
+
+        assert_eq!(encode_publish("t", &[0; MAX_PACKET_LEN], false, &mut buf), Err(MqttError::TooLarge));
+    }
+
+    #[test]
+    fn qos1_publish_skips_packet_id() {
+        let packet = b"\x32\x08\x00\x01t\x00\x07hi!";
+        assert_eq!(decode(packet), Ok(Some((Packet::Publish { topic: "t", payload: b"hi!" }, 10))));
+    }
+
+    #[test]
+    fn subscribe_packet() {
+        let mut buf = [0u8; MAX_PACKET_LEN];
+        let len = encode_subscribe(1, &["p/command"], &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"\x82\x0e\x00\x01\x00\x09p/command\x00");
+        let len = encode_subscribe(2, &["a", "b/c"], &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"\x82\x0c\x00\x02\x00\x01a\x00\x00\x03b/c\x00");
+    }
+
+    #[test]
+    fn broker_packets() {
+        assert_eq!(decode(b"\x20\x02\x00\x00"), Ok(Some((Packet::ConnAck { session_present: false, code: 0 }, 4))));
+        assert_eq!(decode(b"\x20\x02\x00\x05").unwrap().unwrap().0, Packet::ConnAck { session_present: false, code: 5 });
+        assert_eq!(decode(b"\x90\x03\x00\x01\x00"), Ok(Some((Packet::SubAck { packet_id: 1, granted: &[0] }, 5))));
+        assert_eq!(decode(b"\x90\x04\x00\x01\x00\x80").unwrap().unwrap().0, Packet::SubAck { packet_id: 1, granted: &[0, 0x80] });
+        assert_eq!(decode(b"\x90\x02\x00\x01"), Err(MqttError::Malformed));
+        assert_eq!(decode(b"\xd0\x00"), Ok(Some((Packet::PingResp, 2))));
+        assert_eq!(decode(b"\xb0\x02\x00\x01"), Ok(Some((Packet::Other(11), 4))));
+        assert_eq!(decode(b""), Ok(None));
+        assert_eq!(decode(b"\x20\x03\x00\x00\x00"), Err(MqttError::Malformed));
+        assert_eq!(decode(b"\x30\xff\xff\xff\xff\x01"), Err(MqttError::Malformed));
+        assert_eq!(decode(b"\x30\xff\x7f"), Err(MqttError::TooLarge));
+    }
+}
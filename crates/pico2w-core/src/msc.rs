@@ -0,0 +1,384 @@
+// file: msc.rs
+// desc: USB mass storage: bulk-only transport wrappers and the SCSI commands
+// hosts send a removable drive
+//
+// Every transfer starts with a command block wrapper (CBW) from the host and
+// ends with a command status wrapper (CSW) from the device; the SCSI command
+// inside decides what moves in between. `Disk` answers the commands and tells
+// the firmware which blocks to read or write.
+
+/// Mass storage class, SCSI transparent command set, bulk-only transport.
+pub const MSC_CLASS: u8 = 0x08;
+pub const MSC_SUBCLASS_SCSI: u8 = 0x06;
+pub const MSC_PROTOCOL_BULK_ONLY: u8 = 0x50;
+/// Class requests: the highest LUN (IN), and a transport reset (OUT).
+pub const REQUEST_GET_MAX_LUN: u8 = 0xfe;
+pub const REQUEST_RESET: u8 = 0xff;
+
+pub const BLOCK_SIZE: usize = 512;
+pub const CBW_LEN: usize = 31;
+pub const CSW_LEN: usize = 13;
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+const CBW_DIRECTION_IN: u8 = 0x80;
+
+const TEST_UNIT_READY: u8 = 0x00;
+const REQUEST_SENSE: u8 = 0x03;
+const INQUIRY: u8 = 0x12;
+const MODE_SENSE_6: u8 = 0x1a;
+const START_STOP_UNIT: u8 = 0x1b;
+const PREVENT_ALLOW_MEDIUM_REMOVAL: u8 = 0x1e;
+const READ_FORMAT_CAPACITIES: u8 = 0x23;
+const READ_CAPACITY_10: u8 = 0x25;
+const READ_10: u8 = 0x28;
+const WRITE_10: u8 = 0x2a;
+const VERIFY_10: u8 = 0x2f;
+const SYNCHRONIZE_CACHE_10: u8 = 0x35;
+const MODE_SENSE_10: u8 = 0x5a;
+
+// Longest fixed reply, the standard INQUIRY data
+const RESPONSE_LEN: usize = 36;
+
+/// A command block wrapper.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandBlock {
+    pub tag: u32,
+    /// Bytes the host expects to move after the CBW.
+    pub data_len: u32,
+    /// Device to host.
+    pub data_in: bool,
+    pub lun: u8,
+    command: [u8; 16],
+    command_len: usize,
+}
+
+impl CommandBlock {
+    /// Parse the CBW packet; None if it is not one.
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        let packet: &[u8; CBW_LEN] = packet.try_into().ok()?;
+        let u32_at = |offset: usize| u32::from_le_bytes(packet[offset..offset + 4].try_into().unwrap());
+        if u32_at(0) != CBW_SIGNATURE {
+            return None;
+        }
+        let command_len = packet[14] as usize & 0x1f;
+        if !(1..=16).contains(&command_len) {
+            return None;
+        }
+        let mut command = [0u8; 16];
+        command.copy_from_slice(&packet[15..31]);
+        Some(CommandBlock {
+            tag: u32_at(4),
+            data_len: u32_at(8),
+            data_in: packet[12] & CBW_DIRECTION_IN != 0,
+            lun: packet[13] & 0x0f,
+            command,
+            command_len,
+        })
+    }
+
+    /// The SCSI command.
+    pub fn command(&self) -> &[u8] {
+        &self.command[..self.command_len]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Status {
+    Passed = 0,
+    Failed = 1,
+    PhaseError = 2,
+}
+
+/// The command status wrapper; `residue` is how much of `data_len` went unused.
+pub fn status_block(tag: u32, residue: u32, status: Status) -> [u8; CSW_LEN] {
+    let mut csw = [0u8; CSW_LEN];
+    csw[..4].copy_from_slice(&CSW_SIGNATURE.to_le_bytes());
+    csw[4..8].copy_from_slice(&tag.to_le_bytes());
+    csw[8..12].copy_from_slice(&residue.to_le_bytes());
+    csw[12] = status as u8;
+    csw
+}
+
+/// Why the last command failed, as REQUEST SENSE reports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Sense {
+    pub key: u8,
+    pub asc: u8,
+    pub ascq: u8,
+}
+
+impl Sense {
+    pub const NONE: Sense = Sense { key: 0x00, asc: 0x00, ascq: 0x00 };
+    pub const NOT_PRESENT: Sense = Sense { key: 0x02, asc: 0x3a, ascq: 0x00 };
+    pub const WRITE_FAULT: Sense = Sense { key: 0x03, asc: 0x03, ascq: 0x00 };
+    pub const INVALID_COMMAND: Sense = Sense { key: 0x05, asc: 0x20, ascq: 0x00 };
+    pub const OUT_OF_RANGE: Sense = Sense { key: 0x05, asc: 0x21, ascq: 0x00 };
+    pub const INVALID_FIELD: Sense = Sense { key: 0x05, asc: 0x24, ascq: 0x00 };
+}
+
+/// A small reply sent as is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Response {
+    data: [u8; RESPONSE_LEN],
+    len: usize,
+}
+
+impl Response {
+    fn new(bytes: &[u8]) -> Self {
+        let mut data = [0u8; RESPONSE_LEN];
+        data[..bytes.len()].copy_from_slice(bytes);
+        Response { data, len: bytes.len() }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+/// What the firmware does with a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reply {
+    /// Send this, cut to what the host asked for.
+    Data(Response),
+    /// Send `blocks` blocks starting at `lba`.
+    Read { lba: u32, blocks: u32 },
+    /// Take `blocks` blocks from the host and store them at `lba`.
+    Write { lba: u32, blocks: u32 },
+    /// Nothing to move; passed.
+    Done,
+    /// Write out anything cached, then pass.
+    Sync,
+    /// The host let go of the drive: write out anything cached and pass. The
+    /// medium reads as absent from now on.
+    Eject,
+    /// Failed; the sense data says why.
+    Failed,
+}
+
+/// One removable drive of `blocks` 512-byte blocks.
+pub struct Disk {
+    blocks: u32,
+    vendor: &'static [u8; 8],
+    product: &'static [u8; 16],
+    sense: Sense,
+    ejected: bool,
+}
+
+impl Disk {
+    pub const fn new(blocks: u32, vendor: &'static [u8; 8], product: &'static [u8; 16]) -> Self {
+        Disk { blocks, vendor, product, sense: Sense::NONE, ejected: false }
+    }
+
+    pub fn is_ejected(&self) -> bool {
+        self.ejected
+    }
+
+    /// Present again, after the host reset the device.
+    pub fn insert(&mut self) {
+        self.ejected = false;
+        self.sense = Sense::NONE;
+    }
+
+    /// Fail the current command for a reason found while carrying it out.
+    pub fn fail(&mut self, sense: Sense) {
+        self.sense = sense;
+    }
+
+    pub fn command(&mut self, command: &[u8]) -> Reply {
+        let Some(&opcode) = command.first() else {
+            return self.failed(Sense::INVALID_COMMAND);
+        };
+        let byte = |index: usize| command.get(index).copied().unwrap_or(0);
+
+        // Enough still works without a medium for the host to notice it is gone
+        let needs_medium = !matches!(opcode, REQUEST_SENSE | INQUIRY | START_STOP_UNIT | PREVENT_ALLOW_MEDIUM_REMOVAL);
+        if self.ejected && needs_medium {
+            return self.failed(Sense::NOT_PRESENT);
+        }
+
+        match opcode {
+            TEST_UNIT_READY | PREVENT_ALLOW_MEDIUM_REMOVAL | VERIFY_10 => Reply::Done,
+            REQUEST_SENSE => {
+                let sense = core::mem::replace(&mut self.sense, Sense::NONE);
+                // Fixed format, current error, 10 more bytes
+                let mut data = [0u8; 18];
+                data[0] = 0x70;
+                data[2] = sense.key;
+                data[7] = 10;
+                data[12] = sense.asc;
+                data[13] = sense.ascq;
+                Reply::Data(Response::new(&data))
+            }
+            INQUIRY => {
+                // Vital product data pages are optional; hosts fall back
+                if byte(1) & 0x01 != 0 {
+                    return self.failed(Sense::INVALID_FIELD);
+                }
+                let mut data = [0u8; RESPONSE_LEN];
+                // Direct access, removable, SPC-2, 31 more bytes
+                data[..5].copy_from_slice(&[0x00, 0x80, 0x04, 0x02, 31]);
+                data[8..16].copy_from_slice(self.vendor);
+                data[16..32].copy_from_slice(self.product);
+                data[32..36].copy_from_slice(b"1.0 ");
+                Reply::Data(Response::new(&data))
+            }
+            READ_CAPACITY_10 => {
+                let mut data = [0u8; 8];
+                data[..4].copy_from_slice(&(self.blocks - 1).to_be_bytes());
+                data[4..].copy_from_slice(&(BLOCK_SIZE as u32).to_be_bytes());
+                Reply::Data(Response::new(&data))
+            }
+            READ_FORMAT_CAPACITIES => {
+                // One descriptor: the current, formatted capacity
+                let mut data = [0u8; 12];
+                data[3] = 8;
+                data[4..8].copy_from_slice(&self.blocks.to_be_bytes());
+                data[8] = 0x02;
+                data[9..12].copy_from_slice(&(BLOCK_SIZE as u32).to_be_bytes()[1..]);
+                Reply::Data(Response::new(&data))
+            }
+            // Header only: no block descriptors, not write protected
+            MODE_SENSE_6 => Reply::Data(Response::new(&[3, 0, 0, 0])),
+            MODE_SENSE_10 => Reply::Data(Response::new(&[0, 6, 0, 0, 0, 0, 0, 0])),
+            START_STOP_UNIT => {
+                let (start, load_eject) = (byte(4) & 0x01 != 0, byte(4) & 0x02 != 0);
+                match (load_eject, start) {
+                    (true, false) => {
+                        self.ejected = true;
+                        Reply::Eject
+                    }
+                    (true, true) => {
+                        self.ejected = false;
+                        Reply::Done
+                    }
+                    _ => Reply::Done,
+                }
+            }
+            READ_10 | WRITE_10 => {
+                let lba = u32::from_be_bytes([byte(2), byte(3), byte(4), byte(5)]);
+                let blocks = u16::from_be_bytes([byte(7), byte(8)]) as u32;
+                if lba.checked_add(blocks).is_none_or(|end| end > self.blocks) {
+                    return self.failed(Sense::OUT_OF_RANGE);
+                }
+                if opcode == READ_10 { Reply::Read { lba, blocks } } else { Reply::Write { lba, blocks } }
+            }
+            SYNCHRONIZE_CACHE_10 => Reply::Sync,
+            _ => self.failed(Sense::INVALID_COMMAND),
+        }
+    }
+
+    fn failed(&mut self, sense: Sense) -> Reply {
+        self.sense = sense;
+        Reply::Failed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCKS: u32 = 4096;
+
+    fn disk() -> Disk {
+        Disk::new(BLOCKS, b"Pico2W  ", b"Animations      ")
+    }
+
+    fn data(reply: Reply) -> [u8; RESPONSE_LEN] {
+        let Reply::Data(response) = reply else { panic!("no data: {reply:?}") };
+        let mut data = [0u8; RESPONSE_LEN];
+        data[..response.len].copy_from_slice(response.as_bytes());
+        data
+    }
+
+    fn sense(disk: &mut Disk) -> (u8, u8) {
+        let data = data(disk.command(&[REQUEST_SENSE, 0, 0, 0, 18, 0]));
+        (data[2], data[12])
+    }
+
+    #[test]
+    fn parses_command_block() {
+        // READ(10) of 8 blocks at 0x20, 4096 bytes in
+        let mut packet = [0u8; CBW_LEN];
+        packet[..4].copy_from_slice(b"USBC");
+        packet[4..8].copy_from_slice(&0xdead_beefu32.to_le_bytes());
+        packet[8..12].copy_from_slice(&4096u32.to_le_bytes());
+        packet[12] = 0x80;
+        packet[14] = 10;
+        packet[15..25].copy_from_slice(&[READ_10, 0, 0, 0, 0, 0x20, 0, 0, 8, 0]);
+
+        let block = CommandBlock::parse(&packet).unwrap();
+        assert_eq!((block.tag, block.data_len, block.data_in, block.lun), (0xdead_beef, 4096, true, 0));
+        assert_eq!(block.command().len(), 10);
+        assert_eq!(disk().command(block.command()), Reply::Read { lba: 0x20, blocks: 8 });
+
+        assert_eq!(CommandBlock::parse(&packet[..30]), None);
+        packet[0] = b'X';
+        assert_eq!(CommandBlock::parse(&packet), None);
+    }
+
+    #[test]
+    fn status_block_layout() {
+        let csw = status_block(7, 512, Status::Failed);
+        assert_eq!(&csw[..4], b"USBS");
+        assert_eq!(&csw[4..8], &7u32.to_le_bytes());
+        assert_eq!(&csw[8..12], &512u32.to_le_bytes());
+        assert_eq!(csw[12], 1);
+    }
+
+    #[test]
+    fn describes_the_drive() {
+        let mut disk = disk();
+        let inquiry = data(disk.command(&[INQUIRY, 0, 0, 0, 36, 0]));
+        assert_eq!(inquiry[1], 0x80);
+        assert_eq!(&inquiry[8..16], b"Pico2W  ");
+        assert_eq!(&inquiry[16..32], b"Animations      ");
+
+        let capacity = data(disk.command(&[READ_CAPACITY_10, 0, 0, 0, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(&capacity[..8], &[0, 0, 0x0f, 0xff, 0, 0, 2, 0]);
+
+        let formats = data(disk.command(&[READ_FORMAT_CAPACITIES, 0, 0, 0, 0, 0, 0, 0, 12, 0]));
+        assert_eq!(&formats[..12], &[0, 0, 0, 8, 0, 0, 0x10, 0, 2, 0, 2, 0]);
+
+        assert_eq!(disk.command(&[INQUIRY, 1, 0x80, 0, 36, 0]), Reply::Failed);
+        assert_eq!(sense(&mut disk), (0x05, 0x24));
+    }
+
+    #[test]
+    fn reads_and_writes_stay_on_the_drive() {
+        let mut disk = disk();
+        assert_eq!(disk.command(&[WRITE_10, 0, 0, 0, 0x0f, 0xf8, 0, 0, 8, 0]), Reply::Write { lba: 4088, blocks: 8 });
+        assert_eq!(disk.command(&[WRITE_10, 0, 0, 0, 0x0f, 0xf9, 0, 0, 8, 0]), Reply::Failed);
+        assert_eq!(sense(&mut disk), (0x05, 0x21));
+        assert_eq!(disk.command(&[READ_10, 0, 0xff, 0xff, 0xff, 0xff, 0, 0, 1, 0]), Reply::Failed);
+        // Reading the sense clears it
+        assert_eq!(sense(&mut disk), (0x05, 0x21));
+        assert_eq!(sense(&mut disk), (0, 0));
+    }
+
+    #[test]
+    fn eject_takes_the_medium_away() {
+        let mut disk = disk();
+        assert_eq!(disk.command(&[TEST_UNIT_READY, 0, 0, 0, 0, 0]), Reply::Done);
+        assert_eq!(disk.command(&[START_STOP_UNIT, 0, 0, 0, 0x02, 0]), Reply::Eject);
+        assert!(disk.is_ejected());
+
+        assert_eq!(disk.command(&[TEST_UNIT_READY, 0, 0, 0, 0, 0]), Reply::Failed);
+        assert_eq!(sense(&mut disk), (0x02, 0x3a));
+        assert_eq!(disk.command(&[READ_10, 0, 0, 0, 0, 0, 0, 0, 1, 0]), Reply::Failed);
+        assert!(matches!(disk.command(&[INQUIRY, 0, 0, 0, 36, 0]), Reply::Data(_)));
+
+        disk.insert();
+        assert_eq!(disk.command(&[TEST_UNIT_READY, 0, 0, 0, 0, 0]), Reply::Done);
+    }
+
+    #[test]
+    fn unknown_commands_fail() {
+        let mut disk = disk();
+        assert_eq!(disk.command(&[0xff]), Reply::Failed);
+        assert_eq!(sense(&mut disk), (0x05, 0x20));
+        assert_eq!(disk.command(&[]), Reply::Failed);
+        assert_eq!(disk.command(&[SYNCHRONIZE_CACHE_10, 0, 0, 0, 0, 0, 0, 0, 0, 0]), Reply::Sync);
+    }
+}
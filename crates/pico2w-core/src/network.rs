@@ -0,0 +1,370 @@
+// file: network.rs
+// desc: IPv4 addressing settings, their parsing and their flash record
+//
+// The firmware reads NET_MODE, NET_ADDRESS, NET_GATEWAY, NET_DNS,
+// NET_HOSTNAME and NET_DHCP_TIMEOUT_S with `option_env!` and hands them to
+// `NetSettings::from_env`. Unset variables fall back to DHCP with the old
+// 192.168.68.100/24 address as the static fallback. Settings saved from the
+// console take the same keys and are kept as a `"P2WN"` record; they win over
+// the build-time ones.
+use heapless::{String, Vec};
+use serde::{Deserialize, Serialize};
+
+use crate::record::{self, RecordError};
+
+pub type Ipv4 = [u8; 4];
+
+/// embassy-net keeps at most three DNS servers.
+pub const MAX_DNS_SERVERS: usize = 3;
+/// Longest DHCP hostname embassy-net will send.
+pub const HOSTNAME_LEN: usize = 32;
+
+/// Bumped whenever `NetSettings` changes shape.
+pub const NET_RECORD_VERSION: u8 = 1;
+const NET_MAGIC: &[u8; 4] = b"P2WN";
+/// One flash page; the record is padded to it with 0xFF.
+pub const NET_RECORD_LEN: usize = 256;
+
+pub const DEFAULT_HOSTNAME: &str = "pico2w";
+pub const DEFAULT_DHCP_TIMEOUT_S: u16 = 15;
+const DEFAULT_ADDRESS: StaticSettings = StaticSettings {
+    address: [192, 168, 68, 100],
+    prefix_len: 24,
+    gateway: Some([192, 168, 68, 1]),
+    dns: Vec::new(),
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StaticSettings {
+    pub address: Ipv4,
+    pub prefix_len: u8,
+    pub gateway: Option<Ipv4>,
+    pub dns: Vec<Ipv4, MAX_DNS_SERVERS>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum NetMode {
+    Dhcp,
+    Static(StaticSettings),
+    /// Try DHCP, and use the static settings if no lease arrives in time.
+    DhcpWithFallback { fallback: StaticSettings, timeout_s: u16 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NetSettings {
+    pub mode: NetMode,
+    /// Sent with DHCP requests so routers list the board by name.
+    pub hostname: String<HOSTNAME_LEN>,
+}
+
+impl Default for NetSettings {
+    fn default() -> Self {
+        NetSettings {
+            mode: NetMode::DhcpWithFallback { fallback: DEFAULT_ADDRESS, timeout_s: DEFAULT_DHCP_TIMEOUT_S },
+            hostname: String::try_from(DEFAULT_HOSTNAME).unwrap_or_default(),
+        }
+    }
+}
+
+/// The raw build-time variables, or console keys, each `None` when unset.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NetEnv<'a> {
+    /// `dhcp`, `static` or `dhcp-fallback`.
+    pub mode: Option<&'a str>,
+    /// `a.b.c.d/prefix`.
+    pub address: Option<&'a str>,
+    pub gateway: Option<&'a str>,
+    /// Comma-separated, at most three.
+    pub dns: Option<&'a str>,
+    pub hostname: Option<&'a str>,
+    pub dhcp_timeout_s: Option<&'a str>,
+}
+
+impl<'a> NetEnv<'a> {
+    /// `mode=static address=10.0.0.7/24 ...`, keyed like the variables
+    /// without `NET_` and in lower case; `None` on an unknown key.
+    pub fn from_args(args: &'a str) -> Option<Self> {
+        let mut env = NetEnv::default();
+        for arg in args.split_ascii_whitespace() {
+            let (key, value) = arg.split_once('=')?;
+            let slot = match key {
+                "mode" => &mut env.mode,
+                "address" => &mut env.address,
+                "gateway" => &mut env.gateway,
+                "dns" => &mut env.dns,
+                "hostname" => &mut env.hostname,
+                "dhcp_timeout_s" => &mut env.dhcp_timeout_s,
+                _ => return None,
+            };
+            *slot = Some(value);
+        }
+        Some(env)
+    }
+}
+
+/// Which variable was malformed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigError {
+    Mode,
+    Address,
+    Gateway,
+    Dns,
+    Hostname,
+    Timeout,
+}
+
+impl ConfigError {
+    /// What the value should look like, for the console.
+    pub fn message(self) -> &'static str {
+        match self {
+            ConfigError::Mode => "mode: dhcp, static or dhcp-fallback",
+            ConfigError::Address => "address: a.b.c.d/prefix",
+            ConfigError::Gateway => "gateway: a.b.c.d",
+            ConfigError::Dns => "dns: up to three addresses, comma-separated",
+            ConfigError::Hostname => "hostname: letters, digits and inner hyphens",
+            ConfigError::Timeout => "dhcp_timeout_s: seconds, at least 1",
+        }
+    }
+}
+
+impl NetSettings {
+    pub fn from_env(env: &NetEnv) -> Result<Self, ConfigError> {
+        let hostname = match env.hostname {
+            Some(name) if is_valid_hostname(name) => String::try_from(name).map_err(|_| ConfigError::Hostname)?,
+            Some(_) => return Err(ConfigError::Hostname),
+            None => String::try_from(DEFAULT_HOSTNAME).unwrap_or_default(),
+        };
+
+        // Without NET_ADDRESS the old fixed address is kept, gateway included
+        let mut addressing = match env.address {
+            Some(cidr) => {
+                let (address, prefix_len) = parse_cidr(cidr).ok_or(ConfigError::Address)?;
+                StaticSettings { address, prefix_len, gateway: None, dns: Vec::new() }
+            }
+            None => DEFAULT_ADDRESS,
+        };
+        if let Some(gateway) = env.gateway {
+            addressing.gateway = Some(parse_ipv4(gateway).ok_or(ConfigError::Gateway)?);
+        }
+        if let Some(dns) = env.dns {
+            addressing.dns = parse_dns_list(dns).ok_or(ConfigError::Dns)?;
+        }
+
+        let timeout_s = match env.dhcp_timeout_s {
+            Some(seconds) => seconds.trim().parse().ok().filter(|&s| s > 0).ok_or(ConfigError::Timeout)?,
+            None => DEFAULT_DHCP_TIMEOUT_S,
+        };
+
+        let mode = match env.mode.map(str::trim) {
+            Some("dhcp") => NetMode::Dhcp,
+            Some("static") => NetMode::Static(addressing),
+            Some("dhcp-fallback") | None => NetMode::DhcpWithFallback { fallback: addressing, timeout_s },
+            Some(_) => return Err(ConfigError::Mode),
+        };
+
+        Ok(NetSettings { mode, hostname })
+    }
+
+    /// Encode into a full record, ready to be written to an erased page.
+    pub fn to_record<'b>(&self, buf: &'b mut [u8; NET_RECORD_LEN]) -> &'b [u8] {
+        // Under 80 bytes with every field at its longest
+        if record::encode(NET_MAGIC, NET_RECORD_VERSION, self, buf).is_none() {
+            unreachable!("network settings always fit a record");
+        }
+        buf
+    }
+
+    pub fn from_record(record: &[u8]) -> Result<Self, RecordError> {
+        let (_, payload) = record::decode(NET_MAGIC, NET_RECORD_VERSION..=NET_RECORD_VERSION, record)?;
+        let settings: NetSettings = postcard::from_bytes(payload).map_err(|_| RecordError::Corrupt)?;
+        settings.is_valid().then_some(settings).ok_or(RecordError::Corrupt)
+    }
+
+    // Re-check fields read back from flash
+    fn is_valid(&self) -> bool {
+        let addressing_ok = |addressing: &StaticSettings| (1..=32).contains(&addressing.prefix_len);
+        let mode_ok = match &self.mode {
+            NetMode::Dhcp => true,
+            NetMode::Static(addressing) => addressing_ok(addressing),
+            NetMode::DhcpWithFallback { fallback, timeout_s } => addressing_ok(fallback) && *timeout_s > 0,
+        };
+        mode_ok && is_valid_hostname(&self.hostname)
+    }
+}
+
+pub fn parse_ipv4(text: &str) -> Option<Ipv4> {
+    let mut octets = [0u8; 4];
+    let mut parts = text.trim().split('.');
+    for octet in &mut octets {
+        let part = parts.next()?;
+        // Reject "+1" and leading zeros, which some tools read as octal
+        if part.is_empty() || part.len() > 3 || !part.bytes().all(|b| b.is_ascii_digit()) || (part.len() > 1 && part.starts_with('0')) {
+            return None;
+        }
+        *octet = part.parse().ok()?;
+    }
+    parts.next().is_none().then_some(octets)
+}
+
+pub fn parse_cidr(text: &str) -> Option<(Ipv4, u8)> {
+    let (address, prefix_len) = text.trim().split_once('/')?;
+    let prefix_len: u8 = prefix_len.parse().ok().filter(|len| (1..=32).contains(len))?;
+    Some((parse_ipv4(address)?, prefix_len))
+}
+
+pub fn parse_dns_list(text: &str) -> Option<Vec<Ipv4, MAX_DNS_SERVERS>> {
+    let mut servers = Vec::new();
+    for server in text.split(',').filter(|s| !s.trim().is_empty()) {
+        servers.push(parse_ipv4(server)?).ok()?;
+    }
+    Some(servers)
+}
+
+/// One DNS label: letters, digits and inner hyphens.
+pub fn is_valid_hostname(name: &str) -> bool {
+    (1..=HOSTNAME_LEN).contains(&name.len())
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    #[test]
+    fn defaults_keep_the_old_address_as_fallback() {
+        let settings = NetSettings::from_env(&NetEnv::default()).unwrap();
+        assert_eq!(settings, NetSettings::default());
+        assert_eq!(settings.hostname.as_str(), "pico2w");
+        let NetMode::DhcpWithFallback { fallback, timeout_s } = settings.mode else {
+            panic!("expected DHCP with fallback");
+        };
+        assert_eq!(fallback.address, [192, 168, 68, 100]);
+        assert_eq!(fallback.gateway, Some([192, 168, 68, 1]));
+        assert_eq!(timeout_s, DEFAULT_DHCP_TIMEOUT_S);
+    }
+
+    #[test]
+    fn static_mode() {
+        let env = NetEnv {
+            mode: Some("static"),
+            address: Some("10.0.0.7/16"),
+            gateway: Some("10.0.0.1"),
+            dns: Some("1.1.1.1, 9.9.9.9"),
+            hostname: Some("oled-board"),
+            ..NetEnv::default()
+        };
+        let settings = NetSettings::from_env(&env).unwrap();
+        assert_eq!(settings.hostname.as_str(), "oled-board");
+        assert_eq!(
+            settings.mode,
+            NetMode::Static(StaticSettings {
+                address: [10, 0, 0, 7],
+                prefix_len: 16,
+                gateway: Some([10, 0, 0, 1]),
+                dns: Vec::from_slice(&[[1, 1, 1, 1], [9, 9, 9, 9]]).unwrap(),
+            })
+        );
+    }
+
+    #[test]
+    fn explicit_address_drops_default_gateway() {
+        let env = NetEnv { mode: Some("static"), address: Some("172.16.1.2/24"), ..NetEnv::default() };
+        let NetMode::Static(settings) = NetSettings::from_env(&env).unwrap().mode else {
+            panic!("expected static");
+        };
+        assert_eq!(settings.gateway, None);
+    }
+
+    #[test]
+    fn dhcp_only() {
+        let env = NetEnv { mode: Some("dhcp"), ..NetEnv::default() };
+        assert_eq!(NetSettings::from_env(&env).unwrap().mode, NetMode::Dhcp);
+    }
+
+    #[test]
+    fn fallback_timeout() {
+        let env = NetEnv { dhcp_timeout_s: Some("30"), ..NetEnv::default() };
+        let NetMode::DhcpWithFallback { timeout_s, .. } = NetSettings::from_env(&env).unwrap().mode else {
+            panic!("expected DHCP with fallback");
+        };
+        assert_eq!(timeout_s, 30);
+    }
+
+    #[test]
+    fn malformed_variables() {
+        let with = |env: NetEnv<'static>| NetSettings::from_env(&env).unwrap_err();
+        assert_eq!(with(NetEnv { mode: Some("auto"), ..NetEnv::default() }), ConfigError::Mode);
+        assert_eq!(with(NetEnv { address: Some("10.0.0.7"), ..NetEnv::default() }), ConfigError::Address);
+        assert_eq!(with(NetEnv { address: Some("10.0.0.7/33"), ..NetEnv::default() }), ConfigError::Address);
+        assert_eq!(with(NetEnv { gateway: Some("10.0.0"), ..NetEnv::default() }), ConfigError::Gateway);
+        assert_eq!(with(NetEnv { dns: Some("1.1.1.1,2.2.2.2,3.3.3.3,4.4.4.4"), ..NetEnv::default() }), ConfigError::Dns);
+        assert_eq!(with(NetEnv { hostname: Some("pico_2w"), ..NetEnv::default() }), ConfigError::Hostname);
+        assert_eq!(with(NetEnv { dhcp_timeout_s: Some("0"), ..NetEnv::default() }), ConfigError::Timeout);
+    }
+
+    #[test]
+    fn console_keys() {
+        let env = NetEnv::from_args("mode=static  address=10.0.0.7/24 dhcp_timeout_s=5").unwrap();
+        assert_eq!(env, NetEnv { mode: Some("static"), address: Some("10.0.0.7/24"), dhcp_timeout_s: Some("5"), ..NetEnv::default() });
+        assert_eq!(NetEnv::from_args("mode=static ip=10.0.0.7/24"), None);
+        assert_eq!(NetEnv::from_args("static"), None);
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let env = NetEnv {
+            mode: Some("static"),
+            address: Some("255.255.255.255/32"),
+            gateway: Some("255.255.255.255"),
+            dns: Some("255.255.255.255,255.255.255.255,255.255.255.255"),
+            hostname: Some(&"h".repeat(HOSTNAME_LEN)),
+            ..NetEnv::default()
+        };
+        let settings = NetSettings::from_env(&env).unwrap();
+        let mut buf = [0u8; NET_RECORD_LEN];
+        let record = settings.to_record(&mut buf);
+        assert!(record.starts_with(b"P2WN\x01"));
+        assert_eq!(NetSettings::from_record(record), Ok(settings));
+
+        assert_eq!(NetSettings::from_record(&[0xff; NET_RECORD_LEN]), Err(RecordError::Empty));
+        // The saved networks are not network settings
+        let mut networks = [0u8; crate::credentials::RECORD_LEN];
+        crate::credentials::SavedNetworks::new().to_record(&mut networks);
+        assert_eq!(NetSettings::from_record(&networks), Err(RecordError::Corrupt));
+    }
+
+    #[test]
+    fn invalid_records_are_refused() {
+        let settings = NetSettings { hostname: String::try_from("pico_2w").unwrap(), ..NetSettings::default() };
+        let mut buf = [0u8; NET_RECORD_LEN];
+        assert_eq!(NetSettings::from_record(settings.to_record(&mut buf)), Err(RecordError::Corrupt));
+    }
+
+    #[test]
+    fn ipv4_parsing() {
+        assert_eq!(parse_ipv4("192.168.68.100"), Some([192, 168, 68, 100]));
+        assert_eq!(parse_ipv4(" 0.0.0.0 "), Some([0, 0, 0, 0]));
+        for bad in ["", "1.2.3", "1.2.3.4.5", "256.1.1.1", "01.2.3.4", "1.2.3.+4", "a.b.c.d", "1..2.3"] {
+            assert_eq!(parse_ipv4(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn hostnames() {
+        assert!(is_valid_hostname("pico2w"));
+        assert!(is_valid_hostname("oled-1"));
+        assert!(!is_valid_hostname(""));
+        assert!(!is_valid_hostname("-pico"));
+        assert!(!is_valid_hostname("pico-"));
+        assert!(!is_valid_hostname("pico.local"));
+        assert!(!is_valid_hostname("a-really-long-hostname-for-a-tiny-board"));
+    }
+}
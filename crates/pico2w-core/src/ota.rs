@@ -0,0 +1,487 @@
+// file: ota.rs
+// desc: over-the-air firmware images: checked, cut into flash pages, verified
+//
+// An update is the raw application binary (`objcopy -O binary`) followed by
+// a trailer from the host signing tool, sent as the body of
+// `POST /api/firmware`. `ImageWriter` checks the vector table at its start
+// against the slot it will run from and hands the image to the DFU partition
+// in whole erase pages, hashing it on the way. Only once the trailer's
+// ed25519 signature and version check out does the firmware ask the
+// bootloader to swap.
+//
+// Trailer, little-endian: magic `P2WS`, packed version (u32), image length
+// (u32), signature (64 bytes). The signature covers the first twelve trailer
+// bytes followed by the SHA-512 of the image. Signing itself is only built
+// with the `sign` feature, for the host tool; the firmware just verifies.
+use core::fmt::Write as _;
+
+#[cfg(any(test, feature = "sign"))]
+use ed25519_dalek::{Signer, SigningKey};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use heapless::String;
+use sha2::{Digest, Sha512};
+
+use crate::http::Status;
+
+/// Flash erase size; every write covers exactly one page.
+pub const PAGE_LEN: usize = 4096;
+/// What erased NOR flash reads as.
+pub const ERASED: u8 = 0xff;
+pub const TRAILER_LEN: usize = 4 + 4 + 4 + SIGNATURE_LEN;
+pub const SIGNATURE_LEN: usize = 64;
+pub const PUBLIC_KEY_LEN: usize = 32;
+
+const TRAILER_MAGIC: [u8; 4] = *b"P2WS";
+const DIGEST_LEN: usize = 64;
+// Trailer head plus image digest
+const SIGNED_LEN: usize = 12 + DIGEST_LEN;
+// Room per field when a version is packed into a u32
+const VERSION_FIELD_BITS: u32 = 10;
+
+/// Where an application image has to be linked to run from the active slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    /// Address and size of the active partition.
+    pub active_start: u32,
+    pub active_len: u32,
+    /// Bounds the initial stack pointer must fall within.
+    pub ram_start: u32,
+    pub ram_end: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OtaError {
+    /// No Content-Length, or zero.
+    Empty,
+    /// Larger than the active partition.
+    TooLarge,
+    /// The vector table does not belong to an image linked for the active
+    /// slot; an ELF, UF2 or a build without the bootloader layout.
+    NotFirmware,
+    /// The connection ended before the declared length arrived.
+    Truncated,
+    /// The previous update has not confirmed itself yet.
+    Unconfirmed,
+    Flash,
+    /// No trailer, or one that does not match the image.
+    Unsigned,
+    /// Not signed with the key the firmware was built with.
+    BadSignature,
+    /// Older than the running firmware.
+    Downgrade,
+    /// The firmware was built without a public key, so nothing can be
+    /// verified.
+    NoKey,
+}
+
+impl OtaError {
+    pub fn status(self) -> Status {
+        match self {
+            OtaError::Empty => Status::LengthRequired,
+            OtaError::TooLarge => Status::PayloadTooLarge,
+            OtaError::NotFirmware | OtaError::Unsigned => Status::UnprocessableContent,
+            OtaError::Truncated => Status::BadRequest,
+            OtaError::Unconfirmed | OtaError::Downgrade => Status::Conflict,
+            OtaError::BadSignature => Status::Forbidden,
+            OtaError::Flash => Status::InternalServerError,
+            OtaError::NoKey => Status::ServiceUnavailable,
+        }
+    }
+
+    /// The error as a JSON body.
+    pub fn json(self) -> &'static str {
+        match self {
+            OtaError::Empty => r#"{"error":"firmware image required"}"#,
+            OtaError::TooLarge => r#"{"error":"image larger than the firmware slot"}"#,
+            OtaError::NotFirmware => r#"{"error":"not a firmware image for this board"}"#,
+            OtaError::Truncated => r#"{"error":"image truncated"}"#,
+            OtaError::Unconfirmed => r#"{"error":"running firmware not confirmed yet"}"#,
+            OtaError::Flash => r#"{"error":"flash write failed"}"#,
+            OtaError::Unsigned => r#"{"error":"image is not signed"}"#,
+            OtaError::BadSignature => r#"{"error":"signature does not match the firmware key"}"#,
+            OtaError::Downgrade => r#"{"error":"image is older than the running firmware"}"#,
+            OtaError::NoKey => r#"{"error":"firmware built without FIRMWARE_KEY"}"#,
+        }
+    }
+
+    /// One OLED line, 21 characters at most, for rejections worth showing on
+    /// the board itself.
+    pub fn oled_text(self) -> Option<&'static str> {
+        match self {
+            OtaError::Unsigned => Some("Update: not signed"),
+            OtaError::BadSignature => Some("Update: bad signature"),
+            OtaError::Downgrade => Some("Update: downgrade"),
+            OtaError::NoKey => Some("Update: no key built"),
+            _ => None,
+        }
+    }
+}
+
+/// `major.minor.patch` packed into a u32 that orders like the version, each
+/// part below 1024.
+pub fn pack_version(version: &str) -> Option<u32> {
+    let mut parts = version.trim().splitn(3, '.').map(|part| part.parse::<u32>().ok());
+    let mut packed = 0;
+    for _ in 0..3 {
+        let part = parts.next().flatten().filter(|&part| part < 1 << VERSION_FIELD_BITS)?;
+        packed = packed << VERSION_FIELD_BITS | part;
+    }
+    Some(packed)
+}
+
+pub fn version_text(packed: u32) -> String<12> {
+    let field = |shift: u32| packed >> shift & ((1 << VERSION_FIELD_BITS) - 1);
+    let mut text = String::new();
+    write!(text, "{}.{}.{}", field(2 * VERSION_FIELD_BITS), field(VERSION_FIELD_BITS), field(0)).ok();
+    text
+}
+
+/// A hex-encoded ed25519 public key, as given in FIRMWARE_KEY.
+pub fn parse_public_key(hex: &str) -> Option<VerifyingKey> {
+    VerifyingKey::from_bytes(&parse_key_bytes(hex)?).ok()
+}
+
+/// 64 hex digits as 32 key bytes; public keys and the signing tool's secret
+/// seeds are both written this way.
+pub fn parse_key_bytes(hex: &str) -> Option<[u8; PUBLIC_KEY_LEN]> {
+    let hex = hex.trim().as_bytes();
+    if hex.len() != 2 * PUBLIC_KEY_LEN || !hex.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    let mut key = [0u8; PUBLIC_KEY_LEN];
+    for (byte, pair) in key.iter_mut().zip(hex.chunks(2)) {
+        let pair = core::str::from_utf8(pair).ok()?;
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+    Some(key)
+}
+
+/// The trailer that signs `image` as `version`; the host signing tool appends
+/// it.
+#[cfg(any(test, feature = "sign"))]
+pub fn sign(image: &[u8], version: u32, key: &SigningKey) -> [u8; TRAILER_LEN] {
+    let digest: [u8; DIGEST_LEN] = Sha512::digest(image).into();
+    let head = trailer_head(version, image.len() as u32);
+    let signature = key.sign(&signed_message(&head, &digest));
+    let mut trailer = [0u8; TRAILER_LEN];
+    trailer[..12].copy_from_slice(&head);
+    trailer[12..].copy_from_slice(&signature.to_bytes());
+    trailer
+}
+
+fn trailer_head(version: u32, image_len: u32) -> [u8; 12] {
+    let mut head = [0u8; 12];
+    head[..4].copy_from_slice(&TRAILER_MAGIC);
+    head[4..8].copy_from_slice(&version.to_le_bytes());
+    head[8..].copy_from_slice(&image_len.to_le_bytes());
+    head
+}
+
+fn signed_message(head: &[u8; 12], digest: &[u8; DIGEST_LEN]) -> [u8; SIGNED_LEN] {
+    let mut message = [0u8; SIGNED_LEN];
+    message[..12].copy_from_slice(head);
+    message[12..].copy_from_slice(digest);
+    message
+}
+
+/// An image that is in flash, not yet trusted.
+pub struct ReceivedImage {
+    pub len: usize,
+    /// Packed, see `pack_version`.
+    pub version: u32,
+    head: [u8; 12],
+    digest: [u8; DIGEST_LEN],
+    signature: [u8; SIGNATURE_LEN],
+}
+
+impl ReceivedImage {
+    /// Check the signature against `key` and refuse anything older than the
+    /// running firmware.
+    pub fn verify(&self, key: &VerifyingKey, running_version: u32) -> Result<(), OtaError> {
+        let signature = Signature::from_bytes(&self.signature);
+        key.verify(&signed_message(&self.head, &self.digest), &signature)
+            .map_err(|_| OtaError::BadSignature)?;
+        if self.version < running_version {
+            return Err(OtaError::Downgrade);
+        }
+        Ok(())
+    }
+}
+
+/// Collects a signed image arriving in arbitrary pieces and writes it out a
+/// page at a time through a `(offset, page)` callback.
+pub struct ImageWriter {
+    layout: Layout,
+    /// Image length, without the trailer.
+    len: usize,
+    /// Image bytes accepted so far.
+    received: usize,
+    page: [u8; PAGE_LEN],
+    filled: usize,
+    hasher: Sha512,
+    trailer: [u8; TRAILER_LEN],
+    trailer_filled: usize,
+}
+
+impl ImageWriter {
+    /// Expect `body_len` bytes: the image, then its trailer.
+    pub fn new(body_len: usize, layout: Layout) -> Result<Self, OtaError> {
+        if body_len == 0 {
+            return Err(OtaError::Empty);
+        }
+        let len = body_len.checked_sub(TRAILER_LEN).filter(|&len| len > 0).ok_or(OtaError::Unsigned)?;
+        if len > layout.active_len as usize {
+            return Err(OtaError::TooLarge);
+        }
+        Ok(ImageWriter {
+            layout,
+            len,
+            received: 0,
+            page: [ERASED; PAGE_LEN],
+            filled: 0,
+            hasher: Sha512::new(),
+            trailer: [0; TRAILER_LEN],
+            trailer_filled: 0,
+        })
+    }
+
+    /// Body bytes still to come.
+    pub fn remaining(&self) -> usize {
+        self.len - self.received + TRAILER_LEN - self.trailer_filled
+    }
+
+    /// Take the next piece of the body; anything past the declared length is
+    /// ignored.
+    pub fn write<E>(
+        &mut self,
+        mut data: &[u8],
+        mut flash: impl FnMut(usize, &[u8]) -> Result<(), E>,
+    ) -> Result<(), OtaError> {
+        data = &data[..data.len().min(self.remaining())];
+        while !data.is_empty() && self.received < self.len {
+            let take = data.len().min(PAGE_LEN - self.filled).min(self.len - self.received);
+            self.page[self.filled..self.filled + take].copy_from_slice(&data[..take]);
+            self.hasher.update(&data[..take]);
+            self.filled += take;
+            self.received += take;
+            data = &data[take..];
+            if self.filled == PAGE_LEN {
+                self.flush(&mut flash)?;
+            }
+        }
+        self.trailer[self.trailer_filled..self.trailer_filled + data.len()].copy_from_slice(data);
+        self.trailer_filled += data.len();
+        Ok(())
+    }
+
+    /// Write the padded last page once the whole body is in, and read the
+    /// trailer.
+    pub fn finish<E>(mut self, mut flash: impl FnMut(usize, &[u8]) -> Result<(), E>) -> Result<ReceivedImage, OtaError> {
+        if self.remaining() > 0 {
+            return Err(OtaError::Truncated);
+        }
+        if self.filled > 0 {
+            self.page[self.filled..].fill(ERASED);
+            self.flush(&mut flash)?;
+        }
+
+        let mut head = [0u8; 12];
+        head.copy_from_slice(&self.trailer[..12]);
+        let version = u32::from_le_bytes([head[4], head[5], head[6], head[7]]);
+        if head != trailer_head(version, self.len as u32) {
+            return Err(OtaError::Unsigned);
+        }
+        let mut signature = [0u8; SIGNATURE_LEN];
+        signature.copy_from_slice(&self.trailer[12..]);
+        Ok(ReceivedImage { len: self.len, version, head, digest: self.hasher.finalize().into(), signature })
+    }
+
+    fn flush<E>(&mut self, flash: &mut impl FnMut(usize, &[u8]) -> Result<(), E>) -> Result<(), OtaError> {
+        let offset = (self.received - 1) / PAGE_LEN * PAGE_LEN;
+        // Nothing reaches flash before the image has shown it belongs here
+        if offset == 0 && !self.is_vector_table() {
+            return Err(OtaError::NotFirmware);
+        }
+        flash(offset, &self.page).map_err(|_| OtaError::Flash)?;
+        self.filled = 0;
+        Ok(())
+    }
+
+    // Initial stack pointer in RAM, reset handler a Thumb address in the slot
+    fn is_vector_table(&self) -> bool {
+        if self.received < 8 {
+            return false;
+        }
+        let word = |at: usize| u32::from_le_bytes([self.page[at], self.page[at + 1], self.page[at + 2], self.page[at + 3]]);
+        let (stack, reset) = (word(0), word(4));
+        let layout = &self.layout;
+        let active_end = layout.active_start + layout.active_len;
+        stack % 4 == 0
+            && (layout.ram_start + 4..=layout.ram_end).contains(&stack)
+            && reset & 1 == 1
+            && (layout.active_start..active_end).contains(&(reset & !1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    const LAYOUT: Layout = Layout {
+        active_start: 0x1000_9000,
+        active_len: 16 * PAGE_LEN as u32,
+        ram_start: 0x2000_0000,
+        ram_end: 0x2008_0000,
+    };
+    const KEY: [u8; 32] = [7; 32];
+    const VERSION: &str = "0.2.0";
+
+    fn image(len: usize) -> Vec<u8> {
+        let mut image: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        image[..4].copy_from_slice(&0x2008_0000u32.to_le_bytes());
+        image[4..8].copy_from_slice(&0x1000_9101u32.to_le_bytes());
+        image
+    }
+
+    fn signed(image: &[u8], version: &str) -> Vec<u8> {
+        let trailer = sign(image, pack_version(version).unwrap(), &SigningKey::from_bytes(&KEY));
+        [image, &trailer].concat()
+    }
+
+    // Flash as a list of (offset, page) writes
+    type Pages = Vec<(usize, Vec<u8>)>;
+
+    fn upload(body: &[u8], piece: usize) -> Result<(ReceivedImage, Pages), OtaError> {
+        let mut pages = Vec::new();
+        let mut flash = |offset: usize, page: &[u8]| -> Result<(), ()> {
+            pages.push((offset, page.to_vec()));
+            Ok(())
+        };
+        let mut writer = ImageWriter::new(body.len(), LAYOUT)?;
+        for chunk in body.chunks(piece) {
+            writer.write(chunk, &mut flash)?;
+        }
+        let received = writer.finish(&mut flash)?;
+        Ok((received, pages))
+    }
+
+    fn verify(body: &[u8], running: &str) -> Result<(), OtaError> {
+        let key = SigningKey::from_bytes(&KEY).verifying_key();
+        upload(body, 1000)?.0.verify(&key, pack_version(running).unwrap())
+    }
+
+    #[test]
+    fn pages_and_padding() {
+        let image = image(2 * PAGE_LEN + 100);
+        let body = signed(&image, VERSION);
+        for piece in [1, 7, 1024, PAGE_LEN, body.len()] {
+            let (received, pages) = upload(&body, piece).unwrap();
+            assert_eq!(received.len, image.len());
+            assert_eq!(pages.iter().map(|(offset, _)| *offset).collect::<Vec<_>>(), [0, PAGE_LEN, 2 * PAGE_LEN]);
+            assert!(pages.iter().all(|(_, page)| page.len() == PAGE_LEN));
+            assert_eq!(&pages[1].1[..], &image[PAGE_LEN..2 * PAGE_LEN]);
+            // The trailer never reaches flash
+            assert_eq!(&pages[2].1[..100], &image[2 * PAGE_LEN..]);
+            assert!(pages[2].1[100..].iter().all(|&b| b == ERASED));
+        }
+    }
+
+    #[test]
+    fn size_limits() {
+        assert_eq!(ImageWriter::new(0, LAYOUT).err(), Some(OtaError::Empty));
+        assert_eq!(ImageWriter::new(TRAILER_LEN, LAYOUT).err(), Some(OtaError::Unsigned));
+        let too_large = LAYOUT.active_len as usize + 1 + TRAILER_LEN;
+        assert_eq!(ImageWriter::new(too_large, LAYOUT).err(), Some(OtaError::TooLarge));
+        let full = signed(&image(LAYOUT.active_len as usize), VERSION);
+        assert_eq!(upload(&full, 512).unwrap().1.len(), 16);
+    }
+
+    #[test]
+    fn truncated_and_extra_bytes() {
+        let body = signed(&image(PAGE_LEN + 1), VERSION);
+        let mut writer = ImageWriter::new(body.len(), LAYOUT).unwrap();
+        writer.write(&body[..body.len() - 1], |_, _| Ok::<(), ()>(())).unwrap();
+        assert_eq!(writer.finish(|_, _| Ok::<(), ()>(())).err(), Some(OtaError::Truncated));
+
+        let mut writer = ImageWriter::new(body.len(), LAYOUT).unwrap();
+        writer.write(&[&body[..], b"extra"].concat(), |_, _| Ok::<(), ()>(())).unwrap();
+        assert_eq!(writer.remaining(), 0);
+        assert!(writer.finish(|_, _| Ok::<(), ()>(())).is_ok());
+    }
+
+    #[test]
+    fn foreign_images_are_refused() {
+        // An ELF header
+        let mut elf = image(PAGE_LEN);
+        elf[..4].copy_from_slice(b"\x7fELF");
+        assert_eq!(upload(&signed(&elf, VERSION), 256).err(), Some(OtaError::NotFirmware));
+
+        // Linked for the start of flash rather than the active slot
+        let mut plain = image(PAGE_LEN);
+        plain[4..8].copy_from_slice(&0x1000_0101u32.to_le_bytes());
+        assert_eq!(upload(&signed(&plain, VERSION), 256).err(), Some(OtaError::NotFirmware));
+
+        // Too short to even hold a vector table
+        assert_eq!(upload(&signed(&[0, 0, 8, 0x20], VERSION), 4).err(), Some(OtaError::NotFirmware));
+    }
+
+    #[test]
+    fn flash_errors_stop_the_upload() {
+        let body = signed(&image(PAGE_LEN), VERSION);
+        let mut writer = ImageWriter::new(body.len(), LAYOUT).unwrap();
+        assert_eq!(writer.write(&body, |_, _| Err(())), Err(OtaError::Flash));
+    }
+
+    #[test]
+    fn signatures() {
+        let image = image(PAGE_LEN + 10);
+        assert_eq!(verify(&signed(&image, VERSION), "0.1.0"), Ok(()));
+        // Reinstalling the same version is fine
+        assert_eq!(verify(&signed(&image, VERSION), VERSION), Ok(()));
+        assert_eq!(verify(&signed(&image, "0.1.9"), VERSION), Err(OtaError::Downgrade));
+
+        // A plain image with some bytes where the trailer would be
+        assert_eq!(verify(&image, "0.1.0"), Err(OtaError::Unsigned));
+
+        let mut tampered = signed(&image, VERSION);
+        tampered[100] ^= 1;
+        assert_eq!(verify(&tampered, "0.1.0"), Err(OtaError::BadSignature));
+
+        // Claiming a newer version breaks the signature
+        let mut bumped = signed(&image, VERSION);
+        let at = image.len() + 4;
+        bumped[at..at + 4].copy_from_slice(&pack_version("9.0.0").unwrap().to_le_bytes());
+        assert_eq!(verify(&bumped, "0.1.0"), Err(OtaError::BadSignature));
+
+        let other = SigningKey::from_bytes(&[8; 32]);
+        let foreign = [&image[..], &sign(&image, pack_version(VERSION).unwrap(), &other)].concat();
+        assert_eq!(verify(&foreign, "0.1.0"), Err(OtaError::BadSignature));
+    }
+
+    #[test]
+    fn versions_and_keys() {
+        assert!(pack_version("1.0.0") > pack_version("0.999.999"));
+        assert!(pack_version("0.2.0") > pack_version("0.1.10"));
+        assert_eq!(pack_version("1.2"), None);
+        assert_eq!(pack_version("1.2.1024"), None);
+        assert_eq!(version_text(pack_version("3.14.15").unwrap()).as_str(), "3.14.15");
+
+        let public = SigningKey::from_bytes(&KEY).verifying_key();
+        let hex: std::string::String = public.as_bytes().iter().map(|b| std::format!("{b:02x}")).collect();
+        assert_eq!(parse_public_key(&hex), Some(public));
+        assert_eq!(parse_public_key(&hex[2..]), None);
+        assert_eq!(parse_public_key(&hex.replace(&hex[..2], "zz")), None);
+    }
+
+    #[test]
+    fn rejections_fit_the_oled() {
+        for error in [OtaError::Unsigned, OtaError::BadSignature, OtaError::Downgrade, OtaError::NoKey] {
+            assert!(error.oled_text().unwrap().len() <= 21);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+// file: provision.rs
+// desc: setup portal served from the board's own access point
+//
+// When the board cannot join a network it opens `pico2w-setup-XXXX`, hands
+// out addresses with `dhcp_server`, answers every lookup with
+// `captive_dns`, and serves the page rendered here. The form posts back to
+// `SETUP_PATH` as `application/x-www-form-urlencoded`.
+use core::fmt::{self, Write as _};
+
+use heapless::{String, Vec};
+
+use crate::credentials::{Auth, Credentials, CredentialsError, PASSWORD_LEN, SSID_LEN};
+use crate::network::Ipv4;
+use crate::scan::NetworkList;
+
+pub const AP_ADDRESS: Ipv4 = [192, 168, 4, 1];
+pub const AP_PREFIX_LEN: u8 = 24;
+/// First address the DHCP server hands out.
+pub const AP_POOL_START: Ipv4 = [192, 168, 4, 2];
+pub const AP_HOST: &str = "192.168.4.1";
+pub const AP_URL: &str = "http://192.168.4.1/";
+pub const SETUP_PATH: &str = "/setup";
+
+/// Room for the page with every network at its longest.
+pub const SETUP_PAGE_LEN: usize = 4096;
+
+/// `pico2w-setup-` and the last two bytes of the MAC, so boards side by
+/// side stay apart.
+pub fn ap_ssid(mac: &[u8; 6]) -> String<SSID_LEN> {
+    let mut ssid = String::new();
+    write!(ssid, "pico2w-setup-{:02x}{:02x}", mac[4], mac[5]).ok();
+    ssid
+}
+
+/// Whether a request's `Host` header names the portal, with or without a
+/// port; connectivity checks for other hosts get redirected to it.
+pub fn is_portal_host(host: &str) -> bool {
+    let host = host.trim();
+    host.rsplit_once(':').map_or(host, |(name, _port)| name) == AP_HOST
+}
+
+/// Credentials from the posted setup form. `auth`, `hidden` and `priority`
+/// are optional; without `auth` the password decides between open and WPA2.
+pub fn parse_setup_form(body: &[u8]) -> Result<Credentials, CredentialsError> {
+    let body = core::str::from_utf8(body).map_err(|_| CredentialsError::Ssid)?;
+    let ssid: String<SSID_LEN> = form_value(body, "ssid").ok_or(CredentialsError::Ssid)?;
+    let password: String<PASSWORD_LEN> = form_value(body, "password").unwrap_or_default();
+    let auth = match form_value::<8>(body, "auth").as_deref() {
+        Some("open") => Auth::Open,
+        Some("wpa2") => Auth::Wpa2,
+        Some("wpa3") => Auth::Wpa3,
+        _ if password.is_empty() => Auth::Open,
+        _ => Auth::Wpa2,
+    };
+    // Checkboxes only send their name when ticked
+    let hidden = form_value::<8>(body, "hidden").is_some();
+    let priority = form_value::<3>(body, "priority").and_then(|p| p.parse().ok()).unwrap_or(0);
+    Credentials::with_options(&ssid, &password, auth, hidden, priority)
+}
+
+/// The decoded value of `key`, or `None` if it is missing, malformed or too
+/// long for `N`.
+pub fn form_value<const N: usize>(body: &str, key: &str) -> Option<String<N>> {
+    let raw = body.split('&').find_map(|pair| {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        (name == key).then_some(value)
+    })?;
+
+    let mut bytes: Vec<u8, N> = Vec::new();
+    let mut input = raw.bytes();
+    while let Some(b) = input.next() {
+        let decoded = match b {
+            b'+' => b' ',
+            b'%' => {
+                let high = (input.next()? as char).to_digit(16)?;
+                let low = (input.next()? as char).to_digit(16)?;
+                (high * 16 + low) as u8
+            }
+            b => b,
+        };
+        bytes.push(decoded).ok()?;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// The setup page: a form with the scanned networks to pick from.
+pub fn render_setup_page(networks: &NetworkList, message: Option<&str>) -> Option<String<SETUP_PAGE_LEN>> {
+    let mut page = String::new();
+    write_setup_page(&mut page, networks, message).ok()?;
+    Some(page)
+}
+
+fn write_setup_page(out: &mut impl fmt::Write, networks: &NetworkList, message: Option<&str>) -> fmt::Result {
+    out.write_str(SETUP_HEAD)?;
+    if let Some(message) = message {
+        out.write_str("<p class=msg>")?;
+        write_escaped(out, message)?;
+        out.write_str("</p>")?;
+    }
+    write!(out, "<form method=post action={SETUP_PATH}><label>Network<input name=ssid list=nets required maxlength={SSID_LEN}></label><datalist id=nets>")?;
+    for network in networks.iter() {
+        out.write_str("<option value=\"")?;
+        write_escaped(out, &network.ssid)?;
+        write!(out, "\">{} dBm, ch {}{}</option>", network.rssi, network.channel, if network.secure { "" } else { ", open" })?;
+    }
+    out.write_str("</datalist>")?;
+    write!(out, "<label>Password<input name=password type=password maxlength={PASSWORD_LEN}></label>")?;
+    out.write_str("<label>Security<select name=auth><option value=wpa2>WPA2</option><option value=wpa3>WPA3</option>\
+<option value=open>Open</option></select></label><label><input type=checkbox name=hidden class=c>Hidden network</label>\
+<label>Priority<input name=priority type=number min=0 max=255 value=0></label>")?;
+    out.write_str("<button>Save and connect</button></form></body></html>")
+}
+
+fn write_escaped(out: &mut impl fmt::Write, text: &str) -> fmt::Result {
+    for c in text.chars() {
+        match c {
+            '&' => out.write_str("&amp;")?,
+            '<' => out.write_str("&lt;")?,
+            '>' => out.write_str("&gt;")?,
+            '"' => out.write_str("&quot;")?,
+            '\'' => out.write_str("&#39;")?,
+            c => out.write_char(c)?,
+        }
+    }
+    Ok(())
+}
+
+const SETUP_HEAD: &str = "<!DOCTYPE html><html><head><meta charset=utf-8>\
+<meta name=viewport content=\"width=device-width,initial-scale=1\"><title>Pico 2W setup</title>\
+<style>body{font-family:sans-serif;max-width:24em;margin:1em auto;padding:0 1em}\
+label,input,select,button{display:block;width:100%;margin:.4em 0}.c{display:inline;width:auto}input,select,button{padding:.5em;box-sizing:border-box}\
+.msg{background:#fee;padding:.5em}</style></head><body><h1>WiFi setup</h1>";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan::{Network, MAX_NETWORKS};
+
+    #[test]
+    fn ap_name_uses_mac_suffix() {
+        assert_eq!(ap_ssid(&[0x28, 0xcd, 0xc1, 0x00, 0xab, 0x0f]).as_str(), "pico2w-setup-ab0f");
+    }
+
+    #[test]
+    fn portal_host_with_or_without_port() {
+        assert!(is_portal_host("192.168.4.1"));
+        assert!(is_portal_host("192.168.4.1:80"));
+        assert!(!is_portal_host("connectivitycheck.gstatic.com"));
+        assert!(!is_portal_host("captive.apple.com:80"));
+        assert!(!is_portal_host("192.168.4.10"));
+    }
+
+    #[test]
+    fn form_decoding() {
+        let credentials = parse_setup_form(b"ssid=Home+Net%21&password=p%40ss+word").unwrap();
+        assert_eq!(credentials.ssid.as_str(), "Home Net!");
+        assert_eq!(credentials.password.as_str(), "p@ss word");
+
+        let open = parse_setup_form(b"ssid=cafe").unwrap();
+        assert!(open.is_open());
+
+        assert_eq!(credentials.auth, Auth::Wpa2);
+        assert!(!credentials.hidden);
+
+        let lab = parse_setup_form(b"ssid=lab&password=sae-secret&auth=wpa3&hidden=on&priority=7").unwrap();
+        assert_eq!((lab.auth, lab.hidden, lab.priority), (Auth::Wpa3, true, 7));
+        assert_eq!(parse_setup_form(b"ssid=lab&password=secret123&auth=open").unwrap_err(), CredentialsError::Password);
+        assert_eq!(parse_setup_form(b"ssid=lab&priority=999").unwrap().priority, 0);
+
+        assert_eq!(parse_setup_form(b"password=secret123").unwrap_err(), CredentialsError::Ssid);
+        assert_eq!(parse_setup_form(b"ssid=net&password=short").unwrap_err(), CredentialsError::Password);
+        assert_eq!(form_value::<8>("a=%zz", "a"), None);
+        assert_eq!(form_value::<8>("a=%4", "a"), None);
+        assert_eq!(form_value::<4>("a=toolong", "a"), None);
+        assert_eq!(form_value::<8>("flag&a=1", "flag").unwrap().as_str(), "");
+    }
+
+    #[test]
+    fn page_escapes_ssids() {
+        let mut list = NetworkList::new();
+        list.add(Network { ssid: String::try_from("<b>&\"x\"").unwrap(), rssi: -42, channel: 3, secure: false });
+        let page = render_setup_page(&list, Some("Saved <ok>")).unwrap();
+        assert!(page.contains("&lt;b&gt;&amp;&quot;x&quot;"));
+        assert!(page.contains("-42 dBm, ch 3, open"));
+        assert!(page.contains("Saved &lt;ok&gt;"));
+        assert!(!page.contains("<b>"));
+    }
+
+    #[test]
+    fn full_page_fits() {
+        let mut list = NetworkList::new();
+        for i in 0..MAX_NETWORKS {
+            // Worst case: every character escaped
+            let mut ssid: String<SSID_LEN> = String::new();
+            while ssid.push(if i % 2 == 0 { '"' } else { '&' }).is_ok() {}
+            ssid.pop();
+            ssid.pop();
+            write!(ssid, "{i:02}").unwrap();
+            list.add(Network { ssid, rssi: -100, channel: 165, secure: false });
+        }
+        assert_eq!(list.len(), MAX_NETWORKS);
+        assert!(render_setup_page(&list, Some("Could not save the credentials")).is_some());
+    }
+}
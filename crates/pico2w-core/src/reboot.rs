@@ -0,0 +1,81 @@
+// file: reboot.rs
+// desc: where a reboot goes, and the picotool USB reset interface that asks for one
+//
+// `POST /api/reboot` and the reset interface both end in the boot ROM's
+// reboot call. The reset interface is the one pico-sdk's stdio USB exposes: a
+// vendor interface (class 0xff, subclass 0, protocol 1) that takes two class
+// requests, so picotool's `-f` can send a running board into BOOTSEL.
+use serde::Deserialize;
+
+pub const RESET_INTERFACE_CLASS: u8 = 0xff;
+pub const RESET_INTERFACE_SUBCLASS: u8 = 0x00;
+pub const RESET_INTERFACE_PROTOCOL: u8 = 0x01;
+/// Raspberry Pi's VID and the stdio USB PID picotool looks for.
+pub const USB_VID: u16 = 0x2e8a;
+pub const USB_PID: u16 = 0x000a;
+
+const RESET_REQUEST_BOOTSEL: u8 = 0x01;
+const RESET_REQUEST_FLASH: u8 = 0x02;
+
+// Boot ROM reboot flags
+const REBOOT_TYPE_NORMAL: u32 = 0x0;
+const REBOOT_TYPE_BOOTSEL: u32 = 0x2;
+const NO_RETURN_ON_SUCCESS: u32 = 0x100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[serde(rename_all = "lowercase")]
+pub enum RebootTarget {
+    /// Through the A/B bootloader into the application again.
+    #[default]
+    Normal,
+    /// The ROM's USB bootloader (BOOTSEL), as if the button were held.
+    Bootloader,
+}
+
+impl RebootTarget {
+    /// A reset interface request, by `bRequest`.
+    pub fn from_reset_request(request: u8) -> Option<Self> {
+        match request {
+            RESET_REQUEST_BOOTSEL => Some(RebootTarget::Bootloader),
+            RESET_REQUEST_FLASH => Some(RebootTarget::Normal),
+            _ => None,
+        }
+    }
+
+    /// `flags` for the boot ROM's `reboot(flags, delay_ms, p0, p1)`; p0 and p1
+    /// stay zero, leaving both BOOTSEL interfaces on and no activity LED.
+    pub fn rom_flags(self) -> u32 {
+        let reboot_type = match self {
+            RebootTarget::Normal => REBOOT_TYPE_NORMAL,
+            RebootTarget::Bootloader => REBOOT_TYPE_BOOTSEL,
+        };
+        reboot_type | NO_RETURN_ON_SUCCESS
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RebootTarget::Normal => "normal",
+            RebootTarget::Bootloader => "bootloader",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reset_requests() {
+        assert_eq!(RebootTarget::from_reset_request(0x01), Some(RebootTarget::Bootloader));
+        assert_eq!(RebootTarget::from_reset_request(0x02), Some(RebootTarget::Normal));
+        assert_eq!(RebootTarget::from_reset_request(0x00), None);
+        assert_eq!(RebootTarget::from_reset_request(0x03), None);
+    }
+
+    #[test]
+    fn rom_flags() {
+        assert_eq!(RebootTarget::Normal.rom_flags(), 0x100);
+        assert_eq!(RebootTarget::Bootloader.rom_flags(), 0x102);
+    }
+}
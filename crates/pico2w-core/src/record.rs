@@ -0,0 +1,68 @@
+// file: record.rs
+// desc: CRC-checked framing for records kept in flash
+//
+// A record is a 4-byte magic, a format version, the payload length (u16 LE),
+// the postcard-encoded payload and a CRC-32 (LE) over version, length and
+// payload, padded with 0xFF. Erased flash reads as 0xFF, so an unused slot
+// shows up as `RecordError::Empty` rather than as corruption.
+use core::ops::RangeInclusive;
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+use serde::Serialize;
+
+pub(crate) const MAGIC_LEN: usize = 4;
+pub(crate) const HEADER_LEN: usize = MAGIC_LEN + 1 + 2;
+const CRC_LEN: usize = 4;
+
+const RECORD_CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RecordError {
+    /// Nothing has been stored yet.
+    Empty,
+    /// Written by a firmware with another record format.
+    UnsupportedVersion(u8),
+    /// Bad length, CRC or payload.
+    Corrupt,
+}
+
+/// Fill `buf` with `value` as a record; `None` if it does not fit.
+pub(crate) fn encode<T: Serialize>(magic: &[u8; MAGIC_LEN], version: u8, value: &T, buf: &mut [u8]) -> Option<()> {
+    buf.fill(0xff);
+    let payload_end = buf.len().checked_sub(CRC_LEN)?;
+    let payload_len = postcard::to_slice(value, buf.get_mut(HEADER_LEN..payload_end)?).ok()?.len();
+    buf[..MAGIC_LEN].copy_from_slice(magic);
+    buf[MAGIC_LEN] = version;
+    buf[MAGIC_LEN + 1..HEADER_LEN].copy_from_slice(&(payload_len as u16).to_le_bytes());
+
+    let end = HEADER_LEN + payload_len;
+    let crc = RECORD_CRC.checksum(&buf[MAGIC_LEN..end]);
+    buf[end..end + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+    Some(())
+}
+
+/// The version and payload of a `magic` record in one of `versions`.
+pub(crate) fn decode<'r>(
+    magic: &[u8; MAGIC_LEN],
+    versions: RangeInclusive<u8>,
+    record: &'r [u8],
+) -> Result<(u8, &'r [u8]), RecordError> {
+    if record.len() < HEADER_LEN + CRC_LEN || &record[..MAGIC_LEN] != magic {
+        return Err(if record.iter().all(|&b| b == 0xff) { RecordError::Empty } else { RecordError::Corrupt });
+    }
+    let version = record[MAGIC_LEN];
+    if !versions.contains(&version) {
+        return Err(RecordError::UnsupportedVersion(version));
+    }
+
+    let payload_len = u16::from_le_bytes([record[MAGIC_LEN + 1], record[MAGIC_LEN + 2]]) as usize;
+    let end = HEADER_LEN + payload_len;
+    let Some(crc) = record.get(end..end + CRC_LEN) else {
+        return Err(RecordError::Corrupt);
+    };
+    if RECORD_CRC.checksum(&record[MAGIC_LEN..end]).to_le_bytes() != crc {
+        return Err(RecordError::Corrupt);
+    }
+    Ok((version, &record[HEADER_LEN..end]))
+}
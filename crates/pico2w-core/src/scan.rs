@@ -0,0 +1,153 @@
+// file: scan.rs
+// desc: Wi-Fi scan results
+//
+// The firmware fills a `NetworkList` from cyw43 scans. It decides which saved
+// network to join, fills the setup page and answers `GET /api/wifi/scan`.
+use heapless::{String, Vec};
+use serde::Serialize;
+
+use crate::credentials::SSID_LEN;
+
+/// Networks kept from one scan.
+pub const MAX_NETWORKS: usize = 12;
+/// Room for `NetworkList::to_json` with every SSID at its longest.
+pub const SCAN_JSON_LEN: usize = 3072;
+
+/// One access point seen by a scan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Network {
+    pub ssid: String<SSID_LEN>,
+    /// dBm.
+    pub rssi: i16,
+    pub channel: u8,
+    pub secure: bool,
+}
+
+/// Scan results, strongest first, one entry per SSID.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkList {
+    networks: Vec<Network, MAX_NETWORKS>,
+}
+
+impl NetworkList {
+    pub const fn new() -> Self {
+        NetworkList { networks: Vec::new() }
+    }
+
+    /// Add one scan result. Hidden networks are skipped, a repeated SSID keeps
+    /// its strongest signal and a full list drops its weakest entry.
+    pub fn add(&mut self, network: Network) {
+        if network.ssid.is_empty() {
+            return;
+        }
+        if let Some(known) = self.networks.iter_mut().find(|known| known.ssid == network.ssid) {
+            if network.rssi > known.rssi {
+                *known = network;
+            }
+        } else if self.networks.is_full() {
+            match self.networks.last_mut() {
+                Some(weakest) if weakest.rssi < network.rssi => *weakest = network,
+                _ => return,
+            }
+        } else {
+            self.networks.push(network).ok();
+        }
+        self.networks.sort_unstable_by_key(|network| core::cmp::Reverse(network.rssi));
+    }
+
+    pub fn get(&self, ssid: &str) -> Option<&Network> {
+        self.networks.iter().find(|network| network.ssid == ssid)
+    }
+
+    pub fn clear(&mut self) {
+        self.networks.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Network> {
+        self.networks.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.networks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+
+    /// `[{"ssid":"..","rssi":-52,"channel":6,"secure":true},...]`
+    pub fn to_json<'b>(&self, buf: &'b mut [u8]) -> Option<&'b [u8]> {
+        let len = serde_json_core::to_slice(&self.networks, buf).ok()?;
+        Some(&buf[..len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::fmt::Write as _;
+
+    use super::*;
+
+    fn network(ssid: &str, rssi: i16) -> Network {
+        Network { ssid: String::try_from(ssid).unwrap(), rssi, channel: 6, secure: true }
+    }
+
+    #[test]
+    fn keeps_strongest() {
+        let mut list = NetworkList::new();
+        list.add(network("office", -70));
+        list.add(network("home", -50));
+        list.add(network("office", -40));
+        list.add(network("office", -90));
+        list.add(network("", -10));
+        let names: Vec<&str, 4> = list.iter().map(|n| n.ssid.as_str()).collect();
+        assert_eq!(names, ["office", "home"]);
+        assert_eq!(list.get("office").unwrap().rssi, -40);
+        assert!(list.get("lab").is_none());
+    }
+
+    #[test]
+    fn full_list_drops_weakest() {
+        let mut list = NetworkList::new();
+        let mut name: String<8> = String::new();
+        for i in 0..MAX_NETWORKS as i16 {
+            name.clear();
+            write!(name, "net{i}").unwrap();
+            list.add(network(&name, -60 - i));
+        }
+        list.add(network("strong", -30));
+        list.add(network("weak", -99));
+        assert_eq!(list.len(), MAX_NETWORKS);
+        assert_eq!(list.iter().next().unwrap().ssid.as_str(), "strong");
+        assert!(list.iter().all(|n| n.ssid != "weak"));
+    }
+
+    #[test]
+    fn json() {
+        let mut list = NetworkList::new();
+        list.add(Network { ssid: String::try_from("lab \"2\"").unwrap(), rssi: -61, channel: 11, secure: false });
+        let mut buf = [0u8; SCAN_JSON_LEN];
+        assert_eq!(
+            list.to_json(&mut buf).unwrap(),
+            br#"[{"ssid":"lab \"2\"","rssi":-61,"channel":11,"secure":false}]"#
+        );
+        assert_eq!(NetworkList::new().to_json(&mut buf).unwrap(), b"[]");
+    }
+
+    #[test]
+    fn longest_list_fits() {
+        let mut list = NetworkList::new();
+        for i in 0..MAX_NETWORKS {
+            // Control characters are escaped as \u00XX, six bytes each
+            let mut ssid: String<SSID_LEN> = String::new();
+            while ssid.push('\u{1}').is_ok() {}
+            ssid.pop();
+            ssid.pop();
+            write!(ssid, "{i:02}").unwrap();
+            list.networks.push(Network { ssid, rssi: i16::MIN, channel: u8::MAX, secure: false }).unwrap();
+        }
+        let mut buf = [0u8; SCAN_JSON_LEN];
+        assert!(list.to_json(&mut buf).is_some());
+    }
+}
@@ -0,0 +1,92 @@
+// file: schedule.rs
+// desc: when the scheduler moves on to the next animation
+//
+// Rotation is off unless ROTATE_EVERY_S is set at build time. An animation
+// picked by hand, from any source but the scheduler itself, holds the rotation
+// for ROTATE_HOLD_S so the choice is not gone a moment later.
+use crate::command::Source;
+use crate::event::Event;
+
+/// How long a manual pick holds the rotation when ROTATE_HOLD_S is unset.
+pub const DEFAULT_HOLD_S: u32 = 30 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Schedule {
+    /// Seconds each animation plays before the next one.
+    pub every_s: u32,
+    /// Seconds a manual pick plays before rotation resumes.
+    pub hold_s: u32,
+}
+
+/// Which variable was malformed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ScheduleError {
+    Every,
+    Hold,
+}
+
+impl Schedule {
+    /// The schedule the build-time variables ask for; `None` when rotation is
+    /// off.
+    pub fn from_env(every_s: Option<&str>, hold_s: Option<&str>) -> Result<Option<Self>, ScheduleError> {
+        let Some(every_s) = every_s else {
+            return Ok(None);
+        };
+        let every_s = parse_seconds(every_s).ok_or(ScheduleError::Every)?;
+        let hold_s = match hold_s {
+            Some(hold_s) => parse_seconds(hold_s).ok_or(ScheduleError::Hold)?,
+            None => DEFAULT_HOLD_S,
+        };
+        Ok(Some(Schedule { every_s, hold_s }))
+    }
+
+    /// Seconds until the next rotation, if `event` restarts the wait.
+    pub fn wait_after(&self, event: &Event) -> Option<u32> {
+        match event {
+            Event::Animation(animation) if animation.source == Source::Scheduler => Some(self.every_s),
+            Event::Animation(_) => Some(self.hold_s),
+            _ => None,
+        }
+    }
+}
+
+fn parse_seconds(text: &str) -> Option<u32> {
+    text.trim().parse().ok().filter(|&seconds| seconds > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{AnimationEvent, ButtonEvent};
+
+    fn picked(source: Source) -> Event {
+        Event::Animation(AnimationEvent { animation: 2, frame_count: 10, source })
+    }
+
+    #[test]
+    fn off_unless_set() {
+        assert_eq!(Schedule::from_env(None, Some("60")), Ok(None));
+        assert_eq!(
+            Schedule::from_env(Some("300"), None),
+            Ok(Some(Schedule { every_s: 300, hold_s: DEFAULT_HOLD_S }))
+        );
+        assert_eq!(Schedule::from_env(Some(" 60 "), Some("600")), Ok(Some(Schedule { every_s: 60, hold_s: 600 })));
+    }
+
+    #[test]
+    fn malformed_variables() {
+        assert_eq!(Schedule::from_env(Some("5m"), None), Err(ScheduleError::Every));
+        assert_eq!(Schedule::from_env(Some("0"), None), Err(ScheduleError::Every));
+        assert_eq!(Schedule::from_env(Some("300"), Some("-1")), Err(ScheduleError::Hold));
+    }
+
+    #[test]
+    fn manual_pick_holds_rotation() {
+        let schedule = Schedule { every_s: 300, hold_s: 1800 };
+        assert_eq!(schedule.wait_after(&picked(Source::Http)), Some(1800));
+        assert_eq!(schedule.wait_after(&picked(Source::Button)), Some(1800));
+        assert_eq!(schedule.wait_after(&picked(Source::Scheduler)), Some(300));
+        assert_eq!(schedule.wait_after(&Event::Button(ButtonEvent { button: 1 })), None);
+    }
+}
@@ -0,0 +1,257 @@
+// file: screen.rs
+// desc: 128x64 monochrome framebuffer and its PBM/PNG encodings
+//
+// The display task draws each frame here and then blits it to the SSD1306, so
+// a capture is exactly what the panel shows. Pixels are stored row by row,
+// most significant bit first, which is also the raw layout of PBM (P4) and of
+// 1-bit PNG scanlines.
+use core::convert::Infallible;
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+use embedded_graphics_core::pixelcolor::BinaryColor;
+use embedded_graphics_core::prelude::{DrawTarget, OriginDimensions, Pixel, Size};
+
+pub const WIDTH: usize = 128;
+pub const HEIGHT: usize = 64;
+const ROW_BYTES: usize = WIDTH / 8;
+pub const FRAME_BYTES: usize = ROW_BYTES * HEIGHT;
+
+const PBM_HEADER: &[u8] = b"P4\n128 64\n";
+pub const PBM_LEN: usize = PBM_HEADER.len() + FRAME_BYTES;
+
+// Scanlines each start with a filter-type byte
+const PNG_RAW_LEN: usize = HEIGHT * (1 + ROW_BYTES);
+// zlib header, one stored deflate block, Adler-32
+const PNG_ZLIB_LEN: usize = 2 + 5 + PNG_RAW_LEN + 4;
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+pub const PNG_LEN: usize = PNG_SIGNATURE.len() + (12 + 13) + (12 + PNG_ZLIB_LEN) + 12;
+
+const PNG_CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    bits: [u8; FRAME_BYTES],
+}
+
+impl Framebuffer {
+    pub const fn new() -> Self {
+        Framebuffer { bits: [0; FRAME_BYTES] }
+    }
+
+    /// Row-major, MSB-first bits; `embedded_graphics::image::ImageRaw` reads
+    /// this layout directly.
+    pub fn as_bytes(&self) -> &[u8; FRAME_BYTES] {
+        &self.bits
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        x < WIDTH && y < HEIGHT && self.bits[y * ROW_BYTES + x / 8] & (0x80 >> (x % 8)) != 0
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        if x >= WIDTH || y >= HEIGHT {
+            return;
+        }
+        let byte = &mut self.bits[y * ROW_BYTES + x / 8];
+        let mask = 0x80 >> (x % 8);
+        if on {
+            *byte |= mask;
+        } else {
+            *byte &= !mask;
+        }
+    }
+
+    /// Binary PBM. PBM draws set bits black, so the bits are inverted to keep
+    /// lit pixels light, as on the panel.
+    pub fn to_pbm<'b>(&self, buf: &'b mut [u8; PBM_LEN]) -> &'b [u8] {
+        let (header, pixels) = buf.split_at_mut(PBM_HEADER.len());
+        header.copy_from_slice(PBM_HEADER);
+        for (out, bits) in pixels.iter_mut().zip(&self.bits) {
+            *out = !bits;
+        }
+        buf
+    }
+
+    /// 1-bit grayscale PNG. It is stored rather than deflated: a frame is
+    /// only 1 KiB and this keeps the encoder tiny and its size fixed.
+    pub fn to_png<'b>(&self, buf: &'b mut [u8; PNG_LEN]) -> &'b [u8] {
+        let mut out = Writer { buf, at: 0 };
+        out.put(PNG_SIGNATURE);
+
+        let mut ihdr = [0u8; 13];
+        ihdr[..4].copy_from_slice(&(WIDTH as u32).to_be_bytes());
+        ihdr[4..8].copy_from_slice(&(HEIGHT as u32).to_be_bytes());
+        // Bit depth 1, grayscale, deflate, no filter, no interlace
+        ihdr[8..].copy_from_slice(&[1, 0, 0, 0, 0]);
+        out.chunk(b"IHDR", &[&ihdr]);
+
+        let mut raw = [0u8; PNG_RAW_LEN];
+        for (line, row) in raw.chunks_exact_mut(1 + ROW_BYTES).zip(self.bits.chunks_exact(ROW_BYTES)) {
+            // Filter type 0; grayscale 1 is white, matching a lit pixel
+            line[0] = 0;
+            line[1..].copy_from_slice(row);
+        }
+        let len = (PNG_RAW_LEN as u16).to_le_bytes();
+        let nlen = (!(PNG_RAW_LEN as u16)).to_le_bytes();
+        let stored = [0x01, len[0], len[1], nlen[0], nlen[1]];
+        out.chunk(b"IDAT", &[&[0x78, 0x01], &stored, &raw, &adler32(&raw).to_be_bytes()]);
+
+        out.chunk(b"IEND", &[]);
+        debug_assert_eq!(out.at, PNG_LEN);
+        buf
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OriginDimensions for Framebuffer {
+    fn size(&self) -> Size {
+        Size::new(WIDTH as u32, HEIGHT as u32)
+    }
+}
+
+impl DrawTarget for Framebuffer {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            // Off-screen pixels are clipped, negative ones included
+            if let (Ok(x), Ok(y)) = (usize::try_from(point.x), usize::try_from(point.y)) {
+                self.set_pixel(x, y, color.is_on());
+            }
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.bits.fill(if color.is_on() { 0xff } else { 0x00 });
+        Ok(())
+    }
+}
+
+struct Writer<'b> {
+    buf: &'b mut [u8],
+    at: usize,
+}
+
+impl Writer<'_> {
+    fn put(&mut self, bytes: &[u8]) {
+        self.buf[self.at..self.at + bytes.len()].copy_from_slice(bytes);
+        self.at += bytes.len();
+    }
+
+    /// Length, type, data and a CRC over type and data.
+    fn chunk(&mut self, kind: &[u8; 4], parts: &[&[u8]]) {
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+        self.put(&(len as u32).to_be_bytes());
+
+        let mut crc = PNG_CRC.digest();
+        crc.update(kind);
+        self.put(kind);
+        for part in parts {
+            crc.update(part);
+            self.put(part);
+        }
+        self.put(&crc.finalize().to_be_bytes());
+    }
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_graphics_core::prelude::Point;
+
+    fn checkerboard() -> Framebuffer {
+        let mut frame = Framebuffer::new();
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                frame.set_pixel(x, y, (x + y) % 2 == 0);
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn pixels_are_row_major_msb_first() {
+        let mut frame = Framebuffer::new();
+        frame.set_pixel(0, 0, true);
+        frame.set_pixel(9, 0, true);
+        frame.set_pixel(127, 63, true);
+        assert_eq!(frame.as_bytes()[0], 0x80);
+        assert_eq!(frame.as_bytes()[1], 0x40);
+        assert_eq!(frame.as_bytes()[FRAME_BYTES - 1], 0x01);
+        assert!(frame.pixel(9, 0));
+        assert!(!frame.pixel(8, 0));
+
+        frame.set_pixel(9, 0, false);
+        assert!(!frame.pixel(9, 0));
+    }
+
+    #[test]
+    fn drawing_clips() {
+        let mut frame = Framebuffer::new();
+        let pixels = [
+            Pixel(Point::new(-1, 0), BinaryColor::On),
+            Pixel(Point::new(128, 0), BinaryColor::On),
+            Pixel(Point::new(0, 64), BinaryColor::On),
+            Pixel(Point::new(3, 2), BinaryColor::On),
+        ];
+        frame.draw_iter(pixels).unwrap();
+        assert!(frame.pixel(3, 2));
+        assert_eq!(frame.as_bytes().iter().map(|b| b.count_ones()).sum::<u32>(), 1);
+
+        frame.clear(BinaryColor::On).unwrap();
+        assert!(frame.as_bytes().iter().all(|&b| b == 0xff));
+    }
+
+    #[test]
+    fn pbm_is_inverted() {
+        let mut frame = Framebuffer::new();
+        frame.set_pixel(0, 0, true);
+        let mut buf = [0u8; PBM_LEN];
+        let pbm = frame.to_pbm(&mut buf);
+        assert!(pbm.starts_with(b"P4\n128 64\n"));
+        assert_eq!(pbm.len(), PBM_LEN);
+        assert_eq!(pbm[PBM_HEADER.len()], 0x7f);
+        assert!(pbm[PBM_HEADER.len() + 1..].iter().all(|&b| b == 0xff));
+    }
+
+    #[test]
+    fn adler32_known_value() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(adler32(b""), 1);
+    }
+
+    #[test]
+    fn png_decodes_to_the_same_pixels() {
+        let frame = checkerboard();
+        let mut buf = [0u8; PNG_LEN];
+        let png = frame.to_png(&mut buf);
+
+        let decoder = png::Decoder::new(png);
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = [0u8; FRAME_BYTES];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), (WIDTH as u32, HEIGHT as u32));
+        assert_eq!(info.bit_depth, png::BitDepth::One);
+        assert_eq!(info.color_type, png::ColorType::Grayscale);
+        assert_eq!(&pixels, frame.as_bytes());
+    }
+}
@@ -0,0 +1,375 @@
+// file: shell.rs
+// desc: the line-oriented serial console: line editing, parsing, log lines
+//
+// A line like `anim 3` turns into the same `Command` a button or
+// `PUT /api/animation` produces; the rest (`status`, `wifi scan`, `ip`,
+// `net`, `log level`, `reboot`) are answered by the console itself. Bus events are
+// echoed as log lines, filtered by the console's own level.
+use core::fmt::Write as _;
+
+use heapless::String;
+
+use crate::animation::FRAME_INTERVAL_RANGE_MS;
+use crate::command::{Brightness, Command, TEXT_LEN};
+use crate::event::{Event, SSE_EVENT_LEN};
+use crate::network::{NetEnv, NetSettings};
+use crate::reboot::RebootTarget;
+
+/// Longest line the editor takes; room for a full `net` line.
+pub const LINE_LEN: usize = 128;
+pub const PROMPT: &str = "> ";
+/// Room for a log line: level tag, event name and data.
+pub const LOG_LINE_LEN: usize = SSE_EVENT_LEN + 24;
+
+pub const HELP: &str = "\
+anim N              play animation N
+pause | resume      stop or restart playback
+next | prev         step through the animations
+speed MS            time between frames
+brightness 1-5      OLED contrast
+text MESSAGE        show a message
+status              playback and network as JSON
+wifi scan           networks in range
+ip                  address, gateway and DNS
+net KEY=VALUE...    save addressing: mode, address, gateway, dns,
+                    hostname, dhcp_timeout_s; applies after reboot
+net default         back to the build-time addressing
+log level [LEVEL]   off, error, warn, info or debug
+reboot [bootloader] restart, or drop into BOOTSEL
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "off" => Some(LogLevel::Off),
+            "error" => Some(LogLevel::Error),
+            "warn" => Some(LogLevel::Warn),
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Off => "off",
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        }
+    }
+
+    /// Whether a line at `level` shows while this is the console's level.
+    pub fn shows(self, level: LogLevel) -> bool {
+        level != LogLevel::Off && level <= self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ShellCommand {
+    /// Goes to the display task like any other input.
+    Display(Command),
+    Status,
+    WifiScan,
+    Ip,
+    /// Addressing to save; `None` drops the saved one.
+    Net(Option<NetSettings>),
+    /// `None` asks for the current level.
+    LogLevel(Option<LogLevel>),
+    Reboot(RebootTarget),
+    Help,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ShellError {
+    Unknown,
+    /// Right command, wrong arguments; carries the usage line.
+    Usage(&'static str),
+    OutOfRange,
+}
+
+impl ShellError {
+    pub fn message(self) -> &'static str {
+        match self {
+            ShellError::Unknown => "unknown command, try `help`",
+            ShellError::Usage(usage) => usage,
+            ShellError::OutOfRange => "value out of range",
+        }
+    }
+}
+
+/// One console line; `Ok(None)` for a blank one.
+pub fn parse_line(line: &str, animation_count: u8) -> Result<Option<ShellCommand>, ShellError> {
+    let line = line.trim();
+    let (word, rest) = line.split_once(' ').map_or((line, ""), |(word, rest)| (word, rest.trim()));
+    let mut args = rest.split_ascii_whitespace();
+    let (first, second) = (args.next(), args.next());
+
+    let command = match (word, first, second) {
+        ("", None, _) => return Ok(None),
+        ("anim", Some(n), None) => {
+            let n = n.parse::<u8>().map_err(|_| ShellError::Usage("usage: anim N"))?;
+            if !(1..=animation_count).contains(&n) {
+                return Err(ShellError::OutOfRange);
+            }
+            ShellCommand::Display(Command::PlayAnimation(n))
+        }
+        ("anim", ..) => return Err(ShellError::Usage("usage: anim N")),
+        ("pause", None, _) => ShellCommand::Display(Command::Pause),
+        ("resume", None, _) => ShellCommand::Display(Command::Resume),
+        ("next", None, _) => ShellCommand::Display(Command::Next),
+        ("prev", None, _) => ShellCommand::Display(Command::Previous),
+        ("speed", Some(ms), None) => {
+            let ms = ms.parse::<u16>().map_err(|_| ShellError::Usage("usage: speed MS"))?;
+            if !FRAME_INTERVAL_RANGE_MS.contains(&ms) {
+                return Err(ShellError::OutOfRange);
+            }
+            ShellCommand::Display(Command::SetSpeed(ms))
+        }
+        ("speed", ..) => return Err(ShellError::Usage("usage: speed MS")),
+        ("brightness", Some(level), None) => {
+            let level = level.parse::<u8>().map_err(|_| ShellError::Usage("usage: brightness 1-5"))?;
+            ShellCommand::Display(Command::SetBrightness(Brightness::from_level(level).ok_or(ShellError::OutOfRange)?))
+        }
+        ("brightness", ..) => return Err(ShellError::Usage("usage: brightness 1-5")),
+        ("text", Some(_), _) => {
+            let text = String::<TEXT_LEN>::try_from(rest).map_err(|_| ShellError::OutOfRange)?;
+            ShellCommand::Display(Command::ShowText(text))
+        }
+        ("text", None, _) => return Err(ShellError::Usage("usage: text MESSAGE")),
+        ("status", None, _) => ShellCommand::Status,
+        ("wifi", Some("scan"), None) => ShellCommand::WifiScan,
+        ("wifi", ..) => return Err(ShellError::Usage("usage: wifi scan")),
+        ("ip", None, _) => ShellCommand::Ip,
+        ("net", Some("default"), None) => ShellCommand::Net(None),
+        ("net", Some(_), _) => {
+            let env = NetEnv::from_args(rest).ok_or(ShellError::Usage("usage: net KEY=VALUE... | net default"))?;
+            ShellCommand::Net(Some(NetSettings::from_env(&env).map_err(|e| ShellError::Usage(e.message()))?))
+        }
+        ("net", ..) => return Err(ShellError::Usage("usage: net KEY=VALUE... | net default")),
+        ("log", Some("level"), None) => ShellCommand::LogLevel(None),
+        ("log", Some("level"), Some(level)) if args.next().is_none() => {
+            ShellCommand::LogLevel(Some(LogLevel::parse(level).ok_or(ShellError::Usage("levels: off error warn info debug"))?))
+        }
+        ("log", ..) => return Err(ShellError::Usage("usage: log level [LEVEL]")),
+        ("reboot", None, _) => ShellCommand::Reboot(RebootTarget::Normal),
+        ("reboot", Some("bootloader"), None) => ShellCommand::Reboot(RebootTarget::Bootloader),
+        ("reboot", ..) => return Err(ShellError::Usage("usage: reboot [bootloader]")),
+        ("help", None, _) => ShellCommand::Help,
+        _ => return Err(ShellError::Unknown),
+    };
+    Ok(Some(command))
+}
+
+/// The level an event is logged at; frames and heartbeats are chatty.
+pub fn event_level(event: &Event) -> LogLevel {
+    match event {
+        Event::Frame(_) | Event::Heartbeat(_) => LogLevel::Debug,
+        _ => LogLevel::Info,
+    }
+}
+
+/// `[info] animation {...}`, line ending included.
+pub fn log_line(event: &Event) -> Option<String<LOG_LINE_LEN>> {
+    let mut data = [0u8; SSE_EVENT_LEN];
+    let mut line = String::new();
+    let level = event_level(event).as_str();
+    write!(line, "[{level}] {} {}\r\n", event.name(), event.data_json(&mut data)?).ok()?;
+    Some(line)
+}
+
+/// What the terminal should see after a byte was typed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    /// Added to the line; echo it.
+    Echo(u8),
+    /// The last character went; rub it out.
+    Erase,
+    /// The line is complete, see `LineEditor::line`.
+    Enter,
+    /// Ctrl-C dropped the line.
+    Cancel,
+    /// Line full, or a character the editor does not take.
+    Bell,
+    Ignore,
+}
+
+/// Just enough line editing for `screen` and `minicom`: printable ASCII,
+/// backspace, Ctrl-C, and CR, LF or CRLF to end a line.
+#[derive(Debug, Default)]
+pub struct LineEditor {
+    line: String<LINE_LEN>,
+    after_cr: bool,
+}
+
+impl LineEditor {
+    pub const fn new() -> Self {
+        LineEditor { line: String::new(), after_cr: false }
+    }
+
+    pub fn feed(&mut self, byte: u8) -> Key {
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+        match byte {
+            b'\n' if after_cr => Key::Ignore,
+            b'\r' | b'\n' => Key::Enter,
+            0x08 | 0x7f => match self.line.pop() {
+                Some(_) => Key::Erase,
+                None => Key::Ignore,
+            },
+            0x03 => {
+                self.line.clear();
+                Key::Cancel
+            }
+            b' '..=b'~' => match self.line.push(byte as char) {
+                Ok(()) => Key::Echo(byte),
+                Err(()) => Key::Bell,
+            },
+            _ => Key::Bell,
+        }
+    }
+
+    /// The line so far; after `Key::Enter`, the whole line.
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    pub fn clear(&mut self) {
+        self.line.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{ButtonEvent, FrameEvent};
+
+    fn parse(line: &str) -> Result<Option<ShellCommand>, ShellError> {
+        parse_line(line, 4)
+    }
+
+    fn typed(editor: &mut LineEditor, bytes: &[u8]) -> heapless::Vec<Key, 80> {
+        bytes.iter().map(|&byte| editor.feed(byte)).collect()
+    }
+
+    #[test]
+    fn display_commands() {
+        assert_eq!(parse("anim 3"), Ok(Some(ShellCommand::Display(Command::PlayAnimation(3)))));
+        assert_eq!(parse("  pause "), Ok(Some(ShellCommand::Display(Command::Pause))));
+        assert_eq!(parse("prev"), Ok(Some(ShellCommand::Display(Command::Previous))));
+        assert_eq!(parse("speed 250"), Ok(Some(ShellCommand::Display(Command::SetSpeed(250)))));
+        assert_eq!(
+            parse("brightness 5"),
+            Ok(Some(ShellCommand::Display(Command::SetBrightness(Brightness::Brightest))))
+        );
+        assert_eq!(
+            parse("text hello  pico"),
+            Ok(Some(ShellCommand::Display(Command::ShowText(String::try_from("hello  pico").unwrap()))))
+        );
+    }
+
+    #[test]
+    fn console_commands() {
+        assert_eq!(parse(""), Ok(None));
+        assert_eq!(parse("status"), Ok(Some(ShellCommand::Status)));
+        assert_eq!(parse("wifi scan"), Ok(Some(ShellCommand::WifiScan)));
+        assert_eq!(parse("ip"), Ok(Some(ShellCommand::Ip)));
+        assert_eq!(parse("net default"), Ok(Some(ShellCommand::Net(None))));
+        let static_env = NetEnv { mode: Some("static"), address: Some("10.0.0.7/24"), ..NetEnv::default() };
+        assert_eq!(
+            parse("net mode=static address=10.0.0.7/24"),
+            Ok(Some(ShellCommand::Net(Some(NetSettings::from_env(&static_env).unwrap()))))
+        );
+        assert_eq!(parse("log level"), Ok(Some(ShellCommand::LogLevel(None))));
+        assert_eq!(parse("log level debug"), Ok(Some(ShellCommand::LogLevel(Some(LogLevel::Debug)))));
+        assert_eq!(parse("reboot"), Ok(Some(ShellCommand::Reboot(RebootTarget::Normal))));
+        assert_eq!(parse("reboot bootloader"), Ok(Some(ShellCommand::Reboot(RebootTarget::Bootloader))));
+        assert_eq!(parse("help"), Ok(Some(ShellCommand::Help)));
+    }
+
+    #[test]
+    fn rejects() {
+        assert_eq!(parse("anim 0"), Err(ShellError::OutOfRange));
+        assert_eq!(parse("anim 5"), Err(ShellError::OutOfRange));
+        assert_eq!(parse("anim x"), Err(ShellError::Usage("usage: anim N")));
+        assert_eq!(parse("anim 1 2"), Err(ShellError::Usage("usage: anim N")));
+        assert_eq!(parse("speed 1"), Err(ShellError::OutOfRange));
+        assert_eq!(parse("brightness 9"), Err(ShellError::OutOfRange));
+        assert_eq!(parse("text"), Err(ShellError::Usage("usage: text MESSAGE")));
+        assert_eq!(parse(&["text ", &"x".repeat(TEXT_LEN + 1)].concat()), Err(ShellError::OutOfRange));
+        assert_eq!(parse("log level loud"), Err(ShellError::Usage("levels: off error warn info debug")));
+        assert_eq!(parse("log level info debug"), Err(ShellError::Usage("usage: log level [LEVEL]")));
+        assert_eq!(parse("wifi join"), Err(ShellError::Usage("usage: wifi scan")));
+        assert_eq!(parse("net"), Err(ShellError::Usage("usage: net KEY=VALUE... | net default")));
+        assert_eq!(parse("net mode=auto"), Err(ShellError::Usage("mode: dhcp, static or dhcp-fallback")));
+        assert_eq!(parse("net static"), Err(ShellError::Usage("usage: net KEY=VALUE... | net default")));
+        assert_eq!(parse("reboot now"), Err(ShellError::Usage("usage: reboot [bootloader]")));
+        assert_eq!(parse("status now"), Err(ShellError::Unknown));
+        assert_eq!(parse("ANIM 1"), Err(ShellError::Unknown));
+    }
+
+    #[test]
+    fn log_levels() {
+        assert!(LogLevel::Info.shows(LogLevel::Info));
+        assert!(LogLevel::Info.shows(LogLevel::Error));
+        assert!(!LogLevel::Info.shows(LogLevel::Debug));
+        assert!(!LogLevel::Off.shows(LogLevel::Error));
+        assert!(!LogLevel::Debug.shows(LogLevel::Off));
+        for level in ["off", "error", "warn", "info", "debug"] {
+            assert_eq!(LogLevel::parse(level).unwrap().as_str(), level);
+        }
+    }
+
+    #[test]
+    fn log_lines() {
+        let button = Event::Button(ButtonEvent { button: 2 });
+        assert_eq!(log_line(&button).unwrap().as_str(), "[info] button {\"button\":2}\r\n");
+        let frame = Event::Frame(FrameEvent { animation: 1, frame_index: 3, frame_count: 9 });
+        assert_eq!(event_level(&frame), LogLevel::Debug);
+        assert!(log_line(&frame).unwrap().starts_with("[debug] frame {"));
+    }
+
+    #[test]
+    fn line_editing() {
+        let mut editor = LineEditor::new();
+        assert_eq!(typed(&mut editor, b"anx\x7f"), [Key::Echo(b'a'), Key::Echo(b'n'), Key::Echo(b'x'), Key::Erase]);
+        assert_eq!(typed(&mut editor, b"im 3\r\n"), [
+            Key::Echo(b'i'),
+            Key::Echo(b'm'),
+            Key::Echo(b' '),
+            Key::Echo(b'3'),
+            Key::Enter,
+            Key::Ignore
+        ]);
+        assert_eq!(editor.line(), "anim 3");
+        editor.clear();
+
+        // A bare LF ends a line too, and backspace on an empty line does nothing
+        assert_eq!(typed(&mut editor, b"\x08\n\n"), [Key::Ignore, Key::Enter, Key::Enter]);
+        assert_eq!(typed(&mut editor, b"ip\x03"), [Key::Echo(b'i'), Key::Echo(b'p'), Key::Cancel]);
+        assert_eq!(editor.line(), "");
+        assert_eq!(typed(&mut editor, b"\x1b\xc3"), [Key::Bell, Key::Bell]);
+    }
+
+    #[test]
+    fn full_line_rings() {
+        let mut editor = LineEditor::new();
+        for _ in 0..LINE_LEN {
+            assert!(matches!(editor.feed(b'x'), Key::Echo(_)));
+        }
+        assert_eq!(editor.feed(b'x'), Key::Bell);
+        assert_eq!(editor.line().len(), LINE_LEN);
+    }
+}
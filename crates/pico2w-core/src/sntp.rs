@@ -0,0 +1,326 @@
+// file: sntp.rs
+// desc: SNTP request/reply handling and the wall clock it keeps
+//
+// The firmware only has uptime, so requests carry an uptime stamp as their
+// nonce and replies are turned into an offset between uptime and Unix time
+// (RFC 4330). `WallClock` holds the latest offset and turns uptime into
+// wall-clock time for anything that wants it.
+use core::fmt;
+use core::time::Duration;
+
+pub const PORT: u16 = 123;
+pub const PACKET_LEN: usize = 48;
+/// Room for a reply with extension fields or a MAC after the header; only
+/// the header is read.
+pub const MAX_REPLY_LEN: usize = 128;
+pub const DEFAULT_SERVER: &str = "pool.ntp.org";
+/// How often a synced clock asks again.
+pub const RESYNC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Wait before asking again after a failed sync.
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+// Seconds from 1900 (NTP) to 1970 (Unix)
+const NTP_TO_UNIX_S: i64 = 2_208_988_800;
+const VERSION: u8 = 4;
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+const LEAP_UNSYNCHRONIZED: u8 = 3;
+const ORIGINATE_AT: usize = 24;
+const RECEIVE_AT: usize = 32;
+const TRANSMIT_AT: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SntpError {
+    /// Shorter than an NTP header.
+    Short,
+    /// Not a server reply.
+    Mode(u8),
+    /// The server has no time to give (stratum 0, or kiss-o'-death).
+    Unsynchronized,
+    /// Not the answer to our request.
+    Mismatch,
+}
+
+/// One measurement against a server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Sample {
+    /// Unix time minus uptime, in microseconds.
+    pub offset_us: i64,
+    /// Round trip, minus the server's processing time.
+    pub delay_us: i64,
+}
+
+/// A client request; `nonce` comes back in the reply's originate field.
+/// Send the uptime in microseconds and pass the same value to `parse_reply`.
+pub fn request(nonce: u64) -> [u8; PACKET_LEN] {
+    let mut packet = [0u8; PACKET_LEN];
+    packet[0] = VERSION << 3 | MODE_CLIENT;
+    packet[TRANSMIT_AT..TRANSMIT_AT + 8].copy_from_slice(&nonce.to_be_bytes());
+    packet
+}
+
+/// Check a reply to `request(sent_us)` that arrived at uptime `received_us`.
+/// Anything after the header is ignored.
+pub fn parse_reply(reply: &[u8], sent_us: u64, received_us: u64) -> Result<Sample, SntpError> {
+    if reply.len() < PACKET_LEN {
+        return Err(SntpError::Short);
+    }
+    let leap = reply[0] >> 6;
+    let mode = reply[0] & 0x7;
+    let stratum = reply[1];
+    if mode != MODE_SERVER {
+        return Err(SntpError::Mode(mode));
+    }
+    if leap == LEAP_UNSYNCHRONIZED || stratum == 0 || stratum > 15 {
+        return Err(SntpError::Unsynchronized);
+    }
+    if timestamp(reply, ORIGINATE_AT) != sent_us {
+        return Err(SntpError::Mismatch);
+    }
+    let (received, transmitted) = (timestamp(reply, RECEIVE_AT), timestamp(reply, TRANSMIT_AT));
+    if transmitted == 0 {
+        return Err(SntpError::Unsynchronized);
+    }
+
+    let (t1, t4) = (sent_us as i64, received_us as i64);
+    let (t2, t3) = (ntp_to_unix_us(received), ntp_to_unix_us(transmitted));
+    Ok(Sample { offset_us: ((t2 - t1) + (t3 - t4)) / 2, delay_us: (t4 - t1) - (t3 - t2) })
+}
+
+fn timestamp(packet: &[u8], at: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&packet[at..at + 8]);
+    u64::from_be_bytes(bytes)
+}
+
+// 32.32 fixed point seconds since 1900, to microseconds since 1970
+fn ntp_to_unix_us(ntp: u64) -> i64 {
+    let mut seconds = (ntp >> 32) as i64;
+    // Era 1 starts in 2036; small second counts belong to it
+    if seconds < 1 << 31 {
+        seconds += 1 << 32;
+    }
+    let micros = ((ntp & 0xffff_ffff) * 1_000_000) >> 32;
+    (seconds - NTP_TO_UNIX_S) * 1_000_000 + micros as i64
+}
+
+/// Uptime-to-Unix offset from the last successful sync.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WallClock {
+    offset_us: Option<i64>,
+    synced_at_us: u64,
+}
+
+impl WallClock {
+    pub const fn new() -> Self {
+        WallClock { offset_us: None, synced_at_us: 0 }
+    }
+
+    pub fn update(&mut self, sample: Sample, uptime_us: u64) {
+        self.offset_us = Some(sample.offset_us);
+        self.synced_at_us = uptime_us;
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.offset_us.is_some()
+    }
+
+    /// Uptime of the last sync.
+    pub fn synced_at_us(&self) -> u64 {
+        self.synced_at_us
+    }
+
+    /// Wall-clock time at `uptime_us`, or `None` before the first sync.
+    pub fn now(&self, uptime_us: u64) -> Option<UnixTime> {
+        let unix_us = self.offset_us?.checked_add(uptime_us as i64)?;
+        Some(UnixTime { micros: u64::try_from(unix_us).ok()? })
+    }
+}
+
+/// A point in time, UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UnixTime {
+    micros: u64,
+}
+
+impl UnixTime {
+    pub const fn from_micros(micros: u64) -> Self {
+        UnixTime { micros }
+    }
+
+    pub const fn as_micros(&self) -> u64 {
+        self.micros
+    }
+
+    pub const fn as_secs(&self) -> u64 {
+        self.micros / 1_000_000
+    }
+
+    pub fn datetime(&self) -> DateTime {
+        let secs = self.as_secs();
+        let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+        let time = secs % 86_400;
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+}
+
+/// Calendar date and time of day, UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i32,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+// `2026-10-18T09:30:00Z`
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for DateTime {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "{=i32}-{=u8:02}-{=u8:02}T{=u8:02}:{=u8:02}:{=u8:02}Z",
+            self.year,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+}
+
+// Days since 1970-01-01 to (year, month, day), Howard Hinnant's algorithm
+fn civil_from_days(days: i64) -> (i32, u8, u8) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year as i32, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::string::ToString;
+
+    use super::*;
+
+    // 2026-10-18T09:30:00Z
+    const WALL_S: i64 = 1_792_315_800;
+
+    fn to_ntp(unix_us: i64) -> u64 {
+        let seconds = (unix_us.div_euclid(1_000_000) + NTP_TO_UNIX_S) as u64 & 0xffff_ffff;
+        let fraction = ((unix_us.rem_euclid(1_000_000) as u64) << 32) / 1_000_000;
+        seconds << 32 | fraction
+    }
+
+    // A local stand-in for an NTP server whose clock reads `uptime + offset`
+    fn server(request: &[u8; PACKET_LEN], arrived_us: u64, processing_us: u64, offset_us: i64) -> [u8; PACKET_LEN] {
+        assert_eq!(request[0], 0x23);
+        let mut reply = [0u8; PACKET_LEN];
+        reply[0] = VERSION << 3 | MODE_SERVER;
+        reply[1] = 2;
+        reply[ORIGINATE_AT..ORIGINATE_AT + 8].copy_from_slice(&request[TRANSMIT_AT..TRANSMIT_AT + 8]);
+        let received = to_ntp(arrived_us as i64 + offset_us);
+        let transmitted = to_ntp((arrived_us + processing_us) as i64 + offset_us);
+        reply[RECEIVE_AT..RECEIVE_AT + 8].copy_from_slice(&received.to_be_bytes());
+        reply[TRANSMIT_AT..TRANSMIT_AT + 8].copy_from_slice(&transmitted.to_be_bytes());
+        reply
+    }
+
+    #[test]
+    fn symmetric_round_trip_recovers_offset() {
+        let offset_us = WALL_S * 1_000_000 - 5_000_000;
+        let sent_us = 5_000_000;
+        // 20 ms each way, 1 ms in the server
+        let reply = server(&request(sent_us), sent_us + 20_000, 1_000, offset_us);
+        let sample = parse_reply(&reply, sent_us, sent_us + 41_000).unwrap();
+        assert!((sample.offset_us - offset_us).abs() <= 1);
+        assert!((sample.delay_us - 40_000).abs() <= 1);
+
+        let mut clock = WallClock::new();
+        assert_eq!(clock.now(sent_us), None);
+        clock.update(sample, sent_us + 41_000);
+        assert!(clock.is_synced());
+        // The offset may be a microsecond short after rounding
+        assert_eq!(clock.now(5_000_001).unwrap().as_secs(), WALL_S as u64);
+        assert_eq!(clock.now(65_000_001).unwrap().datetime().to_string(), "2026-10-18T09:31:00Z");
+    }
+
+    #[test]
+    fn rejects_bad_replies() {
+        let sent_us = 1_000;
+        let good = server(&request(sent_us), 2_000, 0, WALL_S * 1_000_000);
+        assert_eq!(parse_reply(&good[..40], sent_us, 3_000), Err(SntpError::Short));
+        assert_eq!(parse_reply(&good, sent_us + 1, 3_000), Err(SntpError::Mismatch));
+
+        let mut client = good;
+        client[0] = VERSION << 3 | MODE_CLIENT;
+        assert_eq!(parse_reply(&client, sent_us, 3_000), Err(SntpError::Mode(3)));
+
+        // Kiss-o'-death replies have stratum 0
+        let mut kiss = good;
+        kiss[1] = 0;
+        assert_eq!(parse_reply(&kiss, sent_us, 3_000), Err(SntpError::Unsynchronized));
+
+        let mut unsynced = good;
+        unsynced[0] |= LEAP_UNSYNCHRONIZED << 6;
+        assert_eq!(parse_reply(&unsynced, sent_us, 3_000), Err(SntpError::Unsynchronized));
+    }
+
+    #[test]
+    fn trailing_mac_is_ignored() {
+        let sent_us = 1_000;
+        let header = server(&request(sent_us), 2_000, 0, WALL_S * 1_000_000);
+        // Key ID and an MD5 digest, as symmetric-key servers send
+        let mut reply = [0xa5u8; PACKET_LEN + 20];
+        reply[..PACKET_LEN].copy_from_slice(&header);
+        assert!(reply.len() <= MAX_REPLY_LEN);
+        assert_eq!(parse_reply(&reply, sent_us, 3_000), parse_reply(&header, sent_us, 3_000));
+        assert!(parse_reply(&reply, sent_us, 3_000).is_ok());
+    }
+
+    #[test]
+    fn next_era() {
+        // 2040-01-01, after the NTP seconds counter wraps in 2036
+        let unix_us = 2_208_988_800 * 1_000_000;
+        assert_eq!(ntp_to_unix_us(to_ntp(unix_us)), unix_us);
+        assert_eq!(UnixTime::from_micros(unix_us as u64).datetime().to_string(), "2040-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn calendar() {
+        let date = |secs: u64| UnixTime::from_micros(secs * 1_000_000).datetime().to_string();
+        assert_eq!(date(0), "1970-01-01T00:00:00Z");
+        assert_eq!(date(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(date(1_709_251_199), "2024-02-29T23:59:59Z");
+        assert_eq!(date(WALL_S as u64), "2026-10-18T09:30:00Z");
+    }
+}
@@ -0,0 +1,348 @@
+// file: websocket.rs
+// desc: RFC 6455 upgrade handshake and frame coding
+//
+// Only what a small control channel needs: whole (unfragmented) messages of a
+// few hundred bytes at most. Client frames are unmasked in place; server
+// frames go out unmasked, as the RFC requires.
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD;
+use heapless::String;
+use sha1::{Digest, Sha1};
+
+use crate::command::Command;
+use crate::http::{HttpError, Method, Request};
+
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Longest server frame header: 2 bytes plus a 64-bit length.
+pub const MAX_HEADER_LEN: usize = 10;
+
+/// `Sec-WebSocket-Accept` for the client's `Sec-WebSocket-Key`.
+pub fn accept_key(client_key: &str) -> String<28> {
+    let mut sha = Sha1::new();
+    sha.update(client_key.trim().as_bytes());
+    sha.update(ACCEPT_GUID.as_bytes());
+    let digest = sha.finalize();
+
+    let mut encoded = [0u8; 28];
+    // 20 bytes always encode to exactly 28 characters
+    STANDARD.encode_slice(digest, &mut encoded).ok();
+    let mut key = String::new();
+    key.push_str(core::str::from_utf8(&encoded).unwrap_or_default()).ok();
+    key
+}
+
+/// Check an upgrade request and return the client's key.
+pub fn upgrade_key<'a>(request: &Request<'a>) -> Result<&'a str, HttpError> {
+    let has_token = |name: &str, token: &str| {
+        request
+            .header(name)
+            .is_some_and(|value| value.split(',').any(|part| part.trim().eq_ignore_ascii_case(token)))
+    };
+
+    if request.method != Method::Get {
+        return Err(HttpError::MethodNotAllowed);
+    }
+    if !has_token("upgrade", "websocket") || !has_token("connection", "upgrade") {
+        return Err(HttpError::BadRequest);
+    }
+    if request.header("sec-websocket-version").map(str::trim) != Some("13") {
+        return Err(HttpError::BadRequest);
+    }
+    match request.header("sec-websocket-key") {
+        // A valid key is 16 random bytes in base64
+        Some(key) if key.trim().len() == 24 => Ok(key),
+        _ => Err(HttpError::BadRequest),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_bits(bits: u8) -> Option<Self> {
+        Some(match bits {
+            0x0 => Opcode::Continuation,
+            0x1 => Opcode::Text,
+            0x2 => Opcode::Binary,
+            0x8 => Opcode::Close,
+            0x9 => Opcode::Ping,
+            0xA => Opcode::Pong,
+            _ => return None,
+        })
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    fn is_control(self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+/// Why the connection must be closed; each maps onto a close code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WsError {
+    /// Unmasked client frame, reserved bits, unknown opcode or a bad control
+    /// frame.
+    Protocol,
+    /// Fragmented messages are not supported.
+    Unsupported,
+    /// The frame cannot fit the receive buffer.
+    TooBig,
+}
+
+impl WsError {
+    pub fn close_code(self) -> u16 {
+        match self {
+            WsError::Protocol => 1002,
+            WsError::Unsupported => 1003,
+            WsError::TooBig => 1009,
+        }
+    }
+}
+
+pub const CLOSE_NORMAL: u16 = 1000;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Frame<'a> {
+    pub opcode: Opcode,
+    pub payload: &'a [u8],
+}
+
+/// Decode the frame at the start of `buf[..filled]`.
+///
+/// Returns `Ok(None)` while more bytes are needed, otherwise the frame and the
+/// bytes it used. The payload is unmasked in place. `buf.len()` is the receive
+/// capacity, so a frame that could never fit is rejected early.
+pub fn decode_frame(buf: &mut [u8], filled: usize) -> Result<Option<(Frame<'_>, usize)>, WsError> {
+    let received = &buf[..filled];
+    if received.len() < 2 {
+        return Ok(None);
+    }
+
+    let (fin, rsv, opcode) = (received[0] & 0x80 != 0, received[0] & 0x70, received[0] & 0x0f);
+    let opcode = Opcode::from_bits(opcode).ok_or(WsError::Protocol)?;
+    let masked = received[1] & 0x80 != 0;
+    if rsv != 0 || !masked {
+        return Err(WsError::Protocol);
+    }
+    if opcode.is_control() && (!fin || received[1] & 0x7f > 125) {
+        return Err(WsError::Protocol);
+    }
+    if !fin || opcode == Opcode::Continuation {
+        return Err(WsError::Unsupported);
+    }
+
+    let (payload_len, len_bytes) = match received[1] & 0x7f {
+        126 if received.len() >= 4 => (u16::from_be_bytes([received[2], received[3]]) as u64, 2),
+        127 if received.len() >= 10 => (u64::from_be_bytes(received[2..10].try_into().unwrap_or_default()), 8),
+        126 | 127 => return Ok(None),
+        len => (len as u64, 0),
+    };
+
+    let header_len = 2 + len_bytes + 4;
+    let total = usize::try_from(payload_len)
+        .ok()
+        .and_then(|len| len.checked_add(header_len))
+        .filter(|&total| total <= buf.len())
+        .ok_or(WsError::TooBig)?;
+    if filled < total {
+        return Ok(None);
+    }
+
+    let mask = [buf[header_len - 4], buf[header_len - 3], buf[header_len - 2], buf[header_len - 1]];
+    let payload = &mut buf[header_len..total];
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+
+    Ok(Some((Frame { opcode, payload }, total)))
+}
+
+/// Header of an unmasked, final server frame carrying `len` bytes.
+pub fn encode_header(opcode: Opcode, len: usize, out: &mut [u8; MAX_HEADER_LEN]) -> &[u8] {
+    out[0] = 0x80 | opcode.bits();
+    match len {
+        0..=125 => {
+            out[1] = len as u8;
+            &out[..2]
+        }
+        126..=0xffff => {
+            out[1] = 126;
+            out[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+            &out[..4]
+        }
+        _ => {
+            out[1] = 127;
+            out[2..10].copy_from_slice(&(len as u64).to_be_bytes());
+            &out[..10]
+        }
+    }
+}
+
+/// A command from a client frame: text frames hold the JSON form, e.g.
+/// `{"PlayAnimation":2}` or `"Pause"`, binary frames the versioned wire
+/// encoding from `Command::encode`.
+pub fn parse_command(frame: &Frame) -> Option<Command> {
+    match frame.opcode {
+        Opcode::Text => serde_json_core::from_slice(frame.payload).ok().map(|(command, _)| command),
+        Opcode::Binary => Command::decode(frame.payload).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{Brightness, MAX_ENCODED_LEN};
+
+    /// Build a masked client frame the way a browser would.
+    fn client_frame(first: u8, payload: &[u8], out: &mut [u8]) -> usize {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        out[0] = first;
+        let mut at = match payload.len() {
+            0..=125 => {
+                out[1] = 0x80 | payload.len() as u8;
+                2
+            }
+            _ => {
+                out[1] = 0x80 | 126;
+                out[2..4].copy_from_slice(&(payload.len() as u16).to_be_bytes());
+                4
+            }
+        };
+        out[at..at + 4].copy_from_slice(&mask);
+        at += 4;
+        for (i, byte) in payload.iter().enumerate() {
+            out[at + i] = byte ^ mask[i % 4];
+        }
+        at + payload.len()
+    }
+
+    #[test]
+    fn rfc_example_accept_key() {
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ==").as_str(), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    fn upgrade(raw: &str) -> Result<heapless::String<28>, HttpError> {
+        let mut buf = [0u8; 512];
+        buf[..raw.len()].copy_from_slice(raw.as_bytes());
+        let request = Request::parse(&mut buf[..raw.len()]).unwrap();
+        upgrade_key(&request).map(accept_key)
+    }
+
+    #[test]
+    fn browser_upgrade() {
+        let raw = "GET /ws HTTP/1.1\r\nHost: pico\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\n\
+                   Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+        assert_eq!(upgrade(raw).unwrap().as_str(), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn bad_upgrades() {
+        let plain = "GET /ws HTTP/1.1\r\nHost: pico\r\n\r\n";
+        assert_eq!(upgrade(plain), Err(HttpError::BadRequest));
+        let old_version = "GET /ws HTTP/1.1\r\nHost: pico\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                           Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 8\r\n\r\n";
+        assert_eq!(upgrade(old_version), Err(HttpError::BadRequest));
+        let post = "POST /ws HTTP/1.1\r\nHost: pico\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                    Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+        assert_eq!(upgrade(post), Err(HttpError::MethodNotAllowed));
+    }
+
+    #[test]
+    fn masked_text_frame() {
+        let mut buf = [0u8; 64];
+        let len = client_frame(0x81, b"Hello", &mut buf);
+        let (frame, used) = decode_frame(&mut buf, len).unwrap().unwrap();
+        assert_eq!(frame, Frame { opcode: Opcode::Text, payload: b"Hello" });
+        assert_eq!(used, len);
+    }
+
+    #[test]
+    fn partial_frames_wait() {
+        let mut buf = [0u8; 512];
+        let len = client_frame(0x82, &[7; 200], &mut buf);
+        for filled in [0, 1, 3, 8, len - 1] {
+            assert_eq!(decode_frame(&mut buf, filled), Ok(None));
+        }
+        let (frame, used) = decode_frame(&mut buf, len).unwrap().unwrap();
+        assert_eq!(frame.payload, &[7; 200]);
+        assert_eq!(used, 208);
+    }
+
+    #[test]
+    fn second_frame_follows() {
+        let mut buf = [0u8; 64];
+        let first = client_frame(0x89, b"", &mut buf);
+        let second = client_frame(0x88, &CLOSE_NORMAL.to_be_bytes(), &mut buf[first..]);
+        let (frame, used) = decode_frame(&mut buf, first + second).unwrap().unwrap();
+        assert_eq!(frame.opcode, Opcode::Ping);
+        assert_eq!(used, first);
+
+        buf.copy_within(first..first + second, 0);
+        let (frame, _) = decode_frame(&mut buf, second).unwrap().unwrap();
+        assert_eq!(frame, Frame { opcode: Opcode::Close, payload: &[0x03, 0xe8] });
+    }
+
+    #[test]
+    fn rejected_frames() {
+        let mut buf = [0u8; 32];
+        // Unmasked
+        buf[..3].copy_from_slice(&[0x81, 0x01, b'x']);
+        assert_eq!(decode_frame(&mut buf, 3), Err(WsError::Protocol));
+        // Reserved bit
+        let len = client_frame(0xc1, b"x", &mut buf);
+        assert_eq!(decode_frame(&mut buf, len), Err(WsError::Protocol));
+        // Unknown opcode
+        let len = client_frame(0x83, b"x", &mut buf);
+        assert_eq!(decode_frame(&mut buf, len), Err(WsError::Protocol));
+        // Fragmented
+        let len = client_frame(0x01, b"x", &mut buf);
+        assert_eq!(decode_frame(&mut buf, len), Err(WsError::Unsupported));
+        // Larger than the buffer
+        buf[..4].copy_from_slice(&[0x82, 0x80 | 126, 0x01, 0x00]);
+        assert_eq!(decode_frame(&mut buf, 4), Err(WsError::TooBig));
+    }
+
+    #[test]
+    fn server_headers() {
+        let mut out = [0u8; MAX_HEADER_LEN];
+        assert_eq!(encode_header(Opcode::Text, 5, &mut out), &[0x81, 5]);
+        assert_eq!(encode_header(Opcode::Binary, 300, &mut out), &[0x82, 126, 0x01, 0x2c]);
+        assert_eq!(encode_header(Opcode::Pong, 0, &mut out), &[0x8a, 0]);
+        assert_eq!(encode_header(Opcode::Binary, 70_000, &mut out), &[0x82, 127, 0, 0, 0, 0, 0, 1, 0x11, 0x70]);
+    }
+
+    #[test]
+    fn commands_from_frames() {
+        let text = |payload: &'static [u8]| parse_command(&Frame { opcode: Opcode::Text, payload });
+        assert_eq!(text(br#"{"PlayAnimation":2}"#), Some(Command::PlayAnimation(2)));
+        assert_eq!(text(br#""Pause""#), Some(Command::Pause));
+        assert_eq!(text(br#"{"SetBrightness":"Dim"}"#), Some(Command::SetBrightness(Brightness::Dim)));
+        assert_eq!(text(b"play 2"), None);
+
+        let mut wire = [0u8; MAX_ENCODED_LEN];
+        let encoded = Command::Next.encode(&mut wire).unwrap();
+        assert_eq!(parse_command(&Frame { opcode: Opcode::Binary, payload: encoded }), Some(Command::Next));
+        assert_eq!(parse_command(&Frame { opcode: Opcode::Ping, payload: b"" }), None);
+    }
+}
@@ -0,0 +1,184 @@
+// file: main.rs
+// desc: host tool that makes the key pair and signs images for POST /api/firmware
+//
+//   pico2w-sign keygen <key-file>
+//   pico2w-sign pubkey <key-file>
+//   pico2w-sign sign <key-file> <version> <image.bin> <signed.bin>
+//
+// The key file holds the hex secret seed and stays on the build machine; the
+// public key goes into the firmware as FIRMWARE_KEY. `sign` appends the
+// trailer described in pico2w_core::ota.
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::process::ExitCode;
+
+use ed25519_dalek::SigningKey;
+use pico2w_core::ota::{pack_version, parse_key_bytes, sign, TRAILER_LEN};
+
+const USAGE: &str = "usage:
+  pico2w-sign keygen <key-file>
+  pico2w-sign pubkey <key-file>
+  pico2w-sign sign <key-file> <version> <image.bin> <signed.bin>";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args[..] {
+        ["keygen", key_file] => keygen(key_file),
+        ["pubkey", key_file] => load_key(key_file).map(|key| print_public_key(&key)),
+        ["sign", key_file, version, image, signed] => sign_image(key_file, version, image, signed),
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn keygen(key_file: &str) -> Result<(), String> {
+    let mut seed = [0u8; 32];
+    File::open("/dev/urandom")
+        .and_then(|mut random| random.read_exact(&mut seed))
+        .map_err(|e| format!("no randomness: {e}"))?;
+    // Never replace an existing key; images signed with it would stop verifying
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(key_file)
+        .map_err(|e| format!("{key_file}: {e}"))?;
+    writeln!(file, "{}", hex(&seed)).map_err(|e| format!("{key_file}: {e}"))?;
+    print_public_key(&SigningKey::from_bytes(&seed));
+    Ok(())
+}
+
+fn load_key(key_file: &str) -> Result<SigningKey, String> {
+    let text = fs::read_to_string(key_file).map_err(|e| format!("{key_file}: {e}"))?;
+    let seed = parse_key_bytes(&text).ok_or_else(|| format!("{key_file}: not a 64 digit hex key"))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+fn print_public_key(key: &SigningKey) {
+    println!("FIRMWARE_KEY={}", hex(key.verifying_key().as_bytes()));
+}
+
+fn sign_image(key_file: &str, version: &str, image: &str, signed: &str) -> Result<(), String> {
+    let key = load_key(key_file)?;
+    let packed = pack_version(version).ok_or_else(|| format!("{version}: want major.minor.patch, each below 1024"))?;
+    let mut body = fs::read(image).map_err(|e| format!("{image}: {e}"))?;
+    if body.len() > TRAILER_LEN && body[body.len() - TRAILER_LEN..].starts_with(b"P2WS") {
+        return Err(format!("{image}: already signed"));
+    }
+    let trailer = sign(&body, packed, &key);
+    body.extend_from_slice(&trailer);
+    fs::write(signed, &body).map_err(|e| format!("{signed}: {e}"))?;
+    println!("{signed}: {} bytes, version {version}", body.len());
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut text, byte| {
+        write!(text, "{byte:02x}").ok();
+        text
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use pico2w_core::ota::{ImageWriter, Layout, OtaError, PAGE_LEN};
+
+    use super::*;
+
+    const LAYOUT: Layout = Layout {
+        active_start: 0x1000_9000,
+        active_len: 4 * PAGE_LEN as u32,
+        ram_start: 0x2000_0000,
+        ram_end: 0x2008_0000,
+    };
+
+    // A fresh path in the temp directory, removed again when dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("pico2w-sign-{}-{name}", std::process::id()));
+            fs::remove_file(&path).ok();
+            TempFile(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            fs::remove_file(&self.0).ok();
+        }
+    }
+
+    // Just enough vector table for `ImageWriter`
+    fn image() -> Vec<u8> {
+        let mut image: Vec<u8> = (0..PAGE_LEN + 100).map(|i| i as u8).collect();
+        image[..4].copy_from_slice(&0x2008_0000u32.to_le_bytes());
+        image[4..8].copy_from_slice(&0x1000_9101u32.to_le_bytes());
+        image
+    }
+
+    fn verify(body: &[u8], key: &SigningKey, running: &str) -> Result<(), OtaError> {
+        let mut writer = ImageWriter::new(body.len(), LAYOUT)?;
+        writer.write(body, |_, _| Ok::<(), ()>(()))?;
+        let received = writer.finish(|_, _| Ok::<(), ()>(()))?;
+        received.verify(&key.verifying_key(), pack_version(running).unwrap())
+    }
+
+    #[test]
+    fn signed_images_verify() {
+        let (key_file, unsigned, signed) = (TempFile::new("key"), TempFile::new("unsigned"), TempFile::new("signed"));
+        keygen(key_file.path()).unwrap();
+        let key = load_key(key_file.path()).unwrap();
+        // An existing key is never replaced
+        assert!(keygen(key_file.path()).is_err());
+        assert_eq!(load_key(key_file.path()).unwrap().to_bytes(), key.to_bytes());
+
+        fs::write(&unsigned.0, image()).unwrap();
+        sign_image(key_file.path(), "1.2.3", unsigned.path(), signed.path()).unwrap();
+        let body = fs::read(&signed.0).unwrap();
+        assert_eq!(body.len(), image().len() + TRAILER_LEN);
+        assert_eq!(verify(&body, &key, "1.2.3"), Ok(()));
+        assert_eq!(verify(&body, &key, "1.2.4"), Err(OtaError::Downgrade));
+        assert_eq!(verify(&body, &SigningKey::from_bytes(&[7; 32]), "0.0.1"), Err(OtaError::BadSignature));
+
+        // Signing twice would bury the first trailer inside the image
+        assert!(sign_image(key_file.path(), "1.2.3", signed.path(), unsigned.path()).is_err());
+    }
+
+    #[test]
+    fn malformed_key_files_are_refused() {
+        let key_file = TempFile::new("bad-key");
+        for text in ["", "not a key", &"ab".repeat(31), &"zz".repeat(32)] {
+            fs::write(&key_file.0, text).unwrap();
+            assert!(load_key(key_file.path()).is_err(), "{text:?}");
+        }
+        assert!(load_key(TempFile::new("missing-key").path()).is_err());
+    }
+
+    #[test]
+    fn bad_versions_are_refused() {
+        let (key_file, unsigned, signed) = (TempFile::new("v-key"), TempFile::new("v-unsigned"), TempFile::new("v-signed"));
+        fs::write(&key_file.0, "07".repeat(32)).unwrap();
+        fs::write(&unsigned.0, image()).unwrap();
+        for version in ["1.2", "1.2.x", "1.2.1024", "v1.2.3"] {
+            let error = sign_image(key_file.path(), version, unsigned.path(), signed.path()).unwrap_err();
+            assert!(error.contains("major.minor.patch"), "{version}: {error}");
+        }
+        assert!(!signed.0.exists());
+    }
+}
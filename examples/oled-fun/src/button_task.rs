@@ -2,52 +2,57 @@
 // desc: Handles button press updates - FIXED VERSION
 
 use defmt::info;
-use embassy_time::{Duration, Timer};
-use embassy_sync::pipe::{Writer};
-use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{with_timeout, Duration, Timer};
 use embassy_futures::select::{select4, Either4};
 
+use pico2w_core::macropad::{Press, LONG_PRESS_MS};
+use pico2w_core::Command;
+
+use crate::macropad_task;
 use crate::setup_devices::Buttons;
+use crate::CommandSender;
+
+const DEBOUNCE: Duration = Duration::from_millis(50);
 
 #[embassy_executor::task]
 pub async fn button_task(
     mut buttons: Buttons,
-    pipe_writer: Writer<'static, CriticalSectionRawMutex, 1>
+    sender: CommandSender
 ) {
     info!("Button task started");
     
     loop {
         // Wait for ANY button to be pressed using select4
         // With pull-up resistors, buttons go LOW when pressed
-        match select4(
+        let number = match select4(
             buttons.button_1.wait_for_low(),
             buttons.button_2.wait_for_low(), 
             buttons.button_3.wait_for_low(),
             buttons.button_4.wait_for_low()
         ).await {
-            Either4::First(_) => {
-                info!("Button 1 pressed");
-                let _ = pipe_writer.write(&[1]).await;
-                buttons.button_1.wait_for_high().await;  // Wait for release
-            },
-            Either4::Second(_) => {
-                info!("Button 2 pressed");  
-                let _ = pipe_writer.write(&[2]).await;
-                buttons.button_2.wait_for_high().await;  // Wait for release
-            },
-            Either4::Third(_) => {
-                info!("Button 3 pressed");
-                let _ = pipe_writer.write(&[3]).await;
-                buttons.button_3.wait_for_high().await;  // Wait for release
-            },
-            Either4::Fourth(_) => {
-                info!("Button 4 pressed");
-                let _ = pipe_writer.write(&[4]).await;
-                buttons.button_4.wait_for_high().await;  // Wait for release
-            }
+            Either4::First(_) => 1,
+            Either4::Second(_) => 2,
+            Either4::Third(_) => 3,
+            Either4::Fourth(_) => 4,
+        };
+        info!("Button {} pressed", number);
+        let button = buttons.get_mut(number);
+
+        if macropad_task::connected() {
+            // Macro pad: held past LONG_PRESS_MS switches layers
+            Timer::after(DEBOUNCE).await;
+            let hold = Duration::from_millis(LONG_PRESS_MS) - DEBOUNCE;
+            let press = match with_timeout(hold, button.wait_for_high()).await {
+                Ok(()) => Press::Short,
+                Err(_) => Press::Long,
+            };
+            macropad_task::press(number, press);
+        } else {
+            sender.send(Command::PlayAnimation(number)).await;
         }
+        button.wait_for_high().await;  // Wait for release
         
         // Debounce delay
-        Timer::after(Duration::from_millis(50)).await;
+        Timer::after(DEBOUNCE).await;
     }
-}
\ No newline at end of file
+}
@@ -15,34 +15,36 @@ use embedded_graphics::{
 use tinybmp::Bmp;
 
 
-use embassy_sync::pipe::{Reader};
-use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use core::fmt::Write as _;
+
 use embassy_time::Timer;
+use heapless::String;
+use ssd1306::prelude::Brightness as OledBrightness;
 
 // Import from crate root
-use crate::setup_devices::Display;
-use crate::nooo::{FRAMES as NOOO_FRAMES, frame_count as nooo_frame_count};
-use crate::giga::{FRAMES as GIGA_FRAMES, frame_count as giga_frame_count};
-use crate::no_shake::{FRAMES as NO_SHAKE_FRAMES, frame_count as no_shake_frame_count};
-use crate::reaction::{FRAMES as REACTION_FRAMES, frame_count as reaction_frame_count};
-
-
-fn get_animation_data(animation_num: u8) -> (&'static [&'static [u8]], usize) {
-    match animation_num {
-        1 => (NOOO_FRAMES, nooo_frame_count()),
-        2 => (GIGA_FRAMES, giga_frame_count()),
-        3 => (NO_SHAKE_FRAMES, no_shake_frame_count()),
-        4 => (REACTION_FRAMES, reaction_frame_count()),
-        _ => (NOOO_FRAMES, nooo_frame_count()), // Default to first animation
-    }
-}
+use pico2w_bsp::display::Display;
+use pico2w_core::{get_animation_data, Brightness, Command, Frames, Player, Step};
+use pico2w_core::command::TEXT_LEN;
+use pico2w_core::macropad::{Layer, LABEL_LEN};
+use crate::CommandReceiver;
+use crate::macropad_task::shown_layer;
+use crate::nooo::{FRAMES as NOOO_FRAMES};
+use crate::giga::{FRAMES as GIGA_FRAMES};
+use crate::no_shake::{FRAMES as NO_SHAKE_FRAMES};
+use crate::reaction::{FRAMES as REACTION_FRAMES};
+
+
+// Animation N is ANIMATIONS[N - 1]
+const ANIMATIONS: &[Frames] = &[NOOO_FRAMES, GIGA_FRAMES, NO_SHAKE_FRAMES, REACTION_FRAMES];
+// How quickly a layer switch shows up on the macro pad screen
+const LAYER_POLL_MS: u64 = 50;
 
 
 // Helper function to display a specific frame of an animation
 async fn display_frame(
     display: &mut Display, 
-    current_animation_num: u8,
-    frame_index: usize) {
+    step: &Step,
+    text: Option<&str>) {
     
     // Create text style
     let text_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
@@ -50,29 +52,23 @@ async fn display_frame(
     // Clear the display
     display.clear(BinaryColor::Off).unwrap();
     
-    // Draw title in the top section
-    let title_text = match current_animation_num {
-        1 => "Animation #: 1",
-        2 => "Animation #: 2", 
-        3 => "Animation #: 3",
-        4 => "Animation #: 4",
+    // Draw title in the top section, unless a ShowText message replaces it
+    let title_text = match (text, step.animation) {
+        (Some(text), _) => text,
+        (None, 1) => "Animation #: 1",
+        (None, 2) => "Animation #: 2", 
+        (None, 3) => "Animation #: 3",
+        (None, 4) => "Animation #: 4",
         _ => "Animation #: ?",
     };
     Text::new(title_text, Point::new(0, 10), text_style)
         .draw(display)
         .unwrap();
     
-    // Get the correct animation data
-    let (frames, frame_count) = get_animation_data(current_animation_num);
-    
-    // Make sure frame_index is valid for this animation
-    let safe_frame_index = frame_index % frame_count;
-    
-    // Get current frame data
-    let current_frame_data = frames[safe_frame_index];
+    let safe_frame_index = step.frame_index;
     
     // Parse current frame as BMP
-    match Bmp::from_slice(current_frame_data) {
+    match Bmp::from_slice(step.frame) {
         Ok(bmp) => {
             // Draw the current frame centered
             let image = Image::new(&bmp, Point::new(40, 16)); // Centered for 48x48 image
@@ -88,63 +84,108 @@ async fn display_frame(
     
     // Update display
     match display.flush() {
-        Ok(_) => info!("Displayed frame {}/{}", safe_frame_index + 1, frame_count),
+        Ok(_) => info!("Displayed frame {}/{}", safe_frame_index + 1, step.frame_count),
+        Err(_) => error!("Display flush failed"),
+    }
+}
+
+// Macro pad screen: the layer on top, then what each button sends
+fn display_layer(display: &mut Display, index: usize, layer: &Layer) {
+    let text_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+    display.clear(BinaryColor::Off).unwrap();
+
+    let mut line: String<{ LABEL_LEN + 2 }> = String::new();
+    write!(line, "Layer {}: {}", index + 1, layer.name).ok();
+    Text::new(&line, Point::new(0, 10), text_style).draw(display).unwrap();
+    for (number, binding) in layer.bindings.iter().enumerate() {
+        line.clear();
+        write!(line, "{} {}", number + 1, binding.label).ok();
+        let y = 22 + 12 * number as i32;
+        Text::new(&line, Point::new(0, y), text_style).draw(display).unwrap();
+    }
+
+    match display.flush() {
+        Ok(_) => info!("Displayed macro pad layer {}", index + 1),
         Err(_) => error!("Display flush failed"),
     }
 }
 
-fn try_update_animation_num(
-    current_animation_num: u8,
-    pipe_reader: &Reader<'static, CriticalSectionRawMutex, 1>,
-) -> u8 {
-    let mut buffer = [0u8; 1];
-    match pipe_reader.try_read(&mut buffer) {
-        Ok(bytes_read) if bytes_read > 0 => {
-            let new_animation_num = buffer[0];
-            if new_animation_num >= 1 && new_animation_num <= 4 {
-                info!("Animation changed to: {}", new_animation_num);
-                new_animation_num
-            } else {
-                error!("Invalid animation number: {}", new_animation_num);
-                current_animation_num
+fn oled_brightness(brightness: Brightness) -> OledBrightness {
+    match brightness {
+        Brightness::Dimmest => OledBrightness::DIMMEST,
+        Brightness::Dim => OledBrightness::DIM,
+        Brightness::Normal => OledBrightness::NORMAL,
+        Brightness::Bright => OledBrightness::BRIGHT,
+        Brightness::Brightest => OledBrightness::BRIGHTEST,
+    }
+}
+
+fn try_apply_command(
+    display: &mut Display,
+    player: &mut Player,
+    text: &mut Option<String<TEXT_LEN>>,
+    receiver: &CommandReceiver,
+) {
+    // No command waiting, keep current
+    let Ok(command) = receiver.try_receive() else {
+        return;
+    };
+    info!("Command received: {:?}", command);
+
+    match command {
+        Command::SetBrightness(brightness) => {
+            if display.set_brightness(oled_brightness(brightness)).is_err() {
+                error!("Failed to set brightness");
             }
-        },
-        _ => current_animation_num, // No data or error, keep current
+        }
+        // An empty message hands the title back to the animation number
+        Command::ShowText(message) => *text = (!message.is_empty()).then_some(message),
+        command => {
+            if let Err(e) = player.apply(&command) {
+                error!("Rejected command: {:?}", e);
+            }
+        }
     }
 }
 
 #[embassy_executor::task]
 pub async fn display_task(
     mut display: Display,
-    mut pipe_reader: Reader<'static, CriticalSectionRawMutex, 1>,
+    receiver: CommandReceiver,
 ) {
-    let mut frame_index = 0usize;
-    let mut current_animation_num: u8 = 1;
-    let mut previous_animation_num: u8 = 0; // Track animation changes
+    let mut player = Player::new(ANIMATIONS);
+    let mut text: Option<String<TEXT_LEN>> = None;
+    // Macro pad layer on screen, redrawn only when it changes
+    let mut layer_shown: Option<usize> = None;
     
     // Get initial animation info
-    let (_, initial_frame_count) = get_animation_data(current_animation_num);
-    info!("Starting display task with animation {} ({} frames)", current_animation_num, initial_frame_count);
+    let (_, initial_frame_count) = get_animation_data(ANIMATIONS, player.current());
+    info!("Starting display task with animation {} ({} frames)", player.current(), initial_frame_count);
     
     loop {
-        // Check for animation changes (non-blocking)
-        current_animation_num = try_update_animation_num(current_animation_num, &mut pipe_reader);
+        // Check for new commands (non-blocking)
+        try_apply_command(&mut display, &mut player, &mut text, &receiver);
+
+        // A USB host turns the buttons into a macro pad; show its layer instead
+        if let Some((index, layer)) = shown_layer() {
+            if layer_shown != Some(index) {
+                display_layer(&mut display, index, layer);
+                layer_shown = Some(index);
+            }
+            Timer::after_millis(LAYER_POLL_MS).await;
+            continue;
+        }
+        layer_shown = None;
         
-        // Reset frame index when animation changes
-        if current_animation_num != previous_animation_num {
-            frame_index = 0;
-            previous_animation_num = current_animation_num;
-            let (_, frame_count) = get_animation_data(current_animation_num);
-            info!("Switched to animation {} with {} frames", current_animation_num, frame_count);
+        // Pick the frame to show; the player restarts at frame 0 when the animation changes
+        let step = player.step();
+        if step.switched {
+            info!("Switched to animation {} with {} frames", step.animation, step.frame_count);
         }
         
         // Display current frame
-        display_frame(&mut display, current_animation_num, frame_index).await;
+        display_frame(&mut display, &step, text.as_deref()).await;
         
-        // Advance to next frame (with bounds checking for current animation)
-        let (_, frame_count) = get_animation_data(current_animation_num);
-        frame_index = (frame_index + 1) % frame_count;
-        
-        Timer::after_millis(100).await; // Animation speed
+        Timer::after_millis(player.frame_interval_ms() as u64).await; // Animation speed
     }
-}
\ No newline at end of file
+}
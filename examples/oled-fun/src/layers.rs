@@ -0,0 +1,56 @@
+// file: layers.rs
+// desc: macro pad layers; edit to rebind the buttons
+//
+// Button N sends `bindings[N - 1]` of the active layer; holding button N
+// switches to layer N. Labels are what the OLED shows, at most LABEL_LEN
+// characters.
+use pico2w_core::macropad::{key, media, modifier, Action, Binding, KeyStroke, Layer};
+
+const fn ctrl(c: u8) -> KeyStroke {
+    KeyStroke::new(modifier::CTRL, key::letter(c))
+}
+
+const COPY: &[KeyStroke] = &[ctrl(b'c')];
+const PASTE: &[KeyStroke] = &[ctrl(b'v')];
+const UNDO: &[KeyStroke] = &[ctrl(b'z')];
+const SAVE: &[KeyStroke] = &[ctrl(b's')];
+const NEW_TAB: &[KeyStroke] = &[KeyStroke::new(modifier::CTRL | modifier::SHIFT, key::letter(b't'))];
+
+pub const LAYERS: &[Layer] = &[
+    Layer {
+        name: "Media",
+        bindings: [
+            Binding::new("Play/Pause", Action::Media(media::PLAY_PAUSE)),
+            Binding::new("Previous", Action::Media(media::PREVIOUS_TRACK)),
+            Binding::new("Next", Action::Media(media::NEXT_TRACK)),
+            Binding::new("Mute", Action::Media(media::MUTE)),
+        ],
+    },
+    Layer {
+        name: "Edit",
+        bindings: [
+            Binding::new("Copy", Action::Keys(COPY)),
+            Binding::new("Paste", Action::Keys(PASTE)),
+            Binding::new("Undo", Action::Keys(UNDO)),
+            Binding::new("Save", Action::Keys(SAVE)),
+        ],
+    },
+    Layer {
+        name: "Shell",
+        bindings: [
+            Binding::new("git status", Action::Text("git status\n")),
+            Binding::new("cargo build", Action::Text("cargo build\n")),
+            Binding::new("New tab", Action::Keys(NEW_TAB)),
+            Binding::new("Ctrl+C", Action::Keys(COPY)),
+        ],
+    },
+    Layer {
+        name: "Volume",
+        bindings: [
+            Binding::new("Volume down", Action::Media(media::VOLUME_DOWN)),
+            Binding::new("Volume up", Action::Media(media::VOLUME_UP)),
+            Binding::new("Stop", Action::Media(media::STOP)),
+            Binding::new("", Action::None),
+        ],
+    },
+];
@@ -0,0 +1,166 @@
+// file: macropad_task.rs
+// desc: USB HID keyboard and media keys driven by the buttons
+//
+// While a USB host has the board configured, `button_task` hands presses here
+// instead of switching animations, and the OLED shows the active layer (see
+// `layers.rs`). On a charger nothing enumerates and the board stays an
+// animation player.
+use defmt::{info, warn};
+
+use embassy_rp::bind_interrupts;
+use embassy_rp::peripherals::USB;
+use embassy_rp::usb::{Driver, InterruptHandler};
+use embassy_rp::Peri;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_usb::class::hid::{Config as HidConfig, HidBootProtocol, HidSubclass, HidWriter, State as HidState};
+use embassy_usb::{Builder, Config, Handler, UsbDevice};
+use portable_atomic::{AtomicBool, AtomicUsize, Ordering};
+use static_cell::{ConstStaticCell, StaticCell};
+
+use pico2w_core::macropad::{
+    Layer, MacroPad, Outcome, Press, Report, CONSUMER_REPORT_DESCRIPTOR, CONSUMER_REPORT_LEN,
+    KEYBOARD_REPORT_DESCRIPTOR, KEYBOARD_REPORT_LEN,
+};
+
+use crate::layers::LAYERS;
+
+bind_interrupts!(struct Irqs {
+    USBCTRL_IRQ => InterruptHandler<USB>;
+});
+
+// pid.codes test IDs; fine on a desk, not for anything shipped
+const USB_VID: u16 = 0x1209;
+const USB_PID: u16 = 0x0001;
+const POLL_MS: u8 = 10;
+const PRESS_QUEUE_LEN: usize = 4;
+
+pub type UsbDriver = Driver<'static, USB>;
+pub type Keyboard = HidWriter<'static, UsbDriver, KEYBOARD_REPORT_LEN>;
+pub type MediaKeys = HidWriter<'static, UsbDriver, CONSUMER_REPORT_LEN>;
+
+static CONFIG_DESCRIPTOR: ConstStaticCell<[u8; 256]> = ConstStaticCell::new([0; 256]);
+static BOS_DESCRIPTOR: ConstStaticCell<[u8; 256]> = ConstStaticCell::new([0; 256]);
+static CONTROL_BUF: ConstStaticCell<[u8; 64]> = ConstStaticCell::new([0; 64]);
+static KEYBOARD_STATE: StaticCell<HidState> = StaticCell::new();
+static MEDIA_STATE: StaticCell<HidState> = StaticCell::new();
+static HOST: StaticCell<HostState> = StaticCell::new();
+
+static CONFIGURED: AtomicBool = AtomicBool::new(false);
+static ACTIVE_LAYER: AtomicUsize = AtomicUsize::new(0);
+static PRESSES: Channel<CriticalSectionRawMutex, (u8, Press), PRESS_QUEUE_LEN> = Channel::new();
+
+/// Whether a host is listening; the buttons are a macro pad while it is.
+pub fn connected() -> bool {
+    CONFIGURED.load(Ordering::Relaxed)
+}
+
+/// The layer the OLED should show, if the board is a macro pad right now.
+pub fn shown_layer() -> Option<(usize, &'static Layer)> {
+    let active = ACTIVE_LAYER.load(Ordering::Relaxed);
+    LAYERS.get(active).filter(|_| connected()).map(|layer| (active, layer))
+}
+
+/// Queue a press for `macropad_task`; `button` counted from 1.
+pub fn press(button: u8, press: Press) {
+    if PRESSES.try_send((button, press)).is_err() {
+        warn!("Macro pad busy, dropped button {}", button);
+    }
+}
+
+// Tracks whether the host has configured the device
+struct HostState;
+
+impl Handler for HostState {
+    fn enabled(&mut self, enabled: bool) {
+        if !enabled {
+            CONFIGURED.store(false, Ordering::Relaxed);
+        }
+    }
+
+    fn reset(&mut self) {
+        CONFIGURED.store(false, Ordering::Relaxed);
+    }
+
+    fn configured(&mut self, configured: bool) {
+        info!("USB host {}", if configured { "attached" } else { "gone" });
+        CONFIGURED.store(configured, Ordering::Relaxed);
+    }
+}
+
+/// Describe the device; `usb_task` runs it and `macropad_task` writes the
+/// reports.
+pub fn setup(usb: Peri<'static, USB>) -> (UsbDevice<'static, UsbDriver>, Keyboard, MediaKeys) {
+    let driver = Driver::new(usb, Irqs);
+
+    let mut config = Config::new(USB_VID, USB_PID);
+    config.manufacturer = Some("Pico 2W");
+    config.product = Some("Pico 2W Macro Pad");
+    config.max_power = 100;
+    config.max_packet_size_0 = 64;
+
+    let mut builder = Builder::new(
+        driver,
+        config,
+        CONFIG_DESCRIPTOR.take(),
+        BOS_DESCRIPTOR.take(),
+        &mut [],
+        CONTROL_BUF.take(),
+    );
+
+    // Boot protocol, so BIOS and boot menus take the keys too
+    let keyboard = HidWriter::new(&mut builder, KEYBOARD_STATE.init(HidState::new()), HidConfig {
+        report_descriptor: KEYBOARD_REPORT_DESCRIPTOR,
+        request_handler: None,
+        poll_ms: POLL_MS,
+        max_packet_size: KEYBOARD_REPORT_LEN as u16,
+        hid_subclass: HidSubclass::Boot,
+        hid_boot_protocol: HidBootProtocol::Keyboard,
+    });
+    let media = HidWriter::new(&mut builder, MEDIA_STATE.init(HidState::new()), HidConfig {
+        report_descriptor: CONSUMER_REPORT_DESCRIPTOR,
+        request_handler: None,
+        poll_ms: POLL_MS,
+        max_packet_size: CONSUMER_REPORT_LEN as u16,
+        hid_subclass: HidSubclass::No,
+        hid_boot_protocol: HidBootProtocol::None,
+    });
+    builder.handler(HOST.init(HostState));
+
+    (builder.build(), keyboard, media)
+}
+
+#[embassy_executor::task]
+pub async fn usb_task(mut device: UsbDevice<'static, UsbDriver>) {
+    device.run().await;
+}
+
+#[embassy_executor::task]
+pub async fn macropad_task(mut keyboard: Keyboard, mut media: MediaKeys) {
+    let mut pad = MacroPad::new(LAYERS);
+    info!("Macro pad ready with {} layers", LAYERS.len());
+
+    loop {
+        let (button, press) = PRESSES.receive().await;
+        match pad.press(button, press) {
+            Outcome::Send(action) => {
+                // Reports go out in order; a press and its release never split
+                for report in action.reports() {
+                    let sent = match report {
+                        Report::Keyboard(report) => keyboard.write(&report).await,
+                        Report::Consumer(report) => media.write(&report).await,
+                    };
+                    if let Err(e) = sent {
+                        warn!("HID report not sent: {:?}", e);
+                        break;
+                    }
+                }
+            }
+            Outcome::Layer(layer) => {
+                info!("Macro pad layer {}: {}", layer + 1, LAYERS[layer].name);
+                ACTIVE_LAYER.store(layer, Ordering::Relaxed);
+            }
+            Outcome::Ignored => {}
+        }
+    }
+}
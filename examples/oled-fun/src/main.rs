@@ -6,8 +6,9 @@
 use defmt::*;
 use embassy_executor::Spawner;
 use embassy_time::Timer;
-use embassy_sync::pipe::{Pipe};
+use embassy_sync::channel::{Channel, Receiver, Sender};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use pico2w_core::Command;
 use static_cell::StaticCell;
 
 
@@ -15,13 +16,18 @@ use {defmt_rtt as _, panic_probe as _};
 
 // Import setup mod
 mod setup_devices;
-use setup_devices::{setup_display, setup_wifi, setup_buttons};
+use setup_devices::{setup_buttons};
+use pico2w_bsp::Board;
+use pico2w_bsp::display::setup_display;
 
 // Import task mods
 mod display_task;
 use display_task::{display_task};
 mod button_task;
 use button_task::{button_task};
+mod macropad_task;
+use macropad_task::{macropad_task, usb_task};
+mod layers;
 
 // Import animations
 mod nooo;
@@ -40,7 +46,12 @@ pub static PICOTOOL_ENTRIES: [embassy_rp::binary_info::EntryAddr; 3] = [
     embassy_rp::binary_info::rp_program_build_attribute!(),
 ];
 
-static ANIMATION_STATE_PIPE: StaticCell<Pipe<CriticalSectionRawMutex, 1>> = StaticCell::new();
+// Commands from every input source, consumed by the display task
+const COMMAND_QUEUE_LEN: usize = 4;
+pub type CommandSender = Sender<'static, CriticalSectionRawMutex, Command, COMMAND_QUEUE_LEN>;
+pub type CommandReceiver = Receiver<'static, CriticalSectionRawMutex, Command, COMMAND_QUEUE_LEN>;
+
+static COMMAND_CHANNEL: StaticCell<Channel<CriticalSectionRawMutex, Command, COMMAND_QUEUE_LEN>> = StaticCell::new();
 
 
 #[embassy_executor::main]
@@ -48,39 +59,37 @@ async fn main(spawner: Spawner) {
     // Initialize peripherals
     let p = embassy_rp::init(Default::default());
 
-    // Initialize the pipe and split it
-    let animation_state_pipe = ANIMATION_STATE_PIPE.init(Pipe::new());
-    let (reader, writer) = animation_state_pipe.split();
+    // Initialize the command channel
+    let command_channel = COMMAND_CHANNEL.init(Channel::new());
+    let (sender, receiver) = (command_channel.sender(), command_channel.receiver());
     
     // Setup individual components
-    let display = setup_display(p.I2C0, 
-        p.PIN_0, 
-        p.PIN_1).await;
-    
-    let mut wifi_controller = setup_wifi(
-        p.PIO0,
-        p.PIN_23,
-        p.PIN_25,
-        p.PIN_24,
-        p.PIN_29,
-        p.DMA_CH0,
-        &spawner
-    ).await;
+    let board = Board::init(p, &spawner).await;
+    let pins = board.pins;
+
+    let display = setup_display(pins.I2C0, 
+        pins.PIN_0, 
+        pins.PIN_1).await;
     
     // Turn on WiFi LED
-    wifi_controller.gpio_set(0, true).await;
+    board.led.on().await;
     info!("WiFi LED enabled");
     info!("System initialization complete!");
 
     // Setup Buttons
-    let buttons = setup_buttons(p.PIN_6, 
-        p.PIN_7, 
-        p.PIN_8, 
-        p.PIN_9).await;
+    let buttons = setup_buttons(pins.PIN_6, 
+        pins.PIN_7, 
+        pins.PIN_8, 
+        pins.PIN_9).await;
+
+    // USB HID macro pad, active while a host is attached
+    let (usb, keyboard, media) = macropad_task::setup(pins.USB);
 
     // Create tasks
-    spawner.spawn(display_task(display, reader)).unwrap();
-    spawner.spawn(button_task(buttons, writer)).unwrap();
+    spawner.spawn(display_task(display, receiver)).unwrap();
+    spawner.spawn(button_task(buttons, sender)).unwrap();
+    spawner.spawn(usb_task(usb)).unwrap();
+    spawner.spawn(macropad_task(keyboard, media)).unwrap();
     
     // Main animation loop
     loop {
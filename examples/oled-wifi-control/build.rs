@@ -14,6 +14,9 @@ use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
 
+use pico2w_core::network::{NetEnv, NetSettings};
+use pico2w_core::schedule::Schedule;
+
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
     // on the linker search path.
@@ -33,4 +36,33 @@ fn main() {
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+
+    check_settings();
+}
+
+// The firmware reads these with `option_env!`; a typo should fail the build,
+// not show up as a warning on the board
+fn check_settings() {
+    let var = |name: &str| {
+        println!("cargo:rerun-if-env-changed={name}");
+        env::var(name).ok()
+    };
+
+    if let Err(e) = Schedule::from_env(var("ROTATE_EVERY_S").as_deref(), var("ROTATE_HOLD_S").as_deref()) {
+        panic!("invalid rotation setting: {e:?}; ROTATE_EVERY_S and ROTATE_HOLD_S are whole seconds above 0");
+    }
+
+    let (mode, address, gateway) = (var("NET_MODE"), var("NET_ADDRESS"), var("NET_GATEWAY"));
+    let (dns, hostname, dhcp_timeout_s) = (var("NET_DNS"), var("NET_HOSTNAME"), var("NET_DHCP_TIMEOUT_S"));
+    let env = NetEnv {
+        mode: mode.as_deref(),
+        address: address.as_deref(),
+        gateway: gateway.as_deref(),
+        dns: dns.as_deref(),
+        hostname: hostname.as_deref(),
+        dhcp_timeout_s: dhcp_timeout_s.as_deref(),
+    };
+    if let Err(e) = NetSettings::from_env(&env) {
+        panic!("invalid network setting: {}", e.message());
+    }
 }
\ No newline at end of file
@@ -2,9 +2,28 @@ MEMORY {
     /*
      * The RP2350 has either external or internal flash.
      *
-     * 2 MiB is a safe default here, although a Pico 2 has 4 MiB.
+     * The Pico 2 W has 4 MiB. Firmware and settings fit in the first 2 MiB;
+     * the second holds the USB animations drive.
+     *
+     * The bootloader (crates/pico2w-bootloader) owns the first 32K and the
+     * next 4K holds its state. The application runs from ACTIVE, here
+     * FLASH, and updates are written to DFU. Keep this in sync with the
+     * bootloader's memory.x.
+     */
+    BOOTLOADER : ORIGIN = 0x10000000, LENGTH = 32K
+    BOOTLOADER_STATE : ORIGIN = 0x10008000, LENGTH = 4K
+    FLASH : ORIGIN = 0x10009000, LENGTH = 1000K
+    DFU : ORIGIN = 0x10103000, LENGTH = 1004K
+    /*
+     * The last 4K sector of the first 2 MiB holds the WiFi settings record, see
+     * src/settings_store.rs. Keep the two in sync.
      */
-    FLASH : ORIGIN = 0x10000000, LENGTH = 2048K
+    SETTINGS : ORIGIN = 0x101FF000, LENGTH = 4K
+    /*
+     * FAT volume shared with the host over USB mass storage, see
+     * src/drive_task.rs. Keep the two in sync.
+     */
+    ASSETS : ORIGIN = 0x10200000, LENGTH = 2048K
     /*
      * RAM consists of 8 banks, SRAM0-SRAM7, with a striped mapping.
      * This is usually good for performance, as it distributes load on
@@ -21,6 +40,13 @@ MEMORY {
     SRAM9 : ORIGIN = 0x20081000, LENGTH = 4K
 }
 
+/* Partition offsets from the start of flash, for embassy-boot */
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - ORIGIN(BOOTLOADER);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - ORIGIN(BOOTLOADER);
+
+__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(BOOTLOADER);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(BOOTLOADER);
+
 SECTIONS {
     /* ### Boot ROM info
      *
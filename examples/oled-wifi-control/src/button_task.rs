@@ -0,0 +1,64 @@
+// file: button_task.rs
+// desc: Handles button press updates
+
+use defmt::info;
+use embassy_time::{Duration, Timer};
+use embassy_futures::select::{select4, Either4};
+
+use pico2w_core::event::ButtonEvent;
+use pico2w_core::{Command, Event, Input, Source};
+
+use crate::setup_devices::Buttons;
+use crate::{CommandSender, EVENTS};
+
+#[embassy_executor::task]
+pub async fn button_task(
+    mut buttons: Buttons,
+    sender: CommandSender
+) {
+    info!("Button task started");
+    
+    loop {
+        // Wait for ANY button to be pressed using select4
+        // With pull-up resistors, buttons go LOW when pressed
+        match select4(
+            buttons.button_1.wait_for_low(),
+            buttons.button_2.wait_for_low(), 
+            buttons.button_3.wait_for_low(),
+            buttons.button_4.wait_for_low()
+        ).await {
+            Either4::First(_) => {
+                info!("Button 1 pressed");
+                pressed(1);
+                sender.send(Input { source: Source::Button, command: Command::PlayAnimation(1) }).await;
+                buttons.button_1.wait_for_high().await;  // Wait for release
+            },
+            Either4::Second(_) => {
+                info!("Button 2 pressed");  
+                pressed(2);
+                sender.send(Input { source: Source::Button, command: Command::PlayAnimation(2) }).await;
+                buttons.button_2.wait_for_high().await;  // Wait for release
+            },
+            Either4::Third(_) => {
+                info!("Button 3 pressed");
+                pressed(3);
+                sender.send(Input { source: Source::Button, command: Command::PlayAnimation(3) }).await;
+                buttons.button_3.wait_for_high().await;  // Wait for release
+            },
+            Either4::Fourth(_) => {
+                info!("Button 4 pressed");
+                pressed(4);
+                sender.send(Input { source: Source::Button, command: Command::PlayAnimation(4) }).await;
+                buttons.button_4.wait_for_high().await;  // Wait for release
+            }
+        }
+        
+        // Debounce delay
+        Timer::after(Duration::from_millis(50)).await;
+    }
+}
+
+// Tell subscribers about the press itself, whatever it ends up doing
+fn pressed(button: u8) {
+    EVENTS.immediate_publisher().publish_immediate(Event::Button(ButtonEvent { button }));
+}
@@ -6,74 +6,135 @@ use embedded_graphics::{
     mono_font::{ascii::FONT_6X10, MonoTextStyle},
     pixelcolor::BinaryColor,
     prelude::*,
-    text::Text,
-    image::Image,
+    text::{Alignment, Text},
+    image::{Image, ImageRaw},
 };
 use tinybmp::Bmp;
 
-use defmt::{info, error};
-use embassy_sync::pipe::{Reader};
+use core::cell::{Cell, RefCell};
+use core::fmt::Write as _;
+
+use defmt::{info, error, warn};
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::watch::Watch;
 use embassy_time::Timer;
+use heapless::{String, Vec};
+use ssd1306::prelude::Brightness as OledBrightness;
+use static_cell::ConstStaticCell;
 
 // Import from crate root
-use crate::setup_devices::Display;
-use crate::nooo::{FRAMES as NOOO_FRAMES, frame_count as nooo_frame_count};
-use crate::giga::{FRAMES as GIGA_FRAMES, frame_count as giga_frame_count};
-use crate::no_shake::{FRAMES as NO_SHAKE_FRAMES, frame_count as no_shake_frame_count};
-use crate::reaction::{FRAMES as REACTION_FRAMES, frame_count as reaction_frame_count};
-
-
-fn get_animation_data(animation_num: u8) -> (&'static [&'static [u8]], usize) {
-    match animation_num {
-        1 => (NOOO_FRAMES, nooo_frame_count()),
-        2 => (GIGA_FRAMES, giga_frame_count()),
-        3 => (NO_SHAKE_FRAMES, no_shake_frame_count()),
-        4 => (REACTION_FRAMES, reaction_frame_count()),
-        _ => (NOOO_FRAMES, nooo_frame_count()), // Default to first animation
-    }
+use pico2w_bsp::display::Display;
+use pico2w_core::{get_animation_data, Brightness, Command, Event, Frames, Input, PlaybackState, Player, Step};
+use pico2w_core::assets::{scan, MAX_ANIMATIONS};
+use pico2w_core::command::TEXT_LEN;
+use pico2w_core::event::BrightnessEvent;
+use pico2w_core::fat::Volume;
+use pico2w_core::link::LinkState;
+use pico2w_core::provision::{ap_ssid, AP_URL};
+use pico2w_core::screen::{Framebuffer, WIDTH};
+use crate::{CommandReceiver, EVENTS};
+use crate::drive_task::{self, DriveChange};
+use crate::networking_task::LINK_STATE;
+use crate::provisioning::AP_PASSWORD;
+use crate::nooo::{FRAMES as NOOO_FRAMES};
+use crate::giga::{FRAMES as GIGA_FRAMES};
+use crate::no_shake::{FRAMES as NO_SHAKE_FRAMES};
+use crate::reaction::{FRAMES as REACTION_FRAMES};
+
+
+// Animation N is ANIMATIONS[N - 1]; the USB drive's come after these
+const ANIMATIONS: &[Frames] = &[NOOO_FRAMES, GIGA_FRAMES, NO_SHAKE_FRAMES, REACTION_FRAMES];
+pub const ANIMATION_COUNT: usize = ANIMATIONS.len();
+// Frames of every animation on the drive together
+const MAX_DRIVE_FRAMES: usize = 256;
+type Animations<'a> = Vec<Frames<'a>, { ANIMATION_COUNT + MAX_ANIMATIONS }>;
+
+// Frames found on the drive, each a slice of flash
+static DRIVE_FRAMES: ConstStaticCell<[&'static [u8]; MAX_DRIVE_FRAMES]> = ConstStaticCell::new([&[]; MAX_DRIVE_FRAMES]);
+
+/// How many animations can be played, the drive's included; MQTT follows it to
+/// keep Home Assistant's list in step.
+pub static ANIMATIONS_LOADED: Watch<CriticalSectionRawMutex, u8, 1> = Watch::new();
+
+// Latest player state, published every frame for the status API
+static PLAYBACK: Mutex<CriticalSectionRawMutex, Cell<Option<PlaybackState>>> = Mutex::new(Cell::new(None));
+
+/// What the display is showing; None until the display task has started.
+pub fn playback_state() -> Option<PlaybackState> {
+    PLAYBACK.lock(|state| state.get())
+}
+
+// Contrast preset last applied; the panel starts at its default
+static BRIGHTNESS: Mutex<CriticalSectionRawMutex, Cell<Brightness>> = Mutex::new(Cell::new(Brightness::Normal));
+
+/// The panel's current contrast preset.
+pub fn brightness() -> Brightness {
+    BRIGHTNESS.lock(|current| current.get())
+}
+
+// Last frame flushed to the panel, numbered so viewers can spot new ones
+static SCREEN: Mutex<CriticalSectionRawMutex, RefCell<(u32, Framebuffer)>> =
+    Mutex::new(RefCell::new((0, Framebuffer::new())));
+
+/// Copy the last flushed frame into `frame` and return its number.
+pub fn capture(frame: &mut Framebuffer) -> u32 {
+    SCREEN.lock(|screen| {
+        let screen = screen.borrow();
+        frame.clone_from(&screen.1);
+        screen.0
+    })
 }
 
 
 // Helper function to display a specific frame of an animation
 async fn display_frame(
     display: &mut Display, 
-    current_animation_num: u8,
-    frame_index: usize) {
+    frame: &mut Framebuffer,
+    step: &Step,
+    text: Option<&str>) {
     
     // Create text style
     let text_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
     
-    // Clear the display
-    display.clear(BinaryColor::Off).unwrap();
+    // Draw into our own framebuffer so screenshots match the panel exactly
+    frame.clear(BinaryColor::Off).unwrap();
+
+    let link = LINK_STATE.try_get();
+    if let Some(LinkState::Provisioning { mac }) = link {
+        draw_setup_screen(frame, &mac);
+        return show(display, frame);
+    }
     
-    // Draw title in the top section
-    let title_text = match current_animation_num {
-        1 => "Animation #: 1",
-        2 => "Animation #: 2", 
-        3 => "Animation #: 3",
-        4 => "Animation #: 4",
-        _ => "Animation #: ?",
+    // Draw title in the top section, unless a ShowText message replaces it
+    let mut title: String<16> = String::new();
+    let title_text = match text {
+        Some(text) => text,
+        None => {
+            write!(title, "Animation #: {}", step.animation).ok();
+            title.as_str()
+        }
     };
     Text::new(title_text, Point::new(0, 10), text_style)
-        .draw(display)
+        .draw(frame)
         .unwrap();
+
+    // WiFi link state in the top right corner
+    if let Some(link) = link {
+        Text::with_alignment(link.badge(), Point::new(WIDTH as i32 - 1, 10), text_style, Alignment::Right)
+            .draw(frame)
+            .unwrap();
+    }
     
-    // Get the correct animation data
-    let (frames, frame_count) = get_animation_data(current_animation_num);
-    
-    // Make sure frame_index is valid for this animation
-    let safe_frame_index = frame_index % frame_count;
-    
-    // Get current frame data
-    let current_frame_data = frames[safe_frame_index];
+    let safe_frame_index = step.frame_index;
     
     // Parse current frame as BMP
-    match Bmp::from_slice(current_frame_data) {
+    match Bmp::from_slice(step.frame) {
         Ok(bmp) => {
             // Draw the current frame centered
             let image = Image::new(&bmp, Point::new(40, 16)); // Centered for 48x48 image
-            match image.draw(display) {
+            match image.draw(frame) {
                 Ok(_) => {},
                 Err(_) => error!("Failed to draw frame {}", safe_frame_index),
             }
@@ -83,65 +144,183 @@ async fn display_frame(
         }
     }
     
-    // Update display
-    match display.flush() {
-        Ok(_) => info!("Displayed frame {}/{}", safe_frame_index + 1, frame_count),
-        Err(_) => error!("Display flush failed"),
+    show(display, frame);
+    info!("Displayed frame {}/{}", safe_frame_index + 1, step.frame_count);
+}
+
+// How to join the setup access point, in place of the animation
+fn draw_setup_screen(frame: &mut Framebuffer, mac: &[u8; 6]) {
+    let text_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+    let ssid = ap_ssid(mac);
+    let mut password: String<32> = String::new();
+    write!(password, "Pass: {}", AP_PASSWORD).ok();
+    let lines = ["WiFi setup", ssid.as_str(), password.as_str(), AP_URL.trim_end_matches('/')];
+    for (row, line) in lines.iter().enumerate() {
+        Text::new(line, Point::new(0, 10 + 16 * row as i32), text_style)
+            .draw(frame)
+            .unwrap();
     }
 }
 
-fn try_update_animation_num(
-    current_animation_num: u8,
-    pipe_reader: &Reader<'static, CriticalSectionRawMutex, 1>,
-) -> u8 {
-    let mut buffer = [0u8; 1];
-    match pipe_reader.try_read(&mut buffer) {
-        Ok(bytes_read) if bytes_read > 0 => {
-            let new_animation_num = buffer[0];
-            if new_animation_num >= 1 && new_animation_num <= 4 {
-                info!("Animation changed to: {}", new_animation_num);
-                new_animation_num
-            } else {
-                error!("Invalid animation number: {}", new_animation_num);
-                current_animation_num
+// Copy the frame to the panel and keep it for screenshots
+fn show(display: &mut Display, frame: &Framebuffer) {
+    let raw = ImageRaw::<BinaryColor>::new(frame.as_bytes(), WIDTH as u32);
+    Image::new(&raw, Point::zero()).draw(display).unwrap();
+    if display.flush().is_err() {
+        error!("Display flush failed");
+    }
+
+    SCREEN.lock(|screen| {
+        let mut screen = screen.borrow_mut();
+        screen.0 = screen.0.wrapping_add(1);
+        screen.1.clone_from(frame);
+    });
+}
+
+// The built-in animations, then the drive's unless the host is writing to it
+fn load_animations<'a>(table: &'a mut [&'static [u8]], use_drive: bool) -> Animations<'a> {
+    let mut animations = Animations::new();
+    animations.extend_from_slice(ANIMATIONS).ok();
+    if !use_drive {
+        return animations;
+    }
+    let volume = match Volume::mount(drive_task::volume()) {
+        Ok(volume) => volume,
+        Err(e) => {
+            warn!("Animations drive unreadable: {:?}", e);
+            return animations;
+        }
+    };
+
+    let catalog = scan(&volume, table);
+    let table: &'a [&'static [u8]] = table;
+    for animation in catalog.animations.iter() {
+        info!(
+            "Animation {} from the drive: {=str} ({} frames)",
+            animations.len() + 1,
+            animation.name.as_str(),
+            animation.frames.len()
+        );
+        animations.push(&table[animation.frames.clone()]).ok();
+    }
+    if catalog.skipped > 0 {
+        warn!("Skipped {} frames on the animations drive (GIFs, non-BMPs, fragmented or too many)", catalog.skipped);
+    }
+    animations
+}
+
+// Keep speed, pause and, if it is still there, the animation across a reload
+fn resume(player: &mut Player, state: &PlaybackState) {
+    player.apply(&Command::SetSpeed(state.frame_interval_ms)).ok();
+    if player.select(state.animation).is_err() {
+        info!("Animation {} is gone, back to 1", state.animation);
+    }
+    if state.paused {
+        player.apply(&Command::Pause).ok();
+    }
+}
+
+fn oled_brightness(brightness: Brightness) -> OledBrightness {
+    match brightness {
+        Brightness::Dimmest => OledBrightness::DIMMEST,
+        Brightness::Dim => OledBrightness::DIM,
+        Brightness::Normal => OledBrightness::NORMAL,
+        Brightness::Bright => OledBrightness::BRIGHT,
+        Brightness::Brightest => OledBrightness::BRIGHTEST,
+    }
+}
+
+fn try_apply_command(
+    display: &mut Display,
+    player: &mut Player,
+    text: &mut Option<String<TEXT_LEN>>,
+    receiver: &CommandReceiver,
+) {
+    // No command waiting, keep current
+    let Ok(Input { source, command }) = receiver.try_receive() else {
+        return;
+    };
+    info!("Command received from {:?}: {:?}", source, command);
+
+    match command {
+        Command::SetBrightness(brightness) => {
+            if display.set_brightness(oled_brightness(brightness)).is_err() {
+                error!("Failed to set brightness");
+                return;
             }
-        },
-        _ => current_animation_num, // No data or error, keep current
+            BRIGHTNESS.lock(|current| current.set(brightness));
+            EVENTS
+                .immediate_publisher()
+                .publish_immediate(Event::Brightness(BrightnessEvent { brightness, source }));
+        }
+        // An empty message hands the title back to the animation number
+        Command::ShowText(message) => *text = (!message.is_empty()).then_some(message),
+        command => {
+            let before = player.state();
+            if let Err(e) = player.apply(&command) {
+                error!("Rejected command: {:?}", e);
+                return;
+            }
+
+            // Tell live subscribers; a slow one loses old events rather than stalling us
+            let publisher = EVENTS.immediate_publisher();
+            for event in Event::between(&before, &player.state(), source) {
+                publisher.publish_immediate(event);
+            }
+        }
     }
 }
 
 #[embassy_executor::task]
 pub async fn display_task(
     mut display: Display,
-    mut pipe_reader: Reader<'static, CriticalSectionRawMutex, 1>,
+    receiver: CommandReceiver,
 ) {
-    let mut frame_index = 0usize;
-    let mut current_animation_num: u8 = 1;
-    let mut previous_animation_num: u8 = 0; // Track animation changes
+    let drive_frames = DRIVE_FRAMES.take();
+    let mut text: Option<String<TEXT_LEN>> = None;
+    let mut frame = Framebuffer::new();
+    let mut use_drive = true;
+    let mut resume_from: Option<PlaybackState> = None;
     
     // Get initial animation info
-    let (_, initial_frame_count) = get_animation_data(current_animation_num);
-    info!("Starting display task with animation {} ({} frames)", current_animation_num, initial_frame_count);
+    let (_, initial_frame_count) = get_animation_data(ANIMATIONS, 1);
+    info!("Starting display task with animation 1 ({} frames)", initial_frame_count);
     
+    // Once per change on the animations drive
     loop {
-        // Check for animation changes (non-blocking)
-        current_animation_num = try_update_animation_num(current_animation_num, &mut pipe_reader);
-        
-        // Reset frame index when animation changes
-        if current_animation_num != previous_animation_num {
-            frame_index = 0;
-            previous_animation_num = current_animation_num;
-            let (_, frame_count) = get_animation_data(current_animation_num);
-            info!("Switched to animation {} with {} frames", current_animation_num, frame_count);
+        // The last player and its frames are gone; the drive may rewrite them
+        if !use_drive {
+            drive_task::release();
+        }
+        let animations = load_animations(&mut drive_frames[..], use_drive);
+        ANIMATIONS_LOADED.sender().send(animations.len() as u8);
+        let mut player = Player::new(&animations);
+        if let Some(state) = resume_from {
+            resume(&mut player, &state);
+        }
+
+        loop {
+            // Check for new commands (non-blocking)
+            try_apply_command(&mut display, &mut player, &mut text, &receiver);
+
+            // Pick the frame to show; the player restarts at frame 0 when the animation changes
+            let step = player.step();
+            if step.switched {
+                info!("Switched to animation {} with {} frames", step.animation, step.frame_count);
+            }
+
+            // Display current frame
+            display_frame(&mut display, &mut frame, &step, text.as_deref()).await;
+            PLAYBACK.lock(|state| state.set(Some(player.state())));
+
+            // Animation speed; let go of the drive's frames as soon as the host rewrites them
+            let interval = Timer::after_millis(player.frame_interval_ms() as u64);
+            if let Either::Second(change) = select(interval, drive_task::CHANGES.wait()).await {
+                info!("Animations drive changed: {:?}", change);
+                use_drive = change == DriveChange::Ejected;
+                resume_from = Some(player.state());
+                break;
+            }
         }
-        
-        // Display current frame
-        display_frame(&mut display, current_animation_num, frame_index).await;
-        
-        // Advance to next frame (with bounds checking for current animation)
-        let (_, frame_count) = get_animation_data(current_animation_num);
-        frame_index = (frame_index + 1) % frame_count;
-        
-        Timer::after_millis(100).await; // Animation speed
     }
-}
\ No newline at end of file
+}
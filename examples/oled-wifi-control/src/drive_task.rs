@@ -0,0 +1,385 @@
+// file: drive_task.rs
+// desc: the animations drive: a FAT volume in flash, shared over USB
+//
+// memory.x keeps the upper 2 MiB of flash for it. The host sees an ordinary
+// removable drive and writes 512-byte blocks; they are gathered per 4K erase
+// sector and written out when the host moves on, syncs, ejects or goes quiet.
+// The display reads the volume in place through the XIP window, drops it while
+// the host writes and scans it again on eject (see pico2w_core::assets).
+use defmt::{info, warn};
+
+use embassy_futures::select::{select, Either};
+use embassy_rp::flash::ERASE_SIZE;
+use embassy_rp::otp;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+use embassy_usb::control::{InResponse, OutResponse, Recipient, Request, RequestType};
+use embassy_usb::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
+use embassy_usb::types::InterfaceNumber;
+use embassy_usb::{Builder, Handler};
+use static_cell::StaticCell;
+
+use pico2w_core::fat::{FatError, Geometry, Volume};
+use pico2w_core::msc::{
+    status_block, CommandBlock, Disk, Reply, Sense, Status, BLOCK_SIZE, MSC_CLASS, MSC_PROTOCOL_BULK_ONLY,
+    MSC_SUBCLASS_SCSI, REQUEST_GET_MAX_LUN, REQUEST_RESET,
+};
+
+use crate::settings_store::Store;
+use crate::usb_task::{UsbDriver, MAX_PACKET_LEN};
+
+// Matches ASSETS in memory.x, as an offset from the start of flash
+const DRIVE_OFFSET: u32 = 2 * 1024 * 1024;
+const DRIVE_LEN: usize = 2 * 1024 * 1024;
+const XIP_BASE: usize = 0x1000_0000;
+const BLOCKS: u32 = (DRIVE_LEN / BLOCK_SIZE) as u32;
+const BLOCKS_PER_SECTOR: u32 = (ERASE_SIZE / BLOCK_SIZE) as u32;
+const LABEL: &[u8; 11] = b"PICO2W     ";
+// SCSI INQUIRY strings, space padded
+const VENDOR: &[u8; 8] = b"Pico 2W ";
+const PRODUCT: &[u8; 16] = b"Animations      ";
+// Write out a half-filled sector once the host has been quiet this long
+const FLUSH_AFTER: Duration = Duration::from_millis(200);
+
+type ReadEndpoint = <UsbDriver as Driver<'static>>::EndpointOut;
+type WriteEndpoint = <UsbDriver as Driver<'static>>::EndpointIn;
+
+static CONTROL: StaticCell<Control> = StaticCell::new();
+
+/// What the host did to the drive, for the display task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum DriveChange {
+    /// Blocks are changing; stop reading the volume.
+    Writing,
+    /// Done writing, by eject or by going away; scan it again.
+    Ejected,
+}
+
+pub static CHANGES: Signal<CriticalSectionRawMutex, DriveChange> = Signal::new();
+// The display task's answer to `DriveChange::Writing`
+static RELEASED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Called by the display task once none of its frames point into the volume.
+pub fn release() {
+    RELEASED.signal(());
+}
+
+/// The drive's contents, read straight out of flash.
+pub fn volume() -> &'static [u8] {
+    // SAFETY: memory.x keeps ASSETS out of every other region. The drive task
+    // is the only writer: it formats before the display starts, and `flush`
+    // erases a sector only after the display has answered `Writing` with
+    // `release`. Its own slices never live across a flush.
+    unsafe { core::slice::from_raw_parts((XIP_BASE + DRIVE_OFFSET as usize) as *const u8, DRIVE_LEN) }
+}
+
+/// Give a blank or unreadable drive an empty FAT volume; a volume the host
+/// formatted itself is left alone.
+pub async fn format_if_blank(store: &Store) {
+    match Volume::mount(volume()) {
+        Ok(volume) => {
+            info!("Animations drive is {:?}", volume.fat_type());
+            return;
+        }
+        Err(FatError::NotFat) => info!("Formatting the animations drive"),
+        Err(e) => {
+            warn!("Animations drive unreadable ({:?}), leaving it for the host to format", e);
+            return;
+        }
+    }
+
+    let geometry = Geometry::new(BLOCKS);
+    let volume_id = otp::get_chipid().unwrap_or(0) as u32;
+    let mut store = store.lock().await;
+    let flash = store.flash();
+    let metadata_len = (geometry.data_start() as usize * BLOCK_SIZE).next_multiple_of(ERASE_SIZE) as u32;
+    if let Err(e) = flash.blocking_erase(DRIVE_OFFSET, DRIVE_OFFSET + metadata_len) {
+        warn!("Drive erase failed: {:?}", e);
+        return;
+    }
+    for lba in 0..geometry.data_start() {
+        let sector = geometry.format_sector(lba, LABEL, volume_id);
+        if let Err(e) = flash.blocking_write(DRIVE_OFFSET + lba * BLOCK_SIZE as u32, &sector) {
+            warn!("Drive format failed: {:?}", e);
+            return;
+        }
+    }
+}
+
+// Answers the class requests on the mass storage interface
+struct Control {
+    interface: InterfaceNumber,
+}
+
+impl Control {
+    fn accepts(&self, req: &Request) -> bool {
+        req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.index == u8::from(self.interface) as u16
+    }
+}
+
+impl Handler for Control {
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        if !self.accepts(&req) {
+            return None;
+        }
+        if req.request != REQUEST_GET_MAX_LUN {
+            return Some(InResponse::Rejected);
+        }
+        // A single LUN, number 0
+        buf[0] = 0;
+        Some(InResponse::Accepted(&buf[..1]))
+    }
+
+    fn control_out(&mut self, req: Request, _data: &[u8]) -> Option<OutResponse> {
+        if !self.accepts(&req) {
+            return None;
+        }
+        // Each command is read whole, so there is no transport state to reset
+        match req.request {
+            REQUEST_RESET => Some(OutResponse::Accepted),
+            _ => Some(OutResponse::Rejected),
+        }
+    }
+}
+
+/// The mass storage interface's endpoints, for `drive_task`.
+pub struct MassStorage {
+    read_ep: ReadEndpoint,
+    write_ep: WriteEndpoint,
+}
+
+impl MassStorage {
+    pub fn new(builder: &mut Builder<'static, UsbDriver>) -> Self {
+        let mut function = builder.function(MSC_CLASS, MSC_SUBCLASS_SCSI, MSC_PROTOCOL_BULK_ONLY);
+        let mut interface = function.interface();
+        let interface_number = interface.interface_number();
+        let mut alt = interface.alt_setting(MSC_CLASS, MSC_SUBCLASS_SCSI, MSC_PROTOCOL_BULK_ONLY, None);
+        let read_ep = alt.endpoint_bulk_out(None, MAX_PACKET_LEN);
+        let write_ep = alt.endpoint_bulk_in(None, MAX_PACKET_LEN);
+        drop(function);
+        builder.handler(CONTROL.init(Control { interface: interface_number }));
+        MassStorage { read_ep, write_ep }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn drive_task(storage: MassStorage, store: &'static Store) {
+    let mut drive = Drive {
+        storage,
+        store,
+        disk: Disk::new(BLOCKS, VENDOR, PRODUCT),
+        cache: [0; ERASE_SIZE],
+        cached: None,
+        dirty: false,
+        changed: false,
+        released: false,
+    };
+    loop {
+        drive.storage.read_ep.wait_enabled().await;
+        info!("Animations drive attached");
+        drive.disk.insert();
+        // Only fails once the host goes away
+        let _ = drive.serve().await;
+        drive.done().await;
+        info!("Animations drive detached");
+    }
+}
+
+struct Drive {
+    storage: MassStorage,
+    store: &'static Store,
+    disk: Disk,
+    // One erase sector, as the host last left it
+    cache: [u8; ERASE_SIZE],
+    cached: Option<u32>,
+    dirty: bool,
+    // Written since the display last scanned the volume
+    changed: bool,
+    // The display has dropped its frames since `changed` was set
+    released: bool,
+}
+
+impl Drive {
+    async fn serve(&mut self) -> Result<(), EndpointError> {
+        let mut packet = [0u8; MAX_PACKET_LEN as usize];
+        loop {
+            let len = if self.dirty {
+                match select(self.storage.read_ep.read(&mut packet), Timer::after(FLUSH_AFTER)).await {
+                    Either::First(len) => len?,
+                    Either::Second(()) => {
+                        self.flush().await;
+                        continue;
+                    }
+                }
+            } else {
+                self.storage.read_ep.read(&mut packet).await?
+            };
+            let Some(block) = CommandBlock::parse(&packet[..len]) else {
+                warn!("Not a USB mass storage command ({} bytes)", len);
+                continue;
+            };
+            let (residue, status) = self.execute(&block).await?;
+            self.storage.write_ep.write(&status_block(block.tag, residue, status)).await?;
+        }
+    }
+
+    // Carry out one command; the residue and status go in the CSW
+    async fn execute(&mut self, block: &CommandBlock) -> Result<(u32, Status), EndpointError> {
+        let expected = block.data_len;
+        let (moved, status) = match self.disk.command(block.command()) {
+            Reply::Data(response) => {
+                let data = response.as_bytes();
+                let len = if block.data_in { data.len().min(expected as usize) } else { 0 };
+                if len > 0 {
+                    // Shorter than a packet, so it ends the transfer
+                    self.storage.write_ep.write(&data[..len]).await?;
+                }
+                (len as u32, Status::Passed)
+            }
+            Reply::Read { lba, blocks } => {
+                let blocks = blocks.min(expected / BLOCK_SIZE as u32);
+                let mut buf = [0u8; BLOCK_SIZE];
+                for lba in lba..lba + blocks {
+                    self.read_block(lba, &mut buf);
+                    for chunk in buf.chunks(MAX_PACKET_LEN as usize) {
+                        self.storage.write_ep.write(chunk).await?;
+                    }
+                }
+                (blocks * BLOCK_SIZE as u32, Status::Passed)
+            }
+            Reply::Write { lba, blocks } => {
+                let blocks = blocks.min(expected / BLOCK_SIZE as u32);
+                let mut buf = [0u8; BLOCK_SIZE];
+                let mut status = Status::Passed;
+                for lba in lba..lba + blocks {
+                    for chunk in buf.chunks_mut(MAX_PACKET_LEN as usize) {
+                        self.storage.read_ep.read(chunk).await?;
+                    }
+                    // Keep taking the data so the transfer stays in step
+                    if !self.write_block(lba, &buf).await {
+                        self.disk.fail(Sense::WRITE_FAULT);
+                        status = Status::Failed;
+                    }
+                }
+                (blocks * BLOCK_SIZE as u32, status)
+            }
+            Reply::Done => (0, Status::Passed),
+            Reply::Sync => {
+                let flushed = self.flush().await;
+                (0, self.passed(flushed))
+            }
+            Reply::Eject => {
+                info!("Animations drive ejected");
+                let flushed = self.flush().await;
+                self.notify();
+                (0, self.passed(flushed))
+            }
+            Reply::Failed => (0, Status::Failed),
+        };
+
+        // Whatever the host meant to move and we did not
+        if expected > moved {
+            if block.data_in {
+                if moved % MAX_PACKET_LEN as u32 == 0 {
+                    self.storage.write_ep.write(&[]).await?;
+                }
+            } else {
+                let mut sink = [0u8; MAX_PACKET_LEN as usize];
+                let mut left = expected - moved;
+                while left > 0 {
+                    left = left.saturating_sub(self.storage.read_ep.read(&mut sink).await? as u32);
+                }
+            }
+        }
+        Ok((expected - moved, status))
+    }
+
+    fn passed(&mut self, ok: bool) -> Status {
+        if ok {
+            Status::Passed
+        } else {
+            self.disk.fail(Sense::WRITE_FAULT);
+            Status::Failed
+        }
+    }
+
+    // From the cache if the block is in it; it may not be in flash yet
+    fn read_block(&self, lba: u32, buf: &mut [u8; BLOCK_SIZE]) {
+        let (source, start) = if self.cached == Some(lba / BLOCKS_PER_SECTOR) {
+            (&self.cache[..], (lba % BLOCKS_PER_SECTOR) as usize * BLOCK_SIZE)
+        } else {
+            (volume(), lba as usize * BLOCK_SIZE)
+        };
+        buf.copy_from_slice(&source[start..start + BLOCK_SIZE]);
+    }
+
+    // False if writing out the previous sector failed
+    async fn write_block(&mut self, lba: u32, buf: &[u8; BLOCK_SIZE]) -> bool {
+        let sector = lba / BLOCKS_PER_SECTOR;
+        let mut ok = true;
+        if self.cached != Some(sector) {
+            ok = self.flush().await;
+            let start = sector as usize * ERASE_SIZE;
+            self.cache.copy_from_slice(&volume()[start..start + ERASE_SIZE]);
+            self.cached = Some(sector);
+        }
+        let start = (lba % BLOCKS_PER_SECTOR) as usize * BLOCK_SIZE;
+        self.cache[start..start + BLOCK_SIZE].copy_from_slice(buf);
+        self.dirty = true;
+
+        if !self.changed {
+            self.changed = true;
+            self.released = false;
+            RELEASED.reset();
+            CHANGES.signal(DriveChange::Writing);
+        }
+        ok
+    }
+
+    // Write the cached sector back if the host changed it
+    async fn flush(&mut self) -> bool {
+        let Some(sector) = self.cached.filter(|_| self.dirty) else {
+            return true;
+        };
+        self.dirty = false;
+        let start = sector as usize * ERASE_SIZE;
+        // Hosts rewrite FAT and directory sectors unchanged; spare the flash
+        if volume()[start..start + ERASE_SIZE] == self.cache {
+            return true;
+        }
+
+        // The display may still be drawing straight from this sector
+        if !self.released {
+            RELEASED.wait().await;
+            self.released = true;
+        }
+
+        let offset = DRIVE_OFFSET + start as u32;
+        let mut store = self.store.lock().await;
+        let flash = store.flash();
+        let result = flash
+            .blocking_erase(offset, offset + ERASE_SIZE as u32)
+            .and_then(|()| flash.blocking_write(offset, &self.cache));
+        if let Err(e) = result {
+            warn!("Drive write at {:#x} failed: {:?}", offset, e);
+            return false;
+        }
+        true
+    }
+
+    // The host is gone; whatever it wrote is final
+    async fn done(&mut self) {
+        self.flush().await;
+        self.notify();
+    }
+
+    // Let the display scan again if anything was written
+    fn notify(&mut self) {
+        if core::mem::take(&mut self.changed) {
+            CHANGES.signal(DriveChange::Ejected);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+// file: http_task.rs
+// desc: pool of HTTP listeners sharing the network stack
+//
+// Every worker listens on port 80 with its own socket, so a slow client only
+// ties up one of them. Connections stay open for further requests unless the
+// client asks otherwise. Workers stop accepting while the WiFi link is down,
+// unless the setup portal is up.
+use defmt::{info, warn};
+
+use embassy_futures::select::{select, Either};
+use embassy_net::Stack;
+use embassy_net::tcp::TcpSocket;
+use embassy_time::Duration;
+
+use pico2w_core::http::{HttpError, RequestBuffer, Response};
+
+use crate::networking_task::{wait_reachable, wait_unreachable};
+use crate::routes::{self, Context};
+
+/// Connections served at once; each needs its own socket in `StackResources`.
+pub const HTTP_WORKERS: usize = 3;
+
+const SOCKET_BUFFER_LEN: usize = 1024;
+const REQUEST_BUFFER_LEN: usize = 2048;
+/// Idle time before a quiet connection, kept alive or not, is dropped.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Buffers one worker owns for the lifetime of the program.
+pub struct ConnectionBuffers {
+    rx: [u8; SOCKET_BUFFER_LEN],
+    tx: [u8; SOCKET_BUFFER_LEN],
+    request: RequestBuffer<REQUEST_BUFFER_LEN>,
+}
+
+impl ConnectionBuffers {
+    pub const fn new() -> Self {
+        ConnectionBuffers {
+            rx: [0; SOCKET_BUFFER_LEN],
+            tx: [0; SOCKET_BUFFER_LEN],
+            request: RequestBuffer::new(),
+        }
+    }
+}
+
+#[embassy_executor::task(pool_size = HTTP_WORKERS)]
+pub async fn http_task(
+    worker: usize,
+    stack: Stack<'static>,
+    buffers: &'static mut ConnectionBuffers,
+    ctx: Context,
+) {
+    loop {
+        wait_reachable(stack).await;
+
+        let mut socket = TcpSocket::new(stack, &mut buffers.rx, &mut buffers.tx);
+        socket.set_timeout(Some(IDLE_TIMEOUT));
+
+        match select(socket.accept(80), wait_unreachable(stack)).await {
+            Either::First(Ok(())) => {}
+            Either::First(Err(e)) => {
+                warn!("[http {}] Socket accept error: {:?}", worker, e);
+                continue;
+            }
+            Either::Second(()) => {
+                info!("[http {}] Link down or setup portal closed, pausing", worker);
+                continue;
+            }
+        }
+
+        info!("[http {}] New HTTP connection from {:?}", worker, socket.remote_endpoint());
+        serve(worker, &mut socket, &mut buffers.request, ctx).await;
+
+        // Let the FIN go out before the socket is dropped
+        socket.close();
+        if let Err(e) = socket.flush().await {
+            warn!("[http {}] Flush error: {:?}", worker, e);
+        }
+    }
+}
+
+// Answer requests on one connection until either side wants it closed.
+async fn serve(worker: usize, socket: &mut TcpSocket<'_>, buffer: &mut RequestBuffer<REQUEST_BUFFER_LEN>, ctx: Context) {
+    buffer.clear();
+
+    loop {
+        let len = match read_request(socket, buffer).await {
+            Ok(Some(len)) => len,
+            Ok(None) => return,
+            Err(HttpError::PayloadTooLarge) => return stream(worker, socket, buffer, ctx).await,
+            Err(e) => return reject(socket, e).await,
+        };
+
+        let keep_alive = match buffer.parse(len) {
+            Ok(request) => {
+                info!("[http {}] HTTP Request: {} {=str}", worker, request.method, request.path);
+                if let Err(e) = routes::handle(socket, &request, ctx).await {
+                    warn!("[http {}] Write error: {:?}", worker, e);
+                    return;
+                }
+                request.keep_alive()
+            }
+            Err(e) => return reject(socket, e).await,
+        };
+
+        buffer.consume(len);
+        if !keep_alive {
+            return;
+        }
+    }
+}
+
+// Hand a request with an oversized body to its handler, which reads the body
+// from the socket itself. The connection closes afterwards.
+async fn stream(worker: usize, socket: &mut TcpSocket<'_>, buffer: &mut RequestBuffer<REQUEST_BUFFER_LEN>, ctx: Context) {
+    match buffer.parse_streamed() {
+        Ok((request, received)) => {
+            info!("[http {}] HTTP Request: {} {=str} (streamed body)", worker, request.method, request.path);
+            if let Err(e) = routes::handle_streamed(socket, &request, received, ctx).await {
+                warn!("[http {}] Write error: {:?}", worker, e);
+            }
+        }
+        Err(e) => reject(socket, e).await,
+    }
+    buffer.clear();
+}
+
+// Read until the buffer holds one complete request. Ok(None) means the client
+// went away or fell silent first.
+async fn read_request(socket: &mut TcpSocket<'_>, buffer: &mut RequestBuffer<REQUEST_BUFFER_LEN>) -> Result<Option<usize>, HttpError> {
+    loop {
+        if let Some(len) = buffer.request_len()? {
+            return Ok(Some(len));
+        }
+
+        match socket.read(buffer.space()).await {
+            Ok(0) => return Ok(None),
+            Ok(bytes_read) => buffer.commit(bytes_read),
+            Err(e) => {
+                // An idle keep-alive connection timing out is routine
+                if !buffer.is_empty() {
+                    warn!("Read error: {:?}", e);
+                }
+                return Ok(None);
+            }
+        }
+    }
+}
+
+async fn reject(socket: &mut TcpSocket<'_>, error: HttpError) {
+    let status = error.status();
+    warn!("Rejecting request: {} {=str}", status.code(), status.reason());
+    if let Err(e) = Response::new(status).send_text(socket, status.reason()).await {
+        warn!("Write error: {:?}", e);
+    }
+}
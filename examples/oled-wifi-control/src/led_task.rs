@@ -0,0 +1,62 @@
+// file: led_task.rs
+// desc: show the WiFi link state on the onboard LED
+//
+// Other tasks do not touch the LED; they ask for a short flash and the link
+// pattern comes back afterwards.
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Ticker, Timer};
+
+use pico2w_bsp::OnboardLed;
+use pico2w_core::link::{LedPattern, LinkState};
+
+use crate::networking_task::LINK_STATE;
+
+const FLASH_HALF_PERIOD: Duration = Duration::from_millis(100);
+
+static FLASH: Signal<CriticalSectionRawMutex, u8> = Signal::new();
+
+/// Blink the LED `times` times as feedback, then go back to the link state.
+pub fn flash(times: u8) {
+    FLASH.signal(times);
+}
+
+#[embassy_executor::task]
+pub async fn led_task(led: OnboardLed) {
+    let mut link = defmt::unwrap!(LINK_STATE.receiver());
+    let mut state = link.get().await;
+
+    loop {
+        match select(link.changed(), select(show(led, state), FLASH.wait())).await {
+            Either::First(next) => state = next,
+            Either::Second(Either::First(())) => {}
+            Either::Second(Either::Second(times)) => {
+                for _ in 0..times {
+                    led.off().await;
+                    Timer::after(FLASH_HALF_PERIOD).await;
+                    led.on().await;
+                    Timer::after(FLASH_HALF_PERIOD).await;
+                }
+            }
+        }
+    }
+}
+
+// Show the link state's pattern; only returns by being dropped
+async fn show(led: OnboardLed, state: LinkState) {
+    match state.led() {
+        LedPattern::Off => led.off().await,
+        LedPattern::On => led.on().await,
+        LedPattern::Blink { half_period_ms } => {
+            let mut ticker = Ticker::every(Duration::from_millis(half_period_ms.into()));
+            let mut lit = false;
+            loop {
+                lit = !lit;
+                led.set(lit).await;
+                ticker.next().await;
+            }
+        }
+    }
+    core::future::pending().await
+}
@@ -5,23 +5,59 @@
 
 use defmt::*;
 use embassy_executor::Spawner;
+use embassy_net::{Config as WifiConfig, StackResources};
 use embassy_time::Timer;
-use embassy_sync::pipe::{Pipe};
+use embassy_sync::channel::{Channel, Receiver, Sender};
+use embassy_sync::pubsub::{PubSubChannel, Subscriber};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-use static_cell::StaticCell;
+use pico2w_core::{Event, Input};
+use pico2w_core::mqtt::MqttSettings;
+use pico2w_core::network::NetSettings;
+use static_cell::{ConstStaticCell, StaticCell};
 
 
 use {defmt_rtt as _, panic_probe as _};
 
-// Import setup mod
-mod setup_devices;
-use setup_devices::{setup_display, setup_wifi};
+// Board support
+use pico2w_bsp::Board;
+use pico2w_bsp::display::setup_display;
 
 // Import task mods
 mod display_task;
 use display_task::{display_task};
 mod networking_task;
-use networking_task::{networking_task};
+use networking_task::{build_settings, networking_task, stack_config};
+mod led_task;
+use led_task::{led_task};
+mod mdns_task;
+use mdns_task::{mdns_task};
+mod sntp_task;
+use sntp_task::{sntp_task};
+mod mqtt_task;
+use mqtt_task::{mqtt_task};
+mod ota;
+use ota::{ota_task};
+mod reboot;
+mod usb_task;
+use usb_task::{usb_task};
+mod drive_task;
+use drive_task::{drive_task};
+mod shell_task;
+use shell_task::{shell_task};
+mod http_task;
+use http_task::{http_task, ConnectionBuffers, HTTP_WORKERS};
+mod routes;
+mod settings_store;
+use settings_store::{SettingsStore, Store};
+use routes::Context;
+mod websocket;
+mod provisioning;
+mod button_task;
+use button_task::{button_task};
+mod scheduler_task;
+use scheduler_task::{scheduler_task};
+mod setup_devices;
+use setup_devices::{setup_buttons};
 
 // Import animations
 mod nooo;
@@ -40,7 +76,29 @@ pub static PICOTOOL_ENTRIES: [embassy_rp::binary_info::EntryAddr; 3] = [
     embassy_rp::binary_info::rp_program_build_attribute!(),
 ];
 
-static ANIMATION_STATE_PIPE: StaticCell<Pipe<CriticalSectionRawMutex, 1>> = StaticCell::new();
+// Commands from every input source, consumed by the display task
+const COMMAND_QUEUE_LEN: usize = 4;
+pub type CommandSender = Sender<'static, CriticalSectionRawMutex, Input, COMMAND_QUEUE_LEN>;
+pub type CommandReceiver = Receiver<'static, CriticalSectionRawMutex, Input, COMMAND_QUEUE_LEN>;
+
+static COMMAND_CHANNEL: StaticCell<Channel<CriticalSectionRawMutex, Input, COMMAND_QUEUE_LEN>> = StaticCell::new();
+
+// Playback changes and button presses broadcast to every live subscriber: the
+// /events and /ws streams, one per HTTP worker, MQTT, the USB console and the
+// scheduler
+const EVENT_QUEUE_LEN: usize = 8;
+const EVENT_SUBSCRIBERS: usize = HTTP_WORKERS + 3;
+pub type EventBus = PubSubChannel<CriticalSectionRawMutex, Event, EVENT_QUEUE_LEN, EVENT_SUBSCRIBERS, 1>;
+pub type EventSubscriber = Subscriber<'static, CriticalSectionRawMutex, Event, EVENT_QUEUE_LEN, EVENT_SUBSCRIBERS, 1>;
+pub static EVENTS: EventBus = PubSubChannel::new();
+static NET_SETTINGS: StaticCell<NetSettings> = StaticCell::new();
+static MQTT_SETTINGS: StaticCell<MqttSettings> = StaticCell::new();
+static STORE: StaticCell<Store> = StaticCell::new();
+// One socket per HTTP worker, plus DHCP and DNS, plus the setup portal's DHCP
+// server and DNS responder, plus mDNS, SNTP and MQTT
+static RESOURCES: StaticCell<StackResources<{ HTTP_WORKERS + 7 }>> = StaticCell::new();
+static HTTP_BUFFERS: ConstStaticCell<[ConnectionBuffers; HTTP_WORKERS]> =
+    ConstStaticCell::new([const { ConnectionBuffers::new() }; HTTP_WORKERS]);
 
 
 #[embassy_executor::main]
@@ -48,31 +106,59 @@ async fn main(spawner: Spawner) {
     // Initialize peripherals
     let p = embassy_rp::init(Default::default());
 
-    // Initialize the pipe and split it
-    let animation_state_pipe = ANIMATION_STATE_PIPE.init(Pipe::new());
-    let (reader, writer) = animation_state_pipe.split();
-    
-    // Setup individual components
-    let display = setup_display(p.I2C0, 
-        p.PIN_0, 
-        p.PIN_1).await;
+    // Initialize the command channel
+    let command_channel = COMMAND_CHANNEL.init(Channel::new());
+    let (sender, receiver) = (command_channel.sender(), command_channel.receiver());
     
-    let wifi_stack = setup_wifi(
-        p.PIO0,
-        p.PIN_23,
-        p.PIN_25,
-        p.PIN_24,
-        p.PIN_29,
-        p.DMA_CH0,
-        &spawner
-    ).await;
+    // Setup individual components; the addressing is set once the store is up
+    let board = Board::init_with_net(p, &spawner, WifiConfig::default(), RESOURCES.init(StackResources::new())).await;
+    let stack = unwrap!(board.stack);
+
+    let pins = board.pins;
+    let settings_store = SettingsStore::new(pins.FLASH);
+    // Set up network stack: DHCP, static, or DHCP with a static fallback.
+    // Addressing saved from the console wins over the build-time settings
+    let settings: &'static NetSettings =
+        NET_SETTINGS.init(settings_store.net_settings().cloned().unwrap_or_else(build_settings));
+    stack.set_config_v4(stack_config(settings).ipv4);
+    let store: &'static Store = STORE.init(Store::new(settings_store));
+    // Before the display task first scans it
+    drive_task::format_if_blank(store).await;
+    // Starts the watchdog early; a new image stays on trial until it is healthy
+    spawner.spawn(ota_task(pins.WATCHDOG, stack, store)).unwrap();
+    let display = setup_display(pins.I2C0, 
+        pins.PIN_0, 
+        pins.PIN_1).await;
+
+    // Setup Buttons
+    let buttons = setup_buttons(pins.PIN_6, 
+        pins.PIN_7, 
+        pins.PIN_8, 
+        pins.PIN_9).await;
     
     info!("System initialization complete!");
 
 
     // Create tasks
-    spawner.spawn(display_task(display, reader)).unwrap();
-    spawner.spawn(networking_task(wifi_stack, writer)).unwrap();
+    spawner.spawn(display_task(display, receiver)).unwrap();
+    spawner.spawn(button_task(buttons, sender)).unwrap();
+    spawner.spawn(scheduler_task(sender)).unwrap();
+    spawner.spawn(networking_task(stack, board.radio, store, settings)).unwrap();
+    spawner.spawn(led_task(board.led)).unwrap();
+    let (usb, console, storage) = usb_task::setup(pins.USB);
+    spawner.spawn(usb_task(usb)).unwrap();
+    spawner.spawn(shell_task(console, stack, board.radio, sender, store)).unwrap();
+    spawner.spawn(drive_task(storage, store)).unwrap();
+    spawner.spawn(mdns_task(stack, board.radio, settings)).unwrap();
+    spawner.spawn(sntp_task(stack)).unwrap();
+    if let Some(mqtt) = mqtt_task::build_settings(&settings.hostname) {
+        spawner.spawn(mqtt_task(stack, board.radio, sender, MQTT_SETTINGS.init(mqtt), settings.hostname.as_str())).unwrap();
+    }
+
+    let ctx = Context { stack, radio: board.radio, sender, store };
+    for (worker, buffers) in HTTP_BUFFERS.take().iter_mut().enumerate() {
+        spawner.spawn(http_task(worker, stack, buffers, ctx)).unwrap();
+    }
     
     // Main animation loop
     loop {
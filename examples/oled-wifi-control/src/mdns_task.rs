@@ -0,0 +1,100 @@
+// file: mdns_task.rs
+// desc: answer mDNS for `<NET_HOSTNAME>.local` and advertise the web server
+//
+// Announces the host and its `_http._tcp` service every time the board comes
+// online, then answers queries until the link drops.
+use core::fmt::Write;
+
+use defmt::{info, warn};
+
+use embassy_futures::select::select;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address, Stack};
+use embassy_time::{Duration, Timer};
+use heapless::String;
+
+use pico2w_bsp::Radio;
+use pico2w_core::mdns::{self, Responder};
+use pico2w_core::network::NetSettings;
+
+use crate::display_task::ANIMATION_COUNT;
+use crate::networking_task::wait_online;
+use crate::routes::FIRMWARE_VERSION;
+
+const HTTP_PORT: u16 = 80;
+// RFC 6762 asks for at least two announcements, a second apart
+const ANNOUNCEMENTS: usize = 2;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[embassy_executor::task]
+pub async fn mdns_task(stack: Stack<'static>, radio: &'static Radio, settings: &'static NetSettings) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0u8; 1024];
+    let mut tx_buffer = [0u8; 1024];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    if let Err(e) = socket.bind(mdns::PORT) {
+        warn!("mDNS bind failed: {:?}", e);
+        return;
+    }
+
+    // The radio drops multicast frames it has not been told about
+    if radio.lock().await.add_multicast_address(mdns::GROUP_MAC).await.is_err() {
+        warn!("mDNS multicast filter full");
+    }
+    if let Err(e) = stack.join_multicast_group(Ipv4Address::from(mdns::GROUP)) {
+        warn!("mDNS group join failed: {:?}", e);
+    }
+
+    let mut version: String<32> = String::new();
+    write!(version, "version={}", FIRMWARE_VERSION).ok();
+    let mut animations: String<16> = String::new();
+    write!(animations, "animations={}", ANIMATION_COUNT).ok();
+    let txt = [version.as_str(), animations.as_str()];
+
+    loop {
+        wait_online(stack).await;
+        let Some(config) = stack.config_v4() else {
+            continue;
+        };
+        let responder = Responder {
+            hostname: settings.hostname.as_str(),
+            address: config.address.address().octets(),
+            http_port: HTTP_PORT,
+            txt: &txt,
+        };
+        info!("mDNS: http://{=str}.local/", settings.hostname.as_str());
+
+        select(serve(&mut socket, &responder), stack.wait_link_down()).await;
+    }
+}
+
+async fn serve(socket: &mut UdpSocket<'_>, responder: &Responder<'_>) {
+    let group = IpEndpoint::new(IpAddress::Ipv4(Ipv4Address::from(mdns::GROUP)), mdns::PORT);
+    let mut query = [0u8; mdns::MAX_PACKET_LEN];
+    let mut reply = [0u8; mdns::MAX_PACKET_LEN];
+
+    for _ in 0..ANNOUNCEMENTS {
+        if let Some(len) = responder.announce(&mut reply) {
+            if let Err(e) = socket.send_to(&reply[..len], group).await {
+                warn!("mDNS announcement failed: {:?}", e);
+            }
+        }
+        Timer::after(ANNOUNCE_INTERVAL).await;
+    }
+
+    loop {
+        // Packets over MAX_PACKET_LEN fail as truncated
+        let Ok((len, meta)) = socket.recv_from(&mut query).await else {
+            continue;
+        };
+        let legacy = meta.endpoint.port != mdns::PORT;
+        let Some(answer) = responder.answer(&query[..len], legacy, &mut reply) else {
+            continue;
+        };
+        let to = if answer.unicast { meta.endpoint } else { group };
+        if let Err(e) = socket.send_to(&reply[..answer.len], to).await {
+            warn!("mDNS reply failed: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,293 @@
+// file: mqtt_task.rs
+// desc: MQTT link to a broker for remote control and state publishing
+//
+// Only runs when MQTT_BROKER is set at build time. Commands arrive on
+// `<prefix>/command` and `<prefix>/brightness/set`; the current animation,
+// brightness and online status are kept retained, button presses are
+// published as they happen and the WiFi signal once a minute. Home Assistant
+// discovery configs go out on connect, whenever Home Assistant restarts and
+// whenever the animations drive changes the number of animations. A lost
+// connection is retried after a pause.
+use core::fmt::Write as _;
+
+use defmt::{info, unwrap, warn};
+
+use embassy_futures::select::{select, select4, Either, Either4};
+use embassy_net::dns::DnsQueryType;
+use embassy_net::tcp::{ConnectError, Error as TcpError, TcpSocket};
+use embassy_net::{IpEndpoint, Stack};
+use embassy_sync::pubsub::WaitResult;
+use embassy_time::{Duration, Ticker, Timer};
+use embedded_io_async::Write;
+use heapless::String;
+
+use pico2w_bsp::Radio;
+use pico2w_core::homeassistant::{self, DeviceInfo, Entity, CONFIG_JSON_LEN, CONFIG_TOPIC_LEN};
+use pico2w_core::mqtt::{
+    self, decode, encode_connect, encode_publish, encode_subscribe, Connect, MqttConfigError, MqttEnv, MqttError,
+    MqttSettings, Packet, ANIMATION_TOPIC, BRIGHTNESS_SET_TOPIC, BRIGHTNESS_TOPIC, COMMAND_TOPIC, KEEP_ALIVE_S,
+    MAX_PACKET_LEN, OFFLINE, ONLINE, PRESS, RSSI_TOPIC, STATUS_TOPIC,
+};
+use pico2w_core::{Brightness, Event, Input, Source};
+
+use crate::display_task::{brightness, playback_state, ANIMATIONS_LOADED, ANIMATION_COUNT};
+use crate::routes::FIRMWARE_VERSION;
+use crate::networking_task::wait_online;
+use crate::{CommandSender, EventSubscriber, EVENTS};
+
+// Optional broker settings, see pico2w_core::mqtt
+const MQTT_ENV: MqttEnv<'static> = MqttEnv {
+    broker: option_env!("MQTT_BROKER"),
+    port: option_env!("MQTT_PORT"),
+    client_id: option_env!("MQTT_CLIENT_ID"),
+    username: option_env!("MQTT_USER"),
+    password: option_env!("MQTT_PASS"),
+    prefix: option_env!("MQTT_TOPIC"),
+    discovery_prefix: option_env!("MQTT_DISCOVERY_PREFIX"),
+};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+// Ping at half the keep-alive; a ping still unanswered at the next one ends
+// the connection
+const PING_EVERY: Duration = Duration::from_secs(KEEP_ALIVE_S as u64 / 2);
+const RSSI_EVERY: Duration = Duration::from_secs(60);
+const SUBSCRIBE_ID: u16 = 1;
+const SOCKET_BUFFER_LEN: usize = 1024;
+// Discovery configs are the largest thing we send
+const OUT_LEN: usize = CONFIG_JSON_LEN + CONFIG_TOPIC_LEN + 8;
+
+/// Broker settings chosen at build time; `None` leaves MQTT off.
+pub fn build_settings(hostname: &str) -> Option<MqttSettings> {
+    MqttSettings::from_env(&MQTT_ENV, hostname).unwrap_or_else(|e: MqttConfigError| {
+        warn!("Invalid MQTT setting {:?}, MQTT disabled", e);
+        None
+    })
+}
+
+#[derive(Debug, defmt::Format)]
+enum SessionError {
+    Dns,
+    Connect(ConnectError),
+    Tcp(TcpError),
+    Closed,
+    Refused(u8),
+    Protocol(MqttError),
+    PingTimeout,
+}
+
+impl From<TcpError> for SessionError {
+    fn from(e: TcpError) -> Self {
+        SessionError::Tcp(e)
+    }
+}
+
+impl From<MqttError> for SessionError {
+    fn from(e: MqttError) -> Self {
+        SessionError::Protocol(e)
+    }
+}
+
+#[embassy_executor::task]
+pub async fn mqtt_task(
+    stack: Stack<'static>,
+    radio: &'static Radio,
+    sender: CommandSender,
+    settings: &'static MqttSettings,
+    hostname: &'static str,
+) {
+    let Ok(mut subscriber) = EVENTS.subscriber() else {
+        warn!("No event subscriber left for MQTT");
+        return;
+    };
+    let mut rx_buffer = [0u8; SOCKET_BUFFER_LEN];
+    let mut tx_buffer = [0u8; SOCKET_BUFFER_LEN];
+
+    loop {
+        wait_online(stack).await;
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        let result = session(stack, radio, &mut socket, &mut subscriber, sender, settings, hostname).await;
+        warn!("MQTT connection to {=str} ended: {:?}", settings.broker.as_str(), result);
+        socket.abort();
+        socket.flush().await.ok();
+        Timer::after(RECONNECT_DELAY).await;
+    }
+}
+
+async fn session(
+    stack: Stack<'static>,
+    radio: &'static Radio,
+    socket: &mut TcpSocket<'_>,
+    subscriber: &mut EventSubscriber,
+    sender: CommandSender,
+    settings: &MqttSettings,
+    hostname: &str,
+) -> Result<(), SessionError> {
+    let address = match stack.dns_query(&settings.broker, DnsQueryType::A).await {
+        Ok(addresses) => *addresses.first().ok_or(SessionError::Dns)?,
+        Err(_) => return Err(SessionError::Dns),
+    };
+    socket.set_timeout(Some(Duration::from_secs(u64::from(KEEP_ALIVE_S) * 2)));
+    socket.connect(IpEndpoint::new(address, settings.port)).await.map_err(SessionError::Connect)?;
+
+    let status = topic(settings, STATUS_TOPIC);
+    let command = topic(settings, COMMAND_TOPIC);
+    let brightness_set = topic(settings, BRIGHTNESS_SET_TOPIC);
+    let birth = settings.discovery_prefix.as_deref().and_then(homeassistant::birth_topic);
+    // Counts the ones on the USB drive too, and follows them as it changes
+    let mut loaded = unwrap!(ANIMATIONS_LOADED.receiver());
+    let animation_count = loaded.try_get().unwrap_or(ANIMATION_COUNT as u8);
+    let mut device = DeviceInfo { name: hostname, sw_version: FIRMWARE_VERSION, animation_count };
+    let mut out = [0u8; MAX_PACKET_LEN];
+    let connect = Connect {
+        client_id: settings.client_id.as_str(),
+        keep_alive_s: KEEP_ALIVE_S,
+        username: settings.username.as_deref(),
+        password: settings.password.as_deref(),
+        will: Some((status.as_str(), OFFLINE.as_bytes())),
+    };
+    let len = encode_connect(&connect, &mut out)?;
+    socket.write_all(&out[..len]).await?;
+
+    let mut buf = [0u8; MAX_PACKET_LEN];
+    let mut filled = 0;
+    let mut ping = Ticker::every(PING_EVERY);
+    let mut rssi = Ticker::every(RSSI_EVERY);
+    let mut awaiting_pong = false;
+    loop {
+        // Handle every complete packet already buffered
+        while let Some((packet, used)) = decode(&buf[..filled])? {
+            match packet {
+                Packet::ConnAck { code: 0, .. } => {
+                    info!("MQTT connected to {=str}", settings.broker.as_str());
+                    let (command, brightness_set) = (command.as_str(), brightness_set.as_str());
+                    let len = match &birth {
+                        Some(birth) => encode_subscribe(SUBSCRIBE_ID, &[command, brightness_set, birth.as_str()], &mut out)?,
+                        None => encode_subscribe(SUBSCRIBE_ID, &[command, brightness_set], &mut out)?,
+                    };
+                    socket.write_all(&out[..len]).await?;
+                    publish(socket, &status, ONLINE.as_bytes(), true).await?;
+                    publish_discovery(socket, settings, &device).await?;
+                    if let Some(state) = playback_state() {
+                        publish_animation(socket, settings, state.animation).await?;
+                    }
+                    publish_brightness(socket, settings, brightness()).await?;
+                    publish_rssi(socket, settings, radio).await?;
+                }
+                Packet::ConnAck { code, .. } => return Err(SessionError::Refused(code)),
+                Packet::SubAck { granted, .. } if granted.iter().any(|code| code & 0x80 != 0) => {
+                    warn!("MQTT subscription refused")
+                }
+                Packet::Publish { topic, payload } if topic == command.as_str() => {
+                    match mqtt::parse_command(payload) {
+                        Some(command) => {
+                            info!("MQTT command: {:?}", command);
+                            if sender.try_send(Input { source: Source::Mqtt, command }).is_err() {
+                                warn!("Failed to send command (queue full?)");
+                            }
+                        }
+                        None => warn!("Ignoring MQTT command payload"),
+                    }
+                }
+                Packet::Publish { topic, payload } if topic == brightness_set.as_str() => {
+                    match mqtt::parse_brightness(payload) {
+                        Some(command) => {
+                            info!("MQTT brightness: {:?}", command);
+                            if sender.try_send(Input { source: Source::Mqtt, command }).is_err() {
+                                warn!("Failed to send command (queue full?)");
+                            }
+                        }
+                        None => warn!("Ignoring MQTT brightness payload"),
+                    }
+                }
+                // Home Assistant restarted; hand it the configs again
+                Packet::Publish { topic, payload } if birth.as_deref() == Some(topic) && payload == ONLINE.as_bytes() => {
+                    publish_discovery(socket, settings, &device).await?;
+                }
+                Packet::PingResp => awaiting_pong = false,
+                _ => {}
+            }
+            buf.copy_within(used..filled, 0);
+            filled -= used;
+        }
+
+        let (read, event) = (socket.read(&mut buf[filled..]), subscriber.next_message());
+        match select4(read, event, ping.next(), select(rssi.next(), loaded.changed())).await {
+            Either4::First(Ok(0)) => return Err(SessionError::Closed),
+            Either4::First(Ok(bytes_read)) => filled += bytes_read,
+            Either4::First(Err(e)) => return Err(e.into()),
+            Either4::Second(WaitResult::Message(Event::Animation(event))) => {
+                publish_animation(socket, settings, event.animation).await?;
+            }
+            Either4::Second(WaitResult::Message(Event::Brightness(event))) => {
+                publish_brightness(socket, settings, event.brightness).await?;
+            }
+            Either4::Second(WaitResult::Message(Event::Button(event))) => {
+                if let Some(topic) = settings.button_topic(event.button) {
+                    publish(socket, &topic, PRESS.as_bytes(), false).await?;
+                }
+            }
+            Either4::Second(WaitResult::Message(_)) => {}
+            Either4::Second(WaitResult::Lagged(missed)) => warn!("MQTT lagged, {} events dropped", missed),
+            Either4::Third(()) => {
+                if awaiting_pong {
+                    return Err(SessionError::PingTimeout);
+                }
+                socket.write_all(&mqtt::PINGREQ).await?;
+                awaiting_pong = true;
+            }
+            Either4::Fourth(Either::First(())) => publish_rssi(socket, settings, radio).await?,
+            Either4::Fourth(Either::Second(count)) if count != device.animation_count => {
+                info!("MQTT: {} animations now, updating discovery", count);
+                device.animation_count = count;
+                publish_discovery(socket, settings, &device).await?;
+            }
+            Either4::Fourth(Either::Second(_)) => {}
+        }
+    }
+}
+
+fn topic(settings: &MqttSettings, leaf: &str) -> String<{ mqtt::TOPIC_LEN }> {
+    // The prefix length is capped well below TOPIC_LEN
+    settings.topic(leaf).unwrap_or_default()
+}
+
+async fn publish(socket: &mut TcpSocket<'_>, topic: &str, payload: &[u8], retain: bool) -> Result<(), SessionError> {
+    let mut out = [0u8; OUT_LEN];
+    let len = encode_publish(topic, payload, retain, &mut out)?;
+    socket.write_all(&out[..len]).await?;
+    Ok(())
+}
+
+async fn publish_animation(socket: &mut TcpSocket<'_>, settings: &MqttSettings, animation: u8) -> Result<(), SessionError> {
+    let mut payload: String<3> = String::new();
+    write!(payload, "{}", animation).ok();
+    publish(socket, &topic(settings, ANIMATION_TOPIC), payload.as_bytes(), true).await
+}
+
+async fn publish_brightness(socket: &mut TcpSocket<'_>, settings: &MqttSettings, brightness: Brightness) -> Result<(), SessionError> {
+    let mut payload: String<1> = String::new();
+    write!(payload, "{}", brightness.level()).ok();
+    publish(socket, &topic(settings, BRIGHTNESS_TOPIC), payload.as_bytes(), true).await
+}
+
+async fn publish_rssi(socket: &mut TcpSocket<'_>, settings: &MqttSettings, radio: &'static Radio) -> Result<(), SessionError> {
+    let rssi = radio.lock().await.get_rssi().await;
+    let mut payload: String<4> = String::new();
+    write!(payload, "{}", rssi).ok();
+    publish(socket, &topic(settings, RSSI_TOPIC), payload.as_bytes(), false).await
+}
+
+// Retained so Home Assistant finds the entities even if it starts later
+async fn publish_discovery(socket: &mut TcpSocket<'_>, settings: &MqttSettings, device: &DeviceInfo<'_>) -> Result<(), SessionError> {
+    let Some(discovery_prefix) = settings.discovery_prefix.as_deref() else {
+        return Ok(());
+    };
+    let mut config = [0u8; CONFIG_JSON_LEN];
+    for entity in Entity::all() {
+        match (entity.config_topic(discovery_prefix, settings), entity.config(settings, device, &mut config)) {
+            (Some(topic), Some(payload)) => publish(socket, &topic, payload, true).await?,
+            _ => warn!("Home Assistant config for {:?} does not fit", entity),
+        }
+    }
+    Ok(())
+}
@@ -1,154 +1,259 @@
 // file: networking_task.rs
-// desc: handle networking
+// desc: WiFi supervisor: join, bring up IPv4 addressing, rejoin on link loss
+//
+// Every attempt scans first and tries the saved networks in join order. Failed
+// rounds back off exponentially. Every state change is published on
+// LINK_STATE for the LED task and the display.
+use core::cell::RefCell;
 
-use defmt::{info, warn};
-use core::str::from_utf8;
+use defmt::{info, unwrap, warn};
 
-use embassy_sync::pipe::{Writer};
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-use embassy_net::tcp::TcpSocket;
-use cyw43::JoinOptions;
-use embassy_time::{Duration, Timer};
+use embassy_sync::watch::Watch;
 
-use crate::setup_devices::WifiStack;
+use embassy_net::{Config as WifiConfig, ConfigV4, DhcpConfig, Ipv4Address, Ipv4Cidr, Stack, StaticConfigV4};
+use cyw43::{JoinAuth, JoinOptions, ScanOptions};
+use embassy_time::{with_timeout, Duration, Timer};
+use heapless::String;
 
-// Source from env variables WIFI_ID, WIFI_PASS
-const WIFI_NETWORK: &str = env!("WIFI_ID");
-const WIFI_PASSWORD: &str = env!("WIFI_PASS");
+use pico2w_bsp::Radio;
+use pico2w_core::credentials::{Auth, Credentials};
+use pico2w_core::link::{Backoff, LinkState};
+use pico2w_core::network::{NetEnv, NetMode, NetSettings, StaticSettings};
+use pico2w_core::scan::{Network, NetworkList};
+
+use crate::provisioning;
+use crate::settings_store::Store;
+
+// Optional addressing overrides, see pico2w_core::network
+const NET_ENV: NetEnv<'static> = NetEnv {
+    mode: option_env!("NET_MODE"),
+    address: option_env!("NET_ADDRESS"),
+    gateway: option_env!("NET_GATEWAY"),
+    dns: option_env!("NET_DNS"),
+    hostname: option_env!("NET_HOSTNAME"),
+    dhcp_timeout_s: option_env!("NET_DHCP_TIMEOUT_S"),
+};
+
+/// Addressing chosen at build time, used unless some was saved from the
+/// console. build.rs has already rejected malformed values.
+pub fn build_settings() -> NetSettings {
+    unwrap!(NetSettings::from_env(&NET_ENV))
+}
+
+/// The config the stack starts with. Fallback mode starts on DHCP and only
+/// switches to its static address in `networking_task`.
+pub fn stack_config(settings: &NetSettings) -> WifiConfig {
+    match &settings.mode {
+        NetMode::Static(addressing) => WifiConfig::ipv4_static(static_config(addressing)),
+        NetMode::Dhcp | NetMode::DhcpWithFallback { .. } => {
+            WifiConfig::dhcpv4(dhcp_config(settings))
+        }
+    }
+}
+
+fn dhcp_config(settings: &NetSettings) -> DhcpConfig {
+    let mut dhcp = DhcpConfig::default();
+    dhcp.hostname = Some(settings.hostname.clone());
+    dhcp
+}
+
+fn static_config(addressing: &StaticSettings) -> StaticConfigV4 {
+    StaticConfigV4 {
+        address: Ipv4Cidr::new(Ipv4Address::from(addressing.address), addressing.prefix_len),
+        gateway: addressing.gateway.map(Ipv4Address::from),
+        dns_servers: addressing.dns.iter().copied().map(Ipv4Address::from).collect(),
+    }
+}
+
+// 802.11 capability bit for WEP/WPA networks
+const CAPABILITY_PRIVACY: u16 = 1 << 4;
+
+static NETWORKS: Mutex<CriticalSectionRawMutex, RefCell<NetworkList>> = Mutex::new(RefCell::new(NetworkList::new()));
+
+/// Latest link state; the LED task follows it and the display draws it.
+pub static LINK_STATE: Watch<CriticalSectionRawMutex, LinkState, 1> = Watch::new();
+
+pub fn report(state: LinkState) {
+    info!("WiFi link: {:?}", state);
+    LINK_STATE.sender().send(state);
+}
+
+/// Resolves once the board can be reached over HTTP: online, or serving the
+/// setup portal, which never brings the link up.
+pub async fn wait_reachable(stack: Stack<'static>) {
+    select(wait_online(stack), provisioning::wait_active()).await;
+}
+
+/// Resolves once what `wait_reachable` waited for is gone again.
+pub async fn wait_unreachable(stack: Stack<'static>) {
+    if provisioning::active() {
+        provisioning::wait_inactive().await;
+    } else {
+        stack.wait_link_down().await;
+    }
+}
+
+/// Resolves once the link is joined and has an address.
+pub async fn wait_online(stack: Stack<'static>) {
+    loop {
+        stack.wait_link_up().await;
+        stack.wait_config_up().await;
+        // A static config stays up across link loss, so check the link again
+        if stack.is_link_up() {
+            return;
+        }
+    }
+}
 
 #[embassy_executor::task]
-pub async fn networking_task(
-    mut wifi_stack: WifiStack,
-    pipe_writer: Writer<'static, CriticalSectionRawMutex, 1>,
-) {
-    info!("Starting networking task...");
-    
-    // Connect to WiFi
-    connect_wifi(&mut wifi_stack).await;
-    
-    // HTTP server loop - inline
-    let mut rx_buffer = [0; 1024];
-    let mut tx_buffer = [0; 1024];
-    let mut request_buffer = [0; 512];
+pub async fn networking_task(stack: Stack<'static>, radio: &'static Radio, store: &'static Store, settings: &'static NetSettings) {
+    info!("Starting WiFi supervisor...");
+    let mut backoff = Backoff::new();
 
     loop {
-        // Try dereferencing the stack
-        let mut socket = TcpSocket::new(*wifi_stack.stack, &mut rx_buffer, &mut tx_buffer);
-        socket.set_timeout(Some(Duration::from_secs(10)));
-
-        info!("Listening for HTTP connections on port 80...");
-        
-        if let Err(e) = socket.accept(80).await {
-            warn!("Socket accept error: {:?}", e);
-            Timer::after(Duration::from_secs(1)).await;
+        report(LinkState::Connecting);
+        if !join(radio, store, &mut backoff).await {
+            // Nothing to join, or it keeps failing: ask for new credentials
+            provisioning::run(stack, radio, store).await;
+            stack.set_config_v4(stack_config(settings).ipv4);
+            backoff.reset();
             continue;
         }
 
-        info!("New HTTP connection from {:?}", socket.remote_endpoint());
-
-        // Handle request inline
-        if let Ok(bytes_read) = socket.read(&mut request_buffer).await {
-            if bytes_read > 0 {
-                let request = from_utf8(&request_buffer[..bytes_read]).unwrap_or("Invalid UTF-8");
-                info!("HTTP Request: {=str}", &request[..request.len().min(100)]);
-
-                // Parse command
-                let command = parse_command(request);
-                info!("Parsed command: {:?}", command);
-
-                // Send command through pipe
-                if let Some(cmd) = command {
-                    // Quick inline blink for visual feedback
-                    for _i in 0..cmd {
-                        wifi_stack.wifi_controller.gpio_set(0, false).await;
-                        Timer::after(Duration::from_millis(100)).await;
-                        wifi_stack.wifi_controller.gpio_set(0, true).await;
-                        Timer::after(Duration::from_millis(100)).await;
-                    }
-
-                    match pipe_writer.try_write(&[cmd]) {
-                        Ok(_) => info!("Command {} sent to display task", cmd),
-                        Err(_) => warn!("Failed to send command (pipe full?)"),
-                    }
-                }
+        // The link can drop again before DHCP finishes
+        if let Either::Second(()) = select(configure(stack, settings), stack.wait_link_down()).await {
+            warn!("WiFi link dropped while configuring");
+            radio.lock().await.leave().await;
+            retry_after(&mut backoff).await;
+            continue;
+        }
 
-                // Simple inline response - convert all to &[u8] slices
-                let response: &[u8] = match command {
-                    Some(1) => b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\nAnimation 1 triggered!",
-                    Some(2) => b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\nAnimation 2 triggered!",
-                    Some(3) => b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\nAnimation 3 triggered!",
-                    Some(4) => b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\nAnimation 4 triggered!",
-                    _ => b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nConnection: close\r\n\r\n<h1>Pico 2W Control</h1><p><a href='/1'>Anim 1</a> | <a href='/2'>Anim 2</a> | <a href='/3'>Anim 3</a> | <a href='/4'>Anim 4</a></p>",
-                };
-
-                // Use write instead of write_all
-                if let Err(e) = socket.write(response).await {
-                    warn!("Write error: {:?}", e);
-                }
-            }
+        backoff.reset();
+        report(LinkState::Connected);
+        if let Some(config) = stack.config_v4() {
+            info!("Network configured!");
+            info!("IP Address: {}", config.address.address());
+            info!("Gateway: {:?}", config.gateway);
+            info!("DNS servers: {:?}", config.dns_servers);
+            info!("HTTP Server ready at: http://{}", config.address.address());
         }
 
-        socket.close();
-        Timer::after(Duration::from_millis(100)).await;
+        stack.wait_link_down().await;
+        warn!("WiFi link lost, rejoining...");
+        radio.lock().await.leave().await;
     }
 }
 
-async fn connect_wifi(wifi_stack: &mut WifiStack) {
-    info!("Attempting to connect to WiFi network: {}", WIFI_NETWORK);
-    
+// Join the best saved network, backing off between failed rounds. False
+// once there is no point trying further and the setup portal should take over.
+async fn join(radio: &'static Radio, store: &'static Store, backoff: &mut Backoff) -> bool {
     loop {
-        match wifi_stack.wifi_controller
-            .join(WIFI_NETWORK, JoinOptions::new(WIFI_PASSWORD.as_bytes()))
-            .await
-        {
-            Ok(_) => {
-                info!("WiFi connection successful!");
-                break;
+        // Read every attempt, so newly saved networks are picked up
+        let saved = store.lock().await.networks().clone();
+        if saved.is_empty() {
+            warn!("No WiFi credentials stored");
+            return false;
+        }
+
+        let visible = scan(radio).await;
+        for credentials in saved.join_order(&visible) {
+            if credentials.hidden && visible.get(&credentials.ssid).is_none() {
+                info!("Trying hidden WiFi network by name: {=str}", credentials.ssid.as_str());
+            } else {
+                info!("Attempting to connect to WiFi network: {=str}", credentials.ssid.as_str());
             }
-            Err(err) => {
-                warn!("WiFi join failed with status={}, retrying...", err.status);
-                Timer::after(Duration::from_secs(5)).await;
+            let result = radio.lock().await.join(&credentials.ssid, join_options(credentials)).await;
+            match result {
+                Ok(_) => {
+                    info!("WiFi connection successful!");
+                    return true;
+                }
+                Err(err) => warn!("WiFi join failed with status={}", err.status),
             }
         }
+
+        let (delay, state) = backoff.fail();
+        report(state);
+        if let LinkState::Failed { .. } = state {
+            return false;
+        }
+        Timer::after(Duration::from_secs(delay.as_secs())).await;
+    }
+}
+
+fn join_options(credentials: &Credentials) -> JoinOptions<'_> {
+    let mut options = JoinOptions::new(credentials.password.as_bytes());
+    match credentials.auth {
+        Auth::Open => return JoinOptions::new_open(),
+        Auth::Wpa2 => options.auth = JoinAuth::Wpa2,
+        Auth::Wpa3 => options.auth = JoinAuth::Wpa3,
     }
+    // 64 hex digits are the PSK itself rather than a passphrase
+    options.passphrase_is_prehashed = credentials.password.len() == 64;
+    options
+}
 
-    info!("Waiting for link up...");
-    wifi_stack.stack.wait_link_up().await;
-    
-    info!("Waiting for DHCP...");
-    wifi_stack.stack.wait_config_up().await;
-    
-    if let Some(config) = wifi_stack.stack.config_v4() {
-        info!("Network configured!");
-        info!("IP Address: {}", config.address.address());
-        info!("Gateway: {:?}", config.gateway);
-        info!("HTTP Server ready at: http://{}", config.address.address());
+/// Scan for access points and remember the result for `networks`.
+pub async fn scan(radio: &'static Radio) -> NetworkList {
+    let mut found = NetworkList::new();
+    {
+        let mut control = radio.lock().await;
+        let mut scanner = control.scan(ScanOptions::default()).await;
+        while let Some(bss) = scanner.next().await {
+            let ssid = bss.ssid.get(..bss.ssid_len as usize)
+                .and_then(|ssid| core::str::from_utf8(ssid).ok())
+                .and_then(|ssid| String::try_from(ssid).ok());
+            if let Some(ssid) = ssid {
+                found.add(Network {
+                    ssid,
+                    rssi: bss.rssi,
+                    // The low byte of the chanspec is the control channel
+                    channel: (bss.chanspec & 0xff) as u8,
+                    secure: bss.capability & CAPABILITY_PRIVACY != 0,
+                });
+            }
+        }
     }
+    info!("Scan found {} networks", found.len());
+    NETWORKS.lock(|networks| *networks.borrow_mut() = found.clone());
+    found
+}
 
+/// Networks seen by the last scan.
+pub fn networks() -> NetworkList {
+    NETWORKS.lock(|networks| networks.borrow().clone())
+}
 
-    // Turn on LED if connected
-    wifi_stack.wifi_controller.gpio_set(0, true).await;
+async fn retry_after(backoff: &mut Backoff) {
+    let (delay, state) = backoff.fail();
+    report(state);
+    Timer::after(Duration::from_secs(delay.as_secs())).await;
 }
 
-fn parse_command(request: &str) -> Option<u8> {
-    // Look for GET /command?value=X
-    if let Some(start) = request.find("GET /command?value=") {
-        let value_start = start + "GET /command?value=".len();
-        if let Some(end) = request[value_start..].find(|c: char| c == ' ' || c == '&' || c == '\r' || c == '\n') {
-            let value_str = &request[value_start..value_start + end];
-            if let Ok(value) = value_str.parse::<u8>() {
-                if (1..=4).contains(&value) {
-                    return Some(value);
-                }
+// Bring up IPv4 addressing on a freshly joined link.
+async fn configure(stack: Stack<'static>, settings: &NetSettings) {
+    info!("Waiting for link up...");
+    stack.wait_link_up().await;
+
+    match &settings.mode {
+        NetMode::Static(_) => info!("Using static address"),
+        NetMode::Dhcp => {
+            info!("Waiting for DHCP as {}...", settings.hostname.as_str());
+            stack.wait_config_up().await;
+        }
+        NetMode::DhcpWithFallback { fallback, timeout_s } => {
+            // A previous link may have left the fallback address in place
+            stack.set_config_v4(ConfigV4::Dhcp(dhcp_config(settings)));
+            info!("Waiting up to {}s for DHCP as {}...", timeout_s, settings.hostname.as_str());
+            let timeout = Duration::from_secs(u64::from(*timeout_s));
+            if with_timeout(timeout, stack.wait_config_up()).await.is_err() {
+                warn!("No DHCP lease, falling back to static address");
+                stack.set_config_v4(ConfigV4::Static(static_config(fallback)));
+                stack.wait_config_up().await;
             }
         }
     }
-    
-    // Simple GET /1, /2, /3, /4 URLs
-    if request.starts_with("GET /1 ") || request.starts_with("GET /1\r") { return Some(1); }
-    if request.starts_with("GET /2 ") || request.starts_with("GET /2\r") { return Some(2); }
-    if request.starts_with("GET /3 ") || request.starts_with("GET /3\r") { return Some(3); }
-    if request.starts_with("GET /4 ") || request.starts_with("GET /4\r") { return Some(4); }
-    
-    None
-}
\ No newline at end of file
+}
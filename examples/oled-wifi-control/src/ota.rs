@@ -0,0 +1,188 @@
+// file: ota.rs
+// desc: firmware updates over HTTP, and confirming them after the swap
+//
+// `POST /api/firmware` streams the image into the DFU partition, checks its
+// signature against FIRMWARE_KEY, marks it for the bootloader and reboots.
+// Anything unsigned, signed with another key or older than the running
+// firmware stays in DFU and is never swapped in. The bootloader swaps it into
+// ACTIVE; on that first boot `ota_task` waits for the board to come up
+// properly before marking it booted. A reset before then, from the watchdog
+// or the health deadline, makes the bootloader swap the previous image back.
+use core::cell::RefCell;
+
+use defmt::{error, info, warn};
+
+use embassy_boot_rp::{AlignedBuffer, BlockingFirmwareUpdater, FirmwareUpdaterConfig, FirmwareUpdaterError, State};
+use embassy_net::Stack;
+use embassy_net::tcp::{Error as TcpError, TcpSocket};
+use embassy_rp::Peri;
+use embassy_rp::peripherals::WATCHDOG;
+use embassy_rp::watchdog::Watchdog;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::String;
+
+use pico2w_core::http::{ContentType, Request, Status};
+use pico2w_core::ota::{pack_version, parse_public_key, version_text, ImageWriter, Layout, OtaError};
+use pico2w_core::reboot::RebootTarget;
+use pico2w_core::{Command, Input, Source};
+
+use crate::display_task::playback_state;
+use crate::provisioning;
+use crate::reboot::reboot_soon;
+use crate::routes::{reply, Context, FIRMWARE_VERSION};
+use crate::settings_store::{SettingsStore, Store};
+
+// Hex ed25519 public key of the signing tool's key pair, baked in at build time
+const FIRMWARE_KEY: Option<&str> = option_env!("FIRMWARE_KEY");
+
+// Matches ACTIVE (FLASH) and RAM in memory.x
+const LAYOUT: Layout = Layout {
+    active_start: 0x1000_9000,
+    active_len: 1000 * 1024,
+    ram_start: 0x2000_0000,
+    ram_end: 0x2008_2000,
+};
+
+const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(8);
+const FEED_EVERY: Duration = Duration::from_secs(1);
+// A new image that is not up by then gets rolled back
+const HEALTH_DEADLINE: Duration = Duration::from_secs(120);
+const READ_CHUNK_LEN: usize = 1024;
+
+/// Feeds the watchdog for as long as the executor runs, and confirms a freshly
+/// swapped-in image once it is healthy.
+#[embassy_executor::task]
+pub async fn ota_task(watchdog: Peri<'static, WATCHDOG>, stack: Stack<'static>, store: &'static Store) {
+    let mut watchdog = Watchdog::new(watchdog);
+    watchdog.pause_on_debug(true);
+    watchdog.start(WATCHDOG_TIMEOUT);
+
+    let mut on_trial = match firmware_state(&mut store.lock().await) {
+        Ok(State::Swap) => {
+            info!("Running new firmware on trial");
+            true
+        }
+        Ok(State::Revert) => {
+            warn!("New firmware failed to confirm itself, rolled back");
+            // This is the image that was confirmed before; until the state
+            // says so again, `receive` refuses every upload as unconfirmed
+            if let Err(e) = mark_booted(&mut store.lock().await) {
+                error!("Confirming the previous firmware failed: {:?}", e);
+            }
+            false
+        }
+        Ok(_) => false,
+        Err(e) => {
+            warn!("Bootloader state unreadable: {:?}", e);
+            false
+        }
+    };
+
+    let deadline = Instant::now() + HEALTH_DEADLINE;
+    loop {
+        watchdog.feed();
+        if on_trial && healthy(stack) {
+            match mark_booted(&mut store.lock().await) {
+                Ok(()) => info!("New firmware confirmed"),
+                Err(e) => error!("Confirming new firmware failed: {:?}", e),
+            }
+            on_trial = false;
+        } else if on_trial && Instant::now() > deadline {
+            error!("New firmware never became healthy, rolling back");
+            watchdog.trigger_reset();
+        }
+        Timer::after(FEED_EVERY).await;
+    }
+}
+
+// The display runs and the board is reachable, on WiFi or its setup portal
+fn healthy(stack: Stack<'static>) -> bool {
+    playback_state().is_some() && (stack.is_config_up() || provisioning::active())
+}
+
+fn firmware_state(store: &mut SettingsStore) -> Result<State, FirmwareUpdaterError> {
+    let flash = Mutex::<NoopRawMutex, _>::new(RefCell::new(store.flash()));
+    let config = FirmwareUpdaterConfig::from_linkerfile_blocking(&flash, &flash);
+    let mut aligned = AlignedBuffer([0; 1]);
+    BlockingFirmwareUpdater::new(config, &mut aligned.0).get_state()
+}
+
+fn mark_booted(store: &mut SettingsStore) -> Result<(), FirmwareUpdaterError> {
+    let flash = Mutex::<NoopRawMutex, _>::new(RefCell::new(store.flash()));
+    let config = FirmwareUpdaterConfig::from_linkerfile_blocking(&flash, &flash);
+    let mut aligned = AlignedBuffer([0; 1]);
+    BlockingFirmwareUpdater::new(config, &mut aligned.0).mark_booted()
+}
+
+/// `POST /api/firmware`. `received` is the part of the body that arrived with
+/// the head; the rest is read from the socket. Reboots into the new image on
+/// success.
+pub async fn upload(
+    socket: &mut TcpSocket<'_>,
+    request: &Request<'_>,
+    received: &[u8],
+    ctx: Context,
+) -> Result<(), TcpError> {
+    let len = request.content_length().unwrap_or(0);
+    match receive(socket, len, received, ctx.store).await {
+        Ok(version) => {
+            info!("Stored signed firmware {}, rebooting into it", version_text(version).as_str());
+            reply(request, Status::Accepted)
+                .keep_alive(false)
+                .content_type(ContentType::Json)
+                .send(socket, br#"{"status":"rebooting"}"#)
+                .await?;
+            socket.flush().await?;
+            reboot_soon(RebootTarget::Normal).await
+        }
+        Err(e) => {
+            warn!("Firmware upload rejected: {:?}", e);
+            if let Some(text) = e.oled_text().and_then(|text| String::try_from(text).ok()) {
+                ctx.sender.try_send(Input { source: Source::Http, command: Command::ShowText(text) }).ok();
+            }
+            // Whatever is left of the body is still on its way
+            reply(request, e.status())
+                .keep_alive(false)
+                .content_type(ContentType::Json)
+                .send(socket, e.json().as_bytes())
+                .await
+        }
+    }
+}
+
+// Holds the settings store, and with it the flash, for the whole upload.
+// Returns the packed version of the verified image.
+async fn receive(socket: &mut TcpSocket<'_>, len: usize, received: &[u8], store: &Store) -> Result<u32, OtaError> {
+    let key = FIRMWARE_KEY.and_then(parse_public_key).ok_or(OtaError::NoKey)?;
+    let running = pack_version(FIRMWARE_VERSION).unwrap_or(0);
+    let mut image = ImageWriter::new(len, LAYOUT)?;
+
+    let mut store = store.lock().await;
+    let flash = Mutex::<NoopRawMutex, _>::new(RefCell::new(store.flash()));
+    let config = FirmwareUpdaterConfig::from_linkerfile_blocking(&flash, &flash);
+    let mut aligned = AlignedBuffer([0; 1]);
+    let mut updater = BlockingFirmwareUpdater::new(config, &mut aligned.0);
+    // Overwriting DFU before the running image is confirmed would lose the
+    // copy a rollback needs
+    if !matches!(updater.get_state(), Ok(State::Boot)) {
+        return Err(OtaError::Unconfirmed);
+    }
+
+    let mut write = |offset: usize, page: &[u8]| updater.write_firmware(offset, page);
+    image.write(received, &mut write)?;
+    let mut chunk = [0u8; READ_CHUNK_LEN];
+    while image.remaining() > 0 {
+        match socket.read(&mut chunk).await {
+            Ok(0) | Err(_) => return Err(OtaError::Truncated),
+            Ok(bytes_read) => image.write(&chunk[..bytes_read], &mut write)?,
+        }
+    }
+    let image = image.finish(&mut write)?;
+    // Only a verified image gets scheduled; a rejected one just sits in DFU
+    image.verify(&key, running)?;
+
+    updater.mark_updated().map_err(|_| OtaError::Flash)?;
+    Ok(image.version)
+}
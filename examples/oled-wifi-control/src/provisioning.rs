@@ -0,0 +1,228 @@
+// file: provisioning.rs
+// desc: soft-AP setup portal for boards without working WiFi credentials
+//
+// The supervisor calls `run` when no credentials are stored or joining keeps
+// failing. The board then opens its own WPA2 network, hands out addresses,
+// points every DNS lookup at itself and serves the setup page until new
+// credentials are saved.
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::fmt::Write;
+
+use defmt::{info, unwrap, warn};
+
+use embassy_futures::select::{select3, Either3};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{ConfigV4, IpAddress, IpEndpoint, Ipv4Address, Ipv4Cidr, Stack, StaticConfigV4};
+use embassy_net::tcp::{Error as TcpError, TcpSocket};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_sync::watch::Watch;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use heapless::{String, Vec};
+
+use pico2w_bsp::Radio;
+use pico2w_core::captive_dns;
+use pico2w_core::credentials::CredentialsError;
+use pico2w_core::dhcp_server::{self, DhcpServer};
+use pico2w_core::http::{ContentType, Method, Request, Status};
+use pico2w_core::link::LinkState;
+use pico2w_core::provision::{
+    ap_ssid, is_portal_host, parse_setup_form, render_setup_page, AP_ADDRESS, AP_POOL_START, AP_PREFIX_LEN,
+    AP_URL, SETUP_PATH,
+};
+
+use crate::http_task::HTTP_WORKERS;
+use crate::networking_task::{networks, report, scan};
+use crate::routes::{reply, Context};
+use crate::settings_store::Store;
+
+/// Shown on the OLED; override with AP_PASSWORD at build time.
+pub const AP_PASSWORD: &str = match option_env!("AP_PASSWORD") {
+    Some(password) => password,
+    None => "pico2w-setup",
+};
+// A WPA2 passphrase; the access point will not start with anything else
+const _: () = assert!(
+    AP_PASSWORD.len() >= 8 && AP_PASSWORD.len() <= 63,
+    "AP_PASSWORD must be 8 to 63 characters"
+);
+const AP_CHANNEL: u8 = 6;
+// Give up and retry the stored network after this long without a setup
+const PORTAL_TIMEOUT: Duration = Duration::from_secs(600);
+// Clients the DHCP server can hold at once
+const DHCP_LEASES: usize = 8;
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+// ACTIVE for the HTTP workers to wait on; the access point never brings the
+// WiFi link up, so they cannot wait for that
+static PORTAL: Watch<CriticalSectionRawMutex, bool, HTTP_WORKERS> = Watch::new();
+static SAVED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// True while the setup access point is up; HTTP requests go to `handle`.
+pub fn active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Resolves once the setup access point is up, or right away if it is.
+pub async fn wait_active() {
+    unwrap!(PORTAL.receiver()).get_and(|&active| active).await;
+}
+
+/// Resolves once the setup access point has closed, or right away if it is
+/// not up.
+pub async fn wait_inactive() {
+    if active() {
+        unwrap!(PORTAL.receiver()).get_and(|&active| !active).await;
+    }
+}
+
+fn set_active(active: bool) {
+    ACTIVE.store(active, Ordering::Relaxed);
+    PORTAL.sender().send(active);
+}
+
+/// Serve the setup portal until credentials are saved, or until
+/// `PORTAL_TIMEOUT` if there are saved networks to go back to.
+pub async fn run(stack: Stack<'static>, radio: &'static Radio, store: &'static Store) {
+    // Scan first; the radio cannot scan while it is an access point
+    scan(radio).await;
+
+    let mac = radio.lock().await.address().await;
+    let ssid = ap_ssid(&mac);
+    info!("Opening setup access point {=str}", ssid.as_str());
+    radio.lock().await.start_ap_wpa2(&ssid, AP_PASSWORD, AP_CHANNEL).await;
+    stack.set_config_v4(ConfigV4::Static(StaticConfigV4 {
+        address: Ipv4Cidr::new(Ipv4Address::from(AP_ADDRESS), AP_PREFIX_LEN),
+        gateway: None,
+        dns_servers: Vec::new(),
+    }));
+
+    SAVED.reset();
+    set_active(true);
+    report(LinkState::Provisioning { mac });
+
+    let can_go_back = !store.lock().await.networks().is_empty();
+    let saved = async {
+        match can_go_back {
+            true => with_timeout(PORTAL_TIMEOUT, SAVED.wait()).await.is_ok(),
+            false => {
+                SAVED.wait().await;
+                true
+            }
+        }
+    };
+    match select3(dhcp_server(stack), captive_dns(stack), saved).await {
+        // Let the confirmation page reach the phone before the AP goes away
+        Either3::Third(true) => Timer::after(Duration::from_secs(1)).await,
+        _ => info!("Setup timed out, retrying the saved networks"),
+    }
+
+    set_active(false);
+    radio.lock().await.close_ap().await;
+    info!("Setup access point closed");
+}
+
+async fn dhcp_server(stack: Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0u8; 1024];
+    let mut tx_buffer = [0u8; 1024];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    if let Err(e) = socket.bind(dhcp_server::SERVER_PORT) {
+        warn!("DHCP server bind failed: {:?}", e);
+        return core::future::pending().await;
+    }
+
+    let mut server = DhcpServer::<DHCP_LEASES>::new(AP_ADDRESS, AP_POOL_START);
+    let mut packet = [0u8; 576];
+    let mut reply = [0u8; dhcp_server::MAX_PACKET_LEN];
+    // Clients have no address yet, so replies are broadcast
+    let clients = IpEndpoint::new(IpAddress::Ipv4(Ipv4Address::BROADCAST), dhcp_server::CLIENT_PORT);
+    loop {
+        let Ok((len, _)) = socket.recv_from(&mut packet).await else {
+            continue;
+        };
+        if let Some(reply_len) = server.handle(&packet[..len], Instant::now().as_secs(), &mut reply) {
+            if let Err(e) = socket.send_to(&reply[..reply_len], clients).await {
+                warn!("DHCP reply failed: {:?}", e);
+            }
+        }
+    }
+}
+
+async fn captive_dns(stack: Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0u8; 1024];
+    let mut tx_buffer = [0u8; 1024];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    if let Err(e) = socket.bind(captive_dns::PORT) {
+        warn!("DNS responder bind failed: {:?}", e);
+        return core::future::pending().await;
+    }
+
+    let mut query = [0u8; captive_dns::MAX_PACKET_LEN];
+    let mut reply = [0u8; captive_dns::MAX_PACKET_LEN];
+    loop {
+        let Ok((len, meta)) = socket.recv_from(&mut query).await else {
+            continue;
+        };
+        if let Some(reply_len) = captive_dns::answer(&query[..len], AP_ADDRESS, &mut reply) {
+            if let Err(e) = socket.send_to(&reply[..reply_len], meta.endpoint).await {
+                warn!("DNS reply failed: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Every request while the portal is up lands here.
+pub async fn handle(socket: &mut TcpSocket<'_>, request: &Request<'_>, ctx: Context) -> Result<(), TcpError> {
+    match request.method {
+        Method::Post if request.path == SETUP_PATH => save(socket, request, ctx).await,
+        Method::Get | Method::Head => {
+            // Connectivity checks ask for other hosts; send them to the portal
+            if request.header("Host").is_some_and(|host| !is_portal_host(host)) {
+                return reply(request, Status::Found).header("Location", AP_URL).send(socket, &[]).await;
+            }
+            setup_page(socket, request, Status::Ok, None).await
+        }
+        _ => {
+            reply(request, Status::MethodNotAllowed)
+                .header("Allow", "GET, HEAD, POST")
+                .send_text(socket, "Method Not Allowed")
+                .await
+        }
+    }
+}
+
+async fn save(socket: &mut TcpSocket<'_>, request: &Request<'_>, ctx: Context) -> Result<(), TcpError> {
+    let credentials = match parse_setup_form(request.body) {
+        Ok(credentials) => credentials,
+        Err(CredentialsError::Ssid) => {
+            return setup_page(socket, request, Status::BadRequest, Some("Enter the network name")).await;
+        }
+        Err(CredentialsError::Password) => {
+            let message = "Passwords are 8 to 63 characters, and empty only for open networks";
+            return setup_page(socket, request, Status::BadRequest, Some(message)).await;
+        }
+    };
+
+    let mut body: String<96> = String::new();
+    write!(body, "Saved. The board is joining {}; reconnect your phone to that network.", credentials.ssid.as_str()).ok();
+    if let Err(e) = ctx.store.lock().await.save_network(credentials) {
+        warn!("Saving credentials failed: {:?}", e);
+        return setup_page(socket, request, Status::InternalServerError, Some("Could not save, please try again")).await;
+    }
+    info!("New WiFi credentials saved");
+
+    let result = reply(request, Status::Ok).send_text(socket, &body).await;
+    SAVED.signal(());
+    result
+}
+
+async fn setup_page(socket: &mut TcpSocket<'_>, request: &Request<'_>, status: Status, message: Option<&str>) -> Result<(), TcpError> {
+    let Some(page) = render_setup_page(&networks(), message) else {
+        return reply(request, Status::InternalServerError).send_text(socket, "Setup page too large").await;
+    };
+    reply(request, status).content_type(ContentType::Html).send(socket, page.as_bytes()).await
+}
@@ -0,0 +1,27 @@
+// file: reboot.rs
+// desc: rebooting on request, into the application or BOOTSEL
+use defmt::{info, warn};
+
+use embassy_time::{Duration, Timer};
+
+use pico2w_core::reboot::RebootTarget;
+
+// Time for a reply to leave before the reset
+pub const REPLY_DELAY: Duration = Duration::from_millis(500);
+// The ROM's own delay, so nothing is mid-flash-write when it resets
+const ROM_DELAY_MS: u32 = 10;
+
+/// Reset into `target` right away.
+pub fn reboot(target: RebootTarget) -> ! {
+    info!("Rebooting ({})", target.as_str());
+    embassy_rp::rom_data::reboot(target.rom_flags(), ROM_DELAY_MS, 0, 0);
+    // Only returns if the ROM refused the flags
+    warn!("ROM reboot failed, resetting instead");
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+/// Reset into `target` once a reply has had time to leave.
+pub async fn reboot_soon(target: RebootTarget) -> ! {
+    Timer::after(REPLY_DELAY).await;
+    reboot(target)
+}
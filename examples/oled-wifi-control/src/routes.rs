@@ -0,0 +1,387 @@
+// file: routes.rs
+// desc: HTTP route table and handlers
+use core::fmt::Write;
+
+use defmt::{info, unwrap, warn};
+
+use embassy_net::Stack;
+use embassy_net::tcp::{Error as TcpError, TcpSocket};
+use embassy_futures::select::{select, Either};
+use embassy_sync::pubsub::WaitResult;
+use embassy_time::{Duration, Instant, Ticker, Timer};
+use heapless::String;
+
+use pico2w_bsp::Radio;
+use pico2w_core::api::{parse_animation, parse_playback, parse_reboot, StatusReport, STATUS_JSON_LEN};
+use pico2w_core::assets::MAX_ANIMATIONS;
+use pico2w_core::http::{allow_header, ContentType, Method, Request, Response, Route, RouteError, Router, Status};
+use pico2w_core::scan::SCAN_JSON_LEN;
+use pico2w_core::screen::{Framebuffer, PBM_LEN, PNG_LEN};
+use pico2w_core::event::HeartbeatEvent;
+use pico2w_core::{parse_command, Command, Event, Input, Source};
+
+use crate::display_task::{capture, playback_state, ANIMATION_COUNT};
+use crate::led_task;
+use crate::networking_task;
+use crate::ota;
+use crate::provisioning;
+use crate::reboot::reboot_soon;
+use crate::settings_store::Store;
+use crate::websocket::websocket;
+use crate::{CommandSender, EVENTS};
+
+pub const FIRMWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Room for the heading, the mirror link and one link per animation
+const INDEX_LEN: usize = 96 + 32 * (ANIMATION_COUNT + MAX_ANIMATIONS);
+const MIRROR_HTML: &str = "<!doctype html><title>Pico 2W OLED</title><body style='background:#222'><img src='/screenshot/stream' width='512' height='256' style='image-rendering:pixelated'></body>";
+
+// Parts of the multipart/x-mixed-replace stream behind /screenshot/stream
+const STREAM_CONTENT_TYPE: &str = "multipart/x-mixed-replace; boundary=frame";
+const STREAM_PART_HEAD: &[u8] = b"--frame\r\nContent-Type: image/png\r\n\r\n";
+const STREAM_POLL: Duration = Duration::from_millis(50);
+// How often /events reports the frame on screen and a heartbeat
+const EVENTS_TICK: Duration = Duration::from_secs(1);
+const HEARTBEAT_EVERY_TICKS: u32 = 15;
+// Legacy URLs blink the LED once per animation number, up to this many times
+const MAX_BLINKS: u8 = 4;
+
+#[derive(Clone, Copy)]
+enum Handler {
+    Index,
+    PlayAnimation,
+    Status,
+    SetAnimation,
+    SetPlayback,
+    WifiScan,
+    ScreenshotPbm,
+    ScreenshotPng,
+    ScreenshotStream,
+    Mirror,
+    Events,
+    WebSocket,
+    Firmware,
+    Reboot,
+}
+
+// Literal paths first; `/:n` would swallow them otherwise
+const ROUTES: &[Route<Handler>] = &[
+    Route::get("/", Handler::Index),
+    Route::get("/api/status", Handler::Status),
+    Route::put("/api/animation", Handler::SetAnimation),
+    Route::put("/api/playback", Handler::SetPlayback),
+    Route::get("/api/wifi/scan", Handler::WifiScan),
+    Route::post("/api/firmware", Handler::Firmware),
+    Route::post("/api/reboot", Handler::Reboot),
+    Route::get("/screenshot.pbm", Handler::ScreenshotPbm),
+    Route::get("/screenshot.png", Handler::ScreenshotPng),
+    Route::get("/screenshot/stream", Handler::ScreenshotStream),
+    Route::get("/mirror", Handler::Mirror),
+    Route::get("/events", Handler::Events),
+    Route::get("/ws", Handler::WebSocket),
+    Route::get("/command", Handler::PlayAnimation),
+    Route::get("/:n", Handler::PlayAnimation),
+];
+const ROUTER: Router<Handler> = Router::new(ROUTES);
+
+/// Everything a handler may need besides the request.
+#[derive(Clone, Copy)]
+pub struct Context {
+    pub stack: Stack<'static>,
+    pub radio: &'static Radio,
+    pub sender: CommandSender,
+    pub store: &'static Store,
+}
+
+pub async fn handle(socket: &mut TcpSocket<'_>, request: &Request<'_>, ctx: Context) -> Result<(), TcpError> {
+    if provisioning::active() {
+        return provisioning::handle(socket, request, ctx).await;
+    }
+
+    let handler = match ROUTER.resolve(request.method, request.path) {
+        Ok((handler, _params)) => *handler,
+        Err(RouteError::NotFound) => {
+            return reply(request, Status::NotFound).send_text(socket, "Not Found").await;
+        }
+        Err(RouteError::MethodNotAllowed { allow }) => {
+            let allow = allow_header(&allow);
+            return reply(request, Status::MethodNotAllowed)
+                .header("Allow", &allow)
+                .send_text(socket, "Method Not Allowed")
+                .await;
+        }
+    };
+
+    match handler {
+        Handler::Index => {
+            let animation_count = playback_state().map_or(ANIMATION_COUNT as u8, |state| state.animation_count);
+            reply(request, Status::Ok)
+                .content_type(ContentType::Html)
+                .send(socket, index_html(animation_count).as_bytes())
+                .await
+        }
+        Handler::PlayAnimation => {
+            let Some(playback) = playback_state() else {
+                let status = Status::ServiceUnavailable;
+                return reply(request, status).send_text(socket, "Display not ready").await;
+            };
+            let command = match parse_command(request, playback.animation_count) {
+                Ok(command) => command,
+                Err(e) => {
+                    let status = e.status();
+                    return reply(request, status).send_text(socket, status.reason()).await;
+                }
+            };
+            info!("Parsed command: {:?}", command);
+            let number = match command {
+                Command::PlayAnimation(n) => n,
+                _ => 1,
+            };
+
+            // HEAD gets the reply GET would, without switching anything
+            if request.method != Method::Head {
+                if ctx.sender.try_send(Input { source: Source::Http, command: command.clone() }).is_err() {
+                    warn!("Failed to send command (queue full?)");
+                    return reply(request, Status::ServiceUnavailable).send_text(socket, "Display busy, try again").await;
+                }
+                info!("Command {:?} sent to display task", command);
+                // Visual feedback; the LED task owns the LED
+                led_task::flash(number.min(MAX_BLINKS));
+            }
+
+            let mut body: String<32> = String::new();
+            write!(body, "Animation {} triggered!", number).ok();
+            reply(request, Status::Ok).send_text(socket, &body).await
+        }
+        Handler::Status => status(socket, request, ctx).await,
+        Handler::SetAnimation => {
+            let Some(playback) = playback_state() else {
+                return api_error(socket, request, Status::ServiceUnavailable, r#"{"error":"display not ready"}"#).await;
+            };
+            match parse_animation(request.body, playback.animation_count) {
+                Ok(command) => queue(socket, request, ctx, &[command]).await,
+                Err(e) => api_error(socket, request, e.status(), e.json()).await,
+            }
+        }
+        Handler::SetPlayback => match parse_playback(request.body) {
+            Ok(commands) => queue(socket, request, ctx, &commands).await,
+            Err(e) => api_error(socket, request, e.status(), e.json()).await,
+        },
+        Handler::WifiScan => {
+            let networks = networking_task::scan(ctx.radio).await;
+            let mut json = [0u8; SCAN_JSON_LEN];
+            match networks.to_json(&mut json) {
+                Some(body) => reply(request, Status::Ok).content_type(ContentType::Json).send(socket, body).await,
+                None => api_error(socket, request, Status::InternalServerError, r#"{"error":"scan too large"}"#).await,
+            }
+        }
+        Handler::ScreenshotPbm => {
+            let mut frame = Framebuffer::new();
+            capture(&mut frame);
+            let mut pbm = [0u8; PBM_LEN];
+            reply(request, Status::Ok)
+                .content_type(ContentType::Pbm)
+                .header("Cache-Control", "no-store")
+                .send(socket, frame.to_pbm(&mut pbm))
+                .await
+        }
+        Handler::ScreenshotPng => {
+            let mut frame = Framebuffer::new();
+            capture(&mut frame);
+            let mut png = [0u8; PNG_LEN];
+            reply(request, Status::Ok)
+                .content_type(ContentType::Png)
+                .header("Cache-Control", "no-store")
+                .send(socket, frame.to_png(&mut png))
+                .await
+        }
+        Handler::ScreenshotStream => screenshot_stream(socket, request).await,
+        Handler::Events => events(socket, request).await,
+        Handler::WebSocket => websocket(socket, request, ctx).await,
+        // Like the streamed path, never flash while the setup portal is up
+        Handler::Firmware if !provisioning::active() => ota::upload(socket, request, request.body, ctx).await,
+        Handler::Firmware => provisioning::handle(socket, request, ctx).await,
+        Handler::Reboot => match parse_reboot(request.body) {
+            Ok(target) => {
+                info!("Reboot requested over HTTP ({})", target.as_str());
+                reply(request, Status::Accepted)
+                    .keep_alive(false)
+                    .content_type(ContentType::Json)
+                    .send(socket, br#"{"status":"rebooting"}"#)
+                    .await?;
+                socket.flush().await?;
+                reboot_soon(target).await
+            }
+            Err(e) => api_error(socket, request, e.status(), e.json()).await,
+        },
+        Handler::Mirror => {
+            reply(request, Status::Ok)
+                .content_type(ContentType::Html)
+                .send(socket, MIRROR_HTML.as_bytes())
+                .await
+        }
+    }
+}
+
+/// A request whose body was too large to buffer; `received` is what arrived
+/// with the head. Only firmware uploads take one.
+pub async fn handle_streamed(
+    socket: &mut TcpSocket<'_>,
+    request: &Request<'_>,
+    received: &[u8],
+    ctx: Context,
+) -> Result<(), TcpError> {
+    match ROUTER.resolve(request.method, request.path) {
+        Ok((Handler::Firmware, _params)) if !provisioning::active() => {
+            ota::upload(socket, request, received, ctx).await
+        }
+        _ => {
+            let status = Status::PayloadTooLarge;
+            reply(request, status).keep_alive(false).send_text(socket, status.reason()).await
+        }
+    }
+}
+
+// Server-Sent Events: playback changes as they happen, plus a frame event
+// every tick and a heartbeat now and then. Holds one HTTP worker.
+async fn events(socket: &mut TcpSocket<'_>, request: &Request<'_>) -> Result<(), TcpError> {
+    // One subscriber slot per worker, but be polite if they are all taken
+    let Ok(mut subscriber) = EVENTS.subscriber() else {
+        return reply(request, Status::ServiceUnavailable).send_text(socket, "Too many event streams").await;
+    };
+
+    let mut body = reply(request, Status::Ok)
+        .keep_alive(false)
+        .content_type(ContentType::EventStream)
+        .header("Cache-Control", "no-store")
+        .send_chunked(socket)
+        .await?;
+    if request.method == Method::Head {
+        return body.finish().await;
+    }
+
+    // Ask EventSource to come back quickly if the board drops us
+    body.write(b"retry: 2000\n\n").await?;
+
+    let mut ticker = Ticker::every(EVENTS_TICK);
+    let mut ticks = 0u32;
+    loop {
+        let event = match select(subscriber.next_message(), ticker.next()).await {
+            Either::First(WaitResult::Message(event)) => event,
+            Either::First(WaitResult::Lagged(missed)) => {
+                warn!("Event stream lagged, {} events dropped", missed);
+                continue;
+            }
+            Either::Second(_) => {
+                ticks = ticks.wrapping_add(1);
+                if ticks % HEARTBEAT_EVERY_TICKS == 0 {
+                    Event::Heartbeat(HeartbeatEvent { uptime_s: Instant::now().as_secs() })
+                } else {
+                    match playback_state() {
+                        Some(state) => Event::frame(&state),
+                        None => continue,
+                    }
+                }
+            }
+        };
+
+        if let Some(message) = event.to_sse() {
+            body.write(message.as_bytes()).await?;
+        }
+    }
+}
+
+// Push every new frame as a PNG part until the client goes away. This holds
+// one HTTP worker for as long as the viewer stays open.
+async fn screenshot_stream(socket: &mut TcpSocket<'_>, request: &Request<'_>) -> Result<(), TcpError> {
+    let mut body = reply(request, Status::Ok)
+        .keep_alive(false)
+        .header("Content-Type", STREAM_CONTENT_TYPE)
+        .header("Cache-Control", "no-store")
+        .send_chunked(socket)
+        .await?;
+    if request.method == Method::Head {
+        return body.finish().await;
+    }
+
+    let mut frame = Framebuffer::new();
+    let mut png = [0u8; PNG_LEN];
+    let mut last_sent = None;
+    loop {
+        let number = capture(&mut frame);
+        if last_sent != Some(number) {
+            body.write(STREAM_PART_HEAD).await?;
+            body.write(frame.to_png(&mut png)).await?;
+            body.write(b"\r\n").await?;
+            last_sent = Some(number);
+        }
+        Timer::after(STREAM_POLL).await;
+    }
+}
+
+async fn status(socket: &mut TcpSocket<'_>, request: &Request<'_>, ctx: Context) -> Result<(), TcpError> {
+    if playback_state().is_none() {
+        return api_error(socket, request, Status::ServiceUnavailable, r#"{"error":"display not ready"}"#).await;
+    }
+    let mut json = [0u8; STATUS_JSON_LEN];
+    match status_json(ctx.stack, ctx.radio, &mut json).await {
+        Some(body) => reply(request, Status::Ok).content_type(ContentType::Json).send(socket, body).await,
+        None => api_error(socket, request, Status::InternalServerError, r#"{"error":"status too large"}"#).await,
+    }
+}
+
+/// The `GET /api/status` body, in `buf`; `None` before the display runs.
+pub async fn status_json<'b>(stack: Stack<'static>, radio: &'static Radio, buf: &'b mut [u8]) -> Option<&'b [u8]> {
+    let playback = playback_state()?;
+    let mut ip_text: String<15> = String::new();
+    let ip = match stack.config_v4() {
+        Some(config) => {
+            write!(ip_text, "{}", config.address.address()).ok();
+            Some(ip_text.as_str())
+        }
+        None => None,
+    };
+    let rssi = match stack.is_link_up() {
+        true => Some(radio.lock().await.get_rssi().await),
+        false => None,
+    };
+
+    StatusReport::new(playback, Instant::now().as_secs(), ip, rssi, FIRMWARE_VERSION).to_json(buf)
+}
+
+// Hand API commands to the display task; it applies them on its next frame.
+// All of them or none, so a busy reply means nothing changed.
+async fn queue(socket: &mut TcpSocket<'_>, request: &Request<'_>, ctx: Context, commands: &[Command]) -> Result<(), TcpError> {
+    // No await between the check and the sends, so no other task gets in
+    if ctx.sender.free_capacity() < commands.len() {
+        warn!("Failed to send commands (queue full?)");
+        return api_error(socket, request, Status::ServiceUnavailable, r#"{"error":"display busy, try again"}"#).await;
+    }
+    for command in commands {
+        unwrap!(ctx.sender.try_send(Input { source: Source::Http, command: command.clone() }).ok());
+        info!("Command {:?} sent to display task", command);
+    }
+    reply(request, Status::NoContent).send(socket, &[]).await
+}
+
+async fn api_error(socket: &mut TcpSocket<'_>, request: &Request<'_>, status: Status, json: &str) -> Result<(), TcpError> {
+    reply(request, status).content_type(ContentType::Json).send(socket, json.as_bytes()).await
+}
+
+// The drive's animations come and go, so the links follow the player
+fn index_html(animation_count: u8) -> String<INDEX_LEN> {
+    let mut page = String::new();
+    page.push_str("<h1>Pico 2W Control</h1><p>").ok();
+    for n in 1..=animation_count {
+        write!(page, "<a href='/{}'>Anim {}</a> | ", n, n).ok();
+    }
+    page.push_str("<a href='/mirror'>Mirror</a></p>").ok();
+    page
+}
+
+// Start a response that honours HEAD and the client's keep-alive wish.
+pub fn reply(request: &Request<'_>, status: Status) -> Response<'static> {
+    Response::new(status)
+        .head_only(request.method == Method::Head)
+        .keep_alive(request.keep_alive())
+}
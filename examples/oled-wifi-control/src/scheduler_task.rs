@@ -0,0 +1,43 @@
+// file: scheduler_task.rs
+// desc: rotates through the animations, if the build asks for it
+//
+// See pico2w_core::schedule; build.rs has already rejected malformed values.
+use defmt::{info, unwrap};
+use embassy_futures::select::{select, Either};
+use embassy_sync::pubsub::WaitResult;
+use embassy_time::{Duration, Instant, Timer};
+
+use pico2w_core::schedule::Schedule;
+use pico2w_core::{Command, Input, Source};
+
+use crate::{CommandSender, EVENTS};
+
+const ROTATE_EVERY_S: Option<&str> = option_env!("ROTATE_EVERY_S");
+const ROTATE_HOLD_S: Option<&str> = option_env!("ROTATE_HOLD_S");
+
+#[embassy_executor::task]
+pub async fn scheduler_task(sender: CommandSender) {
+    let Some(schedule) = unwrap!(Schedule::from_env(ROTATE_EVERY_S, ROTATE_HOLD_S)) else {
+        info!("Scheduler off");
+        return;
+    };
+    info!("Scheduler rotating every {}s, held {}s after a manual pick", schedule.every_s, schedule.hold_s);
+    let mut subscriber = unwrap!(EVENTS.subscriber());
+
+    let mut due = Instant::now() + Duration::from_secs(schedule.every_s.into());
+    loop {
+        match select(Timer::at(due), subscriber.next_message()).await {
+            Either::First(()) => {
+                info!("Scheduler: next animation");
+                sender.send(Input { source: Source::Scheduler, command: Command::Next }).await;
+                due = Instant::now() + Duration::from_secs(schedule.every_s.into());
+            }
+            Either::Second(WaitResult::Message(event)) => {
+                if let Some(wait_s) = schedule.wait_after(&event) {
+                    due = Instant::now() + Duration::from_secs(wait_s.into());
+                }
+            }
+            Either::Second(WaitResult::Lagged(_)) => {}
+        }
+    }
+}
@@ -0,0 +1,152 @@
+// file: settings_store.rs
+// desc: saved WiFi networks and addressing kept in the reserved flash sector
+//
+// memory.x leaves the last 4K of the first 2 MiB out of FLASH for this. The
+// networks record (pico2w_core::credentials) starts the sector and the
+// addressing record (pico2w_core::network) follows it. The store owns the
+// flash, so firmware updates and the USB drive borrow it from here.
+use defmt::{info, warn};
+
+use embassy_rp::flash::{Blocking, Error as FlashError, Flash, ERASE_SIZE};
+use embassy_rp::peripherals::FLASH;
+use embassy_rp::Peri;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+
+use pico2w_core::credentials::{Credentials, RecordError, SavedNetworks, RECORD_LEN};
+use pico2w_core::network::{NetSettings, NET_RECORD_LEN};
+
+const FLASH_SIZE: usize = 4 * 1024 * 1024;
+pub type BoardFlash = Flash<'static, FLASH, Blocking, FLASH_SIZE>;
+// Matches SETTINGS in memory.x, as an offset from the start of flash: the last
+// sector of the first 2 MiB
+const SETTINGS_OFFSET: u32 = (2 * 1024 * 1024 - ERASE_SIZE) as u32;
+const NET_OFFSET: u32 = SETTINGS_OFFSET + RECORD_LEN as u32;
+const _: () = assert!(RECORD_LEN + NET_RECORD_LEN <= ERASE_SIZE);
+
+// Optional first-boot default; leave WIFI_ID unset to keep it out of the ELF
+const DEFAULT_SSID: Option<&str> = option_env!("WIFI_ID");
+const DEFAULT_PASSWORD: Option<&str> = option_env!("WIFI_PASS");
+
+/// Shared by the WiFi supervisor and anything that changes the saved networks.
+pub type Store = Mutex<CriticalSectionRawMutex, SettingsStore>;
+
+pub struct SettingsStore {
+    flash: BoardFlash,
+    networks: SavedNetworks,
+    net: Option<NetSettings>,
+}
+
+impl SettingsStore {
+    /// Load the saved networks, seeding an empty sector with the build-time
+    /// default if there is one.
+    pub fn new(flash: Peri<'static, FLASH>) -> Self {
+        let mut store = SettingsStore { flash: Flash::new_blocking(flash), networks: SavedNetworks::new(), net: None };
+
+        let mut net = [0u8; NET_RECORD_LEN];
+        match store.flash.blocking_read(NET_OFFSET, &mut net).map(|()| NetSettings::from_record(&net)) {
+            Ok(Ok(settings)) => {
+                info!("Loaded addressing: {:?}", settings);
+                store.net = Some(settings);
+            }
+            Ok(Err(RecordError::Empty)) => {}
+            Ok(Err(e)) => warn!("Ignoring stored addressing: {:?}", e),
+            Err(e) => warn!("Settings read failed: {:?}", e),
+        }
+
+        let mut record = [0u8; RECORD_LEN];
+        let loaded = match store.flash.blocking_read(SETTINGS_OFFSET, &mut record) {
+            Ok(()) => SavedNetworks::from_record(&record),
+            Err(e) => {
+                warn!("Settings read failed: {:?}", e);
+                Err(RecordError::Corrupt)
+            }
+        };
+
+        match loaded {
+            Ok(networks) => {
+                for credentials in networks.iter() {
+                    info!("Loaded WiFi network {=str}", credentials.ssid.as_str());
+                }
+                store.networks = networks;
+            }
+            Err(RecordError::Empty) => match build_default() {
+                Some(credentials) => {
+                    info!("Storing build-time WiFi credentials for {=str}", credentials.ssid.as_str());
+                    if let Err(e) = store.save_network(credentials) {
+                        warn!("Settings write failed: {:?}", e);
+                    }
+                }
+                None => warn!("No WiFi credentials stored"),
+            },
+            // Keep the sector as is, it may belong to a newer firmware
+            Err(e) => {
+                warn!("Ignoring stored WiFi settings: {:?}", e);
+                if let Some(credentials) = build_default() {
+                    store.networks.save(credentials);
+                }
+            }
+        }
+        store
+    }
+
+    /// The whole flash, for the firmware updater.
+    pub fn flash(&mut self) -> &mut BoardFlash {
+        &mut self.flash
+    }
+
+    pub fn networks(&self) -> &SavedNetworks {
+        &self.networks
+    }
+
+    /// Addressing saved from the console, which wins over the build-time one.
+    pub fn net_settings(&self) -> Option<&NetSettings> {
+        self.net.as_ref()
+    }
+
+    /// Save addressing for the next boot, or drop it with `None`.
+    pub fn save_net_settings(&mut self, settings: Option<NetSettings>) -> Result<(), FlashError> {
+        self.write(self.networks.clone(), settings)
+    }
+
+    /// Add or update a network and rewrite the record; it is tried from the
+    /// next join.
+    pub fn save_network(&mut self, credentials: Credentials) -> Result<(), FlashError> {
+        let mut networks = self.networks.clone();
+        if let Some(dropped) = networks.save(credentials) {
+            info!("Replacing saved WiFi network {=str}", dropped.ssid.as_str());
+        }
+        self.write(networks, self.net.clone())
+    }
+
+    /// Forget the networks and the addressing.
+    pub fn erase(&mut self) -> Result<(), FlashError> {
+        self.flash.blocking_erase(SETTINGS_OFFSET, SETTINGS_OFFSET + ERASE_SIZE as u32)?;
+        self.networks = SavedNetworks::new();
+        self.net = None;
+        Ok(())
+    }
+
+    // Both records share the sector, so both are rewritten
+    fn write(&mut self, networks: SavedNetworks, net: Option<NetSettings>) -> Result<(), FlashError> {
+        let mut record = [0u8; RECORD_LEN];
+        networks.to_record(&mut record);
+        self.flash.blocking_erase(SETTINGS_OFFSET, SETTINGS_OFFSET + ERASE_SIZE as u32)?;
+        self.flash.blocking_write(SETTINGS_OFFSET, &record)?;
+        if let Some(settings) = &net {
+            let mut record = [0u8; NET_RECORD_LEN];
+            self.flash.blocking_write(NET_OFFSET, settings.to_record(&mut record))?;
+        }
+        self.networks = networks;
+        self.net = net;
+        Ok(())
+    }
+}
+
+fn build_default() -> Option<Credentials> {
+    let credentials = Credentials::new(DEFAULT_SSID?, DEFAULT_PASSWORD.unwrap_or(""));
+    if let Err(e) = &credentials {
+        warn!("Invalid build-time WiFi credentials: {:?}", e);
+    }
+    credentials.ok()
+}
@@ -0,0 +1,245 @@
+// file: shell_task.rs
+// desc: command shell on the USB serial port
+//
+// Open the port with `screen /dev/ttyACM0` or `minicom -D /dev/ttyACM0` and
+// type `help`. Display commands go through the same queue as HTTP and the
+// buttons; bus events are printed as log lines above the prompt while a
+// terminal is attached.
+use core::fmt::Write as _;
+
+use defmt::{info, warn};
+
+use embassy_futures::select::{select3, Either3};
+use embassy_net::Stack;
+use embassy_sync::pubsub::WaitResult;
+use embassy_time::{Duration, Ticker};
+use embassy_usb::driver::EndpointError;
+use heapless::String;
+
+use pico2w_bsp::Radio;
+use pico2w_core::api::STATUS_JSON_LEN;
+use pico2w_core::shell::{
+    event_level, log_line, parse_line, Key, LineEditor, LogLevel, ShellCommand, HELP, LINE_LEN, PROMPT,
+};
+use pico2w_core::{Event, Input, Source};
+
+use crate::display_task::{playback_state, ANIMATION_COUNT};
+use crate::networking_task;
+use crate::reboot::reboot_soon;
+use crate::routes::{status_json, FIRMWARE_VERSION};
+use crate::settings_store::Store;
+use crate::usb_task::{Console, MAX_PACKET_LEN};
+use crate::{CommandSender, EventSubscriber, EVENTS};
+
+// Frame events at `debug`, as often as /events sends them
+const FRAME_EVERY: Duration = Duration::from_secs(1);
+// Move to the start of the line and clear it, so a log line replaces the prompt
+const CLEAR_LINE: &[u8] = b"\r\x1b[K";
+
+#[embassy_executor::task]
+pub async fn shell_task(
+    mut console: Console,
+    stack: Stack<'static>,
+    radio: &'static Radio,
+    sender: CommandSender,
+    store: &'static Store,
+) {
+    let Ok(mut events) = EVENTS.subscriber() else {
+        warn!("No event subscriber left for the USB console");
+        return;
+    };
+    let mut shell = Shell { stack, radio, sender, store, level: LogLevel::Info, editor: LineEditor::new() };
+    loop {
+        console.wait_connection().await;
+        info!("USB console connected");
+        // Only fails once the host goes away
+        let _ = shell.run(&mut console, &mut events).await;
+        info!("USB console disconnected");
+    }
+}
+
+struct Shell {
+    stack: Stack<'static>,
+    radio: &'static Radio,
+    sender: CommandSender,
+    store: &'static Store,
+    level: LogLevel,
+    editor: LineEditor,
+}
+
+impl Shell {
+    async fn run(&mut self, console: &mut Console, events: &mut EventSubscriber) -> Result<(), EndpointError> {
+        let mut greeting: String<64> = String::new();
+        write!(greeting, "Pico 2W Control {FIRMWARE_VERSION}, `help` lists commands\r\n").ok();
+        write(console, greeting.as_bytes()).await?;
+        self.prompt(console).await?;
+
+        let mut frames = Ticker::every(FRAME_EVERY);
+        let mut packet = [0u8; MAX_PACKET_LEN as usize];
+        loop {
+            match select3(console.read_packet(&mut packet), events.next_message(), frames.next()).await {
+                Either3::First(read) => {
+                    let len = read?;
+                    for &byte in &packet[..len] {
+                        self.key(console, byte).await?;
+                    }
+                }
+                Either3::Second(WaitResult::Message(event)) => self.log(console, &event).await?,
+                Either3::Second(WaitResult::Lagged(missed)) => warn!("USB console missed {} events", missed),
+                Either3::Third(()) => {
+                    if let Some(state) = playback_state() {
+                        self.log(console, &Event::frame(&state)).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn key(&mut self, console: &mut Console, byte: u8) -> Result<(), EndpointError> {
+        match self.editor.feed(byte) {
+            Key::Echo(byte) => write(console, &[byte]).await,
+            Key::Erase => write(console, b"\x08 \x08").await,
+            Key::Bell => write(console, b"\x07").await,
+            Key::Ignore => Ok(()),
+            Key::Cancel => {
+                write(console, b"^C\r\n").await?;
+                self.prompt(console).await
+            }
+            Key::Enter => {
+                write(console, b"\r\n").await?;
+                let line: String<LINE_LEN> = String::try_from(self.editor.line()).unwrap_or_default();
+                self.editor.clear();
+                self.execute(console, &line).await?;
+                self.prompt(console).await
+            }
+        }
+    }
+
+    async fn execute(&mut self, console: &mut Console, line: &str) -> Result<(), EndpointError> {
+        let animation_count = playback_state().map_or(ANIMATION_COUNT as u8, |state| state.animation_count);
+        let command = match parse_line(line, animation_count) {
+            Ok(Some(command)) => command,
+            Ok(None) => return Ok(()),
+            Err(e) => return writeln(console, e.message()).await,
+        };
+        info!("USB console: {:?}", command);
+
+        match command {
+            ShellCommand::Display(command) => {
+                match self.sender.try_send(Input { source: Source::Serial, command }) {
+                    Ok(()) => writeln(console, "ok").await,
+                    Err(_) => writeln(console, "display busy, try again").await,
+                }
+            }
+            ShellCommand::Status => {
+                let mut json = [0u8; STATUS_JSON_LEN];
+                match status_json(self.stack, self.radio, &mut json).await {
+                    Some(json) => {
+                        write(console, json).await?;
+                        write(console, b"\r\n").await
+                    }
+                    None => writeln(console, "display not ready").await,
+                }
+            }
+            ShellCommand::WifiScan => {
+                writeln(console, "scanning...").await?;
+                let networks = networking_task::scan(self.radio).await;
+                if networks.is_empty() {
+                    return writeln(console, "no networks found").await;
+                }
+                for network in networks.iter() {
+                    let mut line: String<64> = String::new();
+                    let security = if network.secure { "secure" } else { "open" };
+                    write!(line, "{:>4} dBm  ch {:>2}  {:<6}  {}", network.rssi, network.channel, security, network.ssid).ok();
+                    writeln(console, &line).await?;
+                }
+                Ok(())
+            }
+            ShellCommand::Ip => {
+                let Some(config) = self.stack.config_v4() else {
+                    return writeln(console, "no address").await;
+                };
+                let mut line: String<64> = String::new();
+                write!(line, "address {}", config.address).ok();
+                writeln(console, &line).await?;
+                if let Some(gateway) = config.gateway {
+                    line.clear();
+                    write!(line, "gateway {}", gateway).ok();
+                    writeln(console, &line).await?;
+                }
+                for dns in config.dns_servers.iter() {
+                    line.clear();
+                    write!(line, "dns     {}", dns).ok();
+                    writeln(console, &line).await?;
+                }
+                Ok(())
+            }
+            ShellCommand::Net(settings) => {
+                let saved = settings.is_some();
+                let written = self.store.lock().await.save_net_settings(settings);
+                match written {
+                    Ok(()) if saved => writeln(console, "saved, applies after `reboot`").await,
+                    Ok(()) => writeln(console, "build-time addressing from the next `reboot`").await,
+                    Err(e) => {
+                        warn!("Settings write failed: {:?}", e);
+                        writeln(console, "could not save").await
+                    }
+                }
+            }
+            ShellCommand::LogLevel(level) => {
+                if let Some(level) = level {
+                    self.level = level;
+                }
+                let mut line: String<24> = String::new();
+                write!(line, "log level {}", self.level.as_str()).ok();
+                writeln(console, &line).await
+            }
+            ShellCommand::Reboot(target) => {
+                writeln(console, "rebooting").await?;
+                reboot_soon(target).await
+            }
+            ShellCommand::Help => {
+                for line in HELP.lines() {
+                    writeln(console, line).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // A log line above the prompt, with whatever was typed so far put back
+    async fn log(&mut self, console: &mut Console, event: &Event) -> Result<(), EndpointError> {
+        // Nobody is reading without a terminal; writes would only queue up
+        if !console.dtr() || !self.level.shows(event_level(event)) {
+            return Ok(());
+        }
+        let Some(line) = log_line(event) else {
+            return Ok(());
+        };
+        write(console, CLEAR_LINE).await?;
+        write(console, line.as_bytes()).await?;
+        self.prompt(console).await
+    }
+
+    async fn prompt(&self, console: &mut Console) -> Result<(), EndpointError> {
+        write(console, PROMPT.as_bytes()).await?;
+        write(console, self.editor.line().as_bytes()).await
+    }
+}
+
+async fn writeln(console: &mut Console, text: &str) -> Result<(), EndpointError> {
+    write(console, text.as_bytes()).await?;
+    write(console, b"\r\n").await
+}
+
+// Bulk packets of at most MAX_PACKET_LEN; a full last one needs a zero-length
+// packet so the host sees the end of the transfer
+async fn write(console: &mut Console, bytes: &[u8]) -> Result<(), EndpointError> {
+    for chunk in bytes.chunks(MAX_PACKET_LEN as usize) {
+        console.write_packet(chunk).await?;
+    }
+    if !bytes.is_empty() && bytes.len() % MAX_PACKET_LEN as usize == 0 {
+        console.write_packet(&[]).await?;
+    }
+    Ok(())
+}
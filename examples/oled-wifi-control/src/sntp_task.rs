@@ -0,0 +1,121 @@
+// file: sntp_task.rs
+// desc: keep wall-clock time with SNTP
+//
+// Syncs once the board is online and every hour after that. Other tasks read
+// the result through `now()`; it stays `None` until the first sync.
+use core::cell::Cell;
+
+use defmt::{info, warn};
+
+use embassy_net::dns::DnsQueryType;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpEndpoint, Stack};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+
+use pico2w_core::sntp::{self, Sample, SntpError, UnixTime, WallClock};
+
+use crate::networking_task::wait_online;
+
+// Host name or address; override with NTP_SERVER at build time
+const SERVER: &str = match option_env!("NTP_SERVER") {
+    Some(server) => server,
+    None => sntp::DEFAULT_SERVER,
+};
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+// Pause after a failed receive rather than going straight back for more
+const RECEIVE_ERROR_DELAY: Duration = Duration::from_millis(500);
+
+static CLOCK: Mutex<CriticalSectionRawMutex, Cell<WallClock>> = Mutex::new(Cell::new(WallClock::new()));
+
+/// Current UTC time, or `None` until the clock has synced.
+pub fn now() -> Option<UnixTime> {
+    CLOCK.lock(|clock| clock.get()).now(Instant::now().as_micros())
+}
+
+#[embassy_executor::task]
+pub async fn sntp_task(stack: Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0u8; 256];
+    let mut tx_buffer = [0u8; 256];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    // Any local port will do
+    if let Err(e) = socket.bind(0) {
+        warn!("SNTP bind failed: {:?}", e);
+        return;
+    }
+
+    loop {
+        wait_online(stack).await;
+        let wait = match sync(stack, &mut socket).await {
+            Some(sample) => {
+                let uptime_us = Instant::now().as_micros();
+                CLOCK.lock(|clock| {
+                    let mut synced = clock.get();
+                    synced.update(sample, uptime_us);
+                    clock.set(synced);
+                });
+                if let Some(time) = now() {
+                    info!("Clock synced to {}, round trip {} us", time.datetime(), sample.delay_us);
+                }
+                sntp::RESYNC_INTERVAL
+            }
+            None => sntp::RETRY_INTERVAL,
+        };
+        Timer::after(Duration::from_secs(wait.as_secs())).await;
+    }
+}
+
+async fn sync(stack: Stack<'static>, socket: &mut UdpSocket<'_>) -> Option<Sample> {
+    let address = match stack.dns_query(SERVER, DnsQueryType::A).await {
+        Ok(addresses) => *addresses.first()?,
+        Err(e) => {
+            warn!("SNTP server {=str} did not resolve: {:?}", SERVER, e);
+            return None;
+        }
+    };
+    let server = IpEndpoint::new(address, sntp::PORT);
+
+    let sent_us = Instant::now().as_micros();
+    if let Err(e) = socket.send_to(&sntp::request(sent_us), server).await {
+        warn!("SNTP request failed: {:?}", e);
+        return None;
+    }
+
+    let reply = with_timeout(REPLY_TIMEOUT, async {
+        let mut packet = [0u8; sntp::MAX_REPLY_LEN];
+        loop {
+            let (len, meta) = match socket.recv_from(&mut packet).await {
+                Ok(received) => received,
+                Err(e) => {
+                    warn!("SNTP receive failed: {:?}", e);
+                    Timer::after(RECEIVE_ERROR_DELAY).await;
+                    continue;
+                }
+            };
+            if meta.endpoint != server {
+                continue;
+            }
+            match sntp::parse_reply(&packet[..len], sent_us, Instant::now().as_micros()) {
+                // A late answer to an earlier request
+                Err(SntpError::Mismatch) => continue,
+                result => return result,
+            }
+        }
+    })
+    .await;
+
+    match reply {
+        Ok(Ok(sample)) => Some(sample),
+        Ok(Err(e)) => {
+            warn!("SNTP reply rejected: {:?}", e);
+            None
+        }
+        Err(_) => {
+            warn!("No SNTP reply from {=str}", SERVER);
+            None
+        }
+    }
+}
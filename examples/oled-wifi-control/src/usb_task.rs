@@ -0,0 +1,128 @@
+// file: usb_task.rs
+// desc: the USB device: serial console, animations drive and picotool reset
+//
+// The board enumerates with Raspberry Pi's stdio USB IDs so picotool
+// recognises it. The serial port carries the shell (see `shell_task`), the
+// mass storage interface the animations drive (see `drive_task`). With the
+// runner's `-f`, `picotool load` sends the reset request, the board drops into
+// BOOTSEL and the new image is loaded without anyone holding the button.
+use core::fmt::Write;
+
+use defmt::{info, warn};
+
+use embassy_futures::join::join;
+use embassy_rp::bind_interrupts;
+use embassy_rp::peripherals::USB;
+use embassy_rp::usb::{Driver, InterruptHandler};
+use embassy_rp::{otp, Peri};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
+use embassy_usb::control::{OutResponse, Recipient, Request, RequestType};
+use embassy_usb::types::InterfaceNumber;
+use embassy_usb::{Builder, Config, Handler, UsbDevice};
+use heapless::String;
+use static_cell::{ConstStaticCell, StaticCell};
+
+use pico2w_core::reboot::{
+    RebootTarget, RESET_INTERFACE_CLASS, RESET_INTERFACE_PROTOCOL, RESET_INTERFACE_SUBCLASS, USB_PID, USB_VID,
+};
+
+use crate::drive_task::MassStorage;
+use crate::reboot::reboot;
+
+bind_interrupts!(struct Irqs {
+    USBCTRL_IRQ => InterruptHandler<USB>;
+});
+
+// Lets the control transfer finish before the board disappears
+const RESET_DELAY: Duration = Duration::from_millis(50);
+pub const MAX_PACKET_LEN: u16 = 64;
+
+pub type UsbDriver = Driver<'static, USB>;
+pub type Console = CdcAcmClass<'static, UsbDriver>;
+
+static CONFIG_DESCRIPTOR: ConstStaticCell<[u8; 256]> = ConstStaticCell::new([0; 256]);
+static BOS_DESCRIPTOR: ConstStaticCell<[u8; 256]> = ConstStaticCell::new([0; 256]);
+static CONTROL_BUF: ConstStaticCell<[u8; 64]> = ConstStaticCell::new([0; 64]);
+static SERIAL: StaticCell<String<16>> = StaticCell::new();
+static CONSOLE_STATE: StaticCell<State> = StaticCell::new();
+static RESET_INTERFACE: StaticCell<ResetInterface> = StaticCell::new();
+static RESET: Signal<CriticalSectionRawMutex, RebootTarget> = Signal::new();
+
+// Vendor interface without endpoints; everything arrives as class requests
+struct ResetInterface {
+    interface: InterfaceNumber,
+}
+
+impl Handler for ResetInterface {
+    fn control_out(&mut self, req: Request, _data: &[u8]) -> Option<OutResponse> {
+        if req.request_type != RequestType::Class
+            || req.recipient != Recipient::Interface
+            || req.index != u8::from(self.interface) as u16
+        {
+            return None;
+        }
+        match RebootTarget::from_reset_request(req.request) {
+            Some(target) => {
+                RESET.signal(target);
+                Some(OutResponse::Accepted)
+            }
+            None => {
+                warn!("Unknown USB reset request {}", req.request);
+                Some(OutResponse::Rejected)
+            }
+        }
+    }
+}
+
+/// Describe the device; `usb_task` runs it, `shell_task` gets the console and
+/// `drive_task` the mass storage endpoints.
+pub fn setup(usb: Peri<'static, USB>) -> (UsbDevice<'static, UsbDriver>, Console, MassStorage) {
+    let driver = Driver::new(usb, Irqs);
+
+    // The chip ID: unique per board and the same across reboots
+    let serial = SERIAL.init(String::new());
+    write!(serial, "{:016X}", otp::get_chipid().unwrap_or(0)).ok();
+
+    let mut config = Config::new(USB_VID, USB_PID);
+    config.manufacturer = Some("Raspberry Pi");
+    config.product = Some("Pico 2W Control");
+    config.serial_number = Some(serial.as_str());
+    config.max_power = 100;
+    config.max_packet_size_0 = 64;
+
+    let mut builder = Builder::new(
+        driver,
+        config,
+        CONFIG_DESCRIPTOR.take(),
+        BOS_DESCRIPTOR.take(),
+        &mut [],
+        CONTROL_BUF.take(),
+    );
+
+    let console = CdcAcmClass::new(&mut builder, CONSOLE_STATE.init(State::new()), MAX_PACKET_LEN);
+    let storage = MassStorage::new(&mut builder);
+
+    let mut function = builder.function(RESET_INTERFACE_CLASS, RESET_INTERFACE_SUBCLASS, RESET_INTERFACE_PROTOCOL);
+    let mut interface = function.interface();
+    let interface_number = interface.interface_number();
+    interface.alt_setting(RESET_INTERFACE_CLASS, RESET_INTERFACE_SUBCLASS, RESET_INTERFACE_PROTOCOL, None);
+    drop(function);
+    builder.handler(RESET_INTERFACE.init(ResetInterface { interface: interface_number }));
+
+    info!("USB serial number {}", serial.as_str());
+    (builder.build(), console, storage)
+}
+
+#[embassy_executor::task]
+pub async fn usb_task(mut device: UsbDevice<'static, UsbDriver>) {
+    let reset = async {
+        let target = RESET.wait().await;
+        info!("Reboot requested over USB ({})", target.as_str());
+        Timer::after(RESET_DELAY).await;
+        reboot(target)
+    };
+    join(device.run(), reset).await;
+}
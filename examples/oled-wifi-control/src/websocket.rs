@@ -0,0 +1,130 @@
+// file: websocket.rs
+// desc: WebSocket control channel on GET /ws
+//
+// Clients send commands as text (JSON, e.g. {"PlayAnimation":2}) or binary
+// (the versioned wire encoding) frames and get every playback event back as a
+// JSON text frame.
+use defmt::{info, warn};
+
+use embassy_futures::select::{select3, Either3};
+use embassy_net::tcp::{Error as TcpError, TcpSocket};
+use embassy_sync::pubsub::WaitResult;
+use embassy_time::{Duration, Ticker};
+use embedded_io_async::Write;
+
+use pico2w_core::http::{Request, Response, Status};
+use pico2w_core::websocket::{accept_key, decode_frame, encode_header, parse_command, upgrade_key, Frame, Opcode, CLOSE_NORMAL, MAX_HEADER_LEN};
+use pico2w_core::{Event, Input, Source};
+
+use crate::display_task::playback_state;
+use crate::routes::{reply, Context};
+use crate::EVENTS;
+
+// Client frames are small commands; anything bigger is refused
+const FRAME_BUFFER_LEN: usize = 256;
+// Ping quiet clients well inside the idle timeout
+const PING_EVERY: Duration = Duration::from_secs(20);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub async fn websocket(socket: &mut TcpSocket<'_>, request: &Request<'_>, ctx: Context) -> Result<(), TcpError> {
+    let key = match upgrade_key(request) {
+        Ok(key) => key,
+        Err(e) => return reply(request, e.status()).send_text(socket, e.status().reason()).await,
+    };
+    // Event subscribers are shared with /events, one per HTTP worker
+    let Ok(mut subscriber) = EVENTS.subscriber() else {
+        return reply(request, Status::ServiceUnavailable).send_text(socket, "Too many event streams").await;
+    };
+
+    let accept = accept_key(key);
+    Response::new(Status::SwitchingProtocols)
+        .header("Upgrade", "websocket")
+        .header("Connection", "Upgrade")
+        .header("Sec-WebSocket-Accept", &accept)
+        .send(socket, &[])
+        .await?;
+    info!("WebSocket client connected");
+    socket.set_timeout(Some(IDLE_TIMEOUT));
+
+    // Start the client off with what is on screen
+    if let Some(message) = playback_state().and_then(|state| Event::frame(&state).to_json()) {
+        send_frame(socket, Opcode::Text, message.as_bytes()).await?;
+    }
+
+    let mut buf = [0u8; FRAME_BUFFER_LEN];
+    let mut filled = 0;
+    let mut ping = Ticker::every(PING_EVERY);
+    loop {
+        // Answer every complete frame already buffered
+        loop {
+            let (used, closing) = match decode_frame(&mut buf, filled) {
+                Ok(Some((frame, used))) => (used, on_frame(socket, &frame, ctx).await?),
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Closing WebSocket: {:?}", e);
+                    return send_frame(socket, Opcode::Close, &e.close_code().to_be_bytes()).await;
+                }
+            };
+            if closing {
+                info!("WebSocket client closed");
+                return Ok(());
+            }
+            buf.copy_within(used..filled, 0);
+            filled -= used;
+        }
+
+        match select3(socket.read(&mut buf[filled..]), subscriber.next_message(), ping.next()).await {
+            Either3::First(Ok(0)) => return Ok(()),
+            Either3::First(Ok(bytes_read)) => filled += bytes_read,
+            Either3::First(Err(e)) => return Err(e),
+            Either3::Second(WaitResult::Message(event)) => {
+                if let Some(message) = event.to_json() {
+                    send_frame(socket, Opcode::Text, message.as_bytes()).await?;
+                }
+            }
+            Either3::Second(WaitResult::Lagged(missed)) => warn!("WebSocket lagged, {} events dropped", missed),
+            Either3::Third(_) => send_frame(socket, Opcode::Ping, &[]).await?,
+        }
+    }
+}
+
+// Act on one client frame; true once the close handshake is done.
+async fn on_frame(socket: &mut TcpSocket<'_>, frame: &Frame<'_>, ctx: Context) -> Result<bool, TcpError> {
+    match frame.opcode {
+        Opcode::Text | Opcode::Binary => {
+            let reply: &[u8] = match parse_command(frame) {
+                Some(command) => {
+                    info!("WebSocket command: {:?}", command);
+                    match ctx.sender.try_send(Input { source: Source::Websocket, command }) {
+                        Ok(_) => br#"{"ok":true}"#,
+                        Err(_) => br#"{"error":"display busy, try again"}"#,
+                    }
+                }
+                None => br#"{"error":"unknown command"}"#,
+            };
+            send_frame(socket, Opcode::Text, reply).await?;
+            Ok(false)
+        }
+        Opcode::Ping => {
+            send_frame(socket, Opcode::Pong, frame.payload).await?;
+            Ok(false)
+        }
+        Opcode::Pong | Opcode::Continuation => Ok(false),
+        Opcode::Close => {
+            // Echo the client's status code, or a plain normal closure
+            let code = match frame.payload {
+                [high, low, ..] => [*high, *low],
+                _ => CLOSE_NORMAL.to_be_bytes(),
+            };
+            send_frame(socket, Opcode::Close, &code).await?;
+            Ok(true)
+        }
+    }
+}
+
+async fn send_frame(socket: &mut TcpSocket<'_>, opcode: Opcode, payload: &[u8]) -> Result<(), TcpError> {
+    let mut header = [0u8; MAX_HEADER_LEN];
+    socket.write_all(encode_header(opcode, payload.len(), &mut header)).await?;
+    socket.write_all(payload).await?;
+    socket.flush().await
+}